@@ -1,16 +1,38 @@
 pub mod source;
 
-use crate::{backends::common::Repository, utils::auth::UserIdentity};
+use crate::{
+    backends::common::Repository,
+    utils::{auth::UserIdentity, errors::APIError},
+};
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Limits on the listing work a single account may cause, configured per
+/// account in the Source API. Unset limits fall back to the proxy's defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceLimits {
+    /// The number of repositories returned per page when listing the account.
+    pub max_products_listed: Option<u32>,
+    /// The largest `max-keys` honoured when listing a repository.
+    pub max_page_size: Option<u32>,
+    /// The number of listings of the account which may be in flight at once.
+    pub max_concurrent_listings: Option<u32>,
+}
 
 pub struct Account {
     pub repositories: Vec<String>,
+    /// The `(account_id, repository_id)` of the visible repositories of the
+    /// organization's member accounts, when they were asked for.
+    pub member_repositories: Vec<(String, String)>,
+    pub limits: ServiceLimits,
 }
 
 impl Account {
     fn default() -> Account {
         Account {
             repositories: Vec::new(),
+            member_repositories: Vec::new(),
+            limits: ServiceLimits::default(),
         }
     }
 }
@@ -21,11 +43,15 @@ pub trait API {
         &self,
         account_id: &String,
         repository_id: &String,
-    ) -> Result<Box<dyn Repository>, ()>;
+        requester_pays: bool,
+    ) -> Result<Box<dyn Repository>, Box<dyn APIError>>;
 
+    /// Returns the repositories of an account visible to `user_identity`, and
+    /// with `include_members` those of the accounts of an organization too.
     async fn get_account(
         &self,
         account_id: String,
         user_identity: UserIdentity,
+        include_members: bool,
     ) -> Result<Account, ()>;
 }
@@ -1,26 +1,67 @@
-use super::{Account, API};
+use super::{Account, ServiceLimits, API};
 use crate::backends::azure::AzureRepository;
+use crate::backends::azure_aad::AadCredentials;
 use crate::backends::common::Repository;
+use crate::backends::dual_read::{DualReadRepository, FallbackHits};
 use crate::backends::s3::S3Repository;
+use crate::backends::shadow::{ShadowRepository, ShadowStats};
+use crate::utils::api_mirror::ApiMirror;
 use crate::utils::auth::UserIdentity;
-use crate::utils::errors::{APIError, InternalServerError, RepositoryNotFoundError};
+use crate::utils::cassette;
+use crate::utils::clock::SharedClock;
+use crate::utils::errors::{
+    APIError, AccessDeniedError, AccountNotFoundError, ConfigurationError, DenialReason,
+    InternalServerError, RepositoryNotFoundError, RepositoryTombstonedError, TermsNotAcceptedError,
+};
+use crate::utils::lifecycle::LifecycleRule;
+use crate::utils::policy::{evaluate_policy, PolicyStatement};
+use crate::utils::session_token::{SessionClaims, SessionSigner};
+use crate::utils::single_flight::{get_or_fetch, CacheStats};
+use crate::utils::warm_pool::{source_api_client, PingStats};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use moka::future::Cache;
-use rusoto_core::Region;
+use moka::Expiry;
+use rand::distributions::{Alphanumeric, DistString};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::env;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// The share of reads replayed against a shadow mirror when a repository
+/// doesn't set `shadow_sample_rate`.
+const DEFAULT_SHADOW_SAMPLE_RATE: f64 = 0.01;
+
+/// The most API keys cached by each proxy instance.
+const MAX_CACHED_API_KEYS: u64 = 10_000;
+
+/// The longest lifetime of temporary credentials.
+pub const MAX_SESSION_DURATION: Duration = Duration::from_secs(12 * 60 * 60);
 
 #[derive(Clone)]
 pub struct SourceAPI {
     pub endpoint: String,
+    /// Shared by all requests to the Source API, see `source_api_client`.
+    pub http: reqwest::Client,
+    pub ping_stats: PingStats,
     repository_cache: Arc<Cache<String, SourceRepository>>,
     data_connection_cache: Arc<Cache<String, DataConnection>>,
     api_key_cache: Arc<Cache<String, APIKey>>,
     permissions_cache: Arc<Cache<String, Vec<RepositoryPermission>>>,
+    terms_acceptance_cache: Arc<Cache<String, bool>>,
+    service_limits_cache: Arc<Cache<String, ServiceLimits>>,
+    pub fallback_hits: FallbackHits,
+    pub shadow_stats: ShadowStats,
+    pub cache_stats: CacheStats,
+    /// Caches the tokens of Azure service principals across requests.
+    pub aad_credentials: AadCredentials,
+    /// Set when lookups are mirrored to a staging Source API.
+    pub mirror: Option<Arc<ApiMirror>>,
+    /// Signs the session tokens of temporary credentials.
+    session_signer: Arc<SessionSigner>,
+    pub clock: SharedClock,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -29,12 +70,58 @@ pub enum RepositoryPermission {
     Read,
     #[serde(rename = "write")]
     Write,
+    /// Manages the repository, e.g. deleting whole prefixes.
+    #[serde(rename = "admin")]
+    Admin,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct APIKey {
     pub access_key_id: String,
     pub secret_access_key: String,
+    #[serde(default)]
+    pub mfa_authenticated: bool,
+    /// Temporary credentials, whether issued by the proxy or the Source API,
+    /// are only valid with their session token.
+    #[serde(default)]
+    pub session_token: Option<String>,
+    /// Set for temporary credentials issued by the proxy.
+    #[serde(skip)]
+    pub session: Option<SessionScope>,
+}
+
+/// The scope of temporary credentials issued with `issue_session_credentials`.
+#[derive(Debug, Clone)]
+pub struct SessionScope {
+    /// The long-lived key the credentials were derived from.
+    pub parent: Box<APIKey>,
+    pub account_id: String,
+    pub repository_id: String,
+    pub expiration: DateTime<Utc>,
+}
+
+/// Expires temporary credentials when their session ends, and long-lived
+/// keys after 60 seconds like the other caches.
+struct APIKeyExpiry {
+    clock: SharedClock,
+}
+
+impl Expiry<String, APIKey> for APIKeyExpiry {
+    fn expire_after_create(
+        &self,
+        _access_key_id: &String,
+        api_key: &APIKey,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        match &api_key.session {
+            Some(session) => Some(
+                (session.expiration - self.clock.now())
+                    .to_std()
+                    .unwrap_or(Duration::ZERO),
+            ),
+            None => Some(Duration::from_secs(60)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +135,72 @@ pub struct SourceRepository {
     pub state: String,
     pub meta: SourceRepositoryMeta,
     pub data: SourceRepositoryData,
+    pub terms: Option<SourceRepositoryTerms>,
+    /// The `{account_id}/{repository_id}` replacing a repository in the
+    /// `tombstoned` state.
+    #[serde(default)]
+    pub successor: Option<String>,
+    #[serde(default)]
+    pub policies: Vec<PolicyStatement>,
+    /// Prefixes whose objects the proxy expires, e.g. `scratch/` areas.
+    #[serde(default)]
+    pub lifecycle_rules: Vec<LifecycleRule>,
+    /// Keys of the documents served to browsers in place of error responses,
+    /// by status code, e.g. `404: .source/errors/404.html`.
+    #[serde(default)]
+    pub error_documents: HashMap<u16, String>,
+    /// Set while the publisher has the repository in maintenance, e.g.
+    /// during a data fix.
+    #[serde(default)]
+    pub maintenance: Option<Maintenance>,
+    /// Limits which sites browsers may load the repository's objects from.
+    #[serde(default)]
+    pub hotlinking: Option<HotlinkPolicy>,
+}
+
+/// Restricts browser requests for a repository's objects by their `Referer`
+/// or `Origin`, so open data can't be embedded by any third-party site.
+/// Signed requests and requests from other clients are never restricted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotlinkPolicy {
+    /// Hosts allowed to link to objects, e.g. `example.com`, or
+    /// `*.example.com` for its subdomains. Any host is allowed when empty.
+    #[serde(default)]
+    pub allowed_referers: Vec<String>,
+    /// Blocks browser requests sending neither a `Referer` nor an `Origin`.
+    #[serde(default)]
+    pub block_empty: bool,
+    /// When set, browser requests must carry it as the `source-token` query
+    /// parameter, wherever they come from.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Requests for a repository in maintenance fail with `503 Service
+/// Unavailable`, except for its admins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Maintenance {
+    /// Explains the maintenance to clients, e.g. when data will be back.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Seconds clients are asked to wait before retrying.
+    #[serde(default = "default_maintenance_retry_after")]
+    pub retry_after: u64,
+}
+
+fn default_maintenance_retry_after() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceRepositoryTerms {
+    pub required: bool,
+    pub acceptance_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TermsAcceptance {
+    pub accepted: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +211,16 @@ pub struct DataConnectionDetails {
     pub bucket: Option<String>,
     pub account_name: Option<String>,
     pub container_name: Option<String>,
+    pub endpoint: Option<String>,
+    pub signing_region: Option<String>,
+    /// Sends `x-amz-request-payer: requester` with every request, for
+    /// requester-pays buckets.
+    #[serde(default)]
+    pub requester_pays: bool,
+    /// Accepts any TLS certificate from `endpoint`, for on-premises clusters
+    /// with self-signed certificates.
+    #[serde(default)]
+    pub skip_tls_verification: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,6 +229,9 @@ pub struct DataConnectionAuthentication {
     pub auth_type: String,
     pub access_key_id: Option<String>,
     pub secret_access_key: Option<String>,
+    /// The Microsoft Entra ID tenant of Azure service principals.
+    #[serde(default)]
+    pub tenant_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -78,6 +244,50 @@ pub struct DataConnection {
     pub required_flag: Option<String>,
     pub details: DataConnectionDetails,
     pub authentication: Option<DataConnectionAuthentication>,
+    /// The operations the proxy performs on this connection. All operations
+    /// are allowed when unset, subject to `read_only`.
+    #[serde(default)]
+    pub allowed_operations: Option<Vec<BackendOperation>>,
+}
+
+/// The classes of operations a data connection can be restricted to.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendOperation {
+    Read,
+    List,
+    Write,
+    Delete,
+}
+
+impl BackendOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendOperation::Read => "read",
+            BackendOperation::List => "list",
+            BackendOperation::Write => "write",
+            BackendOperation::Delete => "delete",
+        }
+    }
+}
+
+impl DataConnection {
+    /// Returns whether the proxy may perform `operation` on this connection.
+    pub fn allows(&self, operation: BackendOperation) -> bool {
+        if self.read_only
+            && matches!(
+                operation,
+                BackendOperation::Write | BackendOperation::Delete
+            )
+        {
+            return false;
+        }
+
+        match &self.allowed_operations {
+            Some(allowed) => allowed.contains(&operation),
+            None => true,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,6 +300,17 @@ pub struct SourceRepositoryMeta {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SourceRepositoryData {
     pub primary_mirror: String,
+    /// The mirror a repository is being migrated from, which serves reads
+    /// that miss the primary mirror.
+    #[serde(default)]
+    pub fallback_mirror: Option<String>,
+    /// A mirror which is sent a sample of reads for comparison with the
+    /// primary mirror, to validate it before a switch.
+    #[serde(default)]
+    pub shadow_mirror: Option<String>,
+    /// The share of reads sent to the shadow mirror, between 0 and 1.
+    #[serde(default)]
+    pub shadow_sample_rate: Option<f64>,
     pub mirrors: HashMap<String, SourceRepositoryMirror>,
 }
 
@@ -97,6 +318,34 @@ pub struct SourceRepositoryData {
 pub struct SourceRepositoryMirror {
     pub prefix: String,
     pub data_connection_id: String,
+    /// Overrides the endpoint of the data connection for this mirror.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceAccount {
+    pub account_id: String,
+    #[serde(default)]
+    pub service_limits: ServiceLimits,
+    #[serde(default)]
+    pub created: Option<String>,
+    /// The memberships of an organization account, as recorded by the Source
+    /// API rather than by the organization's publishers.
+    #[serde(default)]
+    pub memberships: Vec<SourceMembership>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceMembership {
+    pub account_id: String,
+    /// `member` once the member accepted, or e.g. `invited` or `revoked`.
+    pub state: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceAccountList {
+    pub accounts: Vec<SourceAccount>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -116,142 +365,96 @@ impl API for SourceAPI {
     ///
     /// * `account_id` - The ID of the account owning the repository.
     /// * `repository_id` - The ID of the repository.
+    /// * `requester_pays` - Whether the request acknowledges that the requester
+    ///   pays for it. Data connections may also require this themselves.
     ///
     /// # Returns
     ///
     /// Returns a `Result` containing either a boxed `Repository` trait object
-    /// or an empty error `()` if the client creation fails.
+    /// or a boxed `APIError` if the repository can't be found or its data
+    /// connection is misconfigured.
     async fn get_backend_client(
         &self,
         account_id: &String,
         repository_id: &String,
-    ) -> Result<Box<dyn Repository>, ()> {
+        requester_pays: bool,
+    ) -> Result<Box<dyn Repository>, Box<dyn APIError>> {
         match self
             .get_repository_record(&account_id, &repository_id)
             .await
         {
             Ok(repository) => {
-                match repository
+                let Some(primary) = repository
                     .data
                     .mirrors
                     .get(repository.data.primary_mirror.as_str())
-                {
-                    Some(repository_data) => {
-                        let data_connection_id = repository_data.data_connection_id.clone();
-                        match self.get_data_connection(&data_connection_id).await {
-                            Ok(data_connection) => {
-                                if data_connection.details.provider == "s3" {
-                                    let region: Region;
-
-                                    if data_connection.authentication.clone().unwrap().auth_type
-                                        == "s3_local"
-                                    {
-                                        region = Region::Custom {
-                                            name: data_connection
-                                                .details
-                                                .region
-                                                .clone()
-                                                .unwrap_or("us-west-2".to_string()),
-                                            endpoint: format!("http://localhost:5050"),
-                                        };
-                                    } else {
-                                        region = Region::Custom {
-                                            name: data_connection
-                                                .details
-                                                .region
-                                                .clone()
-                                                .unwrap_or("us-east-1".to_string()),
-                                            endpoint: format!(
-                                                "https://s3.{}.amazonaws.com",
-                                                data_connection
-                                                    .details
-                                                    .region
-                                                    .clone()
-                                                    .unwrap_or("us-east-1".to_string())
-                                            ),
-                                        };
-                                    }
-
-                                    let bucket: String =
-                                        data_connection.details.bucket.clone().unwrap_or_default();
-                                    let base_prefix: String = data_connection
-                                        .details
-                                        .base_prefix
-                                        .clone()
-                                        .unwrap_or_default();
-
-                                    let prefix =
-                                        format!("{}{}", base_prefix, repository_data.prefix);
-
-                                    let prefix = if prefix.ends_with('/') {
-                                        prefix[..prefix.len() - 1].to_string()
-                                    } else {
-                                        prefix
-                                    };
-
-                                    Ok(Box::new(S3Repository {
-                                        account_id: account_id.to_string(),
-                                        repository_id: repository_id.to_string(),
-                                        region,
-                                        bucket,
-                                        base_prefix: prefix,
-                                        auth_method: data_connection
-                                            .authentication
-                                            .clone()
-                                            .unwrap()
-                                            .auth_type,
-                                        access_key_id: data_connection
-                                            .authentication
-                                            .clone()
-                                            .unwrap()
-                                            .access_key_id,
-                                        secret_access_key: data_connection
-                                            .authentication
-                                            .clone()
-                                            .unwrap()
-                                            .secret_access_key,
-                                    }))
-                                } else if data_connection.details.provider == "az" {
-                                    let account_name: String = data_connection
-                                        .details
-                                        .account_name
-                                        .clone()
-                                        .unwrap_or_default();
-
-                                    let container_name: String = data_connection
-                                        .details
-                                        .container_name
-                                        .clone()
-                                        .unwrap_or_default();
-                                    let base_prefix: String = data_connection
-                                        .details
-                                        .base_prefix
-                                        .clone()
-                                        .unwrap_or_default();
-
-                                    Ok(Box::new(AzureRepository {
-                                        account_id: account_id.to_string(),
-                                        repository_id: repository_id.to_string(),
-                                        account_name,
-                                        container_name,
-                                        base_prefix: format!(
-                                            "{}{}",
-                                            base_prefix, repository_data.prefix
-                                        ),
-                                    }))
-                                } else {
-                                    Err(())
-                                }
-                            }
-                            Err(_) => return Err(()),
-                        }
-                    }
-                    None => {
-                        return Err(());
-                    }
+                else {
+                    log::error!(
+                        "Primary mirror {} of {}/{} not found",
+                        repository.data.primary_mirror,
+                        account_id,
+                        repository_id
+                    );
+                    return Err(Box::new(InternalServerError {
+                        message: "Internal Server Error".to_string(),
+                    }));
+                };
+
+                let primary = self
+                    .get_mirror_client(account_id, repository_id, primary, requester_pays)
+                    .await?;
+
+                // While a repository is being migrated, reads which miss the primary
+                // mirror fall back to the mirror it is being migrated from
+                let fallback = repository
+                    .data
+                    .fallback_mirror
+                    .as_deref()
+                    .and_then(|mirror| repository.data.mirrors.get(mirror));
+
+                let client: Box<dyn Repository + Send + Sync> = match fallback {
+                    Some(fallback) => Box::new(DualReadRepository {
+                        repository: format!("{}/{}", account_id, repository_id),
+                        primary,
+                        fallback: self
+                            .get_mirror_client(account_id, repository_id, fallback, requester_pays)
+                            .await?,
+                        fallback_hits: self.fallback_hits.clone(),
+                    }),
+                    None => primary,
+                };
+
+                // A sample of reads is replayed against the shadow mirror, if any
+                let shadow = repository
+                    .data
+                    .shadow_mirror
+                    .as_deref()
+                    .and_then(|mirror| repository.data.mirrors.get(mirror));
+
+                match shadow {
+                    Some(shadow) => Ok(Box::new(ShadowRepository {
+                        repository: format!("{}/{}", account_id, repository_id),
+                        primary: client,
+                        shadow: Arc::from(
+                            self.get_mirror_client(
+                                account_id,
+                                repository_id,
+                                shadow,
+                                requester_pays,
+                            )
+                            .await?,
+                        ),
+                        sample_rate: repository
+                            .data
+                            .shadow_sample_rate
+                            .unwrap_or(DEFAULT_SHADOW_SAMPLE_RATE)
+                            .clamp(0.0, 1.0),
+                        stats: self.shadow_stats.clone(),
+                    })),
+                    None => Ok(client),
                 }
             }
-            Err(_) => Err(()),
+            Err(error) => Err(error),
         }
     }
 
@@ -259,49 +462,43 @@ impl API for SourceAPI {
         &self,
         account_id: String,
         user_identity: UserIdentity,
+        include_members: bool,
     ) -> Result<Account, ()> {
-        let client = reqwest::Client::new();
-        // Create headers
-        let mut headers = reqwest::header::HeaderMap::new();
-        if user_identity.api_key.is_some() {
-            let api_key = user_identity.api_key.unwrap();
-            headers.insert(
-                reqwest::header::AUTHORIZATION,
-                reqwest::header::HeaderValue::from_str(
-                    format!("{} {}", api_key.access_key_id, api_key.secret_access_key).as_str(),
-                )
-                .unwrap(),
-            );
-        }
+        let mut account = Account::default();
+        account.repositories = self
+            .list_visible_repositories(&account_id, &user_identity)
+            .await?;
 
-        match client
-            .get(format!(
-                "{}/api/v1/repositories/{}",
-                self.endpoint, account_id
-            ))
-            .headers(headers)
-            .send()
-            .await
-        {
-            Ok(response) => match response.json::<SourceRepositoryList>().await {
-                Ok(repository_list) => {
-                    let mut account = Account::default();
+        if include_members {
+            let member_account_ids = self
+                .get_member_account_ids(&account_id)
+                .await
+                .map_err(|_| ())?;
+            for member_account_id in member_account_ids {
+                // Members which can't be listed are left out rather than
+                // failing the organization's listing
+                let Ok(repositories) = self
+                    .list_visible_repositories(&member_account_id, &user_identity)
+                    .await
+                else {
+                    continue;
+                };
+                account.member_repositories.extend(
+                    repositories
+                        .into_iter()
+                        .map(|repository_id| (member_account_id.clone(), repository_id)),
+                );
+            }
+        }
 
-                    for repository in repository_list.repositories {
-                        account.repositories.push(repository.repository_id);
-                    }
+        account.limits = self.get_service_limits(&account_id).await;
 
-                    Ok(account)
-                }
-                Err(_) => Err(()),
-            },
-            Err(_) => Err(()),
-        }
+        Ok(account)
     }
 }
 
 impl SourceAPI {
-    pub fn new(endpoint: String) -> Self {
+    pub fn new(endpoint: String, clock: SharedClock) -> Self {
         let repository_cache = Arc::new(
             Cache::builder()
                 .time_to_live(Duration::from_secs(60)) // Set TTL to 60 seconds
@@ -316,7 +513,10 @@ impl SourceAPI {
 
         let api_key_cache = Arc::new(
             Cache::builder()
-                .time_to_live(Duration::from_secs(60)) // Set TTL to 60 seconds
+                .max_capacity(MAX_CACHED_API_KEYS)
+                .expire_after(APIKeyExpiry {
+                    clock: clock.clone(),
+                })
                 .build(),
         );
 
@@ -326,15 +526,93 @@ impl SourceAPI {
                 .build(),
         );
 
+        let terms_acceptance_cache = Arc::new(
+            Cache::builder()
+                .time_to_live(Duration::from_secs(60)) // Set TTL to 60 seconds
+                .build(),
+        );
+
+        let service_limits_cache = Arc::new(
+            Cache::builder()
+                .time_to_live(Duration::from_secs(60)) // Set TTL to 60 seconds
+                .build(),
+        );
+
         SourceAPI {
             endpoint,
             repository_cache,
             data_connection_cache,
             api_key_cache,
             permissions_cache,
+            terms_acceptance_cache,
+            service_limits_cache,
+            fallback_hits: FallbackHits::default(),
+            shadow_stats: ShadowStats::default(),
+            cache_stats: CacheStats::default(),
+            aad_credentials: AadCredentials::default(),
+            mirror: ApiMirror::from_env().map(Arc::new),
+            session_signer: Arc::new(SessionSigner::from_env()),
+            http: source_api_client(),
+            ping_stats: PingStats::default(),
+            clock,
         }
     }
 
+    /// Creates a client of the Source API whose responses are replayed from
+    /// the cassettes recorded in `tests/cassettes`.
+    #[cfg(test)]
+    pub fn replaying(clock: SharedClock) -> Self {
+        const SOURCE_API_URL: &str = "https://api.source.coop";
+
+        cassette::replay_recorded();
+        env::set_var("SOURCE_API_URL", SOURCE_API_URL);
+        env::set_var("SOURCE_KEY", "source-key");
+
+        SourceAPI::new(SOURCE_API_URL.to_string(), clock)
+    }
+
+    /// Creates a backend client for one of a repository's mirrors.
+    async fn get_mirror_client(
+        &self,
+        account_id: &str,
+        repository_id: &str,
+        repository_data: &SourceRepositoryMirror,
+        requester_pays: bool,
+    ) -> Result<Box<dyn Repository + Send + Sync>, Box<dyn APIError>> {
+        let data_connection_id = repository_data.data_connection_id.clone();
+        let data_connection = self.get_data_connection(&data_connection_id).await?;
+
+        let client: Result<Box<dyn Repository + Send + Sync>, ConfigurationError> =
+            match data_connection.details.provider.as_str() {
+                // MinIO and Ceph speak the S3 API at their own endpoints
+                "s3" | "minio" | "ceph" => S3Repository::try_new(
+                    account_id,
+                    repository_id,
+                    &data_connection,
+                    repository_data,
+                    requester_pays,
+                )
+                .map(|client| Box::new(client) as Box<dyn Repository + Send + Sync>),
+                "az" => AzureRepository::try_new(
+                    account_id,
+                    repository_id,
+                    &data_connection,
+                    &repository_data.prefix,
+                    &self.aad_credentials,
+                )
+                .map(|client| Box::new(client) as Box<dyn Repository + Send + Sync>),
+                provider => Err(ConfigurationError {
+                    data_connection_id,
+                    message: format!("unsupported provider {}", provider),
+                }),
+            };
+
+        client.map_err(|error| {
+            log::error!("{}", error);
+            Box::new(error) as Box<dyn APIError>
+        })
+    }
+
     /// Retrieves the repository record for a given account and repository ID.
     ///
     /// # Arguments
@@ -351,24 +629,267 @@ impl SourceAPI {
         account_id: &String,
         repository_id: &String,
     ) -> Result<SourceRepository, Box<dyn APIError>> {
-        // Try to get the cached value
-        let cache_key = format!("{}/{}", account_id, repository_id);
+        get_or_fetch(
+            &self.repository_cache,
+            &self.cache_stats,
+            "repository",
+            format!("{}/{}", account_id, repository_id),
+            self.fetch_repository(account_id, repository_id),
+        )
+        .await
+    }
 
-        if let Some(cached_repo) = self.repository_cache.get(&cache_key).await {
-            return Ok(cached_repo);
+    /// Returns the region of the data connection backing a repository's primary
+    /// mirror, or `None` if the provider has no notion of AWS regions.
+    pub async fn get_repository_region(
+        &self,
+        account_id: &String,
+        repository_id: &String,
+    ) -> Result<Option<String>, Box<dyn APIError>> {
+        let repository = self
+            .get_repository_record(account_id, repository_id)
+            .await?;
+
+        let Some(mirror) = repository
+            .data
+            .mirrors
+            .get(repository.data.primary_mirror.as_str())
+        else {
+            return Ok(None);
+        };
+
+        let data_connection = self.get_data_connection(&mirror.data_connection_id).await?;
+
+        if data_connection.details.provider != "s3" {
+            return Ok(None);
         }
 
-        // If not in cache, fetch it
-        match self.fetch_repository(account_id, repository_id).await {
-            Ok(repository) => {
-                // Cache the successful result
-                self.repository_cache
-                    .insert(cache_key, repository.clone())
+        Ok(Some(
+            data_connection
+                .details
+                .region
+                .unwrap_or("us-east-1".to_string()),
+        ))
+    }
+
+    /// Returns the service limits configured for an account.
+    ///
+    /// Accounts without configured limits, or whose limits cannot be fetched,
+    /// get the default limits so that listings are never blocked on the Source API.
+    pub async fn get_service_limits(&self, account_id: &String) -> ServiceLimits {
+        if let Some(limits) = self.service_limits_cache.get(account_id).await {
+            return limits;
+        }
+
+        match self.fetch_account(account_id).await {
+            Ok(account) => {
+                self.service_limits_cache
+                    .insert(account_id.to_string(), account.service_limits.clone())
                     .await;
-                Ok(repository)
+                account.service_limits
             }
-            Err(e) => Err(e),
+            Err(error) => {
+                log::warn!(
+                    "Could not fetch service limits for {}: {}",
+                    account_id,
+                    error
+                );
+                ServiceLimits::default()
+            }
+        }
+    }
+
+    /// Returns the accounts which are members of an organization account.
+    pub async fn get_member_account_ids(
+        &self,
+        account_id: &String,
+    ) -> Result<Vec<String>, Box<dyn APIError>> {
+        let organization = self.fetch_account(account_id).await?;
+
+        Ok(organization
+            .memberships
+            .into_iter()
+            .filter(|membership| membership.state == "member")
+            .map(|membership| membership.account_id)
+            .collect())
+    }
+
+    async fn fetch_account(&self, account_id: &String) -> Result<SourceAccount, Box<dyn APIError>> {
+        let source_key = env::var("SOURCE_KEY").unwrap();
+        let client = self.http.clone();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            reqwest::header::HeaderValue::from_str(&source_key).unwrap(),
+        );
+        match cassette::send(
+            client
+                .get(format!("{}/api/v1/accounts/{}", self.endpoint, account_id))
+                .headers(headers),
+        )
+        .await
+        {
+            Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => {
+                Err(Box::new(AccountNotFoundError {
+                    account_id: account_id.to_string(),
+                }))
+            }
+            Ok(response) => match response.json::<SourceAccount>().await {
+                Ok(account) => Ok(account),
+                Err(_) => Err(Box::new(InternalServerError {
+                    message: "Internal Server Error".to_string(),
+                })),
+            },
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+
+    /// Returns the ids of the repositories of an account visible to
+    /// `user_identity`, as listed by the Source API on their behalf.
+    async fn list_visible_repositories(
+        &self,
+        account_id: &String,
+        user_identity: &UserIdentity,
+    ) -> Result<Vec<String>, ()> {
+        let principal = user_identity.clone().principal();
+        let client = self.http.clone();
+        // Create headers
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(api_key) = principal.api_key {
+            headers.insert(
+                reqwest::header::AUTHORIZATION,
+                reqwest::header::HeaderValue::from_str(
+                    format!("{} {}", api_key.access_key_id, api_key.secret_access_key).as_str(),
+                )
+                .unwrap(),
+            );
+        }
+
+        match cassette::send(
+            client
+                .get(format!(
+                    "{}/api/v1/repositories/{}",
+                    self.endpoint, account_id
+                ))
+                .headers(headers),
+        )
+        .await
+        {
+            Ok(response) => match response.json::<SourceRepositoryList>().await {
+                Ok(repository_list) => {
+                    let mut repositories = Vec::new();
+
+                    for repository in repository_list.repositories {
+                        if self.is_visible(&repository, user_identity.clone()).await {
+                            repositories.push(repository.repository_id);
+                        }
+                    }
+
+                    Ok(repositories)
+                }
+                Err(_) => Err(()),
+            },
+            Err(_) => Err(()),
+        }
+    }
+
+    /// Returns every repository of an account, including unlisted and
+    /// restricted ones, for background jobs acting on behalf of the proxy.
+    pub async fn list_repositories(
+        &self,
+        account_id: &String,
+    ) -> Result<Vec<SourceRepository>, Box<dyn APIError>> {
+        let source_key = env::var("SOURCE_KEY").unwrap();
+        let client = self.http.clone();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            reqwest::header::HeaderValue::from_str(&source_key).unwrap(),
+        );
+        match cassette::send(
+            client
+                .get(format!(
+                    "{}/api/v1/repositories/{}",
+                    self.endpoint, account_id
+                ))
+                .headers(headers),
+        )
+        .await
+        {
+            Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => {
+                Err(Box::new(AccountNotFoundError {
+                    account_id: account_id.to_string(),
+                }))
+            }
+            Ok(response) => match response.json::<SourceRepositoryList>().await {
+                Ok(repository_list) => Ok(repository_list.repositories),
+                Err(_) => Err(Box::new(InternalServerError {
+                    message: "Internal Server Error".to_string(),
+                })),
+            },
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+
+    /// Returns the accounts an API key has access to, for `ListBuckets`.
+    ///
+    /// Temporary credentials only have access to the account they're scoped to.
+    pub async fn list_accounts(
+        &self,
+        user_identity: &UserIdentity,
+    ) -> Result<Vec<SourceAccount>, Box<dyn APIError>> {
+        let Some(api_key) = user_identity.clone().principal().api_key else {
+            return Ok(Vec::new());
+        };
+
+        let client = self.http.clone();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            reqwest::header::HeaderValue::from_str(
+                format!("{} {}", api_key.access_key_id, api_key.secret_access_key).as_str(),
+            )
+            .unwrap(),
+        );
+
+        let mut accounts = match cassette::send(
+            client
+                .get(format!(
+                    "{}/api/v1/api-keys/{}/accounts",
+                    self.endpoint, api_key.access_key_id
+                ))
+                .headers(headers),
+        )
+        .await
+        {
+            Ok(response) => match response.json::<SourceAccountList>().await {
+                Ok(account_list) => account_list.accounts,
+                Err(_) => {
+                    return Err(Box::new(InternalServerError {
+                        message: "Internal Server Error".to_string(),
+                    }))
+                }
+            },
+            Err(_) => {
+                return Err(Box::new(InternalServerError {
+                    message: "Internal Server Error".to_string(),
+                }))
+            }
+        };
+
+        if let Some(session) = user_identity
+            .api_key
+            .as_ref()
+            .and_then(|api_key| api_key.session.as_ref())
+        {
+            accounts.retain(|account| account.account_id == session.account_id);
         }
+
+        Ok(accounts)
     }
 
     async fn fetch_data_connection(
@@ -376,20 +897,21 @@ impl SourceAPI {
         data_connection_id: &String,
     ) -> Result<DataConnection, Box<dyn APIError>> {
         let source_key = env::var("SOURCE_KEY").unwrap();
-        let client = reqwest::Client::new();
+        let client = self.http.clone();
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
             reqwest::header::AUTHORIZATION,
             reqwest::header::HeaderValue::from_str(&source_key).unwrap(),
         );
-        match client
-            .get(format!(
-                "{}/api/v1/data-connections/{}",
-                self.endpoint, data_connection_id
-            ))
-            .headers(headers)
-            .send()
-            .await
+        match cassette::send(
+            client
+                .get(format!(
+                    "{}/api/v1/data-connections/{}",
+                    self.endpoint, data_connection_id
+                ))
+                .headers(headers),
+        )
+        .await
         {
             Ok(response) => match response.json::<DataConnection>().await {
                 Ok(data_connection) => Ok(data_connection),
@@ -435,34 +957,112 @@ impl SourceAPI {
         }
     }
 
-    pub async fn get_api_key(&self, access_key_id: String) -> Result<APIKey, Box<dyn APIError>> {
-        // Try to get the cached value
-        let cache_key = format!("{}", access_key_id);
+    /// Returns the key of `access_key_id`. Temporary credentials issued by the
+    /// proxy are resolved from their `session_token`, and other keys from the
+    /// Source API.
+    pub async fn get_api_key(
+        &self,
+        access_key_id: String,
+        session_token: Option<&str>,
+    ) -> Result<APIKey, Box<dyn APIError>> {
+        let Some((claims, secret_access_key)) = session_token.and_then(|session_token| {
+            self.session_signer
+                .verify(&access_key_id, session_token, self.clock.now())
+        }) else {
+            return self.get_long_lived_api_key(access_key_id).await;
+        };
 
-        if let Some(cached_secret) = self.api_key_cache.get(&cache_key).await {
-            return Ok(cached_secret);
+        let parent = self
+            .get_long_lived_api_key(claims.parent_access_key_id)
+            .await?;
+        if parent.access_key_id.is_empty() {
+            // The parent key was revoked, and its credentials with it
+            return Ok(parent);
         }
 
-        // If not in cache, fetch it
-        match self.fetch_api_key(access_key_id).await {
-            Ok(secret) => {
-                // Cache the successful result
-                match secret {
-                    Some(secret) => {
-                        self.api_key_cache.insert(cache_key, secret.clone()).await;
-                        Ok(secret)
-                    }
-                    None => {
-                        let secret = APIKey {
-                            access_key_id: "".to_string(),
-                            secret_access_key: "".to_string(),
-                        };
-                        self.api_key_cache.insert(cache_key, secret.clone()).await;
-                        Ok(secret)
-                    }
-                }
-            }
-            Err(e) => Err(e),
+        Ok(APIKey {
+            access_key_id,
+            secret_access_key,
+            mfa_authenticated: parent.mfa_authenticated,
+            session_token: session_token.map(|session_token| session_token.to_string()),
+            session: Some(SessionScope {
+                parent: Box::new(parent),
+                account_id: claims.account_id,
+                repository_id: claims.repository_id,
+                expiration: claims.expiration,
+            }),
+        })
+    }
+
+    async fn get_long_lived_api_key(
+        &self,
+        access_key_id: String,
+    ) -> Result<APIKey, Box<dyn APIError>> {
+        let cache_key = access_key_id.clone();
+        let fetch = async {
+            // Unknown keys are cached too, as a key without a secret
+            Ok(self
+                .fetch_api_key(access_key_id)
+                .await?
+                .unwrap_or_else(|| APIKey {
+                    access_key_id: "".to_string(),
+                    secret_access_key: "".to_string(),
+                    mfa_authenticated: false,
+                    session_token: None,
+                    session: None,
+                }))
+        };
+
+        get_or_fetch(
+            &self.api_key_cache,
+            &self.cache_stats,
+            "api_key",
+            cache_key,
+            fetch,
+        )
+        .await
+    }
+
+    /// Issues temporary credentials for `parent`, scoped to a single repository.
+    ///
+    /// The credentials are never sent to the Source API or stored: their
+    /// session token carries their scope, signed so that any proxy instance
+    /// can verify it, see `SessionSigner`. Requests signed with them must carry
+    /// the session token in `x-amz-security-token`.
+    pub async fn issue_session_credentials(
+        &self,
+        parent: APIKey,
+        account_id: &String,
+        repository_id: &String,
+        duration: Duration,
+    ) -> APIKey {
+        let mut rng = rand::thread_rng();
+        let expiration = self.clock.now()
+            + chrono::Duration::from_std(duration.min(MAX_SESSION_DURATION)).unwrap_or_default();
+
+        let claims = SessionClaims {
+            access_key_id: format!(
+                "ASIA{}",
+                Alphanumeric.sample_string(&mut rng, 16).to_uppercase()
+            ),
+            parent_access_key_id: parent.access_key_id.clone(),
+            account_id: account_id.to_string(),
+            repository_id: repository_id.to_string(),
+            expiration,
+        };
+        let (session_token, secret_access_key) = self.session_signer.sign(&claims);
+
+        APIKey {
+            access_key_id: claims.access_key_id,
+            secret_access_key,
+            mfa_authenticated: parent.mfa_authenticated,
+            session_token: Some(session_token),
+            session: Some(SessionScope {
+                parent: Box::new(parent),
+                account_id: claims.account_id,
+                repository_id: claims.repository_id,
+                expiration,
+            }),
         }
     }
 
@@ -473,7 +1073,7 @@ impl SourceAPI {
         if access_key_id.is_empty() {
             return Ok(None);
         }
-        let client = reqwest::Client::new();
+        let client = self.http.clone();
         let source_key = env::var("SOURCE_KEY").unwrap();
         let source_api_url = env::var("SOURCE_API_URL").unwrap();
 
@@ -483,14 +1083,15 @@ impl SourceAPI {
             reqwest::header::AUTHORIZATION,
             reqwest::header::HeaderValue::from_str(&source_key).unwrap(),
         );
-        match client
-            .get(format!(
-                "{}/api/v1/api-keys/{}/auth",
-                source_api_url, access_key_id
-            ))
-            .headers(headers)
-            .send()
-            .await
+        match cassette::send(
+            client
+                .get(format!(
+                    "{}/api/v1/api-keys/{}/auth",
+                    source_api_url, access_key_id
+                ))
+                .headers(headers),
+        )
+        .await
         {
             Ok(response) => {
                 if response.status().is_success() {
@@ -498,10 +1099,17 @@ impl SourceAPI {
                         Ok(text) => {
                             let json: Value = serde_json::from_str(&text).unwrap();
                             let secret_access_key = json["secret_access_key"].as_str().unwrap();
+                            let mfa_authenticated =
+                                json["mfa_authenticated"].as_bool().unwrap_or(false);
+                            let session_token =
+                                json["session_token"].as_str().map(|s| s.to_string());
 
                             return Ok(Some(APIKey {
                                 access_key_id,
                                 secret_access_key: secret_access_key.to_string(),
+                                mfa_authenticated,
+                                session_token,
+                                session: None,
                             }));
                         }
                         Err(_) => Err(Box::new(InternalServerError {
@@ -528,31 +1136,114 @@ impl SourceAPI {
         account_id: &String,
         repository_id: &String,
     ) -> Result<SourceRepository, Box<dyn APIError>> {
-        match reqwest::get(format!(
-            "{}/api/v1/repositories/{}/{}",
-            self.endpoint, account_id, repository_id
-        ))
-        .await
-        {
-            Ok(response) => match response.json::<SourceRepository>().await {
-                Ok(repository) => Ok(repository),
-                Err(_) => Err(Box::new(InternalServerError {
-                    message: "Internal Server Error".to_string(),
-                })),
-            },
-            Err(error) => {
-                if error.status().is_some() && error.status().unwrap().as_u16() == 404 {
-                    return Err(Box::new(RepositoryNotFoundError {
+        let path = format!("/api/v1/repositories/{}/{}", account_id, repository_id);
+        let result: Result<_, Box<dyn APIError>> =
+            match cassette::send(self.http.get(format!("{}{}", self.endpoint, path))).await {
+                Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => {
+                    Err(Box::new(RepositoryNotFoundError {
                         account_id: account_id.to_string(),
                         repository_id: repository_id.to_string(),
-                    }));
+                    }))
                 }
-
-                Err(Box::new(InternalServerError {
+                Ok(response) => match response.json::<SourceRepository>().await {
+                    Ok(repository) => Ok(repository),
+                    Err(_) => Err(Box::new(InternalServerError {
+                        message: "Internal Server Error".to_string(),
+                    })),
+                },
+                Err(error) if error.status().is_some_and(|status| status.as_u16() == 404) => {
+                    Err(Box::new(RepositoryNotFoundError {
+                        account_id: account_id.to_string(),
+                        repository_id: repository_id.to_string(),
+                    }))
+                }
+                Err(_) => Err(Box::new(InternalServerError {
                     message: "Internal Server Error".to_string(),
+                })),
+            };
+
+        if let Some(mirror) = &self.mirror {
+            mirror.mirror("repository", path, &result);
+        }
+
+        result
+    }
+
+    /// Checks whether an account exists, as seen by the given user.
+    ///
+    /// Returns an `AccountNotFoundError` if the Source API does not know the account.
+    pub async fn check_account_exists(
+        &self,
+        account_id: &String,
+        user_identity: UserIdentity,
+    ) -> Result<(), Box<dyn APIError>> {
+        let user_identity = user_identity.principal();
+        let client = self.http.clone();
+
+        // Create headers
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(api_key) = user_identity.api_key {
+            headers.insert(
+                reqwest::header::AUTHORIZATION,
+                reqwest::header::HeaderValue::from_str(
+                    format!("{} {}", api_key.access_key_id, api_key.secret_access_key).as_str(),
+                )
+                .unwrap(),
+            );
+        }
+
+        match cassette::send(
+            client
+                .get(format!(
+                    "{}/api/v1/repositories/{}",
+                    self.endpoint, account_id
+                ))
+                .headers(headers),
+        )
+        .await
+        {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => {
+                Err(Box::new(AccountNotFoundError {
+                    account_id: account_id.to_string(),
                 }))
             }
+            _ => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+
+    /// Returns whether a repository is shown in the account listing to the
+    /// given user.
+    ///
+    /// Listed, open repositories are shown to everyone. Unlisted, restricted,
+    /// disabled and tombstoned repositories are only shown to users who may
+    /// read them, so they can't be discovered through bucket listings.
+    pub async fn is_visible(
+        &self,
+        repository: &SourceRepository,
+        user_identity: UserIdentity,
+    ) -> bool {
+        if !repository.disabled && repository.state == "listed" && repository.data_mode == "open" {
+            return true;
         }
+
+        self.is_authorized(
+            user_identity,
+            &repository.account_id,
+            &repository.repository_id,
+            RepositoryPermission::Read,
+        )
+        .await
+        .unwrap_or(false)
+    }
+
+    /// Returns whether a repository's data is open to everyone.
+    pub async fn is_public(&self, account_id: &String, repository_id: &String) -> bool {
+        self.get_repository_record(account_id, repository_id)
+            .await
+            .is_ok_and(|repository| !repository.disabled && repository.data_mode == "open")
     }
 
     pub async fn is_authorized(
@@ -562,6 +1253,18 @@ impl SourceAPI {
         repository_id: &String,
         permission: RepositoryPermission,
     ) -> Result<bool, Box<dyn APIError>> {
+        // Temporary credentials only grant their parent's permissions within their scope
+        if let Some(session) = user_identity
+            .api_key
+            .as_ref()
+            .and_then(|api_key| api_key.session.as_ref())
+        {
+            if &session.account_id != account_id || &session.repository_id != repository_id {
+                return Ok(false);
+            }
+        }
+        let user_identity = user_identity.principal();
+
         let anon: bool;
         if user_identity.api_key.is_none() {
             anon = true;
@@ -578,23 +1281,52 @@ impl SourceAPI {
             cache_key = format!("{}/{}/{}", account_id, repository_id, api_key.access_key_id);
         }
 
-        if let Some(cache_permissions) = self.permissions_cache.get(&cache_key).await {
-            return Ok(cache_permissions.contains(&permission));
+        let permissions = get_or_fetch(
+            &self.permissions_cache,
+            &self.cache_stats,
+            "permissions",
+            cache_key,
+            self.fetch_permission(user_identity.clone(), account_id, repository_id),
+        )
+        .await?;
+
+        Ok(permissions.contains(&permission))
+    }
+
+    /// Explains why `is_authorized` denied `permission` on a repository.
+    ///
+    /// Anonymous callers are only told that their role doesn't allow it, so
+    /// that the product's configuration isn't disclosed to them.
+    pub async fn denial_reason(
+        &self,
+        user_identity: &UserIdentity,
+        account_id: &String,
+        repository_id: &String,
+        permission: RepositoryPermission,
+    ) -> DenialReason {
+        if user_identity.api_key.is_none() {
+            return DenialReason::Role;
         }
 
-        // If not in cache, fetch it
-        match self
-            .fetch_permission(user_identity.clone(), &account_id, &repository_id)
-            .await
+        if user_identity
+            .api_key
+            .as_ref()
+            .and_then(|api_key| api_key.session.as_ref())
+            .is_some_and(|session| {
+                &session.account_id != account_id || &session.repository_id != repository_id
+            })
         {
-            Ok(permissions) => {
-                // Cache the successful result
-                self.permissions_cache
-                    .insert(cache_key, permissions.clone())
-                    .await;
-                return Ok(permissions.contains(&permission));
+            return DenialReason::SessionScope;
+        }
+
+        match self.get_repository_record(account_id, repository_id).await {
+            Ok(repository) if repository.disabled => DenialReason::ProductDisabled,
+            Ok(repository)
+                if repository.data_mode != "open" && permission == RepositoryPermission::Read =>
+            {
+                DenialReason::DataMode
             }
-            Err(e) => Err(e),
+            _ => DenialReason::Role,
         }
     }
 
@@ -604,7 +1336,7 @@ impl SourceAPI {
         account_id: &String,
         repository_id: &String,
     ) -> Result<Vec<RepositoryPermission>, Box<dyn APIError>> {
-        let client = reqwest::Client::new();
+        let client = self.http.clone();
         let source_api_url = env::var("SOURCE_API_URL").unwrap();
 
         // Create headers
@@ -620,14 +1352,16 @@ impl SourceAPI {
             );
         }
 
-        match client
-            .get(format!(
-                "{}/api/v1/repositories/{}/{}/permissions",
-                source_api_url, account_id, repository_id
-            ))
-            .headers(headers)
-            .send()
-            .await
+        let path = format!(
+            "/api/v1/repositories/{}/{}/permissions",
+            account_id, repository_id
+        );
+        let result: Result<_, Box<dyn APIError>> = match cassette::send(
+            client
+                .get(format!("{}{}", source_api_url, path))
+                .headers(headers.clone()),
+        )
+        .await
         {
             Ok(response) => match response.json::<Vec<RepositoryPermission>>().await {
                 Ok(permissions) => Ok(permissions),
@@ -638,6 +1372,301 @@ impl SourceAPI {
             Err(_) => Err(Box::new(InternalServerError {
                 message: "Internal Server Error".to_string(),
             })),
+        };
+
+        // Lookups made with a user's credentials aren't mirrored, so their
+        // secrets are never sent to the staging API
+        if let Some(mirror) = self.mirror.as_ref().filter(|_| headers.is_empty()) {
+            mirror.mirror("permissions", path, &result);
+        }
+
+        result
+    }
+
+    /// Checks whether the user has accepted the terms of a terms-gated repository.
+    ///
+    /// Repositories which are not terms-gated always pass. Acceptance is cached
+    /// using the same keys and TTL as the permissions cache.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the repository may be accessed, or a
+    /// `TermsNotAcceptedError` pointing to the acceptance URL otherwise.
+    pub async fn check_terms_accepted(
+        &self,
+        user_identity: UserIdentity,
+        account_id: &String,
+        repository_id: &String,
+    ) -> Result<(), Box<dyn APIError>> {
+        let user_identity = user_identity.principal();
+        let repository = self
+            .get_repository_record(account_id, repository_id)
+            .await?;
+
+        let terms = match repository.terms {
+            Some(terms) if terms.required => terms,
+            _ => return Ok(()),
+        };
+
+        let not_accepted = || -> Box<dyn APIError> {
+            Box::new(TermsNotAcceptedError {
+                account_id: account_id.to_string(),
+                repository_id: repository_id.to_string(),
+                acceptance_url: terms.acceptance_url.clone(),
+            })
+        };
+
+        // Anonymous users can never have accepted the terms
+        let Some(api_key) = user_identity.api_key.clone() else {
+            return Err(not_accepted());
+        };
+
+        let cache_key = format!("{}/{}/{}", account_id, repository_id, api_key.access_key_id);
+
+        let accepted = get_or_fetch(
+            &self.terms_acceptance_cache,
+            &self.cache_stats,
+            "terms_acceptance",
+            cache_key,
+            self.fetch_terms_acceptance(user_identity, account_id, repository_id),
+        )
+        .await?;
+
+        if accepted {
+            Ok(())
+        } else {
+            Err(not_accepted())
+        }
+    }
+
+    /// Checks that the data connection of a repository's primary mirror allows
+    /// an operation, regardless of the user's permissions.
+    ///
+    /// Returns an `AccessDeniedError` for operations the connection forbids.
+    pub async fn check_operation(
+        &self,
+        account_id: &String,
+        repository_id: &String,
+        operation: BackendOperation,
+    ) -> Result<(), Box<dyn APIError>> {
+        let repository = self
+            .get_repository_record(account_id, repository_id)
+            .await?;
+
+        let Some(mirror) = repository
+            .data
+            .mirrors
+            .get(repository.data.primary_mirror.as_str())
+        else {
+            return Ok(());
+        };
+
+        let data_connection = self.get_data_connection(&mirror.data_connection_id).await?;
+
+        if data_connection.allows(operation) {
+            Ok(())
+        } else {
+            Err(Box::new(AccessDeniedError {
+                key: repository_id.to_string(),
+                message: format!(
+                    "The data connection of this repository does not allow {} operations",
+                    operation.as_str()
+                ),
+                reason: DenialReason::DataConnection,
+            }))
+        }
+    }
+
+    /// Returns a `RepositoryTombstonedError` if the repository has been retired.
+    ///
+    /// Repositories which cannot be resolved pass, leaving the error to the
+    /// request's own handling.
+    pub async fn check_not_tombstoned(
+        &self,
+        account_id: &String,
+        repository_id: &String,
+    ) -> Result<(), Box<dyn APIError>> {
+        match self.get_repository_record(account_id, repository_id).await {
+            Ok(repository) if repository.state == "tombstoned" => {
+                Err(Box::new(RepositoryTombstonedError {
+                    account_id: account_id.to_string(),
+                    repository_id: repository_id.to_string(),
+                    successor: repository.successor,
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates the repository's access policy for a request on `key`.
+    ///
+    /// This is checked in addition to `is_authorized`, which only considers the
+    /// caller's role. Returns an `AccessDeniedError` if the policy rejects the
+    /// request.
+    pub async fn check_policy(
+        &self,
+        user_identity: &UserIdentity,
+        account_id: &String,
+        repository_id: &String,
+        key: &str,
+        permission: RepositoryPermission,
+    ) -> Result<(), Box<dyn APIError>> {
+        let repository = self
+            .get_repository_record(account_id, repository_id)
+            .await?;
+
+        if evaluate_policy(&repository.policies, user_identity, key, &permission) {
+            Ok(())
+        } else {
+            Err(Box::new(AccessDeniedError {
+                key: format!("{}/{}", repository_id, key),
+                message: "Access Denied".to_string(),
+                reason: DenialReason::Policy,
+            }))
+        }
+    }
+
+    async fn fetch_terms_acceptance(
+        &self,
+        user_identity: UserIdentity,
+        account_id: &String,
+        repository_id: &String,
+    ) -> Result<bool, Box<dyn APIError>> {
+        let client = self.http.clone();
+
+        // Create headers
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(api_key) = user_identity.api_key {
+            headers.insert(
+                reqwest::header::AUTHORIZATION,
+                reqwest::header::HeaderValue::from_str(
+                    format!("{} {}", api_key.access_key_id, api_key.secret_access_key).as_str(),
+                )
+                .unwrap(),
+            );
+        }
+
+        match cassette::send(
+            client
+                .get(format!(
+                    "{}/api/v1/repositories/{}/{}/terms",
+                    self.endpoint, account_id, repository_id
+                ))
+                .headers(headers),
+        )
+        .await
+        {
+            // No acceptance is recorded for users who never accepted the terms
+            Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => Ok(false),
+            Ok(response) if !response.status().is_success() => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+            Ok(response) => match response.json::<TermsAcceptance>().await {
+                Ok(acceptance) => Ok(acceptance.accepted),
+                Err(_) => Err(Box::new(InternalServerError {
+                    message: "Internal Server Error".to_string(),
+                })),
+            },
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::FixedClock;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-14T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn api_key(session_expiration: Option<DateTime<Utc>>) -> APIKey {
+        let parent = APIKey {
+            access_key_id: "SCKEXAMPLE".to_string(),
+            secret_access_key: "secret".to_string(),
+            mfa_authenticated: false,
+            session_token: None,
+            session: None,
+        };
+
+        match session_expiration {
+            Some(expiration) => APIKey {
+                access_key_id: "ASIAEXAMPLE".to_string(),
+                session_token: Some("token".to_string()),
+                session: Some(SessionScope {
+                    parent: Box::new(parent.clone()),
+                    account_id: "example".to_string(),
+                    repository_id: "weather".to_string(),
+                    expiration,
+                }),
+                ..parent
+            },
+            None => parent,
+        }
+    }
+
+    fn expire_after(api_key: &APIKey) -> Option<Duration> {
+        APIKeyExpiry {
+            clock: Arc::new(FixedClock(now())),
         }
+        .expire_after_create(&api_key.access_key_id, api_key, Instant::now())
+    }
+
+    #[test]
+    fn session_credentials_expire_with_their_session() {
+        let api_key = api_key(Some(now() + chrono::Duration::minutes(15)));
+        assert_eq!(expire_after(&api_key), Some(Duration::from_secs(15 * 60)));
+    }
+
+    #[test]
+    fn expired_session_credentials_expire_immediately() {
+        let api_key = api_key(Some(now() - chrono::Duration::seconds(1)));
+        assert_eq!(expire_after(&api_key), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn long_lived_keys_expire_after_a_minute() {
+        assert_eq!(expire_after(&api_key(None)), Some(Duration::from_secs(60)));
+    }
+
+    #[actix_web::test]
+    async fn session_credentials_end_after_their_duration() {
+        let source_api = SourceAPI::new(
+            "https://api.source.coop".to_string(),
+            Arc::new(FixedClock(now())),
+        );
+        let (account_id, repository_id) = ("example".to_string(), "weather".to_string());
+
+        let issued = source_api
+            .issue_session_credentials(
+                api_key(None),
+                &account_id,
+                &repository_id,
+                Duration::from_secs(60 * 60),
+            )
+            .await;
+        assert_eq!(
+            issued.session.unwrap().expiration,
+            now() + chrono::Duration::hours(1)
+        );
+
+        // Longer sessions are capped
+        let issued = source_api
+            .issue_session_credentials(
+                api_key(None),
+                &account_id,
+                &repository_id,
+                Duration::from_secs(48 * 60 * 60),
+            )
+            .await;
+        assert_eq!(
+            issued.session.unwrap().expiration,
+            now() + chrono::Duration::hours(12)
+        );
     }
 }
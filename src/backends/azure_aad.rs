@@ -0,0 +1,181 @@
+use async_trait::async_trait;
+use azure_core::auth::{AccessToken, TokenCredential};
+use azure_core::error::{Error, ErrorKind};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use time::{Duration, OffsetDateTime};
+
+/// The authority tokens are requested from, unless `AZURE_AUTHORITY_HOST`
+/// names another cloud.
+const DEFAULT_AUTHORITY: &str = "https://login.microsoftonline.com";
+
+/// Tokens are refreshed this long before they expire, so requests in flight
+/// never carry an expired token.
+const REFRESH_MARGIN: Duration = Duration::minutes(5);
+
+/// How long a token request may take, so that requests waiting for a token
+/// don't hang while Entra ID does.
+const TOKEN_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How a service principal proves its identity to Microsoft Entra ID.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AadAssertion {
+    ClientSecret(String),
+    /// A federated token, e.g. the Kubernetes service account token of
+    /// workload identity, read again at every refresh since it is rotated.
+    FederatedTokenFile(PathBuf),
+}
+
+/// The service principal a data connection authenticates as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AadConfig {
+    pub tenant_id: String,
+    pub client_id: String,
+    pub assertion: AadAssertion,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// Requests tokens for a service principal with the client credentials flow,
+/// caching them by scope until shortly before they expire.
+#[derive(Debug)]
+pub struct AadCredential {
+    config: AadConfig,
+    authority: String,
+    http: reqwest::Client,
+    /// Held while a token is refreshed, so concurrent requests wait for the
+    /// refresh instead of each requesting a token.
+    tokens: tokio::sync::Mutex<HashMap<String, AccessToken>>,
+}
+
+impl AadCredential {
+    fn new(config: AadConfig) -> Self {
+        let authority = std::env::var("AZURE_AUTHORITY_HOST")
+            .ok()
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| DEFAULT_AUTHORITY.to_string());
+
+        AadCredential {
+            config,
+            authority: authority.trim_end_matches('/').to_string(),
+            http: reqwest::Client::builder()
+                .timeout(TOKEN_REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            tokens: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn request_token(&self, scope: &str) -> azure_core::Result<AccessToken> {
+        let mut form = vec![
+            ("grant_type", "client_credentials".to_string()),
+            ("client_id", self.config.client_id.clone()),
+            ("scope", scope.to_string()),
+        ];
+        match &self.config.assertion {
+            AadAssertion::ClientSecret(secret) => form.push(("client_secret", secret.clone())),
+            AadAssertion::FederatedTokenFile(path) => {
+                let assertion = tokio::fs::read_to_string(path).await.map_err(|error| {
+                    Error::full(
+                        ErrorKind::Credential,
+                        error,
+                        format!("failed to read federated token {}", path.display()),
+                    )
+                })?;
+                form.push((
+                    "client_assertion_type",
+                    "urn:ietf:params:oauth:client-assertion-type:jwt-bearer".to_string(),
+                ));
+                form.push(("client_assertion", assertion.trim().to_string()));
+            }
+        }
+
+        let response = self
+            .http
+            .post(format!(
+                "{}/{}/oauth2/v2.0/token",
+                self.authority, self.config.tenant_id
+            ))
+            .form(&form)
+            .send()
+            .await
+            .map_err(|error| Error::new(ErrorKind::Credential, error))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(Error::with_message(ErrorKind::Credential, || {
+                format!(
+                    "token request for client {} failed with {}: {}",
+                    self.config.client_id, status, body
+                )
+            }));
+        }
+
+        let token = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(|error| Error::new(ErrorKind::Credential, error))?;
+        Ok(AccessToken::new(
+            token.access_token,
+            OffsetDateTime::now_utc() + Duration::seconds(token.expires_in),
+        ))
+    }
+}
+
+#[async_trait]
+impl TokenCredential for AadCredential {
+    async fn get_token(&self, scopes: &[&str]) -> azure_core::Result<AccessToken> {
+        let scope = scopes.join(" ");
+        let mut tokens = self.tokens.lock().await;
+        if let Some(token) = tokens.get(&scope) {
+            if token.expires_on - REFRESH_MARGIN > OffsetDateTime::now_utc() {
+                return Ok(token.clone());
+            }
+        }
+
+        match self.request_token(&scope).await {
+            Ok(token) => {
+                tokens.insert(scope, token.clone());
+                Ok(token)
+            }
+            Err(error) => {
+                // Released before returning, so waiting requests try again
+                drop(tokens);
+                Err(error)
+            }
+        }
+    }
+
+    async fn clear_cache(&self) -> azure_core::Result<()> {
+        self.tokens.lock().await.clear();
+        Ok(())
+    }
+}
+
+/// The credentials of the service principals of Azure data connections,
+/// shared by the backend clients created for each request so their tokens
+/// are cached between requests.
+#[derive(Clone, Default)]
+pub struct AadCredentials(Arc<Mutex<HashMap<String, Arc<AadCredential>>>>);
+
+impl AadCredentials {
+    /// Returns the credential of a data connection, replacing it if the
+    /// connection's service principal changed.
+    pub fn get(&self, data_connection_id: &str, config: AadConfig) -> Arc<AadCredential> {
+        let mut credentials = self.0.lock().unwrap();
+        match credentials.get(data_connection_id) {
+            Some(credential) if credential.config == config => credential.clone(),
+            _ => {
+                let credential = Arc::new(AadCredential::new(config));
+                credentials.insert(data_connection_id.to_string(), credential.clone());
+                credential
+            }
+        }
+    }
+}
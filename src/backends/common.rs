@@ -1,28 +1,387 @@
-use crate::utils::errors::APIError;
+use crate::utils::checksum::Checksum;
+use crate::utils::errors::{APIError, MalformedXMLError};
 use async_trait::async_trait;
 use bytes::Bytes;
 use core::num::NonZeroU32;
 use futures_core::Stream;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::pin::Pin;
 
 use reqwest::Error as ReqwestError;
-type BoxedReqwestStream = Pin<Box<dyn Stream<Item = Result<Bytes, ReqwestError>> + Send>>;
+pub type BoxedReqwestStream = Pin<Box<dyn Stream<Item = Result<Bytes, ReqwestError>> + Send>>;
+
+/// What an origin GET is expected to return, as reported by a preceding HEAD.
+pub struct ExpectedObject<'a> {
+    pub etag: &'a str,
+    pub content_length: u64,
+    pub content_type: &'a str,
+}
+
+/// Checks that an origin response to a GET is the object that was asked for,
+/// so that error pages of intermediate proxies aren't streamed to clients as
+/// object bytes. Returns the reason a response was rejected.
+pub fn check_origin_response(
+    response: &reqwest::Response,
+    expected: &ExpectedObject,
+    ranged: bool,
+) -> Result<(), String> {
+    let expected_status = if ranged {
+        reqwest::StatusCode::PARTIAL_CONTENT
+    } else {
+        reqwest::StatusCode::OK
+    };
+    if response.status() != expected_status {
+        return Err(format!("unexpected status {}", response.status()));
+    }
+
+    let header = |name: &str| response.headers().get(name).and_then(|h| h.to_str().ok());
+
+    if let Some(content_type) = header("content-type") {
+        if content_type.starts_with("text/html") && !expected.content_type.starts_with("text/html")
+        {
+            return Err(format!("unexpected content type {}", content_type));
+        }
+    }
+
+    if let Some(etag) = header("etag") {
+        if etag.trim_matches('"') != expected.etag.trim_matches('"') {
+            return Err(format!("ETag {} does not match {}", etag, expected.etag));
+        }
+    }
+
+    if ranged {
+        // The complete length of the object follows the `/` of `Content-Range`
+        let total = header("content-range")
+            .and_then(|content_range| content_range.rsplit_once('/'))
+            .map(|(_, total)| total.trim());
+        match total {
+            Some("*") => {}
+            Some(total) if total.parse::<u64>().ok() == Some(expected.content_length) => {}
+            _ => return Err("missing or mismatched Content-Range".to_string()),
+        }
+    } else if response.content_length() != Some(expected.content_length) {
+        return Err(format!(
+            "Content-Length {:?} does not match {}",
+            response.content_length(),
+            expected.content_length
+        ));
+    }
+
+    Ok(())
+}
 
 pub struct GetObjectResponse {
     pub content_length: u64,
     pub content_type: String,
     pub last_modified: String,
     pub etag: String,
+    /// The backend's `Content-Range` header, present if a range was served.
+    pub content_range: Option<String>,
+    /// The checksum of the whole object, if one was stored when it was written.
+    pub checksum: Option<Checksum>,
+    /// The version served, on a versioned backend.
+    pub version_id: Option<String>,
+    /// User metadata, keyed by name without the `x-amz-meta-` prefix.
+    pub metadata: HashMap<String, String>,
+    pub object_lock: ObjectLock,
+    pub server_side_encryption: ServerSideEncryption,
+    /// The number of parts of a multipart object, sent when a part was read.
+    pub parts_count: Option<i64>,
     pub body: BoxedReqwestStream,
 }
 
+/// The headers of a read which describe the object served, kept with cached
+/// ranges so that they are served with the same headers as uncached reads.
+#[derive(Debug, Clone)]
+pub struct ObjectHeaders {
+    pub content_type: String,
+    pub last_modified: String,
+    pub etag: String,
+    pub content_range: Option<String>,
+    pub version_id: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub object_lock: ObjectLock,
+    pub server_side_encryption: ServerSideEncryption,
+    pub parts_count: Option<i64>,
+}
+
+impl GetObjectResponse {
+    pub fn headers(&self) -> ObjectHeaders {
+        ObjectHeaders {
+            content_type: self.content_type.clone(),
+            last_modified: self.last_modified.clone(),
+            etag: self.etag.clone(),
+            content_range: self.content_range.clone(),
+            version_id: self.version_id.clone(),
+            metadata: self.metadata.clone(),
+            object_lock: self.object_lock.clone(),
+            server_side_encryption: self.server_side_encryption.clone(),
+            parts_count: self.parts_count,
+        }
+    }
+}
+
 pub struct HeadObjectResponse {
     pub content_length: u64,
     pub content_type: String,
     pub last_modified: String,
     pub etag: String,
+    pub object_lock: ObjectLock,
+    pub server_side_encryption: ServerSideEncryption,
+    pub checksum: Option<Checksum>,
+    pub version_id: Option<String>,
+    /// User metadata, keyed by name without the `x-amz-meta-` prefix.
+    pub metadata: HashMap<String, String>,
+    /// The S3 storage class of the object, unset for `STANDARD`.
+    pub storage_class: Option<String>,
+    /// The status of a restore of an archived object, as the `x-amz-restore`
+    /// header, e.g. `ongoing-request="false", expiry-date="..."`.
+    pub restore: Option<String>,
+    /// The number of parts of a multipart object, sent when a part was read.
+    pub parts_count: Option<i64>,
+}
+
+/// Returns whether an object must be restored before it can be read: it is
+/// in an archive storage class and no restored copy is available.
+pub fn is_archived(storage_class: Option<&str>, restore: Option<&str>) -> bool {
+    matches!(storage_class, Some("GLACIER" | "DEEP_ARCHIVE"))
+        && !restore.is_some_and(|restore| restore.contains("ongoing-request=\"false\""))
+}
+
+/// The body of a `RestoreObject` request. Both elements are optional; the
+/// backend's defaults apply to those left out.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename = "RestoreRequest")]
+pub struct RestoreRequest {
+    /// How long the restored copy is kept.
+    #[serde(rename = "Days")]
+    pub days: Option<i64>,
+    #[serde(rename = "GlacierJobParameters")]
+    pub glacier_job_parameters: Option<GlacierJobParameters>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GlacierJobParameters {
+    /// `Standard`, `Bulk` or `Expedited`.
+    #[serde(rename = "Tier")]
+    pub tier: String,
+}
+
+/// S3 Object Lock settings of an object, passed through as the
+/// `x-amz-object-lock-*` headers.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ObjectLock {
+    /// `GOVERNANCE` or `COMPLIANCE`.
+    pub mode: Option<String>,
+    /// An ISO 8601 timestamp.
+    pub retain_until_date: Option<String>,
+    /// `ON` or `OFF`.
+    pub legal_hold_status: Option<String>,
+}
+
+/// Server-side encryption settings of an object, passed through as the
+/// `x-amz-server-side-encryption*` headers.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ServerSideEncryption {
+    /// `AES256` or `aws:kms`.
+    pub algorithm: Option<String>,
+    /// The KMS key, for `aws:kms`.
+    pub kms_key_id: Option<String>,
+}
+
+impl ServerSideEncryption {
+    pub fn is_set(&self) -> bool {
+        self.algorithm.is_some() || self.kms_key_id.is_some()
+    }
+}
+
+/// Request headers that affect how an object is written.
+#[derive(Debug, Default, Clone)]
+pub struct PutObjectOptions {
+    pub content_type: Option<String>,
+    /// Fail with `412 Precondition Failed` if the key already exists (`If-None-Match: *`).
+    /// Azure enforces this atomically; on S3 it is a best-effort check made
+    /// before the write, which concurrent writers can both pass.
+    pub if_none_match: bool,
+    pub object_lock: ObjectLock,
+    /// The verified checksum of the body, stored with the object.
+    pub checksum: Option<Checksum>,
+    /// User metadata (`x-amz-meta-*`), keyed by lowercase name without the prefix.
+    pub metadata: HashMap<String, String>,
+    /// Tags as the URL-encoded query string of `x-amz-tagging`, e.g. `a=1&b=2`.
+    pub tagging: Option<String>,
+    pub server_side_encryption: ServerSideEncryption,
+}
+
+/// The content headers and user metadata an object is rewritten with by
+/// `replace_metadata`. Headers left out are cleared, like with the `REPLACE`
+/// metadata directive of S3, except for the content type and user metadata,
+/// which are kept when unset.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct MetadataReplacement {
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    /// User metadata, keyed by name without the `x-amz-meta-` prefix.
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// The outcome of a write on a versioned backend. Unversioned backends leave
+/// the version unset.
+pub struct PutObjectResponse {
+    pub etag: String,
+    pub version_id: Option<String>,
+}
+
+/// The outcome of a delete. On a versioned backend deletes create a delete
+/// marker, whose version is reported in `version_id`.
+pub struct DeleteObjectResponse {
+    pub delete_marker: bool,
+    pub version_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CopyObjectResponse {
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    #[serde(rename = "LastModified")]
+    pub last_modified: String,
+    /// The size of the written object, which isn't part of the response.
+    #[serde(skip)]
+    pub size: u64,
+}
+
+/// The body of a `GetBucketLocation` response. Buckets in `us-east-1` have an
+/// empty location constraint.
+#[derive(Debug, Serialize)]
+pub struct LocationConstraint {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: String,
+    #[serde(rename = "$text")]
+    pub location: String,
+}
+
+/// The body of a `GetBucketVersioning` response. `Status` is omitted for
+/// buckets on which versioning was never enabled.
+#[derive(Debug, Serialize)]
+pub struct VersioningConfiguration {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: String,
+    #[serde(rename = "Status", skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
+
+/// The body of a `GetBucketAcl` or `GetObjectAcl` response. Products have no
+/// ACLs, so a canned one is reported: the owning account has full control,
+/// and everyone can read public products.
+#[derive(Debug, Serialize)]
+pub struct AccessControlPolicy {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: String,
+    #[serde(rename = "Owner")]
+    pub owner: Owner,
+    #[serde(rename = "AccessControlList")]
+    pub access_control_list: AccessControlList,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessControlList {
+    #[serde(rename = "Grant")]
+    pub grants: Vec<Grant>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Grant {
+    #[serde(rename = "Grantee")]
+    pub grantee: Grantee,
+    #[serde(rename = "Permission")]
+    pub permission: String,
+}
+
+/// A grantee, either an account (`CanonicalUser`) or a group (`Group`).
+#[derive(Debug, Serialize)]
+pub struct Grantee {
+    #[serde(rename = "@xmlns:xsi")]
+    pub xmlns_xsi: String,
+    #[serde(rename = "@xsi:type")]
+    pub grantee_type: String,
+    #[serde(rename = "ID", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "DisplayName", skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(rename = "URI", skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+impl AccessControlPolicy {
+    /// The canned ACL of an account's data, `public-read` if `public`.
+    pub fn canned(account_id: &str, public: bool) -> Self {
+        let owner = Owner {
+            id: account_id.to_string(),
+            display_name: account_id.to_string(),
+        };
+        let grantee = |grantee_type: &str| Grantee {
+            xmlns_xsi: "http://www.w3.org/2001/XMLSchema-instance".to_string(),
+            grantee_type: grantee_type.to_string(),
+            id: None,
+            display_name: None,
+            uri: None,
+        };
+
+        let mut grants = vec![Grant {
+            grantee: Grantee {
+                id: Some(owner.id.clone()),
+                display_name: Some(owner.display_name.clone()),
+                ..grantee("CanonicalUser")
+            },
+            permission: "FULL_CONTROL".to_string(),
+        }];
+        if public {
+            grants.push(Grant {
+                grantee: Grantee {
+                    uri: Some("http://acs.amazonaws.com/groups/global/AllUsers".to_string()),
+                    ..grantee("Group")
+                },
+                permission: "READ".to_string(),
+            });
+        }
+
+        AccessControlPolicy {
+            xmlns: "http://s3.amazonaws.com/doc/2006-03-01/".to_string(),
+            owner,
+            access_control_list: AccessControlList { grants },
+        }
+    }
+}
+
+/// The body of a `ListBuckets` response, whose buckets are the accounts the
+/// caller has access to.
+#[derive(Debug, Serialize)]
+pub struct ListAllMyBucketsResult {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: String,
+    #[serde(rename = "Owner")]
+    pub owner: Owner,
+    #[serde(rename = "Buckets")]
+    pub buckets: Buckets,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Buckets {
+    #[serde(rename = "Bucket")]
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "CreationDate")]
+    pub creation_date: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -37,13 +396,78 @@ pub struct CompleteMultipartUploadResponse {
     pub etag: String,
 }
 
+/// Operations which not every backend supports. Reads, listings and plain
+/// writes are supported by all of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    Delete,
+    MultipartUpload,
+    Copy,
+    Tagging,
+    Restore,
+}
+
+impl Capability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Delete => "DeleteObject",
+            Capability::MultipartUpload => "Multipart uploads",
+            Capability::Copy => "CopyObject",
+            Capability::Tagging => "Object tagging",
+            Capability::Restore => "RestoreObject",
+        }
+    }
+}
+
+/// The optional operations a backend supports, so handlers can reject
+/// requests for the others before doing any of their work.
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub delete: bool,
+    pub multipart_upload: bool,
+    pub copy: bool,
+    pub tagging: bool,
+    pub restore: bool,
+}
+
+impl Capabilities {
+    pub const ALL: Capabilities = Capabilities {
+        delete: true,
+        multipart_upload: true,
+        copy: true,
+        tagging: true,
+        restore: true,
+    };
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Delete => self.delete,
+            Capability::MultipartUpload => self.multipart_upload,
+            Capability::Copy => self.copy,
+            Capability::Tagging => self.tagging,
+            Capability::Restore => self.restore,
+        }
+    }
+}
+
 #[async_trait]
 pub trait Repository {
-    async fn delete_object(&self, key: String) -> Result<(), Box<dyn APIError>>;
+    /// The optional operations this backend supports.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::ALL
+    }
+    async fn delete_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> Result<DeleteObjectResponse, Box<dyn APIError>>;
     async fn create_multipart_upload(
         &self,
         key: String,
         content_type: Option<String>,
+        object_lock: ObjectLock,
+        server_side_encryption: ServerSideEncryption,
+        tagging: Option<String>,
     ) -> Result<CreateMultipartUploadResponse, Box<dyn APIError>>;
     async fn abort_multipart_upload(
         &self,
@@ -60,36 +484,86 @@ pub trait Repository {
         &self,
         key: String,
         upload_id: String,
-        part_number: String,
+        part_number: i64,
         bytes: Bytes,
     ) -> Result<UploadPartResponse, Box<dyn APIError>>;
     async fn put_object(
         &self,
         key: String,
         bytes: Bytes,
-        content_type: Option<String>,
-    ) -> Result<(), Box<dyn APIError>>;
+        options: PutObjectOptions,
+    ) -> Result<PutObjectResponse, Box<dyn APIError>>;
+    async fn copy_object(
+        &self,
+        source_key: String,
+        key: String,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>>;
+    /// Rewrites the content headers and user metadata of an object by copying
+    /// it onto itself. Checksums and server-side encryption are kept.
+    async fn replace_metadata(
+        &self,
+        key: String,
+        replacement: MetadataReplacement,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>>;
+    /// Reads an object, or the part `part_number` of a multipart object.
     async fn get_object(
         &self,
         key: String,
         range: Option<String>,
+        version_id: Option<String>,
+        part_number: Option<i64>,
     ) -> Result<GetObjectResponse, Box<dyn APIError>>;
-    async fn head_object(&self, key: String) -> Result<HeadObjectResponse, Box<dyn APIError>>;
+    async fn list_parts(
+        &self,
+        key: String,
+        upload_id: String,
+        max_parts: NonZeroU32,
+        part_number_marker: Option<i64>,
+    ) -> Result<ListPartsResult, Box<dyn APIError>>;
+    async fn get_object_tagging(&self, key: String) -> Result<Tagging, Box<dyn APIError>>;
+    async fn put_object_tagging(
+        &self,
+        key: String,
+        tagging: Tagging,
+    ) -> Result<(), Box<dyn APIError>>;
+    async fn delete_object_tagging(&self, key: String) -> Result<(), Box<dyn APIError>>;
+    /// Starts restoring an archived object, keeping the restored copy for
+    /// `days` days.
+    async fn restore_object(
+        &self,
+        key: String,
+        days: Option<i64>,
+        tier: Option<String>,
+    ) -> Result<(), Box<dyn APIError>>;
+    async fn head_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+        part_number: Option<i64>,
+    ) -> Result<HeadObjectResponse, Box<dyn APIError>>;
     async fn list_objects_v2(
         &self,
         prefix: String,
         continuation_token: Option<String>,
+        start_after: Option<String>,
         delimiter: Option<String>,
         max_keys: NonZeroU32,
     ) -> Result<ListBucketResult, Box<dyn APIError>>;
+    /// Returns the versioning status of the backend bucket, `Enabled` or
+    /// `Suspended`, or `None` if versioning was never enabled.
+    async fn get_bucket_versioning(&self) -> Result<Option<String>, Box<dyn APIError>>;
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ListBucketResult {
     #[serde(rename = "Name")]
     pub name: String,
     #[serde(rename = "Prefix")]
     pub prefix: String,
+    #[serde(rename = "Delimiter", skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    #[serde(rename = "StartAfter", skip_serializing_if = "Option::is_none")]
+    pub start_after: Option<String>,
     #[serde(rename = "KeyCount")]
     pub key_count: i64,
     #[serde(rename = "MaxKeys")]
@@ -100,11 +574,56 @@ pub struct ListBucketResult {
     pub contents: Vec<Content>,
     #[serde(rename = "CommonPrefixes")]
     pub common_prefixes: Vec<CommonPrefix>,
+    /// The token the page was requested with, echoed back.
+    #[serde(rename = "ContinuationToken", skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
     #[serde(rename = "NextContinuationToken")]
     pub next_continuation_token: Option<String>,
 }
 
+/// The body of a ListObjects (V1) response.
+///
+/// `NextMarker` carries the same opaque token as `NextContinuationToken` does
+/// for V2 listings; markers which are plain keys are also accepted.
 #[derive(Debug, Serialize)]
+pub struct ListBucketResultV1 {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Prefix")]
+    pub prefix: String,
+    #[serde(rename = "Delimiter", skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    #[serde(rename = "Marker")]
+    pub marker: String,
+    #[serde(rename = "NextMarker")]
+    pub next_marker: Option<String>,
+    #[serde(rename = "MaxKeys")]
+    pub max_keys: i64,
+    #[serde(rename = "IsTruncated")]
+    pub is_truncated: bool,
+    #[serde(rename = "Contents")]
+    pub contents: Vec<Content>,
+    #[serde(rename = "CommonPrefixes")]
+    pub common_prefixes: Vec<CommonPrefix>,
+}
+
+impl ListBucketResult {
+    pub fn into_v1(self, marker: String) -> ListBucketResultV1 {
+        ListBucketResultV1 {
+            name: self.name,
+            prefix: self.prefix,
+            delimiter: self.delimiter,
+            marker,
+            next_marker: self.next_continuation_token,
+            max_keys: self.max_keys,
+            is_truncated: self.is_truncated,
+            contents: self.contents,
+            common_prefixes: self.common_prefixes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Content {
     #[serde(rename = "Key")]
     pub key: String,
@@ -116,9 +635,21 @@ pub struct Content {
     pub size: i64,
     #[serde(rename = "StorageClass")]
     pub storage_class: String,
+    /// Sent in V1 listings, and in V2 listings requested with `fetch-owner`.
+    #[serde(rename = "Owner", skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
 }
 
-#[derive(Debug, Serialize)]
+/// The owner of an object, which is always the account owning its repository.
+#[derive(Debug, Clone, Serialize)]
+pub struct Owner {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "DisplayName")]
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct CommonPrefix {
     #[serde(rename = "Prefix")]
     pub prefix: String,
@@ -134,6 +665,38 @@ pub struct CreateMultipartUploadResponse {
     pub upload_id: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct Part {
+    #[serde(rename = "PartNumber")]
+    pub part_number: i64,
+    #[serde(rename = "LastModified")]
+    pub last_modified: String,
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    #[serde(rename = "Size")]
+    pub size: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListPartsResult {
+    #[serde(rename = "Bucket")]
+    pub bucket: String,
+    #[serde(rename = "Key")]
+    pub key: String,
+    #[serde(rename = "UploadId")]
+    pub upload_id: String,
+    #[serde(rename = "PartNumberMarker")]
+    pub part_number_marker: i64,
+    #[serde(rename = "NextPartNumberMarker")]
+    pub next_part_number_marker: i64,
+    #[serde(rename = "MaxParts")]
+    pub max_parts: i64,
+    #[serde(rename = "IsTruncated")]
+    pub is_truncated: bool,
+    #[serde(rename = "Part")]
+    pub parts: Vec<Part>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UploadPartResponse {
     #[serde(rename = "ETag")]
@@ -162,3 +725,437 @@ pub struct CompleteMultipartUpload {
     #[serde(rename = "Part")]
     pub parts: Vec<MultipartPart>,
 }
+
+/// A decoded proxy continuation token.
+///
+/// Backends paginate differently (S3 counts keys and prefixes together, Azure
+/// may hand back more entries than requested), so the proxy issues its own
+/// tokens that wrap the backend token together with the number of entries of
+/// that backend page which have already been returned to the client.
+#[derive(Debug, Default, PartialEq)]
+pub struct ListContinuation {
+    pub backend_token: Option<String>,
+    pub skip: usize,
+}
+
+impl ListContinuation {
+    /// Decodes a continuation token previously issued by `encode`.
+    ///
+    /// Tokens which cannot be decoded are passed through to the backend as-is.
+    pub fn decode(token: Option<String>) -> ListContinuation {
+        let Some(token) = token else {
+            return ListContinuation::default();
+        };
+
+        ListContinuation::decode_issued(&token).unwrap_or(ListContinuation {
+            backend_token: Some(token),
+            skip: 0,
+        })
+    }
+
+    /// Decodes a token issued by `encode`, returning `None` for any other string.
+    pub fn decode_issued(token: &str) -> Option<ListContinuation> {
+        let decoded = String::from_utf8(hex::decode(token).ok()?).ok()?;
+        let (skip, backend_token) = decoded.split_once('\n')?;
+
+        Some(ListContinuation {
+            backend_token: (!backend_token.is_empty()).then(|| backend_token.to_string()),
+            skip: skip.parse::<usize>().ok()?,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        hex::encode(format!(
+            "{}\n{}",
+            self.skip,
+            self.backend_token.as_deref().unwrap_or_default()
+        ))
+    }
+}
+
+/// The position of a listing which spans the repositories of an account: the
+/// repository the next page starts in, and the `ListContinuation` token to
+/// resume from within it, if any.
+#[derive(Debug, Clone, Default)]
+pub struct AccountListContinuation {
+    pub repository_id: String,
+    pub token: Option<String>,
+}
+
+impl AccountListContinuation {
+    /// Decodes a token issued by `encode`, returning `None` for any other string.
+    pub fn decode_issued(token: &str) -> Option<AccountListContinuation> {
+        let decoded = String::from_utf8(hex::decode(token).ok()?).ok()?;
+        let (repository_id, token) = decoded.strip_prefix("account\n")?.split_once('\n')?;
+
+        Some(AccountListContinuation {
+            repository_id: repository_id.to_string(),
+            token: (!token.is_empty()).then(|| token.to_string()),
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        hex::encode(format!(
+            "account\n{}\n{}",
+            self.repository_id,
+            self.token.as_deref().unwrap_or_default()
+        ))
+    }
+}
+
+enum ListEntry {
+    Object(Content),
+    Prefix(CommonPrefix),
+}
+
+impl ListEntry {
+    fn name(&self) -> &str {
+        match self {
+            ListEntry::Object(content) => &content.key,
+            ListEntry::Prefix(common_prefix) => &common_prefix.prefix,
+        }
+    }
+}
+
+/// Normalizes a backend listing page to S3 truncation semantics.
+///
+/// Keys and common prefixes are merged in lexicographic order and counted
+/// together against `max_keys`. When the backend page holds more entries than
+/// fit, the page is chunked and the returned continuation token resumes from
+/// the same backend page, skipping the entries that were already returned.
+///
+/// # Arguments
+///
+/// * `result` - The page returned by the backend for `continuation`.
+/// * `continuation` - The decoded token the page was requested with.
+/// * `max_keys` - The maximum number of keys and prefixes to return.
+pub fn normalize_list_result(
+    mut result: ListBucketResult,
+    continuation: ListContinuation,
+    max_keys: NonZeroU32,
+) -> ListBucketResult {
+    let max_keys = max_keys.get() as usize;
+
+    let mut entries: Vec<ListEntry> = result
+        .contents
+        .drain(..)
+        .map(ListEntry::Object)
+        .chain(result.common_prefixes.drain(..).map(ListEntry::Prefix))
+        .collect();
+    entries.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let remaining = entries.len().saturating_sub(continuation.skip);
+    let returned = remaining.min(max_keys);
+
+    for entry in entries.into_iter().skip(continuation.skip).take(returned) {
+        match entry {
+            ListEntry::Object(content) => result.contents.push(content),
+            ListEntry::Prefix(common_prefix) => result.common_prefixes.push(common_prefix),
+        }
+    }
+
+    if remaining > returned {
+        result.is_truncated = true;
+        result.next_continuation_token = Some(
+            ListContinuation {
+                backend_token: continuation.backend_token,
+                skip: continuation.skip + returned,
+            }
+            .encode(),
+        );
+    } else {
+        result.is_truncated = result.next_continuation_token.is_some();
+        result.next_continuation_token = result.next_continuation_token.map(|token| {
+            ListContinuation {
+                backend_token: Some(token),
+                skip: 0,
+            }
+            .encode()
+        });
+    }
+
+    result.key_count = returned as i64;
+    result.max_keys = max_keys as i64;
+
+    result
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ObjectIdentifier {
+    #[serde(rename = "Key")]
+    pub key: String,
+    #[serde(rename = "VersionId")]
+    pub version_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "Delete")]
+pub struct DeleteObjectsRequest {
+    #[serde(rename = "Object", default)]
+    pub objects: Vec<ObjectIdentifier>,
+    #[serde(rename = "Quiet", default)]
+    pub quiet: bool,
+}
+
+/// The most keys a `DeleteObjects` request may name.
+pub const MAX_DELETE_OBJECTS: usize = 1000;
+
+impl DeleteObjectsRequest {
+    /// Parses the body of a `DeleteObjects` request, which S3 rejects as
+    /// malformed when it names more than 1000 keys.
+    pub fn parse(body: &[u8]) -> Result<Self, Box<dyn APIError>> {
+        match std::str::from_utf8(body).map(serde_xml_rs::from_str::<DeleteObjectsRequest>) {
+            Ok(Ok(request)) if request.objects.len() <= MAX_DELETE_OBJECTS => Ok(request),
+            _ => Err(Box::new(MalformedXMLError {})),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedObject {
+    #[serde(rename = "Key")]
+    pub key: String,
+    /// The version that was deleted, if the request named one.
+    #[serde(rename = "VersionId", skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+    #[serde(rename = "DeleteMarker", skip_serializing_if = "Option::is_none")]
+    pub delete_marker: Option<bool>,
+    #[serde(
+        rename = "DeleteMarkerVersionId",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub delete_marker_version_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteError {
+    #[serde(rename = "Key")]
+    pub key: String,
+    #[serde(rename = "Code")]
+    pub code: String,
+    #[serde(rename = "Message")]
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct DeleteObjectsResult {
+    #[serde(rename = "Deleted")]
+    pub deleted: Vec<DeletedObject>,
+    #[serde(rename = "Error")]
+    pub errors: Vec<DeleteError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tag {
+    #[serde(rename = "Key")]
+    pub key: String,
+    #[serde(rename = "Value")]
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct TagSet {
+    #[serde(rename = "Tag", default)]
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename = "Tagging")]
+pub struct Tagging {
+    #[serde(rename = "TagSet", default)]
+    pub tag_set: TagSet,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backend page of `photos/`, listed with the `/` delimiter.
+    fn page(keys: &[&str], prefixes: &[&str], backend_token: Option<&str>) -> ListBucketResult {
+        ListBucketResult {
+            name: "example".to_string(),
+            prefix: "photos/".to_string(),
+            delimiter: Some("/".to_string()),
+            start_after: None,
+            key_count: (keys.len() + prefixes.len()) as i64,
+            max_keys: 1000,
+            is_truncated: backend_token.is_some(),
+            contents: keys
+                .iter()
+                .map(|key| Content {
+                    key: key.to_string(),
+                    last_modified: "2024-05-14T09:30:00.000Z".to_string(),
+                    etag: "\"3f1e5d0c55c7c5f4b7a0e3a9c2b1d4e6\"".to_string(),
+                    size: 34,
+                    storage_class: "STANDARD".to_string(),
+                    owner: None,
+                })
+                .collect(),
+            common_prefixes: prefixes
+                .iter()
+                .map(|prefix| CommonPrefix {
+                    prefix: prefix.to_string(),
+                })
+                .collect(),
+            continuation_token: None,
+            next_continuation_token: backend_token.map(str::to_string),
+        }
+    }
+
+    fn names(result: &ListBucketResult) -> (Vec<&str>, Vec<&str>) {
+        (
+            result.contents.iter().map(|c| c.key.as_str()).collect(),
+            result
+                .common_prefixes
+                .iter()
+                .map(|p| p.prefix.as_str())
+                .collect(),
+        )
+    }
+
+    fn max_keys(max_keys: u32) -> NonZeroU32 {
+        NonZeroU32::new(max_keys).unwrap()
+    }
+
+    #[test]
+    fn keys_and_prefixes_count_together_against_max_keys() {
+        // S3 returns the first two entries in order, whichever kind they are
+        let result = normalize_list_result(
+            page(
+                &["photos/index.html", "photos/readme.md"],
+                &["photos/2023/", "photos/2024/"],
+                None,
+            ),
+            ListContinuation::default(),
+            max_keys(3),
+        );
+
+        assert_eq!(
+            names(&result),
+            (
+                vec!["photos/index.html"],
+                vec!["photos/2023/", "photos/2024/"]
+            )
+        );
+        assert_eq!(result.key_count, 3);
+        assert_eq!(result.max_keys, 3);
+        assert!(result.is_truncated);
+        assert_eq!(result.prefix, "photos/");
+        assert_eq!(result.delimiter.as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn prefixes_beyond_max_keys_are_chunked() {
+        let prefixes = [
+            "photos/2021/",
+            "photos/2022/",
+            "photos/2023/",
+            "photos/2024/",
+        ];
+        let first = normalize_list_result(
+            page(&[], &prefixes, None),
+            ListContinuation::default(),
+            max_keys(3),
+        );
+        assert_eq!(
+            names(&first).1,
+            vec!["photos/2021/", "photos/2022/", "photos/2023/"]
+        );
+        assert!(first.is_truncated);
+
+        // The token resumes from the same backend page
+        let continuation = ListContinuation::decode(first.next_continuation_token);
+        assert_eq!(
+            continuation,
+            ListContinuation {
+                backend_token: None,
+                skip: 3,
+            }
+        );
+
+        let second = normalize_list_result(page(&[], &prefixes, None), continuation, max_keys(3));
+        assert_eq!(names(&second).1, vec!["photos/2024/"]);
+        assert_eq!(second.key_count, 1);
+        assert!(!second.is_truncated);
+        assert_eq!(second.next_continuation_token, None);
+    }
+
+    #[test]
+    fn backend_token_is_wrapped_once_the_page_is_returned() {
+        let result = normalize_list_result(
+            page(
+                &["photos/readme.md"],
+                &["photos/2024/"],
+                Some("2!72!MDAwMDE0IXBob3Rv"),
+            ),
+            ListContinuation::default(),
+            max_keys(1000),
+        );
+
+        assert_eq!(result.key_count, 2);
+        assert!(result.is_truncated);
+        assert_eq!(
+            ListContinuation::decode(result.next_continuation_token),
+            ListContinuation {
+                backend_token: Some("2!72!MDAwMDE0IXBob3Rv".to_string()),
+                skip: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn chunked_token_keeps_the_backend_token() {
+        let continuation = ListContinuation {
+            backend_token: Some("1Xk3Zp9Q".to_string()),
+            skip: 1,
+        };
+        let result = normalize_list_result(
+            page(
+                &["photos/a.jpg", "photos/b.jpg", "photos/c.jpg"],
+                &[],
+                Some("7Ym2Wq4R"),
+            ),
+            continuation,
+            max_keys(1),
+        );
+
+        assert_eq!(names(&result).0, vec!["photos/b.jpg"]);
+        assert_eq!(
+            ListContinuation::decode(result.next_continuation_token),
+            ListContinuation {
+                backend_token: Some("1Xk3Zp9Q".to_string()),
+                skip: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn foreign_tokens_are_passed_to_the_backend() {
+        assert_eq!(
+            ListContinuation::decode(Some("1Xk3Zp9Q".to_string())),
+            ListContinuation {
+                backend_token: Some("1Xk3Zp9Q".to_string()),
+                skip: 0,
+            }
+        );
+        assert_eq!(ListContinuation::decode(None), ListContinuation::default());
+    }
+
+    fn delete_request(keys: usize) -> String {
+        let objects: String = (0..keys)
+            .map(|i| format!("<Object><Key>weather/{}.csv</Key></Object>", i))
+            .collect();
+        format!("<Delete><Quiet>true</Quiet>{}</Delete>", objects)
+    }
+
+    #[test]
+    fn delete_requests_name_at_most_1000_keys() {
+        let request = DeleteObjectsRequest::parse(delete_request(1000).as_bytes()).unwrap();
+        assert_eq!(request.objects.len(), 1000);
+        assert!(request.quiet);
+
+        let error = DeleteObjectsRequest::parse(delete_request(1001).as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "Malformed XML");
+        assert_eq!(error.to_response().status(), 400);
+    }
+}
@@ -0,0 +1,319 @@
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::num::NonZeroU32;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::backends::common::{
+    Capabilities, CompleteMultipartUploadResponse, CopyObjectResponse,
+    CreateMultipartUploadResponse, DeleteObjectResponse, GetObjectResponse, HeadObjectResponse,
+    ListBucketResult, ListPartsResult, MetadataReplacement, MultipartPart, ObjectLock,
+    PutObjectOptions, PutObjectResponse, Repository, ServerSideEncryption, Tagging,
+    UploadPartResponse,
+};
+use crate::utils::errors::APIError;
+
+/// Continuation tokens for pages listed from the fallback mirror carry this prefix.
+const FALLBACK_TOKEN_PREFIX: &str = "fallback:";
+
+/// The number of reads served from a fallback mirror, by `{account_id}/{repository_id}`.
+pub type FallbackHits = Arc<Mutex<HashMap<String, u64>>>;
+
+/// Serves a repository which is being migrated to a new bucket or prefix.
+///
+/// Writes go to the primary mirror only. Reads which miss the primary mirror
+/// are retried against the fallback mirror and counted in `fallback_hits`, so
+/// a migration is complete once a repository stops recording hits. Listings
+/// only fall back when the primary mirror has nothing under their prefix; see
+/// `list_objects_v2`.
+pub struct DualReadRepository {
+    pub repository: String,
+    pub primary: Box<dyn Repository + Send + Sync>,
+    pub fallback: Box<dyn Repository + Send + Sync>,
+    pub fallback_hits: FallbackHits,
+}
+
+fn is_not_found(error: &dyn APIError) -> bool {
+    error.to_response().status() == StatusCode::NOT_FOUND
+}
+
+impl DualReadRepository {
+    fn record_fallback_hit(&self, key: &str) {
+        log::info!(
+            "Served {}/{} from the fallback mirror",
+            self.repository,
+            key
+        );
+
+        *self
+            .fallback_hits
+            .lock()
+            .unwrap()
+            .entry(self.repository.clone())
+            .or_default() += 1;
+    }
+}
+
+#[async_trait]
+impl Repository for DualReadRepository {
+    // Writes only go to the primary mirror
+    fn capabilities(&self) -> Capabilities {
+        self.primary.capabilities()
+    }
+
+    async fn delete_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> Result<DeleteObjectResponse, Box<dyn APIError>> {
+        let res = self
+            .primary
+            .delete_object(key.clone(), version_id.clone())
+            .await?;
+
+        // Otherwise the object would still be readable through the fallback
+        if let Err(error) = self.fallback.delete_object(key.clone(), version_id).await {
+            log::warn!(
+                "Could not delete {}/{} from the fallback mirror: {}",
+                self.repository,
+                key,
+                error
+            );
+        }
+
+        Ok(res)
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        key: String,
+        content_type: Option<String>,
+        object_lock: ObjectLock,
+        server_side_encryption: ServerSideEncryption,
+        tagging: Option<String>,
+    ) -> Result<CreateMultipartUploadResponse, Box<dyn APIError>> {
+        self.primary
+            .create_multipart_upload(
+                key,
+                content_type,
+                object_lock,
+                server_side_encryption,
+                tagging,
+            )
+            .await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        key: String,
+        upload_id: String,
+    ) -> Result<(), Box<dyn APIError>> {
+        self.primary.abort_multipart_upload(key, upload_id).await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: String,
+        upload_id: String,
+        parts: Vec<MultipartPart>,
+    ) -> Result<CompleteMultipartUploadResponse, Box<dyn APIError>> {
+        self.primary
+            .complete_multipart_upload(key, upload_id, parts)
+            .await
+    }
+
+    async fn upload_multipart_part(
+        &self,
+        key: String,
+        upload_id: String,
+        part_number: i64,
+        bytes: Bytes,
+    ) -> Result<UploadPartResponse, Box<dyn APIError>> {
+        self.primary
+            .upload_multipart_part(key, upload_id, part_number, bytes)
+            .await
+    }
+
+    async fn put_object(
+        &self,
+        key: String,
+        bytes: Bytes,
+        options: PutObjectOptions,
+    ) -> Result<PutObjectResponse, Box<dyn APIError>> {
+        self.primary.put_object(key, bytes, options).await
+    }
+
+    async fn copy_object(
+        &self,
+        source_key: String,
+        key: String,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>> {
+        self.primary.copy_object(source_key, key).await
+    }
+
+    async fn replace_metadata(
+        &self,
+        key: String,
+        replacement: MetadataReplacement,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>> {
+        self.primary.replace_metadata(key, replacement).await
+    }
+
+    async fn get_object(
+        &self,
+        key: String,
+        range: Option<String>,
+        version_id: Option<String>,
+        part_number: Option<i64>,
+    ) -> Result<GetObjectResponse, Box<dyn APIError>> {
+        match self
+            .primary
+            .get_object(key.clone(), range.clone(), version_id.clone(), part_number)
+            .await
+        {
+            Err(error) if is_not_found(error.as_ref()) => {
+                let res = self
+                    .fallback
+                    .get_object(key.clone(), range, version_id, part_number)
+                    .await?;
+                self.record_fallback_hit(&key);
+                Ok(res)
+            }
+            res => res,
+        }
+    }
+
+    async fn list_parts(
+        &self,
+        key: String,
+        upload_id: String,
+        max_parts: NonZeroU32,
+        part_number_marker: Option<i64>,
+    ) -> Result<ListPartsResult, Box<dyn APIError>> {
+        self.primary
+            .list_parts(key, upload_id, max_parts, part_number_marker)
+            .await
+    }
+
+    async fn get_object_tagging(&self, key: String) -> Result<Tagging, Box<dyn APIError>> {
+        match self.primary.get_object_tagging(key.clone()).await {
+            Err(error) if is_not_found(error.as_ref()) => {
+                let res = self.fallback.get_object_tagging(key.clone()).await?;
+                self.record_fallback_hit(&key);
+                Ok(res)
+            }
+            res => res,
+        }
+    }
+
+    async fn put_object_tagging(
+        &self,
+        key: String,
+        tagging: Tagging,
+    ) -> Result<(), Box<dyn APIError>> {
+        self.primary.put_object_tagging(key, tagging).await
+    }
+
+    async fn delete_object_tagging(&self, key: String) -> Result<(), Box<dyn APIError>> {
+        self.primary.delete_object_tagging(key).await
+    }
+
+    async fn restore_object(
+        &self,
+        key: String,
+        days: Option<i64>,
+        tier: Option<String>,
+    ) -> Result<(), Box<dyn APIError>> {
+        self.primary.restore_object(key, days, tier).await
+    }
+
+    async fn head_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+        part_number: Option<i64>,
+    ) -> Result<HeadObjectResponse, Box<dyn APIError>> {
+        match self
+            .primary
+            .head_object(key.clone(), version_id.clone(), part_number)
+            .await
+        {
+            Err(error) if is_not_found(error.as_ref()) => {
+                let res = self
+                    .fallback
+                    .head_object(key.clone(), version_id, part_number)
+                    .await?;
+                self.record_fallback_hit(&key);
+                Ok(res)
+            }
+            res => res,
+        }
+    }
+
+    /// Lists the primary mirror, falling back to the fallback mirror only when
+    /// the first page of a listing (one without a continuation token or
+    /// `start_after`) is empty on the primary mirror, as it is for a prefix
+    /// which hasn't been copied yet. Pages from the fallback mirror carry a
+    /// marked continuation token so later pages stay on it.
+    ///
+    /// The listings aren't merged: once the primary mirror has anything under
+    /// the prefix, keys which are only on the fallback mirror aren't listed,
+    /// and a listing never switches mirrors part way through.
+    async fn list_objects_v2(
+        &self,
+        prefix: String,
+        continuation_token: Option<String>,
+        start_after: Option<String>,
+        delimiter: Option<String>,
+        max_keys: NonZeroU32,
+    ) -> Result<ListBucketResult, Box<dyn APIError>> {
+        let fallback_token = continuation_token
+            .as_deref()
+            .and_then(|token| token.strip_prefix(FALLBACK_TOKEN_PREFIX))
+            .map(|token| token.to_string());
+
+        if fallback_token.is_none() {
+            let result = self
+                .primary
+                .list_objects_v2(
+                    prefix.clone(),
+                    continuation_token.clone(),
+                    start_after.clone(),
+                    delimiter.clone(),
+                    max_keys,
+                )
+                .await?;
+
+            let is_empty = result.contents.is_empty() && result.common_prefixes.is_empty();
+            if continuation_token.is_some() || start_after.is_some() || !is_empty {
+                return Ok(result);
+            }
+        }
+
+        let mut result = self
+            .fallback
+            .list_objects_v2(
+                prefix.clone(),
+                fallback_token,
+                start_after,
+                delimiter,
+                max_keys,
+            )
+            .await?;
+
+        if !result.contents.is_empty() || !result.common_prefixes.is_empty() {
+            self.record_fallback_hit(&prefix);
+        }
+
+        result.next_continuation_token = result
+            .next_continuation_token
+            .map(|token| format!("{}{}", FALLBACK_TOKEN_PREFIX, token));
+
+        Ok(result)
+    }
+
+    async fn get_bucket_versioning(&self) -> Result<Option<String>, Box<dyn APIError>> {
+        self.primary.get_bucket_versioning().await
+    }
+}
@@ -1,3 +1,6 @@
 pub mod azure;
+pub mod azure_aad;
 pub mod common;
+pub mod dual_read;
 pub mod s3;
+pub mod shadow;
@@ -1,26 +1,228 @@
+use crate::apis::source::{DataConnection, DataConnectionAuthentication, SourceRepositoryMirror};
 use crate::backends::common::{
-    CommonPrefix, CompleteMultipartUploadResponse, Content, CreateMultipartUploadResponse,
-    GetObjectResponse, HeadObjectResponse, ListBucketResult, Repository,
+    check_origin_response, is_archived, CommonPrefix, CompleteMultipartUploadResponse, Content,
+    CreateMultipartUploadResponse, ExpectedObject, GetObjectResponse, HeadObjectResponse,
+    ListBucketResult, Repository,
 };
+use crate::utils::cassette::{self, CassetteDispatcher};
+use crate::utils::checksum::Checksum;
 use crate::utils::core::replace_first;
-use crate::utils::errors::{APIError, InternalServerError, ObjectNotFoundError};
-use actix_web::http::header::RANGE;
+use crate::utils::errors::{
+    APIError, AccessDeniedError, BadGatewayError, ConfigurationError, DenialReason,
+    InternalServerError, InvalidArgumentError, InvalidObjectStateError, InvalidPartNumberError,
+    InvalidRangeError, NoSuchUploadError, NoSuchVersionError, ObjectNotFoundError,
+    PreconditionFailedError, RestoreAlreadyInProgressError,
+};
+use actix_web::http::header::{CONTENT_RANGE, RANGE};
 use async_trait::async_trait;
 use bytes::Bytes;
-use chrono::Utc;
+use chrono::{SecondsFormat, Utc};
 use core::num::NonZeroU32;
+use futures::{stream, StreamExt};
 use futures_core::Stream;
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use reqwest;
+use rusoto_core::param::{Params, ServiceParams};
+use rusoto_core::request::HttpClient;
+use rusoto_core::signature::SignedRequest;
 use rusoto_core::Region;
 use rusoto_core::RusotoError;
+use rusoto_credential::{
+    AutoRefreshingProvider, AwsCredentials, ChainProvider, ContainerProvider, CredentialsError,
+    ProvideAwsCredentials,
+};
 use rusoto_s3::{
     AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
-    CompletedPart, CreateMultipartUploadRequest, DeleteObjectRequest, HeadObjectRequest,
-    ListObjectsV2Request, PutObjectRequest, S3Client, UploadPartRequest, S3,
+    CompletedPart, CopyObjectRequest, CreateMultipartUploadRequest, DeleteObjectRequest,
+    DeleteObjectTaggingRequest, GetBucketVersioningRequest, GetObjectTaggingRequest,
+    GlacierJobParameters, HeadObjectRequest, ListObjectsV2Request, ListPartsRequest,
+    PutObjectRequest, PutObjectTaggingRequest, RestoreObjectError, RestoreObjectRequest,
+    RestoreRequest, S3Client, UploadPartCopyRequest, UploadPartRequest, S3,
 };
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::common::{
+    CopyObjectResponse, DeleteObjectResponse, ListPartsResult, MetadataReplacement, MultipartPart,
+    ObjectLock, Part, PutObjectOptions, PutObjectResponse, ServerSideEncryption, Tag, TagSet,
+    Tagging, UploadPartResponse,
+};
+
+/// Objects larger than this cannot be copied with a single CopyObject request.
+const MULTIPART_COPY_THRESHOLD: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The preferred part size for multipart copies.
+const MULTIPART_COPY_PART_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The maximum number of UploadPartCopy requests in flight for a single copy.
+const MULTIPART_COPY_CONCURRENCY: usize = 8;
+
+const MAX_MULTIPART_PARTS: u64 = 10000;
+
+/// How long presigned object URLs stay valid. They are used immediately.
+const PRESIGNED_GET_EXPIRY: Duration = Duration::from_secs(300);
+
+/// Characters which must be percent-encoded in the `x-amz-copy-source` header.
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// How long a credential provider which failed keeps answering with its
+/// failure before it is queried again.
+const CREDENTIALS_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Credentials resolved from the environment of the proxy, shared by the S3
+/// backends of every request so that the ECS metadata endpoint or the default
+/// chain is only queried when credentials expire.
+struct SharedCredentials<P: ProvideAwsCredentials + Clone + Send + Sync + 'static> {
+    new_provider: fn() -> P,
+    provider: Mutex<Option<(AutoRefreshingProvider<P>, Instant)>>,
+}
+
+impl<P: ProvideAwsCredentials + Clone + Send + Sync + 'static> SharedCredentials<P> {
+    const fn new(new_provider: fn() -> P) -> Self {
+        SharedCredentials {
+            new_provider,
+            provider: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl<P: ProvideAwsCredentials + Clone + Send + Sync + 'static> ProvideAwsCredentials
+    for &'static SharedCredentials<P>
+{
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        let (provider, created) = self
+            .provider
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| {
+                let provider = AutoRefreshingProvider::new((self.new_provider)())
+                    .expect("AutoRefreshingProvider::new is infallible");
+                (provider, Instant::now())
+            })
+            .clone();
+
+        // Failures are kept by the provider, so it is replaced after a while
+        let credentials = provider.credentials().await;
+        if credentials.is_err() && created.elapsed() > CREDENTIALS_RETRY_INTERVAL {
+            self.provider.lock().unwrap().take();
+        }
+        credentials
+    }
+}
+
+static ECS_TASK_ROLE_CREDENTIALS: SharedCredentials<ContainerProvider> =
+    SharedCredentials::new(ContainerProvider::new);
 
-use super::common::{MultipartPart, UploadPartResponse};
+static DEFAULT_CHAIN_CREDENTIALS: SharedCredentials<ChainProvider> =
+    SharedCredentials::new(ChainProvider::new);
+
+/// Maps the error of a request on a multipart upload. S3 answers with `404
+/// NoSuchUpload` once the upload has been completed or aborted.
+fn upload_error<E>(upload_id: String, error: RusotoError<E>) -> Box<dyn APIError> {
+    match error {
+        RusotoError::Service(_) => Box::new(NoSuchUploadError { upload_id }),
+        RusotoError::Unknown(response) if response.status == 404 => {
+            Box::new(NoSuchUploadError { upload_id })
+        }
+        _ => Box::new(InternalServerError {
+            message: "Internal Server Error".to_string(),
+        }),
+    }
+}
+
+/// Extracts the `<Message>` of an S3 XML error response body.
+fn error_message(body: &[u8]) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let start = body.find("<Message>")? + "<Message>".len();
+    let end = body[start..].find("</Message>")? + start;
+
+    Some(body[start..end].to_string())
+}
+
+/// Accepts any server certificate, for data connections which opt out of
+/// verification with `skip_tls_verification`.
+struct NoCertificateVerification;
+
+impl rustls::ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+/// The connector of clients which don't verify certificates. It is built on
+/// the same rustls stack as rusoto's default connector, which every other
+/// client uses.
+fn unverified_connector() -> HttpsConnector<HttpConnector> {
+    let mut config = rustls::ClientConfig::new();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(NoCertificateVerification));
+
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    HttpsConnector::from((http, config))
+}
+
+/// How an S3 backend authenticates, from the `type` of a data connection's
+/// authentication.
+pub enum S3Auth {
+    /// `s3_access_key`: a static access key.
+    AccessKey {
+        access_key_id: String,
+        secret_access_key: String,
+    },
+    /// `s3_ecs_task_role`: the ECS task role of the proxy.
+    EcsTaskRole,
+    /// `s3_local`: the default credential chain, for local S3-compatible stores.
+    Local,
+}
+
+impl S3Auth {
+    fn try_from_config(
+        authentication: Option<&DataConnectionAuthentication>,
+    ) -> Result<Self, String> {
+        let authentication = authentication.ok_or("authentication is missing")?;
+
+        match authentication.auth_type.as_str() {
+            "s3_access_key" => match (
+                authentication.access_key_id.clone(),
+                authentication.secret_access_key.clone(),
+            ) {
+                (Some(access_key_id), Some(secret_access_key))
+                    if !access_key_id.is_empty() && !secret_access_key.is_empty() =>
+                {
+                    Ok(S3Auth::AccessKey {
+                        access_key_id,
+                        secret_access_key,
+                    })
+                }
+                _ => Err(
+                    "s3_access_key authentication requires access_key_id and secret_access_key"
+                        .to_string(),
+                ),
+            },
+            "s3_ecs_task_role" => Ok(S3Auth::EcsTaskRole),
+            "s3_local" => Ok(S3Auth::Local),
+            auth_type => Err(format!("unsupported authentication type {}", auth_type)),
+        }
+    }
+}
 
 pub struct S3Repository {
     pub account_id: String,
@@ -28,9 +230,370 @@ pub struct S3Repository {
     pub region: Region,
     pub bucket: String,
     pub base_prefix: String,
-    pub auth_method: String,
-    pub access_key_id: Option<String>,
-    pub secret_access_key: Option<String>,
+    pub auth: S3Auth,
+    /// Whether requests acknowledge that the requester pays for them, as
+    /// required by requester-pays buckets.
+    pub requester_pays: bool,
+    pub skip_tls_verification: bool,
+}
+
+impl S3Repository {
+    /// Creates a backend for a repository mirror on an S3 data connection,
+    /// checking that the connection has the settings its authentication
+    /// method needs.
+    ///
+    /// S3-compatible stores such as MinIO and Ceph are reached at the endpoint
+    /// of the mirror or the data connection, which they require. Requests
+    /// always use path-style addressing, which these stores support without
+    /// wildcard DNS.
+    pub fn try_new(
+        account_id: &str,
+        repository_id: &str,
+        data_connection: &DataConnection,
+        mirror: &SourceRepositoryMirror,
+        requester_pays: bool,
+    ) -> Result<Self, ConfigurationError> {
+        let config_error = |message: String| ConfigurationError {
+            data_connection_id: data_connection.data_connection_id.clone(),
+            message,
+        };
+
+        let details = &data_connection.details;
+        let auth = S3Auth::try_from_config(data_connection.authentication.as_ref())
+            .map_err(config_error)?;
+
+        let bucket = match details.bucket.as_deref() {
+            Some(bucket) if !bucket.is_empty() => bucket.to_string(),
+            _ => return Err(config_error("bucket is missing".to_string())),
+        };
+
+        let endpoint = mirror.endpoint.clone().or(details.endpoint.clone());
+        if endpoint.is_none() && details.provider != "s3" {
+            return Err(config_error(format!(
+                "{} data connections require an endpoint",
+                details.provider
+            )));
+        }
+
+        // The signing region may differ from the region in the endpoint
+        // hostname, e.g. for interface VPC endpoints.
+        let region_name = details.region.clone().unwrap_or("us-east-1".to_string());
+        let region = match auth {
+            S3Auth::Local => Region::Custom {
+                name: details
+                    .signing_region
+                    .clone()
+                    .or(details.region.clone())
+                    .unwrap_or("us-west-2".to_string()),
+                endpoint: endpoint.unwrap_or("http://localhost:5050".to_string()),
+            },
+            _ => Region::Custom {
+                name: details
+                    .signing_region
+                    .clone()
+                    .unwrap_or(region_name.clone()),
+                endpoint: endpoint.unwrap_or(format!("https://s3.{}.amazonaws.com", region_name)),
+            },
+        };
+
+        let prefix = format!(
+            "{}{}",
+            details.base_prefix.as_deref().unwrap_or_default(),
+            mirror.prefix
+        );
+
+        Ok(S3Repository {
+            account_id: account_id.to_string(),
+            repository_id: repository_id.to_string(),
+            region,
+            bucket,
+            base_prefix: prefix.strip_suffix('/').unwrap_or(&prefix).to_string(),
+            auth,
+            requester_pays: requester_pays || details.requester_pays,
+            skip_tls_verification: details.skip_tls_verification,
+        })
+    }
+
+    /// Creates an S3 client using the credentials configured for the data connection.
+    fn client(&self) -> Result<S3Client, Box<dyn APIError>> {
+        let http_client = if self.skip_tls_verification {
+            Ok(HttpClient::from_connector(unverified_connector()))
+        } else {
+            HttpClient::new()
+        };
+        let http_client = http_client.map(CassetteDispatcher::new).map_err(|_| {
+            Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            }) as Box<dyn APIError>
+        })?;
+
+        Ok(match &self.auth {
+            S3Auth::AccessKey {
+                access_key_id,
+                secret_access_key,
+            } => S3Client::new_with(
+                http_client,
+                rusoto_credential::StaticProvider::new_minimal(
+                    access_key_id.clone(),
+                    secret_access_key.clone(),
+                ),
+                self.region.clone(),
+            ),
+            S3Auth::EcsTaskRole => {
+                S3Client::new_with(http_client, &ECS_TASK_ROLE_CREDENTIALS, self.region.clone())
+            }
+            S3Auth::Local => {
+                S3Client::new_with(http_client, &DEFAULT_CHAIN_CREDENTIALS, self.region.clone())
+            }
+        })
+    }
+
+    /// Returns the credentials configured for the data connection.
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        match &self.auth {
+            S3Auth::AccessKey {
+                access_key_id,
+                secret_access_key,
+            } => {
+                rusoto_credential::StaticProvider::new_minimal(
+                    access_key_id.clone(),
+                    secret_access_key.clone(),
+                )
+                .credentials()
+                .await
+            }
+            S3Auth::EcsTaskRole => (&ECS_TASK_ROLE_CREDENTIALS).credentials().await,
+            S3Auth::Local => (&DEFAULT_CHAIN_CREDENTIALS).credentials().await,
+        }
+    }
+
+    /// Returns the endpoint requests are sent to, without a trailing slash.
+    fn endpoint(&self) -> String {
+        match &self.region {
+            Region::Custom { endpoint, .. } => endpoint.trim_end_matches('/').to_string(),
+            region => format!("https://s3.{}.amazonaws.com", region.name()),
+        }
+    }
+
+    /// Returns the ETag S3 sends with every write. A response without one is
+    /// answered as a bad gateway, as clients can't use an empty ETag.
+    fn written_etag(&self, key: &str, etag: Option<String>) -> Result<String, Box<dyn APIError>> {
+        etag.ok_or_else(|| -> Box<dyn APIError> {
+            log::error!("No ETag in the response to a write of {}", key);
+            Box::new(BadGatewayError {
+                key: format!("{}/{}", self.repository_id, key),
+                message: "The storage backend returned no ETag".to_string(),
+            })
+        })
+    }
+
+    /// The `x-amz-request-payer` value sent with requests.
+    fn request_payer(&self) -> Option<String> {
+        self.requester_pays.then(|| "requester".to_string())
+    }
+
+    /// Returns the URL object GETs are sent to, presigned with the credentials
+    /// of the data connection so that private buckets can be read. The body
+    /// is streamed from the URL rather than read through the S3 client.
+    ///
+    /// Public buckets can be read without credentials, so when none resolve
+    /// for an ECS task role or the default chain the URL is left unsigned.
+    async fn object_url(
+        &self,
+        key: &str,
+        version_id: Option<&str>,
+        part_number: Option<i64>,
+    ) -> Result<String, Box<dyn APIError>> {
+        let credentials = match self.credentials().await {
+            Ok(credentials) => credentials,
+            Err(error) => {
+                log::debug!(
+                    "No credentials for {}/{}, reading {} unsigned: {}",
+                    self.account_id,
+                    self.repository_id,
+                    key,
+                    error
+                );
+                let mut url = format!(
+                    "{}/{}/{}/{}",
+                    self.endpoint(),
+                    self.bucket,
+                    self.base_prefix,
+                    key
+                );
+                let mut separator = '?';
+                if let Some(version_id) = version_id {
+                    url.push(separator);
+                    url.push_str("versionId=");
+                    url.extend(utf8_percent_encode(version_id, NON_ALPHANUMERIC));
+                    separator = '&';
+                }
+                if let Some(part_number) = part_number {
+                    url.push(separator);
+                    url.push_str(&format!("partNumber={}", part_number));
+                }
+                return Ok(url);
+            }
+        };
+
+        let mut request = SignedRequest::new(
+            "GET",
+            "s3",
+            &self.region,
+            &format!("/{}/{}/{}", self.bucket, self.base_prefix, key),
+        );
+        let mut params = Params::new();
+        if self.requester_pays {
+            params.put("x-amz-request-payer", "requester");
+        }
+        if let Some(version_id) = version_id {
+            params.put("versionId", version_id);
+        }
+        if let Some(part_number) = part_number {
+            params.put("partNumber", part_number);
+        }
+        request.set_params(params);
+
+        Ok(request.generate_presigned_url(&credentials, &PRESIGNED_GET_EXPIRY, false))
+    }
+
+    /// Copies a large object with UploadPartCopy requests.
+    ///
+    /// Parts are copied concurrently, at most `MULTIPART_COPY_CONCURRENCY` at a
+    /// time, and the upload is aborted if any part fails.
+    async fn multipart_copy_object(
+        &self,
+        client: &S3Client,
+        copy_source: String,
+        key: String,
+        size: u64,
+        content_type: Option<String>,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>> {
+        let internal_error = || -> Box<dyn APIError> {
+            Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })
+        };
+
+        let upload_id = match client
+            .create_multipart_upload(CreateMultipartUploadRequest {
+                bucket: self.bucket.clone(),
+                request_payer: self.request_payer(),
+                key: key.clone(),
+                content_type,
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(result) => result.upload_id.ok_or_else(internal_error)?,
+            Err(_) => return Err(internal_error()),
+        };
+
+        let part_size = MULTIPART_COPY_PART_SIZE.max(size.div_ceil(MAX_MULTIPART_PARTS));
+        let part_count = size.div_ceil(part_size);
+
+        let mut parts = Vec::new();
+        let mut failed = false;
+        {
+            let mut uploads = stream::iter(0..part_count)
+                .map(|index| {
+                    let first_byte = index * part_size;
+                    let last_byte = (first_byte + part_size).min(size) - 1;
+                    let request = UploadPartCopyRequest {
+                        bucket: self.bucket.clone(),
+                        request_payer: self.request_payer(),
+                        key: key.clone(),
+                        upload_id: upload_id.clone(),
+                        part_number: (index + 1) as i64,
+                        copy_source: copy_source.clone(),
+                        copy_source_range: Some(format!("bytes={}-{}", first_byte, last_byte)),
+                        ..Default::default()
+                    };
+                    async move { (request.part_number, client.upload_part_copy(request).await) }
+                })
+                .buffer_unordered(MULTIPART_COPY_CONCURRENCY);
+
+            while let Some((part_number, result)) = uploads.next().await {
+                match result
+                    .ok()
+                    .and_then(|output| output.copy_part_result?.e_tag)
+                {
+                    Some(e_tag) => {
+                        parts.push(CompletedPart {
+                            e_tag: Some(e_tag),
+                            part_number: Some(part_number),
+                        });
+                        log::info!(
+                            "Multipart copy of {} progress: {}/{} parts",
+                            key,
+                            parts.len(),
+                            part_count
+                        );
+                    }
+                    None => {
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if failed {
+            let _ = client
+                .abort_multipart_upload(AbortMultipartUploadRequest {
+                    bucket: self.bucket.clone(),
+                    request_payer: self.request_payer(),
+                    key,
+                    upload_id,
+                    ..Default::default()
+                })
+                .await;
+            return Err(internal_error());
+        }
+
+        parts.sort_by_key(|part| part.part_number);
+        let repository_key = key
+            .strip_prefix(&format!("{}/", self.base_prefix))
+            .unwrap_or(&key)
+            .to_string();
+
+        match client
+            .complete_multipart_upload(CompleteMultipartUploadRequest {
+                bucket: self.bucket.clone(),
+                request_payer: self.request_payer(),
+                key,
+                upload_id,
+                multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(result) => Ok(CopyObjectResponse {
+                etag: self.written_etag(&repository_key, result.e_tag)?,
+                last_modified: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                size,
+            }),
+            Err(_) => Err(internal_error()),
+        }
+    }
+
+    /// Checks whether an object exists, without treating a missing object as an error.
+    async fn object_exists(&self, client: &S3Client, key: &str) -> Result<bool, Box<dyn APIError>> {
+        let request = HeadObjectRequest {
+            bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
+            key: format!("{}/{}", self.base_prefix, key),
+            ..Default::default()
+        };
+
+        match client.head_object(request).await {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Unknown(response)) if response.status.eq(&404) => Ok(false),
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
 }
 
 #[async_trait]
@@ -39,39 +602,114 @@ impl Repository for S3Repository {
         &self,
         key: String,
         range: Option<String>,
+        version_id: Option<String>,
+        part_number: Option<i64>,
     ) -> Result<GetObjectResponse, Box<dyn APIError>> {
-        match self.head_object(key.clone()).await {
+        // The whole object is looked up, as responses for parts are checked
+        // against its size
+        match self
+            .head_object(key.clone(), version_id.clone(), None)
+            .await
+        {
             Ok(head_object_response) => {
-                let client = reqwest::Client::new();
-                let url: String;
-
-                if self.auth_method == "s3_local" {
-                    url = format!(
-                        "http://localhost:5050/{}/{}/{}",
-                        self.bucket, self.base_prefix, key
-                    )
-                } else {
-                    url = format!(
-                        "https://s3.{}.amazonaws.com/{}/{}/{}",
-                        self.region.name(),
-                        self.bucket,
-                        self.base_prefix,
-                        key
-                    );
+                if is_archived(
+                    head_object_response.storage_class.as_deref(),
+                    head_object_response.restore.as_deref(),
+                ) {
+                    return Err(Box::new(InvalidObjectStateError {
+                        key: format!("{}/{}", self.repository_id, key),
+                        message:
+                            "The object is archived and must be restored before it can be read"
+                                .to_string(),
+                    }));
                 }
+
+                let client = reqwest::Client::builder()
+                    .danger_accept_invalid_certs(self.skip_tls_verification)
+                    .build()
+                    .map_err(|_| {
+                        Box::new(InternalServerError {
+                            message: "Internal Server Error".to_string(),
+                        }) as Box<dyn APIError>
+                    })?;
+                let url = self
+                    .object_url(&key, version_id.as_deref(), part_number)
+                    .await?;
+
                 // Start building the request
                 let mut request = client.get(url);
 
                 // If a range is provided, add it to the headers
+                let mut ranged = range.is_some();
                 if let Some(range_value) = range {
                     request = request.header(RANGE, range_value);
                 }
 
                 // Send the request and await the response
-                match request.send().await {
+                match cassette::send(request).await {
                     Ok(response) => {
+                        // A part beyond the object's parts is answered with a
+                        // 416 as well, so parts are checked first
+                        if part_number.is_some()
+                            && (response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+                                || response.status() == reqwest::StatusCode::BAD_REQUEST)
+                        {
+                            return Err(Box::new(InvalidPartNumberError {
+                                key: format!("{}/{}", self.repository_id, key),
+                            }));
+                        }
+
+                        if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+                            return Err(Box::new(InvalidRangeError {
+                                key,
+                                content_length: head_object_response.content_length,
+                            }));
+                        }
+
+                        if response.status() == reqwest::StatusCode::NOT_FOUND {
+                            return Err(Box::new(ObjectNotFoundError {
+                                account_id: self.account_id.clone(),
+                                repository_id: self.repository_id.clone(),
+                                key,
+                            }));
+                        }
+
+                        if part_number.is_some() {
+                            // Parts of multipart objects are served as ranges
+                            ranged = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+                        }
+
+                        let expected = ExpectedObject {
+                            etag: &head_object_response.etag,
+                            content_length: head_object_response.content_length,
+                            content_type: &head_object_response.content_type,
+                        };
+                        if let Err(reason) = check_origin_response(&response, &expected, ranged) {
+                            log::warn!(
+                                "Rejected origin response for {}/{}/{}: {}",
+                                self.bucket,
+                                self.base_prefix,
+                                key,
+                                reason
+                            );
+                            return Err(Box::new(BadGatewayError {
+                                key: format!("{}/{}", self.repository_id, key),
+                                message: reason,
+                            }));
+                        }
+
                         // Get the byte stream from the response
                         let content_length = response.content_length();
+                        let content_range = response
+                            .headers()
+                            .get(CONTENT_RANGE)
+                            .and_then(|h| h.to_str().ok())
+                            .map(|s| s.to_string());
+                        let response_parts_count = response
+                            .headers()
+                            .get("x-amz-mp-parts-count")
+                            .and_then(|h| h.to_str().ok())
+                            .and_then(|s| s.parse().ok());
                         let stream = response.bytes_stream();
                         let boxed_stream: Pin<
                             Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>,
@@ -82,6 +720,13 @@ impl Repository for S3Repository {
                             content_type: head_object_response.content_type,
                             etag: head_object_response.etag,
                             last_modified: head_object_response.last_modified,
+                            content_range,
+                            checksum: head_object_response.checksum,
+                            version_id: head_object_response.version_id,
+                            metadata: head_object_response.metadata,
+                            object_lock: head_object_response.object_lock,
+                            server_side_encryption: head_object_response.server_side_encryption,
+                            parts_count: response_parts_count,
                             body: boxed_stream,
                         })
                     }
@@ -114,97 +759,193 @@ impl Repository for S3Repository {
         &self,
         key: String,
         bytes: Bytes,
-        content_type: Option<String>,
-    ) -> Result<(), Box<dyn APIError>> {
-        let client: S3Client;
-
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
+        options: PutObjectOptions,
+    ) -> Result<PutObjectResponse, Box<dyn APIError>> {
+        let client = self.client()?;
+
+        // The S3 API version used by rusoto predates conditional writes, so
+        // `If-None-Match: *` is emulated with a HEAD request. This is only
+        // best-effort: concurrent writers of a new key can both pass the
+        // check, and the last one to finish wins.
+        if options.if_none_match && self.object_exists(&client, &key).await? {
+            return Err(Box::new(PreconditionFailedError {
+                key: format!("{}/{}", self.repository_id, key),
             }));
         }
 
+        // rusoto predates additional checksums, so they are kept as user metadata
+        let mut metadata = options.metadata;
+        if let Some(checksum) = options.checksum {
+            metadata.insert(checksum.algorithm.metadata_key(), checksum.value);
+        }
+
         let request = PutObjectRequest {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             key: format!("{}/{}", self.base_prefix, key),
             body: Some(bytes.to_vec().into()),
-            content_type,
+            content_type: options.content_type,
+            object_lock_mode: options.object_lock.mode,
+            object_lock_retain_until_date: options.object_lock.retain_until_date,
+            object_lock_legal_hold_status: options.object_lock.legal_hold_status,
+            metadata: Some(metadata),
+            tagging: options.tagging,
+            server_side_encryption: options.server_side_encryption.algorithm,
+            ssekms_key_id: options.server_side_encryption.kms_key_id,
             ..Default::default()
         };
 
         match client.put_object(request).await {
-            Ok(_) => Ok(()),
+            Ok(output) => Ok(PutObjectResponse {
+                etag: self.written_etag(&key, output.e_tag)?,
+                version_id: output.version_id,
+            }),
             Err(e) => Err(Box::new(InternalServerError {
                 message: format!("Internal Server Error"),
             })),
         }
     }
 
+    async fn copy_object(
+        &self,
+        source_key: String,
+        key: String,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>> {
+        let source = self.head_object(source_key.clone(), None, None).await?;
+        let client = self.client()?;
+
+        let copy_source = utf8_percent_encode(
+            &format!("{}/{}/{}", self.bucket, self.base_prefix, source_key),
+            COPY_SOURCE_ENCODE_SET,
+        )
+        .to_string();
+        let repository_key = key;
+        let key = format!("{}/{}", self.base_prefix, repository_key);
+
+        // S3 rejects CopyObject requests for objects larger than 5 GB
+        if source.content_length > MULTIPART_COPY_THRESHOLD {
+            return self
+                .multipart_copy_object(
+                    &client,
+                    copy_source,
+                    key,
+                    source.content_length,
+                    Some(source.content_type),
+                )
+                .await;
+        }
+
+        let request = CopyObjectRequest {
+            bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
+            key,
+            copy_source,
+            ..Default::default()
+        };
+
+        match client.copy_object(request).await {
+            Ok(output) => {
+                let result = output.copy_object_result.unwrap_or_default();
+                Ok(CopyObjectResponse {
+                    etag: self.written_etag(&repository_key, result.e_tag)?,
+                    last_modified: result
+                        .last_modified
+                        .unwrap_or_else(|| Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+                    size: source.content_length,
+                })
+            }
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+
+    async fn replace_metadata(
+        &self,
+        key: String,
+        replacement: MetadataReplacement,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>> {
+        let source = self.head_object(key.clone(), None, None).await?;
+        let client = self.client()?;
+
+        // CopyObject is limited to 5 GB, and a multipart copy would change
+        // the object's ETag and drop its checksums
+        if source.content_length > MULTIPART_COPY_THRESHOLD {
+            return Err(Box::new(InvalidArgumentError {
+                key: format!("{}/{}", self.repository_id, key),
+                message: "Objects larger than 5 GB can't be updated in place".to_string(),
+            }));
+        }
+
+        let mut metadata = replacement.metadata.unwrap_or(source.metadata);
+        if let Some(checksum) = source.checksum {
+            metadata.insert(checksum.algorithm.metadata_key(), checksum.value);
+        }
+
+        let full_key = format!("{}/{}", self.base_prefix, key);
+        let request = CopyObjectRequest {
+            bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
+            copy_source: utf8_percent_encode(
+                &format!("{}/{}", self.bucket, full_key),
+                COPY_SOURCE_ENCODE_SET,
+            )
+            .to_string(),
+            key: full_key,
+            metadata_directive: Some("REPLACE".to_string()),
+            content_type: replacement.content_type.or(Some(source.content_type)),
+            cache_control: replacement.cache_control,
+            content_disposition: replacement.content_disposition,
+            content_encoding: replacement.content_encoding,
+            content_language: replacement.content_language,
+            metadata: Some(metadata),
+            server_side_encryption: source.server_side_encryption.algorithm,
+            ssekms_key_id: source.server_side_encryption.kms_key_id,
+            storage_class: source.storage_class,
+            ..Default::default()
+        };
+
+        match client.copy_object(request).await {
+            Ok(output) => {
+                let result = output.copy_object_result.unwrap_or_default();
+                Ok(CopyObjectResponse {
+                    etag: self.written_etag(&key, result.e_tag)?,
+                    last_modified: result
+                        .last_modified
+                        .unwrap_or_else(|| Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+                    size: source.content_length,
+                })
+            }
+            Err(error) => {
+                log::error!("Replacing the metadata of {} failed: {}", key, error);
+                Err(Box::new(InternalServerError {
+                    message: "Internal Server Error".to_string(),
+                }))
+            }
+        }
+    }
+
     async fn create_multipart_upload(
         &self,
         key: String,
         content_type: Option<String>,
+        object_lock: ObjectLock,
+        server_side_encryption: ServerSideEncryption,
+        tagging: Option<String>,
     ) -> Result<CreateMultipartUploadResponse, Box<dyn APIError>> {
-        let client: S3Client;
-
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            }));
-        }
+        let client = self.client()?;
 
         let request = CreateMultipartUploadRequest {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             key: format!("{}/{}", self.base_prefix, key),
             content_type,
+            object_lock_mode: object_lock.mode,
+            object_lock_retain_until_date: object_lock.retain_until_date,
+            object_lock_legal_hold_status: object_lock.legal_hold_status,
+            server_side_encryption: server_side_encryption.algorithm,
+            ssekms_key_id: server_side_encryption.kms_key_id,
+            tagging,
             ..Default::default()
         };
 
@@ -225,50 +966,19 @@ impl Repository for S3Repository {
         key: String,
         upload_id: String,
     ) -> Result<(), Box<dyn APIError>> {
-        let client: S3Client;
-
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            }));
-        }
+        let client = self.client()?;
 
         let request = AbortMultipartUploadRequest {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             key: format!("{}/{}", self.base_prefix, key),
-            upload_id,
+            upload_id: upload_id.clone(),
             ..Default::default()
         };
 
         match client.abort_multipart_upload(request).await {
             Ok(_) => Ok(()),
-            Err(_) => Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            })),
+            Err(error) => Err(upload_error(upload_id, error)),
         }
     }
 
@@ -278,42 +988,13 @@ impl Repository for S3Repository {
         upload_id: String,
         parts: Vec<MultipartPart>,
     ) -> Result<CompleteMultipartUploadResponse, Box<dyn APIError>> {
-        let client: S3Client;
-
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            }));
-        }
+        let client = self.client()?;
 
         let request = CompleteMultipartUploadRequest {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             key: format!("{}/{}", self.base_prefix, key),
-            upload_id,
+            upload_id: upload_id.clone(),
             multipart_upload: Some(CompletedMultipartUpload {
                 parts: Some(
                     parts
@@ -330,14 +1011,12 @@ impl Repository for S3Repository {
 
         match client.complete_multipart_upload(request).await {
             Ok(result) => Ok(CompleteMultipartUploadResponse {
-                location: "".to_string(),
+                location: result.location.unwrap_or_default(),
                 bucket: self.account_id.clone(),
-                key: key.clone(),
-                etag: result.e_tag.unwrap(),
+                etag: self.written_etag(&key, result.e_tag)?,
+                key,
             }),
-            Err(e) => Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            })),
+            Err(error) => Err(upload_error(upload_id, error)),
         }
     }
 
@@ -345,141 +1024,213 @@ impl Repository for S3Repository {
         &self,
         key: String,
         upload_id: String,
-        part_number: String,
+        part_number: i64,
         bytes: Bytes,
     ) -> Result<UploadPartResponse, Box<dyn APIError>> {
-        let client: S3Client;
-
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            }));
-        }
+        let client = self.client()?;
 
         let request = UploadPartRequest {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             key: format!("{}/{}", self.base_prefix, key),
-            upload_id,
-            part_number: part_number.parse().unwrap(),
+            upload_id: upload_id.clone(),
+            part_number,
             body: Some(bytes.to_vec().into()),
             ..Default::default()
         };
 
         match client.upload_part(request).await {
             Ok(result) => Ok(UploadPartResponse {
-                etag: result.e_tag.unwrap(),
+                etag: self.written_etag(&key, result.e_tag)?,
+            }),
+            Err(error) => Err(upload_error(upload_id, error)),
+        }
+    }
+
+    async fn delete_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> Result<DeleteObjectResponse, Box<dyn APIError>> {
+        let client = self.client()?;
+        let request = DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
+            key: format!("{}/{}", self.base_prefix, key),
+            version_id,
+            ..Default::default()
+        };
+
+        match client.delete_object(request).await {
+            Ok(output) => Ok(DeleteObjectResponse {
+                delete_marker: output.delete_marker.unwrap_or(false),
+                version_id: output.version_id,
             }),
+            // Deletions blocked by Object Lock retention or a legal hold
+            Err(RusotoError::Unknown(response)) if response.status.eq(&403) => {
+                Err(Box::new(AccessDeniedError {
+                    key: format!("{}/{}", self.repository_id, key),
+                    message: error_message(&response.body)
+                        .unwrap_or_else(|| "Access Denied".to_string()),
+                    reason: DenialReason::ObjectLock,
+                }))
+            }
             Err(_) => Err(Box::new(InternalServerError {
                 message: format!("Internal Server Error"),
             })),
         }
     }
 
-    async fn delete_object(&self, key: String) -> Result<(), Box<dyn APIError>> {
-        let client: S3Client;
+    async fn get_object_tagging(&self, key: String) -> Result<Tagging, Box<dyn APIError>> {
+        let client = self.client()?;
+        let request = GetObjectTaggingRequest {
+            bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
+            key: format!("{}/{}", self.base_prefix, key),
+            ..Default::default()
+        };
 
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            }));
+        match client.get_object_tagging(request).await {
+            Ok(output) => Ok(Tagging {
+                tag_set: TagSet {
+                    tags: output
+                        .tag_set
+                        .into_iter()
+                        .map(|tag| Tag {
+                            key: tag.key,
+                            value: tag.value,
+                        })
+                        .collect(),
+                },
+            }),
+            Err(RusotoError::Unknown(response)) if response.status.eq(&404) => {
+                Err(Box::new(ObjectNotFoundError {
+                    account_id: self.account_id.clone(),
+                    repository_id: self.repository_id.clone(),
+                    key,
+                }))
+            }
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
         }
-        let request = DeleteObjectRequest {
+    }
+
+    async fn put_object_tagging(
+        &self,
+        key: String,
+        tagging: Tagging,
+    ) -> Result<(), Box<dyn APIError>> {
+        let client = self.client()?;
+        let request = PutObjectTaggingRequest {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             key: format!("{}/{}", self.base_prefix, key),
+            tagging: rusoto_s3::Tagging {
+                tag_set: tagging
+                    .tag_set
+                    .tags
+                    .into_iter()
+                    .map(|tag| rusoto_s3::Tag {
+                        key: tag.key,
+                        value: tag.value,
+                    })
+                    .collect(),
+            },
             ..Default::default()
         };
 
-        match client.delete_object(request).await {
+        match client.put_object_tagging(request).await {
             Ok(_) => Ok(()),
             Err(_) => Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
+                message: "Internal Server Error".to_string(),
             })),
         }
     }
 
-    async fn head_object(&self, key: String) -> Result<HeadObjectResponse, Box<dyn APIError>> {
-        let client: S3Client;
+    async fn delete_object_tagging(&self, key: String) -> Result<(), Box<dyn APIError>> {
+        let client = self.client()?;
+        let request = DeleteObjectTaggingRequest {
+            bucket: self.bucket.clone(),
+            key: format!("{}/{}", self.base_prefix, key),
+            ..Default::default()
+        };
 
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            }));
+        match client.delete_object_tagging(request).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+
+    async fn restore_object(
+        &self,
+        key: String,
+        days: Option<i64>,
+        tier: Option<String>,
+    ) -> Result<(), Box<dyn APIError>> {
+        let client = self.client()?;
+        let request = RestoreObjectRequest {
+            bucket: self.bucket.clone(),
+            key: format!("{}/{}", self.base_prefix, key),
+            request_payer: self.request_payer(),
+            restore_request: Some(RestoreRequest {
+                days,
+                glacier_job_parameters: tier.map(|tier| GlacierJobParameters { tier }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        match client.restore_object(request).await {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(RestoreObjectError::ObjectAlreadyInActiveTierError(
+                message,
+            ))) => Err(Box::new(InvalidObjectStateError {
+                key: format!("{}/{}", self.repository_id, key),
+                message,
+            })),
+            Err(RusotoError::Unknown(response)) if response.status.eq(&404) => {
+                Err(Box::new(ObjectNotFoundError {
+                    account_id: self.account_id.clone(),
+                    repository_id: self.repository_id.clone(),
+                    key,
+                }))
+            }
+            Err(RusotoError::Unknown(response)) if response.status.eq(&409) => {
+                Err(Box::new(RestoreAlreadyInProgressError {
+                    key: format!("{}/{}", self.repository_id, key),
+                }))
+            }
+            // Restores of objects which aren't archived
+            Err(RusotoError::Unknown(response)) if response.status.eq(&403) => {
+                Err(Box::new(InvalidObjectStateError {
+                    key: format!("{}/{}", self.repository_id, key),
+                    message: error_message(&response.body).unwrap_or_else(|| {
+                        "The operation is not valid for the object's storage class".to_string()
+                    }),
+                }))
+            }
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
         }
+    }
+
+    async fn head_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+        part_number: Option<i64>,
+    ) -> Result<HeadObjectResponse, Box<dyn APIError>> {
+        let client = self.client()?;
         let request = HeadObjectRequest {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             key: format!("{}/{}", self.base_prefix, key),
+            version_id: version_id.clone(),
+            part_number,
             ..Default::default()
         };
 
@@ -491,11 +1242,44 @@ impl Repository for S3Repository {
                 last_modified: result
                     .last_modified
                     .unwrap_or_else(|| Utc::now().to_rfc2822()),
+                object_lock: ObjectLock {
+                    mode: result.object_lock_mode,
+                    retain_until_date: result.object_lock_retain_until_date,
+                    legal_hold_status: result.object_lock_legal_hold_status,
+                },
+                server_side_encryption: ServerSideEncryption {
+                    algorithm: result.server_side_encryption,
+                    kms_key_id: result.ssekms_key_id,
+                },
+                checksum: result.metadata.as_ref().and_then(Checksum::from_metadata),
+                version_id: result.version_id,
+                metadata: result
+                    .metadata
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|(key, _)| !Checksum::is_metadata_key(key))
+                    .collect(),
+                storage_class: result
+                    .storage_class
+                    .filter(|storage_class| storage_class != "STANDARD"),
+                restore: result.restore,
+                parts_count: result.parts_count,
             }),
             Err(error) => {
                 match error {
                     RusotoError::Unknown(response) => {
+                        if response.status.eq(&416) && part_number.is_some() {
+                            return Err(Box::new(InvalidPartNumberError {
+                                key: format!("{}/{}", self.repository_id, key),
+                            }));
+                        }
                         if response.status.eq(&404) {
+                            if let Some(version_id) = version_id {
+                                return Err(Box::new(NoSuchVersionError {
+                                    key: format!("{}/{}", self.repository_id, key),
+                                    version_id,
+                                }));
+                            }
                             return Err(Box::new(ObjectNotFoundError {
                                 account_id: self.account_id.clone(),
                                 repository_id: self.repository_id.clone(),
@@ -513,47 +1297,67 @@ impl Repository for S3Repository {
         }
     }
 
+    async fn list_parts(
+        &self,
+        key: String,
+        upload_id: String,
+        max_parts: NonZeroU32,
+        part_number_marker: Option<i64>,
+    ) -> Result<ListPartsResult, Box<dyn APIError>> {
+        let client = self.client()?;
+
+        let request = ListPartsRequest {
+            bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
+            key: format!("{}/{}", self.base_prefix, key),
+            upload_id: upload_id.clone(),
+            max_parts: Some(max_parts.get() as i64),
+            part_number_marker,
+            ..Default::default()
+        };
+
+        match client.list_parts(request).await {
+            Ok(output) => Ok(ListPartsResult {
+                bucket: self.account_id.clone(),
+                key: format!("{}/{}", self.repository_id, key),
+                upload_id,
+                part_number_marker: output.part_number_marker.unwrap_or(0),
+                next_part_number_marker: output.next_part_number_marker.unwrap_or(0),
+                max_parts: output.max_parts.unwrap_or(max_parts.get() as i64),
+                is_truncated: output.is_truncated.unwrap_or(false),
+                parts: output
+                    .parts
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|part| Part {
+                        part_number: part.part_number.unwrap_or(0),
+                        last_modified: part.last_modified.unwrap_or_default(),
+                        etag: part.e_tag.unwrap_or_default(),
+                        size: part.size.unwrap_or(0),
+                    })
+                    .collect(),
+            }),
+            Err(RusotoError::Service(_)) => Err(Box::new(NoSuchUploadError { upload_id })),
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+
     async fn list_objects_v2(
         &self,
         prefix: String,
         continuation_token: Option<String>,
+        start_after: Option<String>,
         delimiter: Option<String>,
         max_keys: NonZeroU32,
     ) -> Result<ListBucketResult, Box<dyn APIError>> {
-        let client: S3Client;
-
-        if self.auth_method == "s3_access_key" {
-            let credentials = rusoto_credential::StaticProvider::new_minimal(
-                self.access_key_id.clone().unwrap(),
-                self.secret_access_key.clone().unwrap(),
-            );
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_ecs_task_role" {
-            let credentials = rusoto_credential::ContainerProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else if self.auth_method == "s3_local" {
-            let credentials = rusoto_credential::ChainProvider::new();
-            client = S3Client::new_with(
-                rusoto_core::request::HttpClient::new().unwrap(),
-                credentials,
-                self.region.clone(),
-            );
-        } else {
-            return Err(Box::new(InternalServerError {
-                message: format!("Internal Server Error"),
-            }));
-        }
+        let client = self.client()?;
         let mut request = ListObjectsV2Request {
             bucket: self.bucket.clone(),
+            request_payer: self.request_payer(),
             prefix: Some(format!("{}/{}", self.base_prefix, prefix)),
+            start_after: start_after.map(|key| format!("{}/{}", self.base_prefix, key)),
             delimiter,
             max_keys: Some(max_keys.get() as i64),
             ..Default::default()
@@ -568,9 +1372,12 @@ impl Repository for S3Repository {
                 let result = ListBucketResult {
                     name: format!("{}", self.account_id),
                     prefix: format!("{}/{}", self.repository_id, prefix),
+                    delimiter: None,
+                    start_after: None,
                     key_count: output.key_count.unwrap_or(0),
                     max_keys: output.max_keys.unwrap_or(0),
                     is_truncated: output.is_truncated.unwrap_or(false),
+                    continuation_token: None,
                     next_continuation_token: output.next_continuation_token,
                     contents: output
                         .contents
@@ -591,7 +1398,8 @@ impl Repository for S3Repository {
                             storage_class: item
                                 .storage_class
                                 .clone()
-                                .unwrap_or_else(|| "".to_string()),
+                                .unwrap_or_else(|| "STANDARD".to_string()),
+                            owner: None,
                         })
                         .collect(),
                     common_prefixes: output
@@ -617,4 +1425,148 @@ impl Repository for S3Repository {
             }
         }
     }
+
+    async fn get_bucket_versioning(&self) -> Result<Option<String>, Box<dyn APIError>> {
+        let client = self.client()?;
+        let request = GetBucketVersioningRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+
+        match client.get_bucket_versioning(request).await {
+            Ok(output) => Ok(output.status),
+            Err(_) => Err(Box::new(InternalServerError {
+                message: "Internal Server Error".to_string(),
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Replays S3 responses recorded in `tests/cassettes`.
+
+    use super::*;
+    use crate::utils::cassette::replay_recorded;
+
+    const BODY: &str = "station,temperature\nA,12.5\nB,13.1\n";
+
+    fn repository() -> S3Repository {
+        S3Repository {
+            account_id: "example".to_string(),
+            repository_id: "weather".to_string(),
+            region: Region::Custom {
+                name: "us-west-2".to_string(),
+                endpoint: "https://s3.us-west-2.amazonaws.com".to_string(),
+            },
+            bucket: "example-bucket".to_string(),
+            base_prefix: "example/weather".to_string(),
+            auth: S3Auth::AccessKey {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            },
+            requester_pays: false,
+            skip_tls_verification: false,
+        }
+    }
+
+    async fn read_body(response: GetObjectResponse) -> String {
+        let chunks: Vec<Bytes> = response.body.map(|chunk| chunk.unwrap()).collect().await;
+        String::from_utf8(chunks.concat()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn head_object() {
+        replay_recorded();
+
+        let head = repository()
+            .head_object("stations.csv".to_string(), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(head.content_length, BODY.len() as u64);
+        assert_eq!(head.content_type, "text/csv");
+        assert_eq!(head.etag, "\"3f1e5d0c55c7c5f4b7a0e3a9c2b1d4e6\"");
+        assert_eq!(head.last_modified, "Tue, 14 May 2024 09:30:00 GMT");
+        assert_eq!(
+            head.metadata.get("source").map(String::as_str),
+            Some("noaa")
+        );
+        assert!(head.storage_class.is_none());
+    }
+
+    #[tokio::test]
+    async fn head_missing_object() {
+        replay_recorded();
+
+        let error = repository()
+            .head_object("missing.csv".to_string(), None, None)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.to_response().status().as_u16(), 404);
+    }
+
+    #[tokio::test]
+    async fn get_object() {
+        replay_recorded();
+
+        let response = repository()
+            .get_object("stations.csv".to_string(), None, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_length, BODY.len() as u64);
+        assert_eq!(response.content_range, None);
+        assert_eq!(read_body(response).await, BODY);
+    }
+
+    #[tokio::test]
+    async fn get_object_range() {
+        replay_recorded();
+
+        let response = repository()
+            .get_object(
+                "stations.csv".to_string(),
+                Some("bytes=20-26".to_string()),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_length, 7);
+        assert_eq!(response.content_range.as_deref(), Some("bytes 20-26/34"));
+        assert_eq!(read_body(response).await, "A,12.5\n");
+    }
+
+    #[tokio::test]
+    async fn list_objects_v2() {
+        replay_recorded();
+
+        let result = repository()
+            .list_objects_v2(
+                "".to_string(),
+                None,
+                None,
+                Some("/".to_string()),
+                NonZeroU32::new(1000).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(result.prefix, "weather/");
+        assert_eq!(result.key_count, 3);
+        assert!(!result.is_truncated);
+        let keys: Vec<&str> = result.contents.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, ["weather/readme.md", "weather/stations.csv"]);
+        assert_eq!(result.contents[1].size, BODY.len() as i64);
+        let prefixes: Vec<&str> = result
+            .common_prefixes
+            .iter()
+            .map(|p| p.prefix.as_str())
+            .collect();
+        assert_eq!(prefixes, ["weather/daily/"]);
+    }
 }
@@ -0,0 +1,376 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use core::num::NonZeroU32;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::backends::common::{
+    Capabilities, CompleteMultipartUploadResponse, CopyObjectResponse,
+    CreateMultipartUploadResponse, DeleteObjectResponse, GetObjectResponse, HeadObjectResponse,
+    ListBucketResult, ListPartsResult, MetadataReplacement, MultipartPart, ObjectLock,
+    PutObjectOptions, PutObjectResponse, Repository, ServerSideEncryption, Tagging,
+    UploadPartResponse,
+};
+use crate::utils::errors::APIError;
+
+/// Every this many divergences of a repository, one is logged with its diff.
+const DIVERGENCE_LOG_INTERVAL: u64 = 10;
+
+/// Comparison counts of a shadowed repository.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ShadowCounts {
+    pub compared: u64,
+    pub diverged: u64,
+    pub status_mismatches: u64,
+    pub etag_mismatches: u64,
+    pub length_mismatches: u64,
+}
+
+/// Shadow comparison counts, by `{account_id}/{repository_id}`.
+pub type ShadowStats = Arc<Mutex<HashMap<String, ShadowCounts>>>;
+
+/// What a read returned, as far as the primary and shadow paths are compared.
+#[derive(Debug, PartialEq)]
+struct Observation {
+    status: u16,
+    etag: Option<String>,
+    /// The object size for object reads, the number of entries for listings.
+    length: Option<u64>,
+}
+
+impl Observation {
+    fn of<T>(
+        res: &Result<T, Box<dyn APIError>>,
+        describe: impl Fn(&T) -> (Option<String>, Option<u64>),
+    ) -> Self {
+        match res {
+            Ok(res) => {
+                let (etag, length) = describe(res);
+                Observation {
+                    status: 200,
+                    etag,
+                    length,
+                }
+            }
+            Err(error) => Observation {
+                status: error.to_response().status().as_u16(),
+                etag: None,
+                length: None,
+            },
+        }
+    }
+
+    fn of_get(res: &Result<GetObjectResponse, Box<dyn APIError>>) -> Self {
+        Observation::of(res, |res| {
+            (Some(res.etag.clone()), Some(res.content_length))
+        })
+    }
+
+    fn of_head(res: &Result<HeadObjectResponse, Box<dyn APIError>>) -> Self {
+        Observation::of(res, |res| {
+            (Some(res.etag.clone()), Some(res.content_length))
+        })
+    }
+
+    fn of_list(res: &Result<ListBucketResult, Box<dyn APIError>>) -> Self {
+        Observation::of(res, |res| {
+            (
+                None,
+                Some((res.contents.len() + res.common_prefixes.len()) as u64),
+            )
+        })
+    }
+}
+
+/// Mirrors a sample of the reads of a repository to a shadow mirror, e.g. a
+/// new bucket or backend before it becomes the primary mirror.
+///
+/// Responses always come from the primary path. Sampled reads are replayed
+/// against the shadow mirror in the background and their status, ETag and
+/// length compared, with divergences counted in `stats` and a sample of them
+/// logged. Writes are never shadowed, and neither are reads of a specific
+/// version, as version IDs are specific to the mirror which issued them.
+pub struct ShadowRepository {
+    pub repository: String,
+    pub primary: Box<dyn Repository + Send + Sync>,
+    pub shadow: Arc<dyn Repository + Send + Sync>,
+    /// The share of reads replayed against the shadow mirror, between 0 and 1.
+    pub sample_rate: f64,
+    pub stats: ShadowStats,
+}
+
+impl ShadowRepository {
+    fn should_sample(&self) -> bool {
+        rand::random::<f64>() < self.sample_rate
+    }
+
+    /// Runs the shadow read in the background and records how it compares
+    /// with the primary read.
+    fn compare<F>(&self, operation: &'static str, key: String, primary: Observation, shadow: F)
+    where
+        F: Future<Output = Observation> + 'static,
+    {
+        let repository = self.repository.clone();
+        let stats = self.stats.clone();
+
+        actix_web::rt::spawn(async move {
+            let shadow = shadow.await;
+
+            let mut stats = stats.lock().unwrap();
+            let counts = stats.entry(repository.clone()).or_default();
+            counts.compared += 1;
+
+            if primary == shadow {
+                return;
+            }
+
+            counts.diverged += 1;
+            if primary.status != shadow.status {
+                counts.status_mismatches += 1;
+            }
+            if primary.etag != shadow.etag {
+                counts.etag_mismatches += 1;
+            }
+            if primary.length != shadow.length {
+                counts.length_mismatches += 1;
+            }
+
+            if counts.diverged % DIVERGENCE_LOG_INTERVAL == 1 {
+                log::warn!(
+                    "Shadow {} of {}/{} diverged: primary {:?}, shadow {:?} ({} of {} compared reads diverged)",
+                    operation,
+                    repository,
+                    key,
+                    primary,
+                    shadow,
+                    counts.diverged,
+                    counts.compared
+                );
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Repository for ShadowRepository {
+    // Writes only go to the primary mirror
+    fn capabilities(&self) -> Capabilities {
+        self.primary.capabilities()
+    }
+
+    async fn delete_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+    ) -> Result<DeleteObjectResponse, Box<dyn APIError>> {
+        self.primary.delete_object(key, version_id).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        key: String,
+        content_type: Option<String>,
+        object_lock: ObjectLock,
+        server_side_encryption: ServerSideEncryption,
+        tagging: Option<String>,
+    ) -> Result<CreateMultipartUploadResponse, Box<dyn APIError>> {
+        self.primary
+            .create_multipart_upload(
+                key,
+                content_type,
+                object_lock,
+                server_side_encryption,
+                tagging,
+            )
+            .await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        key: String,
+        upload_id: String,
+    ) -> Result<(), Box<dyn APIError>> {
+        self.primary.abort_multipart_upload(key, upload_id).await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: String,
+        upload_id: String,
+        parts: Vec<MultipartPart>,
+    ) -> Result<CompleteMultipartUploadResponse, Box<dyn APIError>> {
+        self.primary
+            .complete_multipart_upload(key, upload_id, parts)
+            .await
+    }
+
+    async fn upload_multipart_part(
+        &self,
+        key: String,
+        upload_id: String,
+        part_number: i64,
+        bytes: Bytes,
+    ) -> Result<UploadPartResponse, Box<dyn APIError>> {
+        self.primary
+            .upload_multipart_part(key, upload_id, part_number, bytes)
+            .await
+    }
+
+    async fn put_object(
+        &self,
+        key: String,
+        bytes: Bytes,
+        options: PutObjectOptions,
+    ) -> Result<PutObjectResponse, Box<dyn APIError>> {
+        self.primary.put_object(key, bytes, options).await
+    }
+
+    async fn copy_object(
+        &self,
+        source_key: String,
+        key: String,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>> {
+        self.primary.copy_object(source_key, key).await
+    }
+
+    async fn replace_metadata(
+        &self,
+        key: String,
+        replacement: MetadataReplacement,
+    ) -> Result<CopyObjectResponse, Box<dyn APIError>> {
+        self.primary.replace_metadata(key, replacement).await
+    }
+
+    /// The shadow response body is dropped unread; only its metadata is compared.
+    async fn get_object(
+        &self,
+        key: String,
+        range: Option<String>,
+        version_id: Option<String>,
+        part_number: Option<i64>,
+    ) -> Result<GetObjectResponse, Box<dyn APIError>> {
+        let versioned = version_id.is_some() || part_number.is_some();
+        let res = self
+            .primary
+            .get_object(key.clone(), range.clone(), version_id, part_number)
+            .await;
+
+        if !versioned && self.should_sample() {
+            let shadow = self.shadow.clone();
+            let shadow_key = key.clone();
+            self.compare("GetObject", key, Observation::of_get(&res), async move {
+                Observation::of_get(&shadow.get_object(shadow_key, range, None, None).await)
+            });
+        }
+
+        res
+    }
+
+    async fn list_parts(
+        &self,
+        key: String,
+        upload_id: String,
+        max_parts: NonZeroU32,
+        part_number_marker: Option<i64>,
+    ) -> Result<ListPartsResult, Box<dyn APIError>> {
+        self.primary
+            .list_parts(key, upload_id, max_parts, part_number_marker)
+            .await
+    }
+
+    async fn get_object_tagging(&self, key: String) -> Result<Tagging, Box<dyn APIError>> {
+        self.primary.get_object_tagging(key).await
+    }
+
+    async fn put_object_tagging(
+        &self,
+        key: String,
+        tagging: Tagging,
+    ) -> Result<(), Box<dyn APIError>> {
+        self.primary.put_object_tagging(key, tagging).await
+    }
+
+    async fn delete_object_tagging(&self, key: String) -> Result<(), Box<dyn APIError>> {
+        self.primary.delete_object_tagging(key).await
+    }
+
+    async fn restore_object(
+        &self,
+        key: String,
+        days: Option<i64>,
+        tier: Option<String>,
+    ) -> Result<(), Box<dyn APIError>> {
+        self.primary.restore_object(key, days, tier).await
+    }
+
+    async fn head_object(
+        &self,
+        key: String,
+        version_id: Option<String>,
+        part_number: Option<i64>,
+    ) -> Result<HeadObjectResponse, Box<dyn APIError>> {
+        let versioned = version_id.is_some() || part_number.is_some();
+        let res = self
+            .primary
+            .head_object(key.clone(), version_id, part_number)
+            .await;
+
+        if !versioned && self.should_sample() {
+            let shadow = self.shadow.clone();
+            let shadow_key = key.clone();
+            self.compare("HeadObject", key, Observation::of_head(&res), async move {
+                Observation::of_head(&shadow.head_object(shadow_key, None, None).await)
+            });
+        }
+
+        res
+    }
+
+    /// Only first pages are shadowed, as continuation tokens are specific to
+    /// the mirror which issued them.
+    async fn list_objects_v2(
+        &self,
+        prefix: String,
+        continuation_token: Option<String>,
+        start_after: Option<String>,
+        delimiter: Option<String>,
+        max_keys: NonZeroU32,
+    ) -> Result<ListBucketResult, Box<dyn APIError>> {
+        let is_first_page = continuation_token.is_none();
+        let res = self
+            .primary
+            .list_objects_v2(
+                prefix.clone(),
+                continuation_token,
+                start_after.clone(),
+                delimiter.clone(),
+                max_keys,
+            )
+            .await;
+
+        if is_first_page && self.should_sample() {
+            let shadow = self.shadow.clone();
+            let shadow_prefix = prefix.clone();
+            self.compare(
+                "ListObjectsV2",
+                prefix,
+                Observation::of_list(&res),
+                async move {
+                    Observation::of_list(
+                        &shadow
+                            .list_objects_v2(shadow_prefix, None, start_after, delimiter, max_keys)
+                            .await,
+                    )
+                },
+            );
+        }
+
+        res
+    }
+
+    async fn get_bucket_versioning(&self) -> Result<Option<String>, Box<dyn APIError>> {
+        self.primary.get_bucket_versioning().await
+    }
+}
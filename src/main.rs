@@ -1,30 +1,106 @@
 mod apis;
 mod backends;
 mod utils;
-use crate::utils::core::{split_at_first_slash, StreamingResponse};
+use crate::utils::checksum::{is_checksum_mode_enabled, verify_checksum};
+use crate::utils::core::{
+    check_user_metadata, coalesce_ranges, insert_object_lock, insert_server_side_encryption,
+    insert_user_metadata, is_not_modified, is_requester_payer, object_lock_from_headers,
+    object_url, parse_copy_source, quote_etag, server_side_encryption_from_headers,
+    split_at_first_slash, user_metadata_from_headers, validate_key, ByteRange, StreamingResponse,
+};
+use crate::utils::errors::{
+    APIError, AccessDeniedError, BadGatewayError, DenialReason, EntityTooSmallError,
+    HashRangeTooLargeError, InvalidArgumentError, InvalidObjectStateError, InvalidPartOrderError,
+    InvalidRangeError, InvalidRequestError, MemoryBudgetExhaustedError, NotImplementedError,
+    SlowDownError, UnauthorizedError,
+};
+use crate::utils::expect::check_expectation;
 use actix_cors::Cors;
+use actix_http::HttpService;
+use actix_server::Server;
+use actix_service::map_config;
 use actix_web::body::{BodySize, BoxBody, MessageBody};
 use actix_web::error::ErrorInternalServerError;
 use actix_web::{
-    delete, get, head, http::header::CONTENT_TYPE, http::header::RANGE, middleware, post, put, web,
-    App, HttpRequest, HttpResponse, HttpServer, Responder,
+    delete,
+    dev::{fn_service, AppConfig},
+    get,
+    guard::GuardContext,
+    head,
+    http::header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, IF_MODIFIED_SINCE,
+        IF_NONE_MATCH, RANGE,
+    },
+    http::StatusCode,
+    middleware, options, post, put, web, App, HttpMessage, HttpRequest, HttpResponse,
+    HttpResponseBuilder, Responder,
 };
 
-use apis::source::{RepositoryPermission, SourceAPI};
+use apis::source::{BackendOperation, RepositoryPermission, SourceAPI, MAX_SESSION_DURATION};
 use apis::API;
-use backends::common::{CommonPrefix, CompleteMultipartUpload, ListBucketResult};
+use backends::common::{
+    is_archived, normalize_list_result, AccessControlPolicy, AccountListContinuation,
+    BoxedReqwestStream, Bucket, Buckets, Capability, CommonPrefix, CompleteMultipartUpload,
+    CompleteMultipartUploadResponse, DeleteError, DeleteObjectsRequest, DeleteObjectsResult,
+    DeletedObject, HeadObjectResponse, ListAllMyBucketsResult, ListBucketResult, ListContinuation,
+    LocationConstraint, MetadataReplacement, MultipartPart, ObjectHeaders, Owner, PutObjectOptions,
+    Repository, RestoreRequest, Tagging, VersioningConfiguration,
+};
 use bytes::Bytes;
+use chrono::DateTime;
 use core::num::NonZeroU32;
 use env_logger::Env;
-use futures_util::StreamExt;
+use futures_util::stream::{self, StreamExt};
 use quick_xml::se::to_string_with_root;
-use serde::Deserialize;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
 use serde_xml_rs::from_str;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::str::from_utf8;
+use std::sync::Arc;
 use std::task::{Context, Poll};
-use utils::auth::{LoadIdentity, UserIdentity};
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
+use utils::auth::{load_form_identity, LoadIdentity, UserIdentity};
+use utils::clock::SystemClock;
+use utils::conformance::{Conformance, ConformanceMode};
+use utils::cors::{
+    preflight_response, BUCKET_METHODS, EXPOSED_HEADERS, OBJECT_METHODS, REPOSITORY_METHODS,
+    SERVICE_METHODS,
+};
+use utils::error_documents::ErrorDocuments;
+use utils::header_policy::HeaderPolicy;
+use utils::hotlinking::{HotlinkProtection, HotlinkStats};
+use utils::lifecycle::LifecycleJob;
+use utils::list_cache::{ListCache, ListCacheInvalidation, ListPage};
+use utils::listing_slots::ListingSlots;
+use utils::load_shedding::LoadShedding;
+use utils::maintenance::{check_maintenance, MaintenanceMode};
+use utils::memory_budget::{MemoryBudget, MemoryReservation, MAX_BODY_SIZE};
+use utils::normalize_path::NormalizePaths;
+use utils::params::{
+    BucketPostParams, DeleteParams, GetObjectParams, HeadObjectParams, ListObjectsV2Params,
+    PostParams, PutParams, SessionTokenParams, WarmParams,
+};
+use utils::post_policy::{check_policy_document, is_form_upload, parse_form, PostResponse};
+use utils::prefix_jobs::PrefixJobRegistry;
+use utils::preview::{
+    csv_preview, thumbnail, CSV_PREVIEW_BYTES, DEFAULT_PREVIEW_ROWS, MAX_PREVIEW_ROWS,
+    MAX_THUMBNAIL_SIZE, MAX_THUMBNAIL_SOURCE_BYTES,
+};
+use utils::range_cache::{CachedRange, RangeCache};
+use utils::recent_writes::{
+    merge_recent_writes, written_object, RecentWrites, AFTER_WRITE_HEADER, CONSISTENCY_TOKEN_HEADER,
+};
+use utils::slo::{SloTracker, SloTracking};
+use utils::uploads::{UploadRegistry, UploadSession};
+use utils::virtual_host::VirtualHosts;
+use utils::warm_pool::WarmPool;
+use utils::write_spool::{SpoolTarget, WriteSpool};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -50,572 +126,4524 @@ impl MessageBody for FakeBody {
 // TODO: Map the APIErrors to HTTP Responses
 
 #[get("/{account_id}/{repository_id}/{key:.*}")]
+#[allow(clippy::too_many_arguments)]
 async fn get_object(
     api_client: web::Data<SourceAPI>,
+    range_cache: web::Data<RangeCache>,
+    listing_slots: web::Data<ListingSlots>,
+    memory_budget: web::Data<MemoryBudget>,
+    conformance: web::Data<ConformanceMode>,
     req: HttpRequest,
+    params: web::Query<GetObjectParams>,
     path: web::Path<(String, String, String)>,
     user_identity: web::ReqData<UserIdentity>,
 ) -> impl Responder {
     let (account_id, repository_id, key) = path.into_inner();
-    let headers = req.headers();
-    let mut range = None;
-    let mut range_start = 0;
-    let mut is_range_request = false;
-
-    if let Some(range_header) = headers.get(RANGE) {
-        if let Ok(r) = range_header.to_str() {
-            if let Some(bytes_range) = r.strip_prefix("bytes=") {
-                if let Some((start, end)) = bytes_range.split_once('-') {
-                    if let Ok(s) = start.parse::<u64>() {
-                        range_start = s;
-                        if end.is_empty() || end.parse::<u64>().is_ok() {
-                            range = Some(r.to_string());
-                            is_range_request = true;
-                        }
-                    }
-                }
-            }
-        }
+
+    if let Err(error) = validate_key(&repository_id, &key) {
+        return error.to_response();
     }
 
-    if let Ok(client) = api_client
-        .get_backend_client(&account_id, &repository_id)
-        .await
+    if let Err(response) =
+        check_response_overrides(&user_identity, &key, &params.response_overrides())
     {
-        match api_client
-            .is_authorized(
-                user_identity.into_inner(),
-                &account_id,
-                &repository_id,
-                RepositoryPermission::Read,
-            )
-            .await
-        {
-            Ok(authorized) => {
-                if !authorized {
-                    return HttpResponse::Unauthorized().finish();
-                }
-            }
-            Err(_) => return HttpResponse::InternalServerError().finish(),
-        }
+        return response;
+    }
 
-        // Found the repository, now try to get the object
-        match client.get_object(key.clone(), range).await {
-            Ok(res) => {
-                let mut content_length = String::from("*");
-
-                // Remove this if statement to increase performance since it's making an extra request just to get the total content-length
-                // This is only needed for range requests and in theory, you can return a * in the Content-Range header to indicate that the content length is unknown
-                if is_range_request {
-                    match client.head_object(key.clone()).await {
-                        Ok(head_res) => {
-                            content_length = head_res.content_length.to_string();
-                        }
-                        Err(_) => {}
-                    }
-                }
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
 
-                let stream = res.body.map(|result| {
-                    result
-                        .map(web::Bytes::from)
-                        .map_err(|e| ErrorInternalServerError(e.to_string()))
-                });
+    let headers = req.headers();
+    let mut byte_range = headers
+        .get(RANGE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(ByteRange::parse);
+    // Requests for too many ranges are answered with the whole object
+    let byte_ranges = headers
+        .get(RANGE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(ByteRange::parse_all)
+        .filter(|ranges| ranges.len() > 1 && ranges.len() <= MAX_BYTE_RANGES);
 
-                let streaming_response = StreamingResponse::new(stream, res.content_length);
-                let mut response = if is_range_request {
-                    HttpResponse::PartialContent()
-                } else {
-                    HttpResponse::Ok()
-                };
+    if let Err(error) = check_part_number(
+        headers,
+        format!("{}/{}", repository_id, key),
+        params.part_number,
+    ) {
+        return error.to_response();
+    }
 
-                let mut response = response
-                    .insert_header(("Content-Type", res.content_type))
-                    .insert_header(("Last-Modified", res.last_modified))
-                    .insert_header(("Content-Length", res.content_length.to_string()))
-                    .insert_header(("ETag", res.etag));
-
-                if is_range_request {
-                    response = response.insert_header((
-                        "Content-Range",
-                        format!(
-                            "bytes {}-{}/{}",
-                            range_start,
-                            range_start + res.content_length - 1,
-                            content_length
-                        ),
-                    ));
-                }
+    let client = match api_client
+        .get_backend_client(
+            &account_id,
+            &repository_id,
+            is_requester_payer(req.headers()),
+        )
+        .await
+    {
+        Ok(client) => client,
+        // The repository doesn't exist, or its data connection is misconfigured
+        Err(error) => return error.to_response(),
+    };
 
-                return response.body(streaming_response);
+    match api_client
+        .is_authorized(
+            (*user_identity).clone(),
+            &account_id,
+            &repository_id,
+            if params.upload_id.is_some() {
+                RepositoryPermission::Write
+            } else {
+                RepositoryPermission::Read
+            },
+        )
+        .await
+    {
+        Ok(authorized) => {
+            if !authorized {
+                return unauthorized(
+                    &api_client,
+                    &user_identity,
+                    &account_id,
+                    &repository_id,
+                    if params.upload_id.is_some() {
+                        RepositoryPermission::Write
+                    } else {
+                        RepositoryPermission::Read
+                    },
+                )
+                .await;
             }
-            Err(_) => HttpResponse::NotFound().finish(),
         }
-    } else {
-        // Could not find the repository
-        return HttpResponse::NotFound().finish();
+        Err(_) => return HttpResponse::InternalServerError().finish(),
     }
-}
-
-#[derive(Debug, Deserialize)]
-struct DeleteParams {
-    #[serde(rename = "uploadId")]
-    upload_id: Option<String>,
-}
 
-#[delete("/{account_id}/{repository_id}/{key:.*}")]
-async fn delete_object(
-    api_client: web::Data<SourceAPI>,
-    params: web::Query<DeleteParams>,
-    path: web::Path<(String, String, String)>,
-    user_identity: web::ReqData<UserIdentity>,
-) -> impl Responder {
-    let (account_id, repository_id, key) = path.into_inner();
+    if let Err(error) = api_client
+        .check_policy(
+            &user_identity,
+            &account_id,
+            &repository_id,
+            &key,
+            if params.upload_id.is_some() {
+                RepositoryPermission::Write
+            } else {
+                RepositoryPermission::Read
+            },
+        )
+        .await
+    {
+        return error.to_response();
+    }
 
-    if let Ok(client) = api_client
-        .get_backend_client(&account_id, &repository_id)
+    if let Err(error) = api_client
+        .check_operation(
+            &account_id,
+            &repository_id,
+            if params.upload_id.is_some() {
+                BackendOperation::Write
+            } else if params.stats.is_some() {
+                BackendOperation::List
+            } else {
+                BackendOperation::Read
+            },
+        )
         .await
     {
-        match api_client
-            .is_authorized(
-                user_identity.into_inner(),
-                &account_id,
-                &repository_id,
-                RepositoryPermission::Write,
+        return error.to_response();
+    }
+
+    let capability = if params.upload_id.is_some() {
+        Some(Capability::MultipartUpload)
+    } else if params.tagging.is_some() {
+        Some(Capability::Tagging)
+    } else {
+        None
+    };
+    if let Some(capability) = capability {
+        if let Err(response) = check_capability(client.as_ref(), &key, capability) {
+            return response;
+        }
+    }
+
+    if let Some(upload_id) = params.upload_id.clone() {
+        // ListParts for an in-progress multipart upload
+        return match client
+            .list_parts(
+                key,
+                upload_id,
+                params.max_parts.unwrap_or(NonZeroU32::new(1000).unwrap()),
+                params.part_number_marker,
             )
             .await
         {
-            Ok(authorized) => {
-                if !authorized {
-                    return HttpResponse::Unauthorized().finish();
-                }
-            }
-            Err(_) => return HttpResponse::InternalServerError().finish(),
-        }
+            Ok(res) => match to_string_with_root("ListPartsResult", &res) {
+                Ok(serialized) => HttpResponse::Ok()
+                    .content_type("application/xml")
+                    .body(serialized),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            },
+            Err(error) => error.to_response(),
+        };
+    }
 
-        if params.upload_id.is_none() {
-            // Found the repository, now try to delete the object
-            match client.delete_object(key.clone()).await {
-                Ok(_) => {
-                    return HttpResponse::NoContent().finish();
-                }
-                Err(_) => HttpResponse::NotFound().finish(),
-            }
-        } else {
-            match client
-                .abort_multipart_upload(key.clone(), params.upload_id.clone().unwrap())
-                .await
-            {
-                Ok(_) => {
-                    return HttpResponse::NoContent().finish();
-                }
-                Err(_) => HttpResponse::NotFound().finish(),
+    let is_anonymous = user_identity.api_key.is_none();
+
+    if let Err(error) = api_client
+        .check_terms_accepted(user_identity.into_inner(), &account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    if let Some(algorithm) = &params.hash {
+        if algorithm != "sha256" {
+            return HttpResponse::BadRequest().finish();
+        }
+        // Hashing reads the range in full, so it is only offered to API keys
+        if is_anonymous {
+            return UnauthorizedError {
+                key: repository_id,
+                reason: DenialReason::Credentials,
             }
+            .to_response();
         }
-    } else {
-        // Could not find the repository
-        return HttpResponse::NotFound().finish();
+
+        return hash_range(
+            client.as_ref(),
+            &repository_id,
+            key,
+            params.version_id.clone(),
+            byte_range,
+        )
+        .await;
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct PutParams {
-    #[serde(rename = "partNumber")]
-    part_number: Option<String>,
-    #[serde(rename = "uploadId")]
-    upload_id: Option<String>,
-}
+    if params.stats.is_some() {
+        let limits = api_client.get_service_limits(&account_id).await;
+        let Some(_listing_slot) =
+            listing_slots.try_acquire(&account_id, limits.max_concurrent_listings)
+        else {
+            return SlowDownError { account_id }.to_response();
+        };
 
-#[put("/{account_id}/{repository_id}/{key:.*}")]
-async fn put_object(
-    api_client: web::Data<SourceAPI>,
-    req: HttpRequest,
-    bytes: Bytes,
-    params: web::Query<PutParams>,
-    path: web::Path<(String, String, String)>,
-    user_identity: web::ReqData<UserIdentity>,
-) -> impl Responder {
-    let (account_id, repository_id, key) = path.into_inner();
-    let headers = req.headers();
+        return prefix_stats(client.as_ref(), &repository_id, &key).await;
+    }
 
-    if let Ok(client) = api_client
-        .get_backend_client(&account_id, &repository_id)
-        .await
-    {
-        match api_client
-            .is_authorized(
-                user_identity.into_inner(),
-                &account_id,
-                &repository_id,
-                RepositoryPermission::Write,
-            )
+    if params.acl.is_some() {
+        return match client
+            .head_object(key, params.version_id.clone(), None)
             .await
         {
-            Ok(authorized) => {
-                if !authorized {
-                    return HttpResponse::Unauthorized().finish();
-                }
-            }
-            Err(_) => return HttpResponse::InternalServerError().finish(),
-        }
-
-        if params.part_number.is_none() && params.upload_id.is_none() {
-            // Found the repository, now try to upload the object
-            match client
-                .put_object(
-                    key.clone(),
-                    bytes,
-                    headers
-                        .get(CONTENT_TYPE)
-                        .and_then(|h| h.to_str().ok())
-                        .map(|s| s.to_string()),
-                )
-                .await
-            {
-                Ok(_) => HttpResponse::NoContent().finish(),
+            Ok(_) => acl_response(
+                &account_id,
+                api_client.is_public(&account_id, &repository_id).await,
+            ),
+            Err(error) => error.to_response(),
+        };
+    }
 
-                Err(_) => HttpResponse::NotFound().finish(),
-            }
-        } else if params.part_number.is_some() && params.upload_id.is_some() {
-            match client
-                .upload_multipart_part(
-                    key.clone(),
-                    params.upload_id.clone().unwrap(),
-                    params.part_number.clone().unwrap(),
-                    bytes,
-                )
-                .await
-            {
-                Ok(res) => HttpResponse::Ok()
-                    .insert_header(("ETag", res.etag))
-                    .finish(),
+    if params.tagging.is_some() {
+        return match client.get_object_tagging(key).await {
+            Ok(res) => match to_string_with_root("Tagging", &res) {
+                Ok(serialized) => HttpResponse::Ok()
+                    .content_type("application/xml")
+                    .body(serialized),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            },
+            Err(error) => error.to_response(),
+        };
+    }
 
-                Err(_) => HttpResponse::NotFound().finish(),
-            }
-        } else {
-            return HttpResponse::NotFound().finish();
-        }
-    } else {
-        // Could not find the repository
-        return HttpResponse::NotFound().finish();
+    if params.preview.is_some() || params.thumbnail.is_some() {
+        let is_public = api_client.is_public(&account_id, &repository_id).await;
+        return preview_object(client, &memory_budget, key, &params, is_public).await;
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct PostParams {
-    uploads: Option<String>,
-    #[serde(rename = "uploadId")]
-    upload_id: Option<String>,
-}
+    let is_conditional =
+        headers.contains_key(IF_NONE_MATCH) || headers.contains_key(IF_MODIFIED_SINCE);
 
-#[post("/{account_id}/{repository_id}/{key:.*}")]
-async fn post_handler(
-    api_client: web::Data<SourceAPI>,
-    req: HttpRequest,
-    params: web::Query<PostParams>,
-    mut payload: web::Payload,
-    path: web::Path<(String, String, String)>,
-    user_identity: web::ReqData<UserIdentity>,
-) -> impl Responder {
-    let (account_id, repository_id, key) = path.into_inner();
-    let headers = req.headers();
+    // Ranges of cloud-optimized formats may be served from the range cache,
+    // which needs the object size and ETag to resolve and key them. The
+    // cache only holds current versions.
+    let may_cache = byte_range.is_some()
+        && params.version_id.is_none()
+        && RangeCache::is_cacheable_format(&key);
 
-    if let Ok(client) = api_client
-        .get_backend_client(&account_id, &repository_id)
-        .await
-    {
-        match api_client
-            .is_authorized(
-                user_identity.into_inner(),
-                &account_id,
-                &repository_id,
-                RepositoryPermission::Write,
-            )
+    // Multiple ranges are resolved against the object size first, as only
+    // the satisfiable ones are served
+    if is_conditional || may_cache || byte_ranges.is_some() {
+        match client
+            .head_object(key.clone(), params.version_id.clone(), None)
             .await
         {
-            Ok(authorized) => {
-                if !authorized {
-                    return HttpResponse::Unauthorized().finish();
+            Ok(head_res) => {
+                if is_not_modified(headers, &head_res.etag, &head_res.last_modified) {
+                    return HttpResponse::NotModified()
+                        .insert_header(("Last-Modified", head_res.last_modified))
+                        .insert_header(("ETag", head_res.etag))
+                        .finish();
                 }
-            }
-            Err(_) => return HttpResponse::InternalServerError().finish(),
-        }
 
-        if params.uploads.is_some() {
-            match client
-                .create_multipart_upload(
-                    key,
-                    headers
-                        .get(CONTENT_TYPE)
-                        .and_then(|h| h.to_str().ok())
-                        .map(|s| s.to_string()),
-                )
-                .await
-            {
-                Ok(res) => match to_string_with_root("InitiateMultipartUploadResult", &res) {
-                    Ok(serialized) => {
-                        return HttpResponse::Ok()
-                            .content_type("application/xml")
-                            .body(serialized)
+                if is_archived(
+                    head_res.storage_class.as_deref(),
+                    head_res.restore.as_deref(),
+                ) {
+                    return InvalidObjectStateError {
+                        key: format!("{}/{}", repository_id, key),
+                        message:
+                            "The object is archived and must be restored before it can be read"
+                                .to_string(),
                     }
-                    Err(_) => return HttpResponse::InternalServerError().finish(),
-                },
-                Err(_) => {
-                    return HttpResponse::NotFound().finish();
+                    .to_response();
                 }
-            }
-        } else if params.upload_id.is_some() {
-            let mut body = String::new();
-            while let Some(chunk) = payload.next().await {
-                match chunk {
-                    Ok(chunk) => match from_utf8(&chunk) {
-                        Ok(s) => body.push_str(s),
-                        Err(_) => return HttpResponse::BadRequest().body("Invalid UTF-8"),
-                    },
-                    Err(_) => return HttpResponse::InternalServerError().finish(),
+
+                let total_length = head_res.content_length;
+
+                if let Some(byte_ranges) = &byte_ranges {
+                    let resolved: Vec<(u64, u64)> = byte_ranges
+                        .iter()
+                        .filter_map(|byte_range| byte_range.resolve(total_length))
+                        .collect();
+                    let requested: u64 = resolved.iter().map(|(start, end)| end - start + 1).sum();
+
+                    let resolved = coalesce_ranges(resolved);
+
+                    match resolved[..] {
+                        // Ranges asking for more than the object get all of it, once
+                        _ if requested > total_length => byte_range = None,
+                        // Legacy clients get the whole object instead
+                        [] if !conformance.is_strict() => byte_range = None,
+                        [] => {
+                            return InvalidRangeError {
+                                key,
+                                content_length: total_length,
+                            }
+                            .to_response()
+                        }
+                        [(start, end)] => {
+                            byte_range = Some(ByteRange::FromStart {
+                                start,
+                                end: Some(end),
+                            })
+                        }
+                        _ => {
+                            return multi_range_response(
+                                client,
+                                key,
+                                params.version_id.clone(),
+                                head_res,
+                                resolved,
+                            )
+                        }
+                    }
                 }
-            }
 
-            match from_str::<CompleteMultipartUpload>(&body) {
-                Ok(upload) => {
-                    match client
-                        .complete_multipart_upload(
+                match byte_range.map(|byte_range| byte_range.resolve(total_length)) {
+                    Some(Some((start, end)))
+                        if params.version_id.is_none()
+                            && RangeCache::is_cacheable(&key, start, end, total_length) =>
+                    {
+                        let mut response = cached_range_response(
+                            client.as_ref(),
+                            &range_cache,
+                            &memory_budget,
+                            &format!("{}/{}/{}", account_id, repository_id, key),
                             key,
-                            params.upload_id.clone().unwrap(),
-                            upload.parts,
+                            &head_res.etag,
+                            (start, end, total_length),
                         )
-                        .await
-                    {
-                        Ok(res) => match to_string_with_root("CompleteMultipartUploadResult", &res)
-                        {
-                            Ok(serialized) => {
-                                return HttpResponse::Ok()
-                                    .content_type("application/xml")
-                                    .body(serialized)
-                            }
-                            Err(_) => return HttpResponse::InternalServerError().finish(),
-                        },
-                        Err(_) => {
-                            return HttpResponse::NotFound().finish();
+                        .await;
+                        apply_response_overrides(&mut response, params.response_overrides());
+                        return response;
+                    }
+                    Some(None) if !conformance.is_strict() => byte_range = None,
+                    Some(None) => {
+                        return InvalidRangeError {
+                            key,
+                            content_length: total_length,
                         }
+                        .to_response()
                     }
-                }
-                Err(_) => {
-                    return HttpResponse::BadRequest().finish();
+                    _ => {}
                 }
             }
-        } else {
-            return HttpResponse::NotFound().finish();
+            Err(error) => return error.to_response(),
         }
-    } else {
-        // Could not find the repository
-        return HttpResponse::NotFound().finish();
     }
-}
 
-#[head("/{account_id}/{repository_id}/{key:.*}")]
-async fn head_object(
-    api_client: web::Data<SourceAPI>,
-    path: web::Path<(String, String, String)>,
-    user_identity: web::ReqData<UserIdentity>,
-) -> impl Responder {
-    let (account_id, repository_id, key) = path.into_inner();
+    // Found the repository, now try to get the object
+    let mut result = client
+        .get_object(
+            key.clone(),
+            byte_range.map(|r| r.to_string()),
+            params.version_id.clone(),
+            params.part_number,
+        )
+        .await;
+    // Ranges not resolved up front are only found unsatisfiable by the
+    // backend, in which case legacy clients get the whole object
+    let is_unsatisfiable = matches!(
+        &result,
+        Err(error) if error.to_response().status() == StatusCode::RANGE_NOT_SATISFIABLE
+    );
+    if is_unsatisfiable && byte_range.is_some() && !conformance.is_strict() {
+        result = client
+            .get_object(
+                key.clone(),
+                None,
+                params.version_id.clone(),
+                params.part_number,
+            )
+            .await;
+    }
 
-    match api_client
-        .get_backend_client(&account_id, &repository_id)
-        .await
-    {
-        Ok(client) => {
-            match api_client
-                .is_authorized(
-                    user_identity.into_inner(),
-                    &account_id,
-                    &repository_id,
-                    RepositoryPermission::Read,
-                )
-                .await
+    match result {
+        Ok(res) => {
+            let object_headers = res.headers();
+            let stream = res.body.map(|result| {
+                result
+                    .map(web::Bytes::from)
+                    .map_err(|e| ErrorInternalServerError(e.to_string()))
+            });
+
+            let streaming_response = StreamingResponse::new(stream, res.content_length);
+
+            // The backend reports the range it served and the object's size,
+            // so its Content-Range is passed through as is
+            let is_range = res.content_range.is_some();
+            let mut response = if is_range {
+                HttpResponse::PartialContent()
+            } else {
+                HttpResponse::Ok()
+            };
+            insert_object_headers(&mut response, object_headers, res.content_length);
+
+            // Checksums cover the whole object, so they are not sent for ranges
+            if let Some(checksum) = res
+                .checksum
+                .filter(|_| !is_range && is_checksum_mode_enabled(headers))
             {
-                Ok(authorized) => {
-                    if !authorized {
-                        return HttpResponse::Unauthorized().finish();
-                    }
-                }
-                Err(_) => return HttpResponse::InternalServerError().finish(),
+                response.insert_header((checksum.algorithm.header_name(), checksum.value));
             }
 
-            match client.head_object(key.clone()).await {
-                Ok(res) => HttpResponse::Ok()
-                    .insert_header(("Content-Type", res.content_type))
-                    .insert_header(("Last-Modified", res.last_modified))
-                    .insert_header(("ETag", res.etag))
-                    .body(BoxBody::new(FakeBody {
-                        size: res.content_length as usize,
-                    })),
-                Err(error) => error.to_response(),
-            }
+            let mut response = response.body(streaming_response);
+            apply_response_overrides(&mut response, params.response_overrides());
+            return response;
         }
-        Err(_) => HttpResponse::NotFound().finish(),
+        Err(error) => error.to_response(),
     }
 }
 
-#[derive(Deserialize)]
-struct ListObjectsV2Query {
-    #[serde(rename = "prefix")]
-    prefix: Option<String>,
-    #[serde(rename = "list-type")]
-    _list_type: u8,
-    #[serde(rename = "max-keys")]
-    max_keys: Option<NonZeroU32>,
-    #[serde(rename = "delimiter")]
-    delimiter: Option<String>,
-    #[serde(rename = "continuation-token")]
-    continuation_token: Option<String>,
-}
+/// Adds the headers describing the object served by a read, whether it comes
+/// from the backend or the range cache.
+fn insert_object_headers(
+    response: &mut HttpResponseBuilder,
+    object_headers: ObjectHeaders,
+    content_length: u64,
+) {
+    response
+        .insert_header(("Content-Type", object_headers.content_type))
+        .insert_header(("Last-Modified", object_headers.last_modified))
+        .insert_header(("Content-Length", content_length.to_string()))
+        .insert_header(("Accept-Ranges", "bytes"))
+        .insert_header(("ETag", object_headers.etag));
 
-#[get("/{account_id}")]
-async fn list_objects(
-    api_client: web::Data<SourceAPI>,
-    info: web::Query<ListObjectsV2Query>,
-    path: web::Path<String>,
-    user_identity: web::ReqData<UserIdentity>,
-) -> impl Responder {
-    let account_id = path.into_inner();
+    if let Some(version_id) = object_headers.version_id {
+        response.insert_header(("x-amz-version-id", version_id));
+    }
+    if let Some(parts_count) = object_headers.parts_count {
+        response.insert_header(("x-amz-mp-parts-count", parts_count));
+    }
+    insert_user_metadata(response, object_headers.metadata);
+    insert_object_lock(response, object_headers.object_lock);
+    insert_server_side_encryption(response, object_headers.server_side_encryption);
 
-    if info.prefix.clone().is_some_and(|s| s.is_empty()) || info.prefix.is_none() {
-        match api_client
-            .get_account(account_id.clone(), (*user_identity).clone())
-            .await
+    if let Some(content_range) = object_headers.content_range {
+        response.insert_header(("Content-Range", content_range));
+    }
+}
+
+/// Checks the `partNumber` of a read, which S3 doesn't allow with a `Range`.
+fn check_part_number(
+    headers: &HeaderMap,
+    key: String,
+    part_number: Option<i64>,
+) -> Result<(), Box<dyn APIError>> {
+    let message = match part_number {
+        Some(part_number) if !(1..=10_000).contains(&part_number) => {
+            "Part number must be an integer between 1 and 10000, inclusive"
+        }
+        Some(_) if headers.contains_key(RANGE) => {
+            "Cannot specify both Range header and partNumber query parameter"
+        }
+        _ => return Ok(()),
+    };
+
+    Err(Box::new(InvalidArgumentError {
+        key,
+        message: message.to_string(),
+    }))
+}
+
+/// Rejects `response-*` overrides on anonymous reads, as S3 does, so that
+/// anonymous clients can't have an object served as e.g. `text/html` from the
+/// proxy's origin.
+fn check_response_overrides(
+    user_identity: &UserIdentity,
+    key: &str,
+    overrides: &[(&'static str, &str)],
+) -> Result<(), HttpResponse> {
+    if overrides.is_empty() || user_identity.api_key.is_some() {
+        return Ok(());
+    }
+
+    Err(InvalidRequestError {
+        key: key.to_string(),
+        message: "Request specific response headers cannot be used for anonymous GET requests."
+            .to_string(),
+    }
+    .to_response())
+}
+
+/// Rejects an operation the repository's backend doesn't support, before any
+/// of its work is done.
+fn check_capability(
+    client: &dyn Repository,
+    key: &str,
+    capability: Capability,
+) -> Result<(), HttpResponse> {
+    if client.capabilities().supports(capability) {
+        Ok(())
+    } else {
+        Err(NotImplementedError {
+            key: key.to_string(),
+            operation: capability.as_str(),
+        }
+        .to_response())
+    }
+}
+
+/// Reserves the memory an operation on `key` buffers, answering with
+/// `503 Slow Down` if the memory budget stays exhausted.
+async fn reserve_memory(
+    memory_budget: &MemoryBudget,
+    key: &str,
+    bytes: u64,
+) -> Result<MemoryReservation, HttpResponse> {
+    memory_budget.reserve(bytes).await.ok_or_else(|| {
+        MemoryBudgetExhaustedError {
+            key: key.to_string(),
+        }
+        .to_response()
+    })
+}
+
+/// Buffers the body of a request on `key` under the memory budget, rejecting
+/// bodies longer than `limit`. Bodies `LoadIdentity` already read to verify
+/// their signature keep the reservation it took for them.
+async fn buffer_body(
+    req: &HttpRequest,
+    memory_budget: &MemoryBudget,
+    key: &str,
+    payload: web::Payload,
+    limit: u64,
+) -> Result<(Bytes, MemoryReservation), HttpResponse> {
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    let reservation = req.extensions_mut().remove::<MemoryReservation>();
+
+    memory_budget
+        .buffer(payload, content_length, limit, reservation)
+        .await
+        .map(|(body, reservation)| (body.freeze(), reservation))
+        .map_err(|error| error.into_api_error(key).to_response())
+}
+
+/// The longest `CompleteMultipartUpload` document, which lists up to 10,000
+/// parts.
+const MAX_COMPLETE_MULTIPART_UPLOAD_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The longest `RestoreRequest` document.
+const MAX_RESTORE_REQUEST_SIZE: u64 = 64 * 1024;
+
+/// The smallest part of a multipart upload other than the last.
+const MIN_PART_SIZE: i64 = 5 * 1024 * 1024;
+
+/// The most parts a multipart upload can have, numbered from 1.
+const MAX_PART_NUMBER: i64 = 10_000;
+
+/// Checks a multipart completion the way S3 does, so malformed completions
+/// fail with a descriptive error rather than an opaque one from the backend:
+/// parts must be numbered from 1 to 10,000 in ascending order, and all but the
+/// last must be at least 5 MiB. Sizes are read by listing the upload's parts;
+/// parts the backend doesn't list are left for it to reject. Returns the size
+/// of the completed object.
+async fn validate_completion(
+    client: &dyn Repository,
+    repository_id: &str,
+    key: &str,
+    upload_id: &str,
+    parts: &[MultipartPart],
+) -> Result<i64, Box<dyn APIError>> {
+    let full_key = format!("{}/{}", repository_id, key);
+
+    let mut previous = 0;
+    for part in parts {
+        if !(1..=MAX_PART_NUMBER).contains(&part.part_number) {
+            return Err(Box::new(InvalidArgumentError {
+                key: full_key,
+                message: format!(
+                    "Part number must be an integer between 1 and {}, inclusive",
+                    MAX_PART_NUMBER
+                ),
+            }));
+        }
+        if part.part_number <= previous {
+            return Err(Box::new(InvalidPartOrderError { key: full_key }));
+        }
+        previous = part.part_number;
+    }
+
+    let mut sizes = HashMap::new();
+    let mut part_number_marker = None;
+    loop {
+        let listing = client
+            .list_parts(
+                key.to_string(),
+                upload_id.to_string(),
+                NonZeroU32::new(1000).unwrap(),
+                part_number_marker,
+            )
+            .await?;
+        sizes.extend(
+            listing
+                .parts
+                .iter()
+                .map(|part| (part.part_number, part.size)),
+        );
+        if !listing.is_truncated {
+            break;
+        }
+        part_number_marker = Some(listing.next_part_number_marker);
+    }
+
+    let size = parts
+        .iter()
+        .filter_map(|part| sizes.get(&part.part_number))
+        .sum();
+
+    let Some((_, leading)) = parts.split_last() else {
+        return Ok(size);
+    };
+    for part in leading {
+        if sizes
+            .get(&part.part_number)
+            .is_some_and(|size| *size < MIN_PART_SIZE)
+        {
+            return Err(Box::new(EntityTooSmallError {
+                key: full_key,
+                part_number: part.part_number,
+            }));
+        }
+    }
+
+    Ok(size)
+}
+
+/// The response to a request `is_authorized` denied, naming why it was denied.
+async fn unauthorized(
+    api_client: &SourceAPI,
+    user_identity: &UserIdentity,
+    account_id: &String,
+    repository_id: &String,
+    permission: RepositoryPermission,
+) -> HttpResponse {
+    UnauthorizedError {
+        key: repository_id.to_string(),
+        reason: api_client
+            .denial_reason(user_identity, account_id, repository_id, permission)
+            .await,
+    }
+    .to_response()
+}
+
+/// Returns whether uploads with a checksum are first checked against the
+/// object they replace (`DEDUP_UPLOADS=true`).
+fn is_dedup_enabled() -> bool {
+    env::var("DEDUP_UPLOADS").is_ok_and(|value| value == "true")
+}
+
+/// Returns whether an upload would write the object the backend already
+/// holds: the same size and checksum, and the same content type and user
+/// metadata. Conditional, Object Lock, tagged and encrypted writes are always
+/// written.
+fn is_duplicate(existing: &HeadObjectResponse, options: &PutObjectOptions, size: u64) -> bool {
+    let (Some(checksum), Some(existing_checksum)) = (&options.checksum, &existing.checksum) else {
+        return false;
+    };
+
+    !options.if_none_match
+        && options.object_lock.mode.is_none()
+        && options.object_lock.legal_hold_status.is_none()
+        && options.tagging.is_none()
+        && !options.server_side_encryption.is_set()
+        && existing.content_length == size
+        && existing_checksum.algorithm == checksum.algorithm
+        && existing_checksum.value == checksum.value
+        && options
+            .content_type
+            .as_ref()
+            .is_none_or(|content_type| *content_type == existing.content_type)
+        && existing.metadata == options.metadata
+}
+
+/// Replaces the headers of a successful GET with those requested with
+/// `response-*` query parameters. Values which aren't valid headers are ignored.
+fn apply_response_overrides(response: &mut HttpResponse, overrides: Vec<(&'static str, &str)>) {
+    if !response.status().is_success() {
+        return;
+    }
+
+    for (name, value) in overrides {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(name), value);
+        }
+    }
+}
+
+/// Serves a metadata range of an object from the range cache, fetching it
+/// from the backend on a miss.
+async fn cached_range_response(
+    client: &dyn Repository,
+    range_cache: &RangeCache,
+    memory_budget: &MemoryBudget,
+    path: &str,
+    key: String,
+    etag: &str,
+    (start, end, total_length): (u64, u64, u64),
+) -> HttpResponse {
+    let range = match range_cache.get(path, etag, start, end).await {
+        Some(range) => range,
+        None => {
+            // Only held while the range is read: once inserted, it counts
+            // towards the range cache's own limit instead, see `RangeCache`
+            let _reservation = match reserve_memory(memory_budget, &key, end - start + 1).await {
+                Ok(reservation) => reservation,
+                Err(response) => return response,
+            };
+            let res = match client
+                .get_object(
+                    key.clone(),
+                    Some(format!("bytes={}-{}", start, end)),
+                    None,
+                    None,
+                )
+                .await
+            {
+                Ok(res) => res,
+                Err(error) => return error.to_response(),
+            };
+            let headers = res.headers();
+
+            let bytes = match read_object_body(res.body).await {
+                Ok(bytes) => bytes,
+                Err(response) => return response,
+            };
+
+            let range = CachedRange {
+                bytes: Bytes::from(bytes),
+                headers,
+            };
+            range_cache.insert(path, start, end, range.clone()).await;
+            range
+        }
+    };
+
+    let mut headers = range.headers;
+    headers
+        .content_range
+        .get_or_insert_with(|| format!("bytes {}-{}/{}", start, end, total_length));
+
+    let mut response = HttpResponse::PartialContent();
+    insert_object_headers(&mut response, headers, range.bytes.len() as u64);
+    response.body(range.bytes)
+}
+
+/// The most objects a prefix statistics request counts.
+const MAX_STATS_OBJECTS: u64 = 100_000;
+
+#[derive(Serialize)]
+struct PrefixStats {
+    prefix: String,
+    object_count: u64,
+    total_size: u64,
+    /// The latest modification of an object under the prefix.
+    last_modified: Option<String>,
+    /// Whether every object was counted, or counting stopped at `MAX_STATS_OBJECTS`.
+    complete: bool,
+}
+
+/// Counts the objects under a folder of a repository (`{key}/`) and their
+/// total size, by walking its listing.
+async fn prefix_stats(client: &dyn Repository, repository_id: &str, key: &str) -> HttpResponse {
+    let prefix = format!("{}/", key.trim_end_matches('/'));
+    let max_keys = NonZeroU32::new(1000).unwrap();
+    let mut stats = PrefixStats {
+        prefix: format!("{}/{}", repository_id, prefix),
+        object_count: 0,
+        total_size: 0,
+        last_modified: None,
+        complete: false,
+    };
+    let mut latest = None;
+    let mut continuation_token = None;
+
+    while stats.object_count < MAX_STATS_OBJECTS {
+        let page = match client
+            .list_objects_v2(prefix.clone(), continuation_token, None, None, max_keys)
+            .await
+        {
+            Ok(page) => page,
+            Err(error) => return error.to_response(),
+        };
+
+        for content in page.contents {
+            stats.object_count += 1;
+            stats.total_size += content.size.max(0) as u64;
+
+            if let Ok(last_modified) = DateTime::parse_from_rfc3339(&content.last_modified) {
+                if latest.is_none_or(|latest| last_modified > latest) {
+                    latest = Some(last_modified);
+                    stats.last_modified = Some(content.last_modified);
+                }
+            }
+        }
+
+        match page.next_continuation_token {
+            Some(token) if page.is_truncated => continuation_token = Some(token),
+            _ => {
+                stats.complete = true;
+                break;
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(stats)
+}
+
+/// The largest range a hash request may cover, `HASH_MAX_BYTES` (default 1 GiB).
+fn hash_max_bytes() -> u64 {
+    env::var("HASH_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(1024 * 1024 * 1024)
+}
+
+#[derive(Serialize)]
+struct RangeHash {
+    key: String,
+    /// The hashed range as a `Content-Range` value, e.g. `bytes 0-99/1000`.
+    range: String,
+    size: u64,
+    /// The hex-encoded SHA-256 of the range.
+    sha256: String,
+}
+
+/// Hashes a range of an object, or the whole object, as it is read from the
+/// backend, so verification tools don't have to download it.
+async fn hash_range(
+    client: &dyn Repository,
+    repository_id: &str,
+    key: String,
+    version_id: Option<String>,
+    byte_range: Option<ByteRange>,
+) -> HttpResponse {
+    let head_res = match client
+        .head_object(key.clone(), version_id.clone(), None)
+        .await
+    {
+        Ok(head_res) => head_res,
+        Err(error) => return error.to_response(),
+    };
+    let full_key = format!("{}/{}", repository_id, key);
+
+    if is_archived(
+        head_res.storage_class.as_deref(),
+        head_res.restore.as_deref(),
+    ) {
+        return InvalidObjectStateError {
+            key: full_key,
+            message: "The object is archived and must be restored before it can be read"
+                .to_string(),
+        }
+        .to_response();
+    }
+
+    let total_length = head_res.content_length;
+    let resolved = match byte_range {
+        Some(byte_range) => match byte_range.resolve(total_length) {
+            Some(resolved) => Some(resolved),
+            None => {
+                return InvalidRangeError {
+                    key,
+                    content_length: total_length,
+                }
+                .to_response()
+            }
+        },
+        None => (total_length > 0).then(|| (0, total_length - 1)),
+    };
+
+    let size = resolved.map_or(0, |(start, end)| end - start + 1);
+    let max_bytes = hash_max_bytes();
+    if size > max_bytes {
+        return HashRangeTooLargeError {
+            key: full_key,
+            max_bytes,
+        }
+        .to_response();
+    }
+
+    let mut hasher = Sha256::new();
+    if let Some((start, end)) = resolved {
+        let range = ByteRange::FromStart {
+            start,
+            end: Some(end),
+        };
+        let mut res = match client
+            .get_object(key, Some(range.to_string()), version_id, None)
+            .await
+        {
+            Ok(res) => res,
+            Err(error) => return error.to_response(),
+        };
+
+        let mut hashed = 0;
+        while let Some(chunk) = res.body.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    hashed += chunk.len() as u64;
+                    hasher.update(&chunk);
+                }
+                Err(error) => {
+                    return BadGatewayError {
+                        key: full_key,
+                        message: error.to_string(),
+                    }
+                    .to_response()
+                }
+            }
+        }
+
+        // A short read would hash something other than the range
+        if hashed != size {
+            return BadGatewayError {
+                key: full_key,
+                message: format!("read {} of {} bytes", hashed, size),
+            }
+            .to_response();
+        }
+    }
+
+    HttpResponse::Ok().json(RangeHash {
+        range: match resolved {
+            Some((start, end)) => format!("bytes {}-{}/{}", start, end, total_length),
+            None => format!("bytes */{}", total_length),
+        },
+        key: full_key,
+        size,
+        sha256: hex::encode(hasher.finalize()),
+    })
+}
+
+/// The most ranges served as a `multipart/byteranges` response.
+const MAX_BYTE_RANGES: usize = 64;
+
+/// Serves several ranges of an object as a `multipart/byteranges` body, with
+/// each range read from the backend as the body is sent.
+fn multi_range_response(
+    client: Box<dyn Repository>,
+    key: String,
+    version_id: Option<String>,
+    head_res: HeadObjectResponse,
+    ranges: Vec<(u64, u64)>,
+) -> HttpResponse {
+    let boundary = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let total_length = head_res.content_length;
+
+    let parts: Vec<(String, u64, u64)> = ranges
+        .into_iter()
+        .enumerate()
+        .map(|(position, (start, end))| {
+            let header = format!(
+                "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                if position == 0 { "" } else { "\r\n" },
+                boundary,
+                head_res.content_type,
+                start,
+                end,
+                total_length
+            );
+            (header, start, end)
+        })
+        .collect();
+    let closing = format!("\r\n--{}--\r\n", boundary);
+
+    let content_length = parts
+        .iter()
+        .map(|(header, start, end)| header.len() as u64 + end - start + 1)
+        .sum::<u64>()
+        + closing.len() as u64;
+
+    let client: Rc<dyn Repository> = Rc::from(client);
+    let body = stream::iter(parts)
+        .then(move |(header, start, end)| {
+            let (client, key, version_id) = (client.clone(), key.clone(), version_id.clone());
+            async move {
+                let header = stream::once(async move { Ok(web::Bytes::from(header)) });
+                match client
+                    .get_object(
+                        key,
+                        Some(format!("bytes={}-{}", start, end)),
+                        version_id,
+                        None,
+                    )
+                    .await
+                {
+                    Ok(res) => header
+                        .chain(res.body.map(|result| {
+                            result.map_err(|e| ErrorInternalServerError(e.to_string()))
+                        }))
+                        .boxed_local(),
+                    Err(error) => {
+                        stream::once(
+                            async move { Err(ErrorInternalServerError(error.to_string())) },
+                        )
+                        .boxed_local()
+                    }
+                }
+            }
+        })
+        .flatten()
+        .chain(stream::once(async move { Ok(web::Bytes::from(closing)) }));
+
+    let mut response = HttpResponse::PartialContent();
+    response
+        .insert_header((
+            "Content-Type",
+            format!("multipart/byteranges; boundary={}", boundary),
+        ))
+        .insert_header(("Last-Modified", head_res.last_modified))
+        .insert_header(("Content-Length", content_length.to_string()))
+        .insert_header(("Accept-Ranges", "bytes"))
+        .insert_header(("ETag", head_res.etag));
+    if let Some(version_id) = head_res.version_id {
+        response.insert_header(("x-amz-version-id", version_id));
+    }
+
+    response.body(StreamingResponse::new(body, content_length))
+}
+
+/// Reads an object, or the given range of it, into memory.
+async fn read_object(
+    client: &dyn Repository,
+    key: String,
+    range: Option<String>,
+) -> Result<Vec<u8>, HttpResponse> {
+    match client.get_object(key, range, None, None).await {
+        Ok(res) => read_object_body(res.body).await,
+        Err(error) => Err(error.to_response()),
+    }
+}
+
+/// Collects a backend response body into memory.
+async fn read_object_body(mut body: BoxedReqwestStream) -> Result<Vec<u8>, HttpResponse> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(chunk) => bytes.extend_from_slice(&chunk),
+            Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Serves a CSV preview (`?preview=csv&rows=N`) or an image thumbnail
+/// (`?thumbnail=N`) of an object.
+///
+/// CSV previews only read the first `CSV_PREVIEW_BYTES` of the object and
+/// thumbnails are limited to images of `MAX_THUMBNAIL_SOURCE_BYTES`, so the
+/// work done per request is bounded. Responses are cacheable and keyed on the
+/// source object's ETag, by shared caches only for public repositories.
+async fn preview_object(
+    client: Box<dyn Repository>,
+    memory_budget: &MemoryBudget,
+    key: String,
+    params: &GetObjectParams,
+    is_public: bool,
+) -> HttpResponse {
+    let head = match client.head_object(key.clone(), None, None).await {
+        Ok(head) => head,
+        Err(error) => return error.to_response(),
+    };
+
+    let (content_type, body, variant) = if let Some(format) = &params.preview {
+        if format != "csv" {
+            return HttpResponse::BadRequest().body("Unsupported preview format");
+        }
+
+        let rows = params
+            .rows
+            .map_or(DEFAULT_PREVIEW_ROWS, |rows| rows.get() as usize)
+            .min(MAX_PREVIEW_ROWS);
+        let partial = head.content_length > CSV_PREVIEW_BYTES;
+        let range = partial.then(|| format!("bytes=0-{}", CSV_PREVIEW_BYTES - 1));
+
+        let _reservation = match reserve_memory(
+            memory_budget,
+            &key,
+            head.content_length.min(CSV_PREVIEW_BYTES),
+        )
+        .await
+        {
+            Ok(reservation) => reservation,
+            Err(response) => return response,
+        };
+
+        let bytes = match read_object(client.as_ref(), key, range).await {
+            Ok(bytes) => bytes,
+            Err(response) => return response,
+        };
+
+        match csv_preview(&bytes, rows, partial).map(|preview| serde_json::to_vec(&preview)) {
+            Ok(Ok(body)) => ("application/json", body, format!("csv-{}", rows)),
+            Ok(Err(_)) => return HttpResponse::InternalServerError().finish(),
+            Err(message) => return HttpResponse::UnprocessableEntity().body(message),
+        }
+    } else {
+        let size = params.thumbnail.unwrap_or(0);
+        if !(1..=MAX_THUMBNAIL_SIZE).contains(&size) {
+            return HttpResponse::BadRequest().body(format!(
+                "thumbnail must be between 1 and {}",
+                MAX_THUMBNAIL_SIZE
+            ));
+        }
+
+        if head.content_length > MAX_THUMBNAIL_SOURCE_BYTES {
+            return HttpResponse::PayloadTooLarge().body("Image is too large to thumbnail");
+        }
+
+        let _reservation = match reserve_memory(memory_budget, &key, head.content_length).await {
+            Ok(reservation) => reservation,
+            Err(response) => return response,
+        };
+
+        let bytes = match read_object(client.as_ref(), key, None).await {
+            Ok(bytes) => bytes,
+            Err(response) => return response,
+        };
+
+        // Decoding is CPU bound, so it is kept off the worker's event loop
+        match web::block(move || thumbnail(&bytes, size)).await {
+            Ok(Ok(body)) => ("image/png", body, format!("thumbnail-{}", size)),
+            Ok(Err(message)) => return HttpResponse::UnprocessableEntity().body(message),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            "Cache-Control",
+            if is_public {
+                "public, max-age=3600"
+            } else {
+                "private, max-age=3600"
+            },
+        ))
+        .insert_header(("Last-Modified", head.last_modified))
+        .insert_header((
+            "ETag",
+            format!("\"{}-{}\"", head.etag.trim_matches('"'), variant),
+        ))
+        .body(body)
+}
+
+/// Records an object written by a copy or multipart upload in the recent writes
+/// registry, returning its consistency token.
+async fn record_written_object(
+    req: &HttpRequest,
+    account_id: &str,
+    repository_id: &str,
+    key: &str,
+    size: i64,
+    etag: String,
+) -> String {
+    let recent_writes = req.app_data::<web::Data<RecentWrites>>().unwrap();
+    let object = written_object(repository_id, key, size, etag);
+
+    recent_writes
+        .record(account_id, repository_id, key, Some(object))
+        .await
+}
+
+#[delete("/{account_id}/{repository_id}/{key:.*}")]
+async fn delete_object(
+    api_client: web::Data<SourceAPI>,
+    upload_registry: web::Data<UploadRegistry>,
+    req: HttpRequest,
+    params: web::Query<DeleteParams>,
+    path: web::Path<(String, String, String)>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let (account_id, repository_id, key) = path.into_inner();
+
+    if let Err(error) = validate_key(&repository_id, &key) {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    let client = match api_client
+        .get_backend_client(
+            &account_id,
+            &repository_id,
+            is_requester_payer(req.headers()),
+        )
+        .await
+    {
+        Ok(client) => client,
+        // The repository doesn't exist, or its data connection is misconfigured
+        Err(error) => return error.to_response(),
+    };
+
+    match api_client
+        .is_authorized(
+            (*user_identity).clone(),
+            &account_id,
+            &repository_id,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        Ok(authorized) => {
+            if !authorized {
+                return unauthorized(
+                    &api_client,
+                    &user_identity,
+                    &account_id,
+                    &repository_id,
+                    RepositoryPermission::Write,
+                )
+                .await;
+            }
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    if let Err(error) = api_client
+        .check_policy(
+            &user_identity,
+            &account_id,
+            &repository_id,
+            &key,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        return error.to_response();
+    }
+
+    let operation = if params.upload_id.is_some() || params.tagging.is_some() {
+        BackendOperation::Write
+    } else {
+        BackendOperation::Delete
+    };
+    if let Err(error) = api_client
+        .check_operation(&account_id, &repository_id, operation)
+        .await
+    {
+        return error.to_response();
+    }
+
+    let capability = if params.tagging.is_some() {
+        Capability::Tagging
+    } else if params.upload_id.is_some() {
+        Capability::MultipartUpload
+    } else {
+        Capability::Delete
+    };
+    if let Err(response) = check_capability(client.as_ref(), &key, capability) {
+        return response;
+    }
+
+    if params.tagging.is_some() {
+        return match client.delete_object_tagging(key).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    if params.upload_id.is_none() {
+        // Found the repository, now try to delete the object
+        match client
+            .delete_object(key.clone(), params.version_id.clone())
+            .await
+        {
+            Ok(res) => {
+                let mut response = HttpResponse::NoContent();
+                if res.delete_marker {
+                    response.insert_header(("x-amz-delete-marker", "true"));
+                }
+                if let Some(version_id) = res.version_id {
+                    response.insert_header(("x-amz-version-id", version_id));
+                }
+                // Deleting an older version leaves the object listed
+                if params.version_id.is_none() {
+                    let recent_writes = req.app_data::<web::Data<RecentWrites>>().unwrap();
+                    let token = recent_writes
+                        .record(&account_id, &repository_id, &key, None)
+                        .await;
+                    response.insert_header((CONSISTENCY_TOKEN_HEADER, token));
+                }
+                return response.finish();
+            }
+            Err(error) => error.to_response(),
+        }
+    } else {
+        match client
+            .abort_multipart_upload(key.clone(), params.upload_id.clone().unwrap())
+            .await
+        {
+            Ok(_) => {
+                upload_registry
+                    .finish(params.upload_id.as_deref().unwrap())
+                    .await;
+                return HttpResponse::NoContent().finish();
+            }
+            Err(error) => error.to_response(),
+        }
+    }
+}
+
+#[put("/{account_id}/{repository_id}/{key:.*}")]
+#[allow(clippy::too_many_arguments)]
+async fn put_object(
+    api_client: web::Data<SourceAPI>,
+    upload_registry: web::Data<UploadRegistry>,
+    memory_budget: web::Data<MemoryBudget>,
+    req: HttpRequest,
+    payload: web::Payload,
+    params: web::Query<PutParams>,
+    path: web::Path<(String, String, String)>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let (account_id, repository_id, key) = path.into_inner();
+
+    if let Err(error) = validate_key(&repository_id, &key) {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    let headers = req.headers();
+
+    let client = match api_client
+        .get_backend_client(
+            &account_id,
+            &repository_id,
+            is_requester_payer(req.headers()),
+        )
+        .await
+    {
+        Ok(client) => client,
+        // The repository doesn't exist, or its data connection is misconfigured
+        Err(error) => return error.to_response(),
+    };
+
+    match api_client
+        .is_authorized(
+            (*user_identity).clone(),
+            &account_id,
+            &repository_id,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        Ok(authorized) => {
+            if !authorized {
+                return unauthorized(
+                    &api_client,
+                    &user_identity,
+                    &account_id,
+                    &repository_id,
+                    RepositoryPermission::Write,
+                )
+                .await;
+            }
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    if let Err(error) = api_client
+        .check_policy(
+            &user_identity,
+            &account_id,
+            &repository_id,
+            &key,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_operation(&account_id, &repository_id, BackendOperation::Write)
+        .await
+    {
+        return error.to_response();
+    }
+
+    // Products have no ACLs, but tools set them after uploads
+    if params.acl.is_some() {
+        return HttpResponse::Ok().finish();
+    }
+
+    let capability = if params.tagging.is_some() {
+        Some(Capability::Tagging)
+    } else if params.part_number.is_some() || params.upload_id.is_some() {
+        Some(Capability::MultipartUpload)
+    } else if headers.contains_key("x-amz-copy-source") {
+        Some(Capability::Copy)
+    } else {
+        None
+    };
+    if let Some(capability) = capability {
+        if let Err(response) = check_capability(client.as_ref(), &key, capability) {
+            return response;
+        }
+    }
+
+    // Only read once the write is authorized, and held until it completes
+    let (bytes, _reservation) =
+        match buffer_body(&req, &memory_budget, &key, payload, MAX_BODY_SIZE).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+
+    if params.tagging.is_some() {
+        let tagging = match from_utf8(&bytes).map(from_str::<Tagging>) {
+            Ok(Ok(tagging)) => tagging,
+            _ => return HttpResponse::BadRequest().finish(),
+        };
+
+        return match client.put_object_tagging(key, tagging).await {
+            Ok(_) => HttpResponse::Ok().finish(),
+            Err(error) => error.to_response(),
+        };
+    }
+
+    if params.part_number.is_none() && params.upload_id.is_none() {
+        if let Some(copy_source) = headers.get("x-amz-copy-source") {
+            let Some((source_account_id, source_repository_id, source_key)) =
+                copy_source.to_str().ok().and_then(parse_copy_source)
+            else {
+                return HttpResponse::BadRequest().finish();
+            };
+
+            // Server-side copies are only possible within a single repository
+            if source_account_id != account_id || source_repository_id != repository_id {
+                return HttpResponse::NotImplemented().finish();
+            }
+
+            if let Err(error) = api_client
+                .check_policy(
+                    &user_identity,
+                    &account_id,
+                    &repository_id,
+                    &source_key,
+                    RepositoryPermission::Read,
+                )
+                .await
+            {
+                return error.to_response();
+            }
+
+            return match client.copy_object(source_key, key.clone()).await {
+                Ok(res) => match to_string_with_root("CopyObjectResult", &res) {
+                    Ok(serialized) => {
+                        let token = record_written_object(
+                            &req,
+                            &account_id,
+                            &repository_id,
+                            &key,
+                            res.size as i64,
+                            res.etag,
+                        )
+                        .await;
+                        HttpResponse::Ok()
+                            .insert_header((CONSISTENCY_TOKEN_HEADER, token))
+                            .content_type("application/xml")
+                            .body(serialized)
+                    }
+                    Err(_) => HttpResponse::InternalServerError().finish(),
+                },
+                Err(error) => error.to_response(),
+            };
+        }
+
+        let checksum = match verify_checksum(headers, &format!("{}/{}", repository_id, key), &bytes)
+        {
+            Ok(checksum) => checksum,
+            Err(error) => return error.to_response(),
+        };
+
+        let metadata = user_metadata_from_headers(headers);
+        if let Err(error) = check_user_metadata(&format!("{}/{}", repository_id, key), &metadata) {
+            return error.to_response();
+        }
+
+        // Found the repository, now try to upload the object
+        let options = PutObjectOptions {
+            content_type: headers
+                .get(CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .map(|s| s.to_string()),
+            if_none_match: headers
+                .get(IF_NONE_MATCH)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|s| s.trim() == "*"),
+            object_lock: object_lock_from_headers(headers),
+            checksum: checksum.clone(),
+            metadata,
+            tagging: headers
+                .get("x-amz-tagging")
+                .and_then(|h| h.to_str().ok())
+                .map(|s| s.to_string()),
+            server_side_encryption: server_side_encryption_from_headers(headers),
+        };
+
+        let write_spool = req.app_data::<web::Data<WriteSpool>>().unwrap();
+        let target = SpoolTarget {
+            account_id: account_id.clone(),
+            repository_id: repository_id.clone(),
+            key: key.clone(),
+            requester_pays: is_requester_payer(headers),
+        };
+
+        // Writes to a key with spooled writes queue behind them, so they
+        // reach the backend in order
+        let recent_writes = req.app_data::<web::Data<RecentWrites>>().unwrap();
+        // Spooled objects have no ETag until they reach the backend
+        let spooled_object =
+            written_object(&repository_id, &key, bytes.len() as i64, "".to_string());
+
+        if write_spool.has_pending(&account_id, &repository_id, &key) {
+            return match write_spool.spool(target, &options, bytes).await {
+                Ok(()) => {
+                    let token = recent_writes
+                        .record(&account_id, &repository_id, &key, Some(spooled_object))
+                        .await;
+                    HttpResponse::Accepted()
+                        .insert_header((CONSISTENCY_TOKEN_HEADER, token))
+                        .finish()
+                }
+                Err(reason) => {
+                    log::warn!(
+                        "Could not spool write of {}/{}: {}",
+                        repository_id,
+                        key,
+                        reason
+                    );
+                    HttpResponse::ServiceUnavailable().finish()
+                }
+            };
+        }
+
+        // Objects the backend already holds aren't written again, so
+        // mirroring pipelines don't pay for re-uploads
+        if is_dedup_enabled() && options.checksum.is_some() {
+            if let Ok(head_res) = client.head_object(key.clone(), None, None).await {
+                if is_duplicate(&head_res, &options, bytes.len() as u64) {
+                    let mut response = HttpResponse::NoContent();
+                    response.insert_header(("x-source-dedup", "hit"));
+                    if let Some(version_id) = head_res.version_id {
+                        response.insert_header(("x-amz-version-id", version_id));
+                    }
+                    if let Some(checksum) = checksum {
+                        response.insert_header((checksum.algorithm.header_name(), checksum.value));
+                    }
+                    return response.finish();
+                }
+            }
+        }
+
+        match client
+            .put_object(key.clone(), bytes.clone(), options.clone())
+            .await
+        {
+            Ok(res) => {
+                let object = written_object(&repository_id, &key, bytes.len() as i64, res.etag);
+                let token = recent_writes
+                    .record(&account_id, &repository_id, &key, Some(object))
+                    .await;
+
+                let mut response = HttpResponse::NoContent();
+                response.insert_header((CONSISTENCY_TOKEN_HEADER, token));
+                if let Some(version_id) = res.version_id {
+                    response.insert_header(("x-amz-version-id", version_id));
+                }
+                if let Some(checksum) = checksum {
+                    response.insert_header((checksum.algorithm.header_name(), checksum.value));
+                }
+                response.finish()
+            }
+
+            Err(error) if write_spool.is_spoolable(error.as_ref()) => {
+                match write_spool.spool(target, &options, bytes).await {
+                    Ok(()) => {
+                        let token = recent_writes
+                            .record(&account_id, &repository_id, &key, Some(spooled_object))
+                            .await;
+                        HttpResponse::Accepted()
+                            .insert_header((CONSISTENCY_TOKEN_HEADER, token))
+                            .finish()
+                    }
+                    Err(reason) => {
+                        log::warn!(
+                            "Could not spool write of {}/{}: {}",
+                            repository_id,
+                            key,
+                            reason
+                        );
+                        error.to_response()
+                    }
+                }
+            }
+
+            Err(error) => error.to_response(),
+        }
+    } else if let (Some(upload_id), Some(part_number)) =
+        (params.upload_id.as_deref(), params.part_number)
+    {
+        let size = bytes.len() as u64;
+
+        // Part checksums are verified here, as the backend cannot be sent them
+        let checksum = match verify_checksum(headers, &format!("{}/{}", repository_id, key), &bytes)
+        {
+            Ok(checksum) => checksum,
+            Err(error) => return error.to_response(),
+        };
+
+        match client
+            .upload_multipart_part(key.clone(), upload_id.to_string(), part_number, bytes)
+            .await
+        {
+            Ok(res) => {
+                upload_registry
+                    .record_part(upload_id, part_number, size)
+                    .await;
+
+                let mut response = HttpResponse::Ok();
+                response.insert_header(("ETag", res.etag));
+                if let Some(checksum) = checksum {
+                    response.insert_header((checksum.algorithm.header_name(), checksum.value));
+                }
+                response.finish()
+            }
+
+            Err(error) => error.to_response(),
+        }
+    } else {
+        // UploadPart needs both the upload and the part it writes
+        InvalidArgumentError {
+            key: format!("{}/{}", repository_id, key),
+            message: "partNumber and uploadId must be sent together".to_string(),
+        }
+        .to_response()
+    }
+}
+
+#[post("/{account_id}/{repository_id}/{key:.*}")]
+#[allow(clippy::too_many_arguments)]
+async fn post_handler(
+    api_client: web::Data<SourceAPI>,
+    upload_registry: web::Data<UploadRegistry>,
+    memory_budget: web::Data<MemoryBudget>,
+    req: HttpRequest,
+    params: web::Query<PostParams>,
+    payload: web::Payload,
+    path: web::Path<(String, String, String)>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let (account_id, repository_id, key) = path.into_inner();
+
+    if let Err(error) = validate_key(&repository_id, &key) {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    let headers = req.headers();
+
+    let client = match api_client
+        .get_backend_client(
+            &account_id,
+            &repository_id,
+            is_requester_payer(req.headers()),
+        )
+        .await
+    {
+        Ok(client) => client,
+        // The repository doesn't exist, or its data connection is misconfigured
+        Err(error) => return error.to_response(),
+    };
+
+    match api_client
+        .is_authorized(
+            (*user_identity).clone(),
+            &account_id,
+            &repository_id,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        Ok(authorized) => {
+            if !authorized {
+                return unauthorized(
+                    &api_client,
+                    &user_identity,
+                    &account_id,
+                    &repository_id,
+                    RepositoryPermission::Write,
+                )
+                .await;
+            }
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    if let Err(error) = api_client
+        .check_policy(
+            &user_identity,
+            &account_id,
+            &repository_id,
+            &key,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_operation(&account_id, &repository_id, BackendOperation::Write)
+        .await
+    {
+        return error.to_response();
+    }
+
+    let capability = if params.uploads.is_some() || params.upload_id.is_some() {
+        Some(Capability::MultipartUpload)
+    } else if params.restore.is_some() {
+        Some(Capability::Restore)
+    } else {
+        None
+    };
+    if let Some(capability) = capability {
+        if let Err(response) = check_capability(client.as_ref(), &key, capability) {
+            return response;
+        }
+    }
+
+    if params.uploads.is_some() {
+        match client
+            .create_multipart_upload(
+                key.clone(),
+                headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|h| h.to_str().ok())
+                    .map(|s| s.to_string()),
+                object_lock_from_headers(headers),
+                server_side_encryption_from_headers(headers),
+                headers
+                    .get("x-amz-tagging")
+                    .and_then(|h| h.to_str().ok())
+                    .map(|s| s.to_string()),
+            )
+            .await
+        {
+            Ok(res) => match to_string_with_root("InitiateMultipartUploadResult", &res) {
+                Ok(serialized) => {
+                    if let Some(api_key) = user_identity.into_inner().api_key {
+                        upload_registry
+                            .start(
+                                api_key.access_key_id,
+                                account_id,
+                                repository_id,
+                                key,
+                                res.upload_id.clone(),
+                            )
+                            .await;
+                    }
+
+                    return HttpResponse::Ok()
+                        .content_type("application/xml")
+                        .body(serialized);
+                }
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            },
+            Err(error) => {
+                return error.to_response();
+            }
+        }
+    } else if params.upload_id.is_some() {
+        let (body, _reservation) = match buffer_body(
+            &req,
+            &memory_budget,
+            &key,
+            payload,
+            MAX_COMPLETE_MULTIPART_UPLOAD_SIZE,
+        )
+        .await
+        {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let Ok(body) = from_utf8(&body) else {
+            return HttpResponse::BadRequest().body("Invalid UTF-8");
+        };
+
+        match from_str::<CompleteMultipartUpload>(body) {
+            Ok(upload) => {
+                let size = match validate_completion(
+                    client.as_ref(),
+                    &repository_id,
+                    &key,
+                    params.upload_id.as_deref().unwrap(),
+                    &upload.parts,
+                )
+                .await
+                {
+                    Ok(size) => size,
+                    Err(error) => return error.to_response(),
+                };
+
+                match client
+                    .complete_multipart_upload(
+                        key.clone(),
+                        params.upload_id.clone().unwrap(),
+                        upload.parts,
+                    )
+                    .await
+                {
+                    // Backends report their own bucket and key, and not
+                    // all of them quote the composite ETag
+                    Ok(res) => match to_string_with_root(
+                        "CompleteMultipartUploadResult",
+                        &CompleteMultipartUploadResponse {
+                            location: object_url(
+                                &req,
+                                &account_id,
+                                &format!("{}/{}", repository_id, key),
+                            ),
+                            bucket: account_id.clone(),
+                            key: format!("{}/{}", repository_id, key),
+                            etag: quote_etag(&res.etag),
+                        },
+                    ) {
+                        Ok(serialized) => {
+                            upload_registry
+                                .finish(params.upload_id.as_deref().unwrap())
+                                .await;
+
+                            let token = record_written_object(
+                                &req,
+                                &account_id,
+                                &repository_id,
+                                &key,
+                                size,
+                                res.etag,
+                            )
+                            .await;
+                            return HttpResponse::Ok()
+                                .insert_header((CONSISTENCY_TOKEN_HEADER, token))
+                                .content_type("application/xml")
+                                .body(serialized);
+                        }
+                        Err(_) => return HttpResponse::InternalServerError().finish(),
+                    },
+                    Err(error) => {
+                        return error.to_response();
+                    }
+                }
+            }
+            Err(_) => {
+                return HttpResponse::BadRequest().finish();
+            }
+        }
+    } else if params.restore.is_some() {
+        let (body, _reservation) = match buffer_body(
+            &req,
+            &memory_budget,
+            &key,
+            payload,
+            MAX_RESTORE_REQUEST_SIZE,
+        )
+        .await
+        {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let Ok(body) = from_utf8(&body) else {
+            return HttpResponse::BadRequest().body("Invalid UTF-8");
+        };
+
+        // The request body is optional
+        let restore = if body.trim().is_empty() {
+            RestoreRequest::default()
+        } else {
+            match from_str::<RestoreRequest>(body) {
+                Ok(restore) => restore,
+                Err(_) => return HttpResponse::BadRequest().finish(),
+            }
+        };
+
+        return match client
+            .restore_object(
+                key,
+                restore.days,
+                restore
+                    .glacier_job_parameters
+                    .map(|parameters| parameters.tier),
+            )
+            .await
+        {
+            Ok(()) => HttpResponse::Accepted().finish(),
+            Err(error) => error.to_response(),
+        };
+    } else {
+        return HttpResponse::NotFound().finish();
+    }
+}
+
+/// Handles the S3 `DeleteObjects` operation (`POST /{account_id}?delete`).
+///
+/// Keys are of the form `{repository_id}/{key}`, so a single request may span
+/// several repositories. Each repository is resolved and authorized once, and
+/// failures are reported per key in the `DeleteResult` body.
+#[post("/{account_id}")]
+async fn delete_objects(
+    api_client: web::Data<SourceAPI>,
+    memory_budget: web::Data<MemoryBudget>,
+    req: HttpRequest,
+    params: web::Query<BucketPostParams>,
+    payload: web::Payload,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let account_id = path.into_inner();
+
+    if params.delete.is_none() {
+        if is_form_upload(req.headers()) {
+            // Form uploads carry their credentials in the body, so it is read
+            // before they are authorized
+            let (bytes, _reservation) = match buffer_body(
+                &req,
+                &memory_budget,
+                &account_id,
+                payload,
+                MAX_BODY_SIZE,
+            )
+            .await
+            {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+            return form_upload(api_client, &req, account_id, bytes).await;
+        }
+        return HttpResponse::NotFound().finish();
+    }
+
+    // Deletions are authorized for the repositories their keys name, so the
+    // body is only read for authenticated callers
+    if user_identity.api_key.is_none() {
+        return AccessDeniedError {
+            key: account_id,
+            message: "Access Denied".to_string(),
+            reason: DenialReason::Credentials,
+        }
+        .to_response();
+    }
+
+    // Held until the deletions complete
+    let (bytes, _reservation) =
+        match buffer_body(&req, &memory_budget, &account_id, payload, MAX_BODY_SIZE).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+
+    let request = match DeleteObjectsRequest::parse(&bytes) {
+        Ok(request) => request,
+        Err(error) => return error.to_response(),
+    };
+
+    let mut clients = HashMap::new();
+    let mut result = DeleteObjectsResult::default();
+
+    for object in request.objects {
+        let (repository_id, key) = split_at_first_slash(&object.key);
+        let repository_id = repository_id.to_string();
+
+        if let Err(error) = validate_key(&repository_id, key) {
+            result.errors.push(DeleteError {
+                key: object.key.clone(),
+                code: "KeyTooLongError".to_string(),
+                message: error.to_string(),
+            });
+            continue;
+        }
+
+        if !clients.contains_key(&repository_id) {
+            let client = match api_client
+                .get_backend_client(
+                    &account_id,
+                    &repository_id,
+                    is_requester_payer(req.headers()),
+                )
+                .await
+            {
+                Ok(client) => match api_client
+                    .is_authorized(
+                        (*user_identity).clone(),
+                        &account_id,
+                        &repository_id,
+                        RepositoryPermission::Write,
+                    )
+                    .await
+                {
+                    Ok(true) => Ok(client),
+                    Ok(false) => Err(("AccessDenied", "Access Denied")),
+                    Err(_) => Err(("InternalError", "Internal Server Error")),
+                },
+                Err(error) if error.to_response().status() == StatusCode::NOT_FOUND => {
+                    Err(("NoSuchKey", "Repository Not Found"))
+                }
+                Err(_) => Err(("InternalError", "Internal Server Error")),
+            };
+            let client = match api_client
+                .check_not_tombstoned(&account_id, &repository_id)
+                .await
+            {
+                Ok(_) => client,
+                Err(_) => Err(("Gone", "Repository Tombstoned")),
+            };
+            let client = match client {
+                Ok(client) => match check_maintenance(
+                    &api_client,
+                    (*user_identity).clone(),
+                    &account_id,
+                    &repository_id,
+                )
+                .await
+                {
+                    Some(_) => Err(("ServiceUnavailable", "Repository In Maintenance")),
+                    None => Ok(client),
+                },
+                Err(error) => Err(error),
+            };
+            clients.insert(repository_id.clone(), client);
+        }
+
+        let outcome = match clients.get(&repository_id).unwrap() {
+            Ok(client) => match api_client
+                .check_policy(
+                    &user_identity,
+                    &account_id,
+                    &repository_id,
+                    key,
+                    RepositoryPermission::Write,
+                )
+                .await
+            {
+                Ok(_) => match api_client
+                    .check_operation(&account_id, &repository_id, BackendOperation::Delete)
+                    .await
+                {
+                    Ok(_) => match client
+                        .delete_object(key.to_string(), object.version_id.clone())
+                        .await
+                    {
+                        Ok(res) => Ok(res),
+                        // Object Lock protected objects are rejected with a reason
+                        Err(error) if error.to_response().status() == StatusCode::FORBIDDEN => {
+                            Err(("AccessDenied".to_string(), error.to_string()))
+                        }
+                        Err(error) => Err(("InternalError".to_string(), error.to_string())),
+                    },
+                    Err(error) => Err(("AccessDenied".to_string(), error.to_string())),
+                },
+                Err(error) => Err(("AccessDenied".to_string(), error.to_string())),
+            },
+            Err((code, message)) => Err((code.to_string(), message.to_string())),
+        };
+
+        match outcome {
+            Ok(res) => {
+                if !request.quiet {
+                    result.deleted.push(DeletedObject {
+                        key: object.key,
+                        version_id: object.version_id,
+                        delete_marker: res.delete_marker.then_some(true),
+                        delete_marker_version_id: res.version_id.filter(|_| res.delete_marker),
+                    });
+                }
+            }
+            Err((code, message)) => result.errors.push(DeleteError {
+                key: object.key,
+                code,
+                message,
+            }),
+        }
+    }
+
+    match to_string_with_root("DeleteResult", &result) {
+        Ok(serialized) => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(serialized),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Handles a browser form upload to `POST /{account_id}`.
+///
+/// The form is authenticated by the signature of its policy document rather
+/// than a signed request, and its `key` field holds `{repository_id}/{key}`.
+async fn form_upload(
+    api_client: web::Data<SourceAPI>,
+    req: &HttpRequest,
+    account_id: String,
+    bytes: Bytes,
+) -> HttpResponse {
+    let form = match parse_form(req.headers(), bytes).await {
+        Ok(form) => form,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
+    let Some((repository_id, key)) = form.key().and_then(|full_key| {
+        full_key
+            .split_once('/')
+            .map(|(repository_id, key)| (repository_id.to_string(), key.to_string()))
+    }) else {
+        return HttpResponse::BadRequest().body("Bucket POST must contain a field named 'key'.");
+    };
+
+    let api_key = match load_form_identity(&api_client, &form.fields).await {
+        Ok(api_key) => api_key,
+        Err(message) => {
+            return AccessDeniedError {
+                key: format!("{}/{}", repository_id, key),
+                message,
+                reason: DenialReason::Credentials,
+            }
+            .to_response()
+        }
+    };
+
+    if let Err(reason) = check_policy_document(
+        form.field("policy").unwrap_or(""),
+        &account_id,
+        &form,
+        api_client.clock.now(),
+    ) {
+        return AccessDeniedError {
+            key: format!("{}/{}", repository_id, key),
+            message: format!("Invalid according to Policy: {}", reason),
+            reason: DenialReason::PostPolicy,
+        }
+        .to_response();
+    }
+
+    if let Err(error) = validate_key(&repository_id, &key) {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    let client = match api_client
+        .get_backend_client(
+            &account_id,
+            &repository_id,
+            is_requester_payer(req.headers()),
+        )
+        .await
+    {
+        Ok(client) => client,
+        Err(error) => return error.to_response(),
+    };
+
+    let user_identity = UserIdentity {
+        api_key: Some(api_key),
+    };
+
+    match api_client
+        .is_authorized(
+            user_identity.clone(),
+            &account_id,
+            &repository_id,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return unauthorized(
+                &api_client,
+                &user_identity,
+                &account_id,
+                &repository_id,
+                RepositoryPermission::Write,
+            )
+            .await
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    if let Some(error) = check_maintenance(
+        &api_client,
+        user_identity.clone(),
+        &account_id,
+        &repository_id,
+    )
+    .await
+    {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_policy(
+            &user_identity,
+            &account_id,
+            &repository_id,
+            &key,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_operation(&account_id, &repository_id, BackendOperation::Write)
+        .await
+    {
+        return error.to_response();
+    }
+
+    let metadata = form.metadata();
+    if let Err(error) = check_user_metadata(&format!("{}/{}", repository_id, key), &metadata) {
+        return error.to_response();
+    }
+
+    let options = PutObjectOptions {
+        content_type: form.content_type(),
+        metadata,
+        ..Default::default()
+    };
+
+    let res = match client
+        .put_object(key.clone(), form.file.clone(), options)
+        .await
+    {
+        Ok(res) => res,
+        Err(error) => return error.to_response(),
+    };
+
+    let full_key = format!("{}/{}", repository_id, key);
+    let mut response = match form.field("success_action_redirect") {
+        Some(redirect) if !redirect.is_empty() => {
+            let separator = if redirect.contains('?') { '&' } else { '?' };
+            let query = form_urlencoded::Serializer::new(String::new())
+                .append_pair("bucket", &account_id)
+                .append_pair("key", &full_key)
+                .finish();
+            let mut response = HttpResponse::SeeOther();
+            response.insert_header(("Location", format!("{}{}{}", redirect, separator, query)));
+            response
+        }
+        _ => match form.field("success_action_status") {
+            Some("200") => HttpResponse::Ok(),
+            Some("201") => HttpResponse::Created(),
+            _ => HttpResponse::NoContent(),
+        },
+    };
+
+    if let Some(version_id) = res.version_id {
+        response.insert_header(("x-amz-version-id", version_id));
+    }
+
+    if form.field("success_action_status") == Some("201") {
+        let post_response = PostResponse {
+            location: object_url(req, &account_id, &full_key),
+            bucket: account_id,
+            key: full_key,
+        };
+        return match to_string_with_root("PostResponse", &post_response) {
+            Ok(serialized) => response.content_type("application/xml").body(serialized),
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        };
+    }
+
+    response.finish()
+}
+
+#[head("/{account_id}/{repository_id}/{key:.*}")]
+async fn head_object(
+    api_client: web::Data<SourceAPI>,
+    conformance: web::Data<ConformanceMode>,
+    req: HttpRequest,
+    params: web::Query<HeadObjectParams>,
+    path: web::Path<(String, String, String)>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let (account_id, repository_id, key) = path.into_inner();
+
+    if let Err(error) = validate_key(&repository_id, &key) {
+        return error.to_response();
+    }
+
+    if let Err(response) =
+        check_response_overrides(&user_identity, &key, &params.response_overrides())
+    {
+        return response;
+    }
+
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    match api_client
+        .get_backend_client(
+            &account_id,
+            &repository_id,
+            is_requester_payer(req.headers()),
+        )
+        .await
+    {
+        Ok(client) => {
+            match api_client
+                .is_authorized(
+                    (*user_identity).clone(),
+                    &account_id,
+                    &repository_id,
+                    RepositoryPermission::Read,
+                )
+                .await
+            {
+                Ok(authorized) => {
+                    if !authorized {
+                        return unauthorized(
+                            &api_client,
+                            &user_identity,
+                            &account_id,
+                            &repository_id,
+                            RepositoryPermission::Read,
+                        )
+                        .await;
+                    }
+                }
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            }
+
+            if let Err(error) = api_client
+                .check_policy(
+                    &user_identity,
+                    &account_id,
+                    &repository_id,
+                    &key,
+                    RepositoryPermission::Read,
+                )
+                .await
+            {
+                return error.to_response();
+            }
+
+            if let Err(error) = api_client
+                .check_operation(&account_id, &repository_id, BackendOperation::Read)
+                .await
+            {
+                return error.to_response();
+            }
+
+            if let Err(error) = api_client
+                .check_terms_accepted(user_identity.into_inner(), &account_id, &repository_id)
+                .await
+            {
+                return error.to_response();
+            }
+
+            let byte_range = req
+                .headers()
+                .get(RANGE)
+                .and_then(|h| h.to_str().ok())
+                .and_then(ByteRange::parse);
+
+            if let Err(error) = check_part_number(
+                req.headers(),
+                format!("{}/{}", repository_id, key),
+                params.part_number,
+            ) {
+                return error.to_response();
+            }
+
+            match client
+                .head_object(key.clone(), params.version_id.clone(), params.part_number)
+                .await
+            {
+                Ok(res) => {
+                    if is_not_modified(req.headers(), &res.etag, &res.last_modified) {
+                        return HttpResponse::NotModified()
+                            .insert_header(("Last-Modified", res.last_modified))
+                            .insert_header(("ETag", res.etag))
+                            .finish();
+                    }
+
+                    // Emulate S3, which answers a ranged HEAD with the headers of the partial GET
+                    let resolved = match byte_range {
+                        Some(byte_range) => match byte_range.resolve(res.content_length) {
+                            Some(resolved) => Some(resolved),
+                            None if !conformance.is_strict() => None,
+                            None => {
+                                return InvalidRangeError {
+                                    key,
+                                    content_length: res.content_length,
+                                }
+                                .to_response()
+                            }
+                        },
+                        None => None,
+                    };
+                    let (mut response, content_length) = match resolved {
+                        Some((start, end)) => (HttpResponse::PartialContent(), end - start + 1),
+                        // As with S3, heads of a part describe a partial GET of it
+                        None if params.part_number.is_some() => {
+                            (HttpResponse::PartialContent(), res.content_length)
+                        }
+                        None => (HttpResponse::Ok(), res.content_length),
+                    };
+                    if let Some(parts_count) = res.parts_count {
+                        response.insert_header(("x-amz-mp-parts-count", parts_count));
+                    }
+
+                    // DuckDB and GDAL's vsicurl only issue range requests when
+                    // Accept-Ranges is advertised on the HEAD response
+                    response
+                        .insert_header(("Content-Type", res.content_type))
+                        .insert_header(("Last-Modified", res.last_modified))
+                        .insert_header(("Content-Length", content_length.to_string()))
+                        .insert_header(("Accept-Ranges", "bytes"))
+                        .insert_header(("ETag", res.etag));
+
+                    if let Some(version_id) = res.version_id {
+                        response.insert_header(("x-amz-version-id", version_id));
+                    }
+                    insert_user_metadata(&mut response, res.metadata);
+
+                    insert_object_lock(&mut response, res.object_lock);
+                    insert_server_side_encryption(&mut response, res.server_side_encryption);
+
+                    if let Some(storage_class) = res.storage_class {
+                        response.insert_header(("x-amz-storage-class", storage_class));
+                    }
+                    if let Some(restore) = res.restore {
+                        response.insert_header(("x-amz-restore", restore));
+                    }
+
+                    if let Some(checksum) = res
+                        .checksum
+                        .filter(|_| resolved.is_none() && is_checksum_mode_enabled(req.headers()))
+                    {
+                        response.insert_header((checksum.algorithm.header_name(), checksum.value));
+                    }
+
+                    if let Some((start, end)) = resolved {
+                        response.insert_header((
+                            "Content-Range",
+                            format!("bytes {}-{}/{}", start, end, res.content_length),
+                        ));
+                    }
+
+                    let mut response = response.body(BoxBody::new(FakeBody {
+                        size: content_length as usize,
+                    }));
+                    apply_response_overrides(&mut response, params.response_overrides());
+                    response
+                }
+                Err(error) => error.to_response(),
+            }
+        }
+        Err(error) => error.to_response(),
+    }
+}
+
+/// Handles `HeadBucket`, which SDKs use to check that a bucket exists.
+#[head("/{account_id}")]
+async fn head_bucket(
+    api_client: web::Data<SourceAPI>,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let account_id = path.into_inner();
+
+    match api_client
+        .check_account_exists(&account_id, user_identity.into_inner())
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(error) => error.to_response(),
+    }
+}
+
+/// Handles `HEAD /{account_id}/{repository_id}`, reporting whether the
+/// repository exists and can be read by the caller.
+#[head("/{account_id}/{repository_id}")]
+async fn head_repository(
+    api_client: web::Data<SourceAPI>,
+    path: web::Path<(String, String)>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let (account_id, repository_id) = path.into_inner();
+
+    if let Err(error) = api_client
+        .get_repository_record(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id)
+        .await
+    {
+        return error.to_response();
+    }
+
+    match api_client
+        .is_authorized(
+            (*user_identity).clone(),
+            &account_id,
+            &repository_id,
+            RepositoryPermission::Read,
+        )
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => {
+            unauthorized(
+                &api_client,
+                &user_identity,
+                &account_id,
+                &repository_id,
+                RepositoryPermission::Read,
+            )
+            .await
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Answers CORS preflight requests for object routes.
+#[options("/{account_id}/{repository_id}/{key:.*}")]
+async fn object_preflight(req: HttpRequest) -> impl Responder {
+    preflight_response(&req, OBJECT_METHODS)
+}
+
+/// Answers CORS preflight requests for repository routes.
+#[options("/{account_id}/{repository_id}")]
+async fn repository_preflight(req: HttpRequest) -> impl Responder {
+    preflight_response(&req, REPOSITORY_METHODS)
+}
+
+/// Answers CORS preflight requests for bucket routes.
+#[options("/{account_id}")]
+async fn bucket_preflight(req: HttpRequest) -> impl Responder {
+    preflight_response(&req, BUCKET_METHODS)
+}
+
+/// Answers CORS preflight requests for `ListBuckets`.
+#[options("/")]
+async fn service_preflight(req: HttpRequest) -> impl Responder {
+    preflight_response(&req, SERVICE_METHODS)
+}
+
+#[derive(Serialize)]
+struct UploadProgressResponse {
+    uploads: Vec<UploadSession>,
+}
+
+/// Returns the progress of the caller's in-progress multipart uploads.
+#[get("/_uploads")]
+async fn upload_progress(
+    upload_registry: web::Data<UploadRegistry>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    match &user_identity.api_key {
+        Some(api_key) => HttpResponse::Ok().json(UploadProgressResponse {
+            uploads: upload_registry.sessions_for(&api_key.access_key_id),
+        }),
+        None => HttpResponse::Unauthorized().finish(),
+    }
+}
+
+/// Runs the checks of a job over a prefix of a repository, which only its
+/// admins can start, and returns the client of its backend.
+#[allow(clippy::too_many_arguments)]
+async fn authorize_prefix_job(
+    api_client: &SourceAPI,
+    req: &HttpRequest,
+    user_identity: &UserIdentity,
+    account_id: &String,
+    repository_id: &String,
+    prefix: &str,
+    operation: BackendOperation,
+    capability: Capability,
+) -> Result<Box<dyn Repository>, HttpResponse> {
+    validate_key(repository_id, prefix).map_err(|error| error.to_response())?;
+
+    api_client
+        .check_not_tombstoned(account_id, repository_id)
+        .await
+        .map_err(|error| error.to_response())?;
+
+    let client = api_client
+        .get_backend_client(account_id, repository_id, is_requester_payer(req.headers()))
+        .await
+        .map_err(|error| error.to_response())?;
+
+    match api_client
+        .is_authorized(
+            user_identity.clone(),
+            account_id,
+            repository_id,
+            RepositoryPermission::Admin,
+        )
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(unauthorized(
+                api_client,
+                user_identity,
+                account_id,
+                repository_id,
+                RepositoryPermission::Admin,
+            )
+            .await)
+        }
+        Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+    }
+
+    api_client
+        .check_operation(account_id, repository_id, operation)
+        .await
+        .map_err(|error| error.to_response())?;
+
+    check_capability(client.as_ref(), prefix, capability)?;
+
+    Ok(client)
+}
+
+/// The most objects a prefix job works on at once.
+const PREFIX_JOB_CONCURRENCY: usize = 16;
+
+#[derive(Serialize)]
+struct PrefixJobResponse {
+    job_id: String,
+}
+
+/// Starts deleting every object under a prefix of a repository, for product
+/// admins. The objects are listed and deleted in the background; the returned
+/// job ID reports the progress at `GET /_jobs/{job_id}`.
+#[post("/_jobs/delete-prefix/{account_id}/{repository_id}/{prefix:.*}")]
+async fn delete_prefix(
+    api_client: web::Data<SourceAPI>,
+    prefix_jobs: web::Data<PrefixJobRegistry>,
+    list_cache: web::Data<ListCache>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let (account_id, repository_id, prefix) = path.into_inner();
+
+    let Some(api_key) = user_identity.api_key.clone() else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    // Deleting a whole repository is not a prefix deletion
+    if prefix.is_empty() {
+        return HttpResponse::BadRequest().body("A prefix is required");
+    }
+
+    let client = match authorize_prefix_job(
+        &api_client,
+        &req,
+        &user_identity,
+        &account_id,
+        &repository_id,
+        &prefix,
+        BackendOperation::Delete,
+        Capability::Delete,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    let job = prefix_jobs
+        .start(
+            "delete-prefix",
+            api_key.access_key_id,
+            account_id.clone(),
+            repository_id.clone(),
+            prefix.clone(),
+        )
+        .await;
+    let job_id = job.job_id();
+
+    let user_identity = user_identity.into_inner();
+    actix_web::rt::spawn(async move {
+        let repository_prefix = format!("{}/", repository_id);
+        let max_keys = NonZeroU32::new(1000).unwrap();
+        let mut continuation_token = None;
+
+        loop {
+            let page = match client
+                .list_objects_v2(prefix.clone(), continuation_token, None, None, max_keys)
+                .await
+            {
+                Ok(page) => page,
+                Err(error) => return job.finish(Some(error.to_string())),
+            };
+
+            let keys: Vec<String> = page
+                .contents
+                .iter()
+                .filter_map(|content| content.key.strip_prefix(&repository_prefix))
+                .map(|key| key.to_string())
+                .collect();
+            job.record_listed(keys.len() as u64);
+
+            // Policies may deny writes to some of the keys under the prefix
+            stream::iter(keys)
+                .for_each_concurrent(PREFIX_JOB_CONCURRENCY, |key| {
+                    let (api_client, client, job) = (&api_client, &client, &job);
+                    let (user_identity, account_id, repository_id) =
+                        (&user_identity, &account_id, &repository_id);
+                    async move {
+                        let outcome = match api_client
+                            .check_policy(
+                                user_identity,
+                                account_id,
+                                repository_id,
+                                &key,
+                                RepositoryPermission::Write,
+                            )
+                            .await
+                        {
+                            Ok(_) => client.delete_object(key.clone(), None).await.map(|_| ()),
+                            Err(error) => Err(error),
+                        };
+
+                        match outcome {
+                            Ok(()) => job.record_deleted(),
+                            Err(error) => job.record_failed(&key, error.to_string()),
+                        }
+                    }
+                })
+                .await;
+            // Jobs run outside of requests, so their deletions are evicted here
+            list_cache.invalidate(&account_id, Some(&repository_id));
+
+            match page.next_continuation_token {
+                Some(token) if page.is_truncated => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+
+        log::info!(
+            "Deleted prefix {}/{}/{} (job {})",
+            account_id,
+            repository_id,
+            prefix,
+            job.job_id()
+        );
+        job.finish(None);
+    });
+
+    HttpResponse::Accepted().json(PrefixJobResponse { job_id })
+}
+
+/// Starts rewriting the content headers and user metadata of every object
+/// under a prefix of a repository, for product admins, e.g. to fix the
+/// `Content-Type` or `Cache-Control` of a whole upload. Objects are copied
+/// onto themselves in the background with the headers of the JSON body; the
+/// returned job ID reports the progress at `GET /_jobs/{job_id}`.
+#[post("/_jobs/update-metadata/{account_id}/{repository_id}/{prefix:.*}")]
+async fn update_metadata(
+    api_client: web::Data<SourceAPI>,
+    prefix_jobs: web::Data<PrefixJobRegistry>,
+    list_cache: web::Data<ListCache>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    replacement: web::Json<MetadataReplacement>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let (account_id, repository_id, prefix) = path.into_inner();
+
+    let Some(api_key) = user_identity.api_key.clone() else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    let client = match authorize_prefix_job(
+        &api_client,
+        &req,
+        &user_identity,
+        &account_id,
+        &repository_id,
+        &prefix,
+        BackendOperation::Write,
+        Capability::Copy,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    if let Some(metadata) = &replacement.metadata {
+        if let Err(error) = check_user_metadata(&format!("{}/{}", repository_id, prefix), metadata)
+        {
+            return error.to_response();
+        }
+    }
+
+    let job = prefix_jobs
+        .start(
+            "update-metadata",
+            api_key.access_key_id,
+            account_id.clone(),
+            repository_id.clone(),
+            prefix.clone(),
+        )
+        .await;
+    let job_id = job.job_id();
+
+    let replacement = replacement.into_inner();
+    let user_identity = user_identity.into_inner();
+    actix_web::rt::spawn(async move {
+        let repository_prefix = format!("{}/", repository_id);
+        let max_keys = NonZeroU32::new(1000).unwrap();
+        let mut continuation_token = None;
+
+        loop {
+            let page = match client
+                .list_objects_v2(prefix.clone(), continuation_token, None, None, max_keys)
+                .await
+            {
+                Ok(page) => page,
+                Err(error) => return job.finish(Some(error.to_string())),
+            };
+
+            let keys: Vec<String> = page
+                .contents
+                .iter()
+                .filter_map(|content| content.key.strip_prefix(&repository_prefix))
+                .map(|key| key.to_string())
+                .collect();
+            job.record_listed(keys.len() as u64);
+
+            stream::iter(keys)
+                .for_each_concurrent(PREFIX_JOB_CONCURRENCY, |key| {
+                    let (api_client, client, job) = (&api_client, &client, &job);
+                    let (user_identity, account_id, repository_id, replacement) =
+                        (&user_identity, &account_id, &repository_id, &replacement);
+                    async move {
+                        let outcome = match api_client
+                            .check_policy(
+                                user_identity,
+                                account_id,
+                                repository_id,
+                                &key,
+                                RepositoryPermission::Write,
+                            )
+                            .await
+                        {
+                            Ok(_) => client
+                                .replace_metadata(key.clone(), replacement.clone())
+                                .await
+                                .map(|_| ()),
+                            Err(error) => Err(error),
+                        };
+
+                        match outcome {
+                            Ok(()) => job.record_updated(),
+                            Err(error) => job.record_failed(&key, error.to_string()),
+                        }
+                    }
+                })
+                .await;
+            // Copies change the ETags of listed objects
+            list_cache.invalidate(&account_id, Some(&repository_id));
+
+            match page.next_continuation_token {
+                Some(token) if page.is_truncated => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+
+        log::info!(
+            "Updated metadata under {}/{}/{} (job {})",
+            account_id,
+            repository_id,
+            prefix,
+            job.job_id()
+        );
+        job.finish(None);
+    });
+
+    HttpResponse::Accepted().json(PrefixJobResponse { job_id })
+}
+
+/// Returns the progress of one of the caller's prefix jobs.
+#[get("/_jobs/{job_id}")]
+async fn job_progress(
+    prefix_jobs: web::Data<PrefixJobRegistry>,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let Some(api_key) = &user_identity.api_key else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    match prefix_jobs
+        .get(&path.into_inner(), &api_key.access_key_id)
+        .await
+    {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[derive(Serialize)]
+struct SessionTokenResponse {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: String,
+}
+
+/// Issues temporary credentials derived from the caller's API key, scoped to
+/// a single repository.
+///
+/// The credentials carry the caller's permissions on that repository only and
+/// expire after `duration_seconds` (default one hour). They cannot be used to
+/// issue further credentials.
+#[post("/_auth/session-token")]
+async fn issue_session_token(
+    api_client: web::Data<SourceAPI>,
+    params: web::Query<SessionTokenParams>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let Some(api_key) = user_identity.api_key.clone() else {
+        return AccessDeniedError {
+            key: params.repository_id.clone(),
+            message: "Session tokens are only issued to signed requests".to_string(),
+            reason: DenialReason::Credentials,
+        }
+        .to_response();
+    };
+
+    if api_key.session.is_some() {
+        return AccessDeniedError {
+            key: params.repository_id.clone(),
+            message: "Temporary credentials cannot issue session tokens".to_string(),
+            reason: DenialReason::Credentials,
+        }
+        .to_response();
+    }
+
+    let duration = Duration::from_secs(params.duration_seconds.unwrap_or(60 * 60));
+    if duration < Duration::from_secs(15 * 60) || duration > MAX_SESSION_DURATION {
+        return HttpResponse::BadRequest().body(format!(
+            "duration_seconds must be between 900 and {}",
+            MAX_SESSION_DURATION.as_secs()
+        ));
+    }
+
+    match api_client
+        .is_authorized(
+            (*user_identity).clone(),
+            &params.account_id,
+            &params.repository_id,
+            RepositoryPermission::Read,
+        )
+        .await
+    {
+        Ok(authorized) => {
+            if !authorized {
+                return unauthorized(
+                    &api_client,
+                    &user_identity,
+                    &params.account_id,
+                    &params.repository_id,
+                    RepositoryPermission::Read,
+                )
+                .await;
+            }
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    let credentials = api_client
+        .issue_session_credentials(api_key, &params.account_id, &params.repository_id, duration)
+        .await;
+    let (Some(session_token), Some(session)) = (credentials.session_token, credentials.session)
+    else {
+        return HttpResponse::InternalServerError().finish();
+    };
+
+    HttpResponse::Ok().json(SessionTokenResponse {
+        access_key_id: credentials.access_key_id,
+        secret_access_key: credentials.secret_access_key,
+        session_token,
+        expiration: session.expiration.to_rfc3339(),
+    })
+}
+
+fn is_location_request(ctx: &GuardContext) -> bool {
+    ctx.head().uri.query().is_some_and(|query| {
+        form_urlencoded::parse(query.as_bytes()).any(|(name, _)| name == "location")
+    })
+}
+
+/// Handles `GetBucketLocation`, reporting the region of the account's data.
+///
+/// Accounts map to buckets but can span several repositories, so the region
+/// of the first repository backed by S3 is reported.
+#[get("/{account_id}", guard = "is_location_request")]
+async fn get_bucket_location(
+    api_client: web::Data<SourceAPI>,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let account_id = path.into_inner();
+
+    if let Err(error) = api_client
+        .check_account_exists(&account_id, (*user_identity).clone())
+        .await
+    {
+        return error.to_response();
+    }
+
+    let Ok(account) = api_client
+        .get_account(account_id.clone(), user_identity.into_inner(), false)
+        .await
+    else {
+        return HttpResponse::InternalServerError().finish();
+    };
+
+    let mut region = None;
+    for repository_id in account.repositories.iter() {
+        if let Ok(Some(repository_region)) = api_client
+            .get_repository_region(&account_id, repository_id)
+            .await
+        {
+            region = Some(repository_region);
+            break;
+        }
+    }
+
+    let location = match region {
+        Some(region) if region != "us-east-1" => region,
+        _ => "".to_string(),
+    };
+
+    match to_string_with_root(
+        "LocationConstraint",
+        &LocationConstraint {
+            xmlns: "http://s3.amazonaws.com/doc/2006-03-01/".to_string(),
+            location,
+        },
+    ) {
+        Ok(serialized) => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(serialized),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+fn is_acl_request(ctx: &GuardContext) -> bool {
+    ctx.head().uri.query().is_some_and(|query| {
+        form_urlencoded::parse(query.as_bytes()).any(|(name, _)| name == "acl")
+    })
+}
+
+/// Serializes the canned ACL of an account's data.
+fn acl_response(account_id: &str, public: bool) -> HttpResponse {
+    match to_string_with_root(
+        "AccessControlPolicy",
+        &AccessControlPolicy::canned(account_id, public),
+    ) {
+        Ok(serialized) => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(serialized),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Handles `GetBucketAcl` with a canned ACL, which is `public-read` if any of
+/// the account's repositories is public.
+#[get("/{account_id}", guard = "is_acl_request")]
+async fn get_bucket_acl(
+    api_client: web::Data<SourceAPI>,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let account_id = path.into_inner();
+
+    if let Err(error) = api_client
+        .check_account_exists(&account_id, (*user_identity).clone())
+        .await
+    {
+        return error.to_response();
+    }
+
+    let Ok(account) = api_client
+        .get_account(account_id.clone(), user_identity.into_inner(), false)
+        .await
+    else {
+        return HttpResponse::InternalServerError().finish();
+    };
+
+    let mut public = false;
+    for repository_id in account.repositories.iter() {
+        if api_client.is_public(&account_id, repository_id).await {
+            public = true;
+            break;
+        }
+    }
+
+    acl_response(&account_id, public)
+}
+
+/// Handles `PutBucketAcl`, which is accepted but ignored, as accounts have no
+/// ACLs.
+#[put("/{account_id}", guard = "is_acl_request")]
+async fn put_bucket_acl(
+    api_client: web::Data<SourceAPI>,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let account_id = path.into_inner();
+
+    if let Err(error) = api_client
+        .check_account_exists(&account_id, user_identity.into_inner())
+        .await
+    {
+        return error.to_response();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+fn is_versioning_request(ctx: &GuardContext) -> bool {
+    ctx.head().uri.query().is_some_and(|query| {
+        form_urlencoded::parse(query.as_bytes()).any(|(name, _)| name == "versioning")
+    })
+}
+
+/// Handles `GetBucketVersioning`, reporting the versioning state of the
+/// backends of the account's repositories.
+///
+/// Versioning is `Enabled` only if it is enabled on every backend, so clients
+/// don't rely on versions some repositories don't keep. It is `Suspended` if
+/// only some backends are or were versioned, and unset if none ever were.
+#[get("/{account_id}", guard = "is_versioning_request")]
+async fn get_bucket_versioning(
+    api_client: web::Data<SourceAPI>,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let account_id = path.into_inner();
+
+    if let Err(error) = api_client
+        .check_account_exists(&account_id, (*user_identity).clone())
+        .await
+    {
+        return error.to_response();
+    }
+
+    let Ok(account) = api_client
+        .get_account(account_id.clone(), user_identity.into_inner(), false)
+        .await
+    else {
+        return HttpResponse::InternalServerError().finish();
+    };
+
+    let mut statuses = Vec::new();
+    for repository_id in account.repositories.iter() {
+        let Ok(client) = api_client
+            .get_backend_client(&account_id, repository_id, false)
+            .await
+        else {
+            continue;
+        };
+        match client.get_bucket_versioning().await {
+            Ok(status) => statuses.push(status),
+            Err(error) => return error.to_response(),
+        }
+    }
+
+    let status = if !statuses.is_empty()
+        && statuses
+            .iter()
+            .all(|status| status.as_deref() == Some("Enabled"))
+    {
+        Some("Enabled".to_string())
+    } else if statuses.iter().any(|status| status.is_some()) {
+        Some("Suspended".to_string())
+    } else {
+        None
+    };
+
+    match to_string_with_root(
+        "VersioningConfiguration",
+        &VersioningConfiguration {
+            xmlns: "http://s3.amazonaws.com/doc/2006-03-01/".to_string(),
+            status,
+        },
+    ) {
+        Ok(serialized) => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(serialized),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[get("/{account_id}")]
+async fn list_objects(
+    api_client: web::Data<SourceAPI>,
+    listing_slots: web::Data<ListingSlots>,
+    list_cache: web::Data<ListCache>,
+    req: HttpRequest,
+    info: web::Query<ListObjectsV2Params>,
+    path: web::Path<String>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let account_id = path.into_inner();
+
+    let limits = api_client.get_service_limits(&account_id).await;
+    let mut max_keys = NonZeroU32::new(1000).unwrap();
+    if let Some(mk) = info.max_keys {
+        max_keys = mk;
+    }
+    if let Some(max_page_size) = limits.max_page_size.and_then(NonZeroU32::new) {
+        max_keys = max_keys.min(max_page_size);
+    }
+
+    if info.prefix.clone().is_some_and(|s| s.is_empty()) || info.prefix.is_none() {
+        // Account listings leave out the repositories the caller may not list,
+        // rather than being authorized up front
+        let Some(_listing_slot) =
+            listing_slots.try_acquire(&account_id, limits.max_concurrent_listings)
+        else {
+            return SlowDownError { account_id }.to_response();
+        };
+
+        // Recursive listings of the account span all of its repositories
+        if info.delimiter.is_none() {
+            return list_account_objects(
+                &api_client,
+                &list_cache,
+                &req,
+                &info,
+                &account_id,
+                &user_identity,
+                max_keys,
+            )
+            .await;
+        }
+
+        let Ok((position, start_after)) =
+            decode_account_position(&info, info.include_members.unwrap_or(false))
+        else {
+            return HttpResponse::BadRequest().finish();
+        };
+
+        match api_client
+            .get_account(
+                account_id.clone(),
+                (*user_identity).clone(),
+                info.include_members.unwrap_or(false),
+            )
+            .await
         {
             Ok(account) => {
-                let repositories = account.repositories;
-                let mut common_prefixes = Vec::new();
-                for repository_id in repositories.iter() {
-                    common_prefixes.push(CommonPrefix {
-                        prefix: format!("{}/", repository_id.clone()),
+                let mut repositories = account.repositories;
+                repositories.extend(account.member_repositories.into_iter().map(
+                    |(account_id, repository_id)| format!("{}/{}", account_id, repository_id),
+                ));
+                let mut is_truncated = false;
+                // Pages list at most `max_products_listed` repositories
+                let page_size = account
+                    .limits
+                    .max_products_listed
+                    .map_or(max_keys.get(), |max| max_keys.get().min(max.max(1)));
+
+                let mut repository_prefixes: Vec<String> = repositories
+                    .iter()
+                    .map(|repository_id| format!("{}/", repository_id))
+                    .collect();
+                repository_prefixes.sort();
+
+                // Pages resume at the repository named by the token, and a
+                // repository is listed after a start key within it
+                let mut repository_prefixes =
+                    repository_prefixes.into_iter().filter(|repository_prefix| {
+                        position.as_ref().is_none_or(|position| {
+                            *repository_prefix >= format!("{}/", position.repository_id)
+                        }) && start_after.as_ref().is_none_or(|key| {
+                            key < repository_prefix || key.starts_with(repository_prefix.as_str())
+                        })
                     });
-                }
+
+                let common_prefixes: Vec<CommonPrefix> = repository_prefixes
+                    .by_ref()
+                    .take(page_size as usize)
+                    .map(|prefix| CommonPrefix { prefix })
+                    .collect();
+                let next_continuation_token = repository_prefixes.next().map(|repository_prefix| {
+                    is_truncated = true;
+                    AccountListContinuation {
+                        repository_id: repository_prefix.trim_end_matches('/').to_string(),
+                        token: None,
+                    }
+                    .encode()
+                });
+
                 let list_response = ListBucketResult {
                     name: account_id.clone(),
                     prefix: "/".to_string(),
-                    key_count: 0,
-                    max_keys: 0,
-                    is_truncated: false,
+                    delimiter: None,
+                    start_after: None,
+                    key_count: common_prefixes.len() as i64,
+                    max_keys: max_keys.get() as i64,
+                    is_truncated,
                     contents: vec![],
                     common_prefixes,
-                    next_continuation_token: None,
+                    continuation_token: None,
+                    next_continuation_token,
                 };
 
-                match to_string_with_root("ListBucketResult", &list_response) {
-                    Ok(serialized) => {
-                        return HttpResponse::Ok()
-                            .content_type("application/xml")
-                            .body(serialized)
-                    }
-                    Err(_) => return HttpResponse::InternalServerError().finish(),
-                }
+                return list_objects_response(list_response, &info, &account_id);
+            }
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        }
+    }
+
+    let mut path_prefix = info.prefix.clone().unwrap_or("".to_string());
+
+    // With `include-members`, an organization's `{member}/{repository}/`
+    // prefixes name the repositories of its member accounts, before any of
+    // its own repository named like the member
+    let organization_id = account_id.clone();
+    let mut account_id = account_id;
+    let mut member_prefix = String::new();
+    if info.include_members.unwrap_or(false) {
+        let member_account_ids = match api_client.get_member_account_ids(&account_id).await {
+            Ok(member_account_ids) => member_account_ids,
+            Err(error) => return error.to_response(),
+        };
+        let (member_account_id, member_path) = split_at_first_slash(&path_prefix);
+        if !member_path.is_empty()
+            && member_account_ids
+                .iter()
+                .any(|member| member == member_account_id)
+        {
+            member_prefix = format!("{}/", member_account_id);
+            account_id = member_account_id.to_string();
+            path_prefix = member_path.to_string();
+        }
+    }
+
+    let (repository_id, prefix) = split_at_first_slash(&path_prefix);
+
+    if let Err(error) = api_client
+        .check_not_tombstoned(&account_id, &repository_id.to_string())
+        .await
+    {
+        return error.to_response();
+    }
+
+    let client = match api_client
+        .get_backend_client(
+            &account_id,
+            &repository_id.to_string(),
+            is_requester_payer(req.headers()),
+        )
+        .await
+    {
+        Ok(client) => client,
+        // The repository doesn't exist, or its data connection is misconfigured
+        Err(error) => return error.to_response(),
+    };
+
+    match api_client
+        .is_authorized(
+            (*user_identity).clone(),
+            &account_id,
+            &repository_id.to_string(),
+            RepositoryPermission::Read,
+        )
+        .await
+    {
+        Ok(authorized) => {
+            if !authorized {
+                return unauthorized(
+                    &api_client,
+                    &user_identity,
+                    &account_id,
+                    &repository_id.to_string(),
+                    RepositoryPermission::Read,
+                )
+                .await;
             }
-            Err(_) => return HttpResponse::InternalServerError().finish(),
         }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
     }
 
-    let path_prefix = info.prefix.clone().unwrap_or("".to_string());
-
-    let (repository_id, prefix) = split_at_first_slash(&path_prefix);
+    if let Err(error) = api_client
+        .check_policy(
+            &user_identity,
+            &account_id,
+            &repository_id.to_string(),
+            prefix,
+            RepositoryPermission::Read,
+        )
+        .await
+    {
+        return error.to_response();
+    }
 
-    let mut max_keys = NonZeroU32::new(1000).unwrap();
-    if let Some(mk) = info.max_keys {
-        max_keys = mk;
+    if let Err(error) = api_client
+        .check_operation(
+            &account_id,
+            &repository_id.to_string(),
+            BackendOperation::List,
+        )
+        .await
+    {
+        return error.to_response();
     }
 
-    if let Ok(client) = api_client
-        .get_backend_client(&account_id, &repository_id.to_string())
+    if let Err(error) = api_client
+        .check_terms_accepted(
+            user_identity.into_inner(),
+            &account_id,
+            &repository_id.to_string(),
+        )
         .await
     {
-        match api_client
-            .is_authorized(
-                user_identity.into_inner(),
+        return error.to_response();
+    }
+
+    // Acquired once the caller is authorized, against the listed account
+    let Some(_listing_slot) =
+        listing_slots.try_acquire(&organization_id, limits.max_concurrent_listings)
+    else {
+        return SlowDownError {
+            account_id: organization_id,
+        }
+        .to_response();
+    };
+
+    let is_v1 = info.list_type != Some(2);
+
+    // V1 markers are either a NextMarker we issued or the last key of the previous page
+    let (continuation, start_after) = if is_v1 {
+        match info.marker.as_deref() {
+            Some(marker) => match ListContinuation::decode_issued(marker) {
+                Some(continuation) => (continuation, None),
+                None => (ListContinuation::default(), Some(marker.to_string())),
+            },
+            None => (ListContinuation::default(), None),
+        }
+    } else {
+        (
+            ListContinuation::decode(info.continuation_token.clone()),
+            info.start_after.clone(),
+        )
+    };
+
+    // Keys are relative to the repository in the backend
+    let repository_prefix = format!("{}/", repository_id);
+    let (start_after, past_repository) = match start_after {
+        Some(key) => match key.strip_prefix(&format!("{}{}", member_prefix, repository_prefix)) {
+            Some(key) => (Some(key.to_string()), false),
+            // Keys outside the repository sort either before or after all of its keys
+            None => (
+                None,
+                key > format!("{}{}", member_prefix, repository_prefix),
+            ),
+        },
+        None => (None, false),
+    };
+
+    let listing = if past_repository {
+        Ok(ListBucketResult {
+            name: account_id.clone(),
+            prefix: path_prefix.clone(),
+            delimiter: None,
+            start_after: None,
+            key_count: 0,
+            max_keys: 0,
+            is_truncated: false,
+            contents: vec![],
+            common_prefixes: vec![],
+            continuation_token: None,
+            next_continuation_token: None,
+        })
+    } else {
+        let cacheable = !is_requester_payer(req.headers())
+            && api_client
+                .is_public(&account_id, &repository_id.to_string())
+                .await;
+
+        // We're listing within a repository, so we need to query the object store backend
+        list_cache
+            .list(
+                client.as_ref(),
                 &account_id,
-                &repository_id.to_string(),
-                RepositoryPermission::Read,
+                repository_id,
+                &ListPage {
+                    prefix,
+                    continuation_token: continuation.backend_token.as_deref(),
+                    start_after: start_after.as_deref(),
+                    delimiter: info.delimiter.as_deref(),
+                    max_keys,
+                },
+                cacheable,
             )
             .await
-        {
-            Ok(authorized) => {
-                if !authorized {
-                    return HttpResponse::Unauthorized().finish();
-                }
+    };
+
+    let continued = continuation.backend_token.is_some() || continuation.skip > 0;
+
+    match listing {
+        Ok(res) => {
+            let mut result = normalize_list_result(res, continuation, max_keys);
+
+            // Writes made through the proxy which the backend may not list yet
+            if let Some(tokens) = req
+                .headers()
+                .get(AFTER_WRITE_HEADER)
+                .and_then(|h| h.to_str().ok())
+            {
+                let recent_writes = req.app_data::<web::Data<RecentWrites>>().unwrap();
+                let writes = recent_writes
+                    .lookup(tokens, &account_id, repository_id)
+                    .await;
+                let after = start_after.map(|key| format!("{}{}", repository_prefix, key));
+                merge_recent_writes(
+                    &mut result,
+                    &writes,
+                    &path_prefix,
+                    info.delimiter.as_deref(),
+                    after.as_deref(),
+                    continued,
+                );
             }
-            Err(_) => return HttpResponse::InternalServerError().finish(),
+
+            if !member_prefix.is_empty() {
+                prefix_member_listing(&mut result, &organization_id, &member_prefix);
+            }
+
+            list_objects_response(result, &info, &account_id)
         }
+        Err(error) => error.to_response(),
+    }
+    // Found the repository, now make the list objects request
+}
 
-        // We're listing within a repository, so we need to query the object store backend
-        match client
-            .list_objects_v2(
-                prefix.to_string(),
-                info.continuation_token.clone(),
-                info.delimiter.clone(),
-                max_keys,
+/// Turns the listing of a member's repository into the listing of the
+/// organization it was requested from, with keys under `{member}/`.
+fn prefix_member_listing(
+    result: &mut ListBucketResult,
+    organization_id: &str,
+    member_prefix: &str,
+) {
+    result.name = organization_id.to_string();
+    result.prefix = format!("{}{}", member_prefix, result.prefix);
+    for content in result.contents.iter_mut() {
+        content.key = format!("{}{}", member_prefix, content.key);
+    }
+    for common_prefix in result.common_prefixes.iter_mut() {
+        common_prefix.prefix = format!("{}{}", member_prefix, common_prefix.prefix);
+    }
+}
+
+/// Serializes a listing page, echoing the request's parameters.
+fn list_objects_response(
+    mut result: ListBucketResult,
+    info: &ListObjectsV2Params,
+    account_id: &str,
+) -> HttpResponse {
+    let is_v1 = info.list_type != Some(2);
+    result.start_after = info.start_after.clone();
+    result.delimiter = info.delimiter.clone();
+    result.continuation_token = info.continuation_token.clone();
+
+    // As with S3, V1 listings always include owners
+    if is_v1 || info.fetch_owner == Some(true) {
+        let owner = Owner {
+            id: account_id.to_string(),
+            display_name: account_id.to_string(),
+        };
+        for content in result.contents.iter_mut() {
+            content.owner = Some(owner.clone());
+        }
+    }
+
+    let serialized = if is_v1 {
+        to_string_with_root(
+            "ListBucketResult",
+            &result.into_v1(info.marker.clone().unwrap_or_default()),
+        )
+    } else {
+        to_string_with_root("ListBucketResult", &result)
+    };
+
+    match serialized {
+        Ok(serialized) => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(serialized),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Returns whether a repository may be listed as part of an account listing.
+/// Repositories the requester can't list are left out rather than failing the
+/// whole listing.
+async fn is_listable(
+    api_client: &SourceAPI,
+    user_identity: &UserIdentity,
+    account_id: &String,
+    repository_id: &String,
+) -> bool {
+    if api_client
+        .check_not_tombstoned(account_id, repository_id)
+        .await
+        .is_err()
+    {
+        return false;
+    }
+
+    if !api_client
+        .is_authorized(
+            user_identity.clone(),
+            account_id,
+            repository_id,
+            RepositoryPermission::Read,
+        )
+        .await
+        .unwrap_or(false)
+    {
+        return false;
+    }
+
+    api_client
+        .check_policy(
+            user_identity,
+            account_id,
+            repository_id,
+            "",
+            RepositoryPermission::Read,
+        )
+        .await
+        .is_ok()
+        && api_client
+            .check_operation(account_id, repository_id, BackendOperation::List)
+            .await
+            .is_ok()
+        && api_client
+            .check_terms_accepted(user_identity.clone(), account_id, repository_id)
+            .await
+            .is_ok()
+}
+
+/// Decodes where an account listing resumes: the position named by a
+/// continuation token or marker we issued, or a key to start after.
+///
+/// Fails for V2 continuation tokens we didn't issue, and for positions in the
+/// `{member}/{repository}` of a member account unless `include_members` is set.
+fn decode_account_position(
+    info: &ListObjectsV2Params,
+    include_members: bool,
+) -> Result<(Option<AccountListContinuation>, Option<String>), ()> {
+    let (position, start_after) = if info.list_type != Some(2) {
+        // V1 markers are either a NextMarker we issued or the last key of the previous page
+        match info.marker.as_deref() {
+            Some(marker) => match AccountListContinuation::decode_issued(marker) {
+                Some(position) => (Some(position), None),
+                None => (None, Some(marker.to_string())),
+            },
+            None => (None, None),
+        }
+    } else {
+        match info.continuation_token.as_deref() {
+            Some(token) => (
+                Some(AccountListContinuation::decode_issued(token).ok_or(())?),
+                None,
+            ),
+            None => (None, info.start_after.clone()),
+        }
+    };
+
+    match &position {
+        Some(position) if position.repository_id.contains('/') && !include_members => Err(()),
+        _ => Ok((position, start_after)),
+    }
+}
+
+/// Lists the objects of all repositories of an account the requester can
+/// list, in key order.
+///
+/// Repositories are listed one after the other, each page filled from as many
+/// of them as it takes. Continuation tokens and markers name the repository
+/// the next page starts in, along with the position within it.
+async fn list_account_objects(
+    api_client: &SourceAPI,
+    list_cache: &ListCache,
+    req: &HttpRequest,
+    info: &ListObjectsV2Params,
+    account_id: &String,
+    user_identity: &UserIdentity,
+    max_keys: NonZeroU32,
+) -> HttpResponse {
+    let account = match api_client
+        .get_account(account_id.clone(), user_identity.clone(), false)
+        .await
+    {
+        Ok(account) => account,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    // In the order of their keys, where `a-b/` sorts before `a/`
+    let mut repository_prefixes: Vec<String> = account
+        .repositories
+        .iter()
+        .map(|repository_id| format!("{}/", repository_id))
+        .collect();
+    repository_prefixes.sort();
+
+    let Ok((position, start_after)) = decode_account_position(info, false) else {
+        return HttpResponse::BadRequest().finish();
+    };
+
+    let mut result = ListBucketResult {
+        name: account_id.clone(),
+        prefix: info.prefix.clone().unwrap_or_default(),
+        delimiter: None,
+        start_after: None,
+        key_count: 0,
+        max_keys: max_keys.get() as i64,
+        is_truncated: false,
+        contents: vec![],
+        common_prefixes: vec![],
+        continuation_token: None,
+        next_continuation_token: None,
+    };
+
+    for repository_prefix in repository_prefixes {
+        let repository_id = repository_prefix.trim_end_matches('/').to_string();
+
+        let mut continuation = ListContinuation::default();
+        if let Some(position) = &position {
+            let position_prefix = format!("{}/", position.repository_id);
+            if repository_prefix < position_prefix {
+                continue;
+            }
+            if repository_prefix == position_prefix {
+                continuation = ListContinuation::decode(position.token.clone());
+            }
+        }
+
+        // Keys outside the repository sort either before or after all of its keys
+        let repository_start_after = match &start_after {
+            Some(key) => match key.strip_prefix(&repository_prefix) {
+                Some(key) => Some(key.to_string()),
+                None if *key > repository_prefix => continue,
+                None => None,
+            },
+            None => None,
+        };
+
+        let Some(remaining) = NonZeroU32::new(max_keys.get() - result.key_count as u32) else {
+            // The page is full, the next one starts with this repository
+            result.is_truncated = true;
+            result.next_continuation_token = Some(
+                AccountListContinuation {
+                    repository_id,
+                    token: None,
+                }
+                .encode(),
+            );
+            break;
+        };
+
+        if !is_listable(api_client, user_identity, account_id, &repository_id).await {
+            continue;
+        }
+
+        let Ok(client) = api_client
+            .get_backend_client(
+                account_id,
+                &repository_id,
+                is_requester_payer(req.headers()),
+            )
+            .await
+        else {
+            continue;
+        };
+
+        let cacheable = !is_requester_payer(req.headers())
+            && api_client.is_public(account_id, &repository_id).await;
+        let page = match list_cache
+            .list(
+                client.as_ref(),
+                account_id,
+                &repository_id,
+                &ListPage {
+                    prefix: "",
+                    continuation_token: continuation.backend_token.as_deref(),
+                    start_after: repository_start_after.as_deref(),
+                    delimiter: None,
+                    max_keys: remaining,
+                },
+                cacheable,
             )
             .await
         {
-            Ok(res) => match to_string_with_root("ListBucketResult", &res) {
-                Ok(serialized) => HttpResponse::Ok()
-                    .content_type("application/xml")
-                    .body(serialized),
-                Err(e) => HttpResponse::InternalServerError().finish(),
+            Ok(page) => normalize_list_result(page, continuation, remaining),
+            Err(error) => return error.to_response(),
+        };
+
+        result.key_count += page.key_count;
+        result.contents.extend(page.contents);
+
+        if page.is_truncated {
+            result.is_truncated = true;
+            result.next_continuation_token = Some(
+                AccountListContinuation {
+                    repository_id,
+                    token: page.next_continuation_token,
+                }
+                .encode(),
+            );
+            break;
+        }
+    }
+
+    list_objects_response(result, info, account_id)
+}
+
+/// Answers `ListBuckets` for authenticated callers, listing the accounts
+/// their API key has access to. Anonymous callers get the version banner.
+#[get("/")]
+async fn index(
+    api_client: web::Data<SourceAPI>,
+    user_identity: web::ReqData<UserIdentity>,
+) -> impl Responder {
+    let Some(api_key) = &user_identity.api_key else {
+        return HttpResponse::Ok().body(format!("Source Cooperative Data Proxy v{}", VERSION));
+    };
+
+    let accounts = match api_client.list_accounts(&user_identity).await {
+        Ok(accounts) => accounts,
+        Err(error) => return error.to_response(),
+    };
+
+    let mut buckets: Vec<Bucket> = accounts
+        .into_iter()
+        .map(|account| Bucket {
+            name: account.account_id,
+            // Accounts created before creation dates were recorded
+            creation_date: account
+                .created
+                .unwrap_or_else(|| "1970-01-01T00:00:00.000Z".to_string()),
+        })
+        .collect();
+    buckets.sort_by(|a, b| a.name.cmp(&b.name));
+
+    match to_string_with_root(
+        "ListAllMyBucketsResult",
+        &ListAllMyBucketsResult {
+            xmlns: "http://s3.amazonaws.com/doc/2006-03-01/".to_string(),
+            owner: Owner {
+                id: api_key.access_key_id.clone(),
+                display_name: api_key.access_key_id.clone(),
             },
-            Err(_) => HttpResponse::NotFound().finish(),
+            buckets: Buckets { buckets },
+        },
+    ) {
+        Ok(serialized) => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(serialized),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Serialize)]
+struct HealthStage {
+    stage: &'static str,
+    ok: bool,
+    duration_ms: u128,
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeepHealthResponse {
+    ok: bool,
+    version: &'static str,
+    stages: Vec<HealthStage>,
+}
+
+/// Exercises a synthetic end-to-end request against a canary object.
+///
+/// The canary is configured with the `CANARY_ACCOUNT_ID`, `CANARY_REPOSITORY_ID`
+/// and `CANARY_OBJECT_KEY` environment variables. Each stage (resolving the
+/// repository, creating the backend client and HEADing the object) is reported
+/// individually so that partial outages can be told apart.
+#[get("/healthz/deep")]
+async fn deep_health(api_client: web::Data<SourceAPI>) -> impl Responder {
+    let mut stages = Vec::new();
+
+    let canary = (
+        env::var("CANARY_ACCOUNT_ID"),
+        env::var("CANARY_REPOSITORY_ID"),
+        env::var("CANARY_OBJECT_KEY"),
+    );
+
+    if let (Ok(account_id), Ok(repository_id), Ok(key)) = canary {
+        let started = Instant::now();
+        let repository = api_client
+            .get_repository_record(&account_id, &repository_id)
+            .await;
+        stages.push(HealthStage {
+            stage: "repository",
+            ok: repository.is_ok(),
+            duration_ms: started.elapsed().as_millis(),
+            message: repository.err().map(|e| e.to_string()),
+        });
+
+        if stages.iter().all(|stage| stage.ok) {
+            let started = Instant::now();
+            let client = api_client
+                .get_backend_client(&account_id, &repository_id, false)
+                .await;
+            stages.push(HealthStage {
+                stage: "backend",
+                ok: client.is_ok(),
+                duration_ms: started.elapsed().as_millis(),
+                message: client
+                    .as_ref()
+                    .err()
+                    .map(|_| "Could not create backend client".to_string()),
+            });
+
+            if let Ok(client) = client {
+                let started = Instant::now();
+                let head = client.head_object(key, None, None).await;
+                stages.push(HealthStage {
+                    stage: "object",
+                    ok: head.is_ok(),
+                    duration_ms: started.elapsed().as_millis(),
+                    message: head.err().map(|e| e.to_string()),
+                });
+            }
         }
-        // Found the repository, now make the list objects request
     } else {
-        // Could not find the repository
-        return HttpResponse::NotFound().finish();
+        stages.push(HealthStage {
+            stage: "configuration",
+            ok: false,
+            duration_ms: 0,
+            message: Some("Canary object is not configured".to_string()),
+        });
+    }
+
+    let ok = stages.iter().all(|stage| stage.ok);
+    let body = DeepHealthResponse {
+        ok,
+        version: VERSION,
+        stages,
+    };
+
+    if ok {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
     }
 }
 
-#[get("/")]
-async fn index() -> impl Responder {
-    HttpResponse::Ok().body(format!("Source Cooperative Data Proxy v{}", VERSION))
+/// Checks the `X-Admin-Token` header of a request to an admin endpoint.
+///
+/// Admin endpoints are disabled unless the `ADMIN_TOKEN` environment variable is set.
+fn check_admin_token(req: &HttpRequest) -> Result<(), HttpResponse> {
+    let Ok(admin_token) = env::var("ADMIN_TOKEN") else {
+        return Err(HttpResponse::NotFound().finish());
+    };
+
+    let provided = req
+        .headers()
+        .get("X-Admin-Token")
+        .and_then(|h| h.to_str().ok());
+    if admin_token.is_empty() || provided != Some(admin_token.as_str()) {
+        return Err(HttpResponse::Forbidden().finish());
+    }
+
+    Ok(())
+}
+
+/// Reports how many reads of each migrating repository were served from its
+/// fallback mirror since this instance started.
+#[get("/_admin/migrations")]
+async fn migration_fallbacks(api_client: web::Data<SourceAPI>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    let fallback_hits = api_client.fallback_hits.lock().unwrap().clone();
+    HttpResponse::Ok().json(fallback_hits)
+}
+
+/// Reports time to first byte compliance of each request class over the
+/// rolling SLO window of this instance.
+#[get("/_admin/slo")]
+async fn slo_report(slo_tracker: web::Data<SloTracker>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    HttpResponse::Ok().json(slo_tracker.report())
+}
+
+/// Reports the browser requests blocked by the hotlinking policies of
+/// products since this instance started.
+#[get("/_admin/hotlinks")]
+async fn hotlink_report(
+    hotlink_stats: web::Data<HotlinkStats>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    HttpResponse::Ok().json(hotlink_stats.report())
+}
+
+/// Reports the memory held by buffered operations and the operations
+/// rejected because the memory budget was exhausted.
+#[get("/_admin/memory")]
+async fn memory_report(memory_budget: web::Data<MemoryBudget>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    HttpResponse::Ok().json(memory_budget.report())
+}
+
+/// Reports how reads of each shadowed repository compared with its shadow
+/// mirror since this instance started.
+#[get("/_admin/shadow")]
+async fn shadow_comparisons(api_client: web::Data<SourceAPI>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    let shadow_stats = api_client.shadow_stats.lock().unwrap().clone();
+    HttpResponse::Ok().json(shadow_stats)
+}
+
+/// Reports how lookups of the Source API caches were answered since this
+/// instance started, including those coalesced into a fetch already in flight.
+#[get("/_admin/caches")]
+async fn cache_report(api_client: web::Data<SourceAPI>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    HttpResponse::Ok().json(api_client.cache_stats.report())
+}
+
+/// Reports the round trips of the pings keeping connections to the Source API
+/// warm.
+#[get("/_admin/source-api")]
+async fn source_api_report(api_client: web::Data<SourceAPI>, req: HttpRequest) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    let ping_stats = api_client.ping_stats.lock().unwrap().clone();
+    HttpResponse::Ok().json(ping_stats)
+}
+
+/// Reports how the Source API lookups mirrored to the staging Source API
+/// compared, when mirroring is enabled.
+#[get("/_admin/source-api/mirror")]
+async fn source_api_mirror_report(
+    api_client: web::Data<SourceAPI>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    match &api_client.mirror {
+        Some(mirror) => HttpResponse::Ok().json(mirror.report()),
+        None => HttpResponse::NotFound().body("Source API mirroring is disabled"),
+    }
+}
+
+/// Reports the writes waiting in the write spool of this instance.
+#[get("/_admin/spool")]
+async fn write_spool_report(
+    write_spool: web::Data<WriteSpool>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    HttpResponse::Ok().json(write_spool.report())
+}
+
+/// The most objects a warm-up request will HEAD.
+const MAX_WARM_SAMPLE: u32 = 100;
+
+#[derive(Serialize)]
+struct WarmResponse {
+    ok: bool,
+    stages: Vec<HealthStage>,
+}
+
+/// Pre-populates the repository, permission and data connection caches for a
+/// repository ahead of expected traffic.
+///
+/// Requires the `X-Admin-Token` header to match the `ADMIN_TOKEN` environment
+/// variable; the endpoint is disabled when `ADMIN_TOKEN` is unset. Passing
+/// `?sample=N` additionally lists and HEADs up to N objects so backend
+/// connections are opened before the first client request.
+#[post("/_admin/warm/{account_id}/{repository_id}")]
+async fn warm_repository(
+    api_client: web::Data<SourceAPI>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    params: web::Query<WarmParams>,
+) -> impl Responder {
+    if let Err(response) = check_admin_token(&req) {
+        return response;
+    }
+
+    let (account_id, repository_id) = path.into_inner();
+    let mut stages = Vec::new();
+
+    let started = Instant::now();
+    let repository = api_client
+        .get_repository_record(&account_id, &repository_id)
+        .await;
+    stages.push(HealthStage {
+        stage: "repository",
+        ok: repository.is_ok(),
+        duration_ms: started.elapsed().as_millis(),
+        message: repository.err().map(|e| e.to_string()),
+    });
+
+    if stages.iter().all(|stage| stage.ok) {
+        // Anonymous permissions are the ones shared by launch-day traffic
+        let started = Instant::now();
+        let permissions = api_client
+            .is_authorized(
+                UserIdentity { api_key: None },
+                &account_id,
+                &repository_id,
+                RepositoryPermission::Read,
+            )
+            .await;
+        stages.push(HealthStage {
+            stage: "permissions",
+            ok: permissions.is_ok(),
+            duration_ms: started.elapsed().as_millis(),
+            message: permissions.err().map(|e| e.to_string()),
+        });
+
+        let started = Instant::now();
+        let client = api_client
+            .get_backend_client(&account_id, &repository_id, false)
+            .await;
+        stages.push(HealthStage {
+            stage: "backend",
+            ok: client.is_ok(),
+            duration_ms: started.elapsed().as_millis(),
+            message: client.as_ref().err().map(|error| error.to_string()),
+        });
+
+        let sample = params.sample.unwrap_or(0).min(MAX_WARM_SAMPLE);
+        if let (Ok(client), Some(max_keys)) = (client, NonZeroU32::new(sample)) {
+            let started = Instant::now();
+            let listing = client
+                .list_objects_v2("".to_string(), None, None, None, max_keys)
+                .await;
+            let keys: Vec<String> = listing
+                .as_ref()
+                .map(|result| {
+                    let repository_prefix = format!("{}/", repository_id);
+                    result
+                        .contents
+                        .iter()
+                        .filter_map(|content| content.key.strip_prefix(&repository_prefix))
+                        .map(|key| key.to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            stages.push(HealthStage {
+                stage: "list",
+                ok: listing.is_ok(),
+                duration_ms: started.elapsed().as_millis(),
+                message: listing.err().map(|e| e.to_string()),
+            });
+
+            let started = Instant::now();
+            let heads = futures::future::join_all(
+                keys.into_iter()
+                    .map(|key| client.head_object(key, None, None)),
+            )
+            .await;
+            let failed = heads.iter().filter(|head| head.is_err()).count();
+            stages.push(HealthStage {
+                stage: "objects",
+                ok: failed == 0,
+                duration_ms: started.elapsed().as_millis(),
+                message: Some(format!(
+                    "{} of {} objects reachable",
+                    heads.len() - failed,
+                    heads.len()
+                )),
+            });
+        }
+    }
+
+    let ok = stages.iter().all(|stage| stage.ok);
+    let body = WarmResponse { ok, stages };
+
+    if ok {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::BadGateway().json(body)
+    }
 }
 
 // Main function to set up and run the HTTP server
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let source_api_url = env::var("SOURCE_API_URL").unwrap();
-    let source_api = web::Data::new(SourceAPI::new(source_api_url));
+    let source_api = web::Data::new(SourceAPI::new(source_api_url, Arc::new(SystemClock)));
+    let upload_registry = web::Data::new(UploadRegistry::new());
+    let prefix_jobs = web::Data::new(PrefixJobRegistry::new());
+    let range_cache = web::Data::new(RangeCache::from_env());
+    let list_cache = web::Data::new(ListCache::from_env());
+    let listing_slots = web::Data::new(ListingSlots::default());
+    let slo_tracker = web::Data::new(SloTracker::from_env());
+    let hotlink_stats = web::Data::new(HotlinkStats::default());
+    let conformance = web::Data::new(ConformanceMode::from_env());
+    let memory_budget = web::Data::new(MemoryBudget::from_env());
     json_env_logger::builder()
         .target(json_env_logger::env_logger::Target::Stdout)
         .init();
     // env_logger::init_from_env(Env::default().default_filter_or("info"));
+    let recent_writes = web::Data::new(RecentWrites::new());
+    let write_spool = web::Data::new(WriteSpool::from_env()?);
+    WriteSpool::start(write_spool.clone(), source_api.clone());
+    LifecycleJob::from_env().start(source_api.clone());
+    WarmPool::from_env().start(source_api.clone());
 
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::PayloadConfig::new(1024 * 1024 * 50))
-            .app_data(source_api.clone())
-            .app_data(web::Data::new(UserIdentity { api_key: None }))
-            .wrap(
-                // Configure CORS
-                Cors::default()
-                    .allow_any_origin()
-                    .allow_any_method()
-                    .allow_any_header()
-                    .supports_credentials()
-                    .block_on_origin_mismatch(false)
-                    .max_age(3600),
-            )
-            .wrap(middleware::NormalizePath::trim())
-            .wrap(middleware::DefaultHeaders::new().add(("X-Version", VERSION)))
-            .wrap(middleware::Logger::default())
-            .wrap(LoadIdentity)
-            // Register the endpoints
-            .service(get_object)
-            .service(delete_object)
-            .service(post_handler)
-            .service(put_object)
-            .service(head_object)
-            .service(list_objects)
-            .service(index)
-    })
-    .bind("0.0.0.0:8080")?
-    .run()
-    .await
+    // actix-web's HttpServer answers `Expect: 100-continue` before the request
+    // reaches the app, so the HTTP service is built directly with an expect
+    // handler that authorizes uploads first
+    Server::build()
+        .bind("source-data-proxy", "0.0.0.0:8080", move || {
+            let expect_api = source_api.clone();
+            let virtual_hosts = VirtualHosts::from_env();
+            let expect_hosts = virtual_hosts.clone();
+            let expect_conformance = conformance.clone();
+            let app = App::new()
+                .app_data(web::PayloadConfig::new(1024 * 1024 * 50))
+                .app_data(source_api.clone())
+                .app_data(upload_registry.clone())
+                .app_data(prefix_jobs.clone())
+                .app_data(range_cache.clone())
+                .app_data(list_cache.clone())
+                .app_data(listing_slots.clone())
+                .app_data(slo_tracker.clone())
+                .app_data(hotlink_stats.clone())
+                .app_data(conformance.clone())
+                .app_data(memory_budget.clone())
+                .app_data(write_spool.clone())
+                .app_data(recent_writes.clone())
+                .app_data(web::Data::new(UserIdentity { api_key: None }))
+                .wrap(ErrorDocuments)
+                .wrap(MaintenanceMode)
+                .wrap(HotlinkProtection)
+                .wrap(Conformance::new(**conformance))
+                .wrap(ListCacheInvalidation::new(list_cache.clone()))
+                .wrap(
+                    // Configure CORS
+                    Cors::default()
+                        .allow_any_origin()
+                        .allow_any_method()
+                        .allow_any_header()
+                        .expose_headers(EXPOSED_HEADERS)
+                        .disable_preflight()
+                        .supports_credentials()
+                        .block_on_origin_mismatch(false)
+                        .max_age(3600),
+                )
+                .wrap(NormalizePaths)
+                .wrap(middleware::DefaultHeaders::new().add(("X-Version", VERSION)))
+                // The default format, with the reason of denied requests
+                .wrap(middleware::Logger::new(
+                    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-source-denial-reason}o"#,
+                ))
+                .wrap(LoadIdentity)
+                .wrap(LoadShedding::from_env())
+                .wrap(SloTracking::new(slo_tracker.clone()))
+                // Outside the other middleware, so they see path-style requests
+                .wrap(virtual_hosts)
+                // Outermost, so it filters every response, including those
+                // produced or rewritten by the middleware
+                .wrap(HeaderPolicy::from_env())
+                // Register the endpoints
+                .service(deep_health)
+                .service(upload_progress)
+                .service(delete_prefix)
+                .service(update_metadata)
+                .service(job_progress)
+                .service(issue_session_token)
+                .service(warm_repository)
+                .service(migration_fallbacks)
+                .service(shadow_comparisons)
+                .service(write_spool_report)
+                .service(slo_report)
+                .service(hotlink_report)
+                .service(memory_report)
+                .service(cache_report)
+                .service(source_api_report)
+                .service(source_api_mirror_report)
+                .service(get_object)
+                .service(delete_object)
+                .service(post_handler)
+                .service(delete_objects)
+                .service(put_object)
+                .service(head_object)
+                .service(head_repository)
+                .service(head_bucket)
+                .service(object_preflight)
+                .service(repository_preflight)
+                .service(bucket_preflight)
+                .service(service_preflight)
+                .service(get_bucket_location)
+                .service(get_bucket_versioning)
+                .service(get_bucket_acl)
+                .service(put_bucket_acl)
+                .service(list_objects)
+                .service(index);
+
+            HttpService::build()
+                .expect(fn_service(move |req| {
+                    check_expectation(
+                        expect_api.clone(),
+                        expect_hosts.clone(),
+                        **expect_conformance,
+                        req,
+                    )
+                }))
+                .finish(map_config(app, |_| AppConfig::default()))
+                .tcp()
+        })?
+        .run()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    //! Replays request traces recorded from clients in `tests/traces`, with
+    //! the Source API and storage responses they cause replayed from
+    //! `tests/cassettes`.
+
+    use super::*;
+    use crate::utils::clock::{FixedClock, SharedClock};
+    use actix_web::http::Method;
+    use actix_web::test;
+    use chrono::Utc;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Trace {
+        /// When signed requests were recorded, which the proxy's clock is
+        /// stopped at so that their signatures are still in date.
+        #[serde(default)]
+        recorded_at: Option<DateTime<Utc>>,
+        requests: Vec<TracedRequest>,
+    }
+
+    #[derive(Deserialize)]
+    struct TracedRequest {
+        method: String,
+        path: String,
+        #[serde(default)]
+        headers: Vec<(String, String)>,
+        #[serde(default)]
+        body: String,
+        status: u16,
+        /// The response headers the client relies on, with their exact values.
+        #[serde(default)]
+        expected_headers: Vec<(String, String)>,
+        #[serde(default)]
+        body_length: Option<usize>,
+        #[serde(default)]
+        body_contains: Option<String>,
+    }
+
+    async fn replay_trace(name: &str) {
+        let path = format!("{}/tests/traces/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+        let trace: Trace = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+
+        let clock: SharedClock = match trace.recorded_at {
+            Some(recorded_at) => Arc::new(FixedClock(recorded_at)),
+            None => Arc::new(SystemClock),
+        };
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(SourceAPI::replaying(clock)))
+                .app_data(web::Data::new(RangeCache::from_env()))
+                .app_data(web::Data::new(ListCache::from_env()))
+                .app_data(web::Data::new(ListingSlots::default()))
+                .app_data(web::Data::new(MemoryBudget::from_env()))
+                .app_data(web::Data::new(ConformanceMode::from_env()))
+                .app_data(web::Data::new(UploadRegistry::new()))
+                .app_data(web::Data::new(RecentWrites::new()))
+                .app_data(web::Data::new(WriteSpool::from_env().unwrap()))
+                .wrap(LoadIdentity)
+                .service(get_object)
+                .service(head_object)
+                .service(put_object)
+                .service(post_handler)
+                .service(list_objects),
+        )
+        .await;
+
+        for traced in trace.requests {
+            let request = format!("{} {}", traced.method, traced.path);
+            let mut test_request = test::TestRequest::default()
+                .method(Method::from_bytes(traced.method.as_bytes()).unwrap())
+                .uri(&traced.path)
+                .set_payload(traced.body);
+            for header in traced.headers {
+                test_request = test_request.insert_header(header);
+            }
+
+            let response = test::call_service(&app, test_request.to_request()).await;
+            assert_eq!(response.status().as_u16(), traced.status, "{}", request);
+            for (name, value) in &traced.expected_headers {
+                // actix-web only writes the Content-Length of sized bodies
+                // when it encodes the response
+                let actual = match response.headers().get(name) {
+                    Some(actual) => actual.to_str().ok().map(|v| v.to_string()),
+                    None if name == "content-length" => match response.response().body().size() {
+                        BodySize::Sized(length) => Some(length.to_string()),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                assert_eq!(
+                    actual.as_deref(),
+                    Some(value.as_str()),
+                    "{} of {}",
+                    name,
+                    request
+                );
+            }
+
+            let body = test::read_body(response).await;
+            if let Some(body_length) = traced.body_length {
+                assert_eq!(body.len(), body_length, "body of {}", request);
+            }
+            if let Some(expected) = &traced.body_contains {
+                assert!(
+                    from_utf8(&body).unwrap().contains(expected),
+                    "body of {}",
+                    request
+                );
+            }
+        }
+    }
+
+    /// DuckDB's httpfs globs with ListObjectsV2, then HEADs each file and
+    /// reads the Parquet footer, metadata and row groups with ranged GETs.
+    #[actix_web::test]
+    async fn duckdb_read_parquet() {
+        replay_trace("duckdb_read_parquet").await;
+    }
+
+    /// aws-cli's multipart uploads, with the part body sent `aws-chunked`
+    /// with a trailing checksum as aws-cli does by default.
+    #[actix_web::test]
+    async fn aws_cli_multipart_upload() {
+        replay_trace("aws_cli_multipart_upload").await;
+    }
 }
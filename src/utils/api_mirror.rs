@@ -0,0 +1,164 @@
+use crate::utils::errors::APIError;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+};
+
+/// The share of lookups mirrored when `SOURCE_API_MIRROR_SAMPLE_RATE` isn't set.
+const DEFAULT_SAMPLE_RATE: f64 = 0.01;
+
+/// At most this many differing fields are logged for a lookup.
+const MAX_LOGGED_DIFFERENCES: usize = 20;
+
+/// How the mirrored lookups of one kind compared with the Source API.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MirrorCounters {
+    pub mirrored: u64,
+    pub matched: u64,
+    pub differed: u64,
+    /// Lookups the staging API couldn't be reached for.
+    pub failed: u64,
+}
+
+/// Replays a sample of the Source API lookups of this instance against a
+/// staging Source API, set by `SOURCE_API_MIRROR_URL`, and logs where its
+/// responses differ, so API changes can be validated against real traffic
+/// before they are released.
+///
+/// `SOURCE_API_MIRROR_SAMPLE_RATE` (default 0.01) sets the share of lookups
+/// mirrored. Mirrored lookups run in the background after the Source API has
+/// answered, so they never delay or change the response of a request. Only
+/// anonymous lookups are mirrored, and they are sent without headers, so user
+/// credentials never reach the staging API.
+pub struct ApiMirror {
+    endpoint: String,
+    sample_rate: f64,
+    http: reqwest::Client,
+    stats: Arc<Mutex<HashMap<&'static str, MirrorCounters>>>,
+}
+
+impl ApiMirror {
+    pub fn from_env() -> Option<Self> {
+        let endpoint = env::var("SOURCE_API_MIRROR_URL")
+            .ok()
+            .filter(|url| !url.is_empty())?;
+        let sample_rate = env::var("SOURCE_API_MIRROR_SAMPLE_RATE")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(DEFAULT_SAMPLE_RATE)
+            .clamp(0.0, 1.0);
+
+        Some(ApiMirror {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            sample_rate,
+            http: reqwest::Client::new(),
+            stats: Arc::default(),
+        })
+    }
+
+    pub fn report(&self) -> HashMap<&'static str, MirrorCounters> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Mirrors a sample of lookups of `path` to the staging API, comparing
+    /// its answer with `primary`, the answer of the Source API.
+    ///
+    /// Answers are compared as the proxy sees them: successful responses
+    /// after being parsed into `T`, errors by their status code.
+    pub fn mirror<T>(
+        &self,
+        lookup: &'static str,
+        path: String,
+        primary: &Result<T, Box<dyn APIError>>,
+    ) where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        if rand::random::<f64>() >= self.sample_rate {
+            return;
+        }
+
+        let primary = match primary {
+            Ok(value) => Ok(serde_json::to_value(value).unwrap_or_default()),
+            Err(error) => Err(error.to_response().status().as_u16()),
+        };
+        let request = self.http.get(format!("{}{}", self.endpoint, path));
+        let stats = self.stats.clone();
+
+        actix_web::rt::spawn(async move {
+            let mirrored = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    match response.json::<T>().await {
+                        Ok(value) => Ok(serde_json::to_value(value).unwrap_or_default()),
+                        Err(_) => Err(500),
+                    }
+                }
+                Ok(response) => Err(response.status().as_u16()),
+                Err(error) => {
+                    log::warn!("Source API mirror lookup of {} failed: {}", path, error);
+                    stats.lock().unwrap().entry(lookup).or_default().failed += 1;
+                    return;
+                }
+            };
+
+            let mut differences = Vec::new();
+            match (&primary, &mirrored) {
+                (Ok(primary), Ok(mirrored)) => diff_values("", primary, mirrored, &mut differences),
+                (Err(primary), Err(mirrored)) if primary == mirrored => {}
+                _ => differences.push(format!(
+                    "status {} != {}",
+                    status_of(&primary),
+                    status_of(&mirrored)
+                )),
+            }
+
+            let mut stats = stats.lock().unwrap();
+            let counters = stats.entry(lookup).or_default();
+            counters.mirrored += 1;
+            if differences.is_empty() {
+                counters.matched += 1;
+            } else {
+                counters.differed += 1;
+                differences.truncate(MAX_LOGGED_DIFFERENCES);
+                log::warn!(
+                    "Source API mirror differs for {} lookup of {}: {}",
+                    lookup,
+                    path,
+                    differences.join(", ")
+                );
+            }
+        });
+    }
+}
+
+fn status_of(outcome: &Result<Value, u16>) -> u16 {
+    match outcome {
+        Ok(_) => 200,
+        Err(status) => *status,
+    }
+}
+
+/// Collects the paths of the fields which differ between two JSON values.
+fn diff_values(path: &str, primary: &Value, mirrored: &Value, differences: &mut Vec<String>) {
+    match (primary, mirrored) {
+        (Value::Object(primary), Value::Object(mirrored)) => {
+            for (key, value) in primary {
+                let field = format!("{}/{}", path, key);
+                match mirrored.get(key) {
+                    Some(other) => diff_values(&field, value, other, differences),
+                    None => differences.push(format!("{} missing", field)),
+                }
+            }
+            for key in mirrored.keys().filter(|key| !primary.contains_key(*key)) {
+                differences.push(format!("{}/{} added", path, key));
+            }
+        }
+        (primary, mirrored) if primary != mirrored => differences.push(format!(
+            "{} changed",
+            if path.is_empty() { "/" } else { path }
+        )),
+        _ => {}
+    }
+}
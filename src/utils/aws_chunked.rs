@@ -0,0 +1,190 @@
+use crate::utils::errors::IncompleteBodyError;
+use actix_web::{
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    web::BytesMut,
+};
+
+/// The payload hash of a streaming upload with signed chunks.
+pub const STREAMING_PAYLOAD: &str = "STREAMING-AWS4-HMAC-SHA256-PAYLOAD";
+
+/// A chunk of an `aws-chunked` body.
+pub struct Chunk<'a> {
+    pub data: &'a [u8],
+    pub signature: Option<&'a str>,
+}
+
+/// Returns whether the body of a request is framed with `aws-chunked` encoding.
+pub fn is_aws_chunked(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-amz-content-sha256")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|content_hash| content_hash.starts_with("STREAMING-"))
+}
+
+/// Splits an `aws-chunked` body into its chunks.
+///
+/// Each chunk is framed as `<hex size>[;chunk-signature=<signature>]\r\n<data>\r\n`
+/// and the body ends with a zero length chunk. Trailing headers after the final
+/// chunk are ignored.
+pub fn parse_chunks(body: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+    split_chunks(body).map(|(chunks, _)| chunks)
+}
+
+/// Splits an `aws-chunked` body into its chunks and the trailer following the
+/// final chunk.
+fn split_chunks(body: &[u8]) -> Result<(Vec<Chunk<'_>>, &[u8]), String> {
+    let mut chunks = Vec::new();
+    let mut rest = body;
+
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or("Chunk header is not terminated")?;
+        let header = std::str::from_utf8(&rest[..line_end])
+            .map_err(|_| "Chunk header is not valid UTF-8")?;
+
+        let mut parts = header.splitn(2, ';');
+        let size = usize::from_str_radix(parts.next().unwrap_or("").trim(), 16)
+            .map_err(|_| format!("Invalid chunk size: {}", header))?;
+        let signature = parts
+            .next()
+            .and_then(|extension| extension.trim().strip_prefix("chunk-signature="));
+
+        rest = &rest[line_end + 2..];
+
+        if size == 0 {
+            chunks.push(Chunk {
+                data: &[],
+                signature,
+            });
+            return Ok((chunks, rest));
+        }
+
+        // Sizes are read from the client, so may be close to `usize::MAX`
+        let end = size
+            .checked_add(2)
+            .filter(|end| *end <= rest.len())
+            .ok_or("Chunk is shorter than its declared size")?;
+        if &rest[size..end] != b"\r\n" {
+            return Err("Chunk is shorter than its declared size".to_string());
+        }
+
+        chunks.push(Chunk {
+            data: &rest[..size],
+            signature,
+        });
+        rest = &rest[end..];
+    }
+}
+
+/// Parses the `name:value` lines of the trailer following the final chunk.
+fn parse_trailer(trailer: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(trailer)
+        .split("\r\n")
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect()
+}
+
+/// Strips the `aws-chunked` framing from a request body.
+///
+/// The decoded length is checked against `x-amz-decoded-content-length` when
+/// the client sent it. Checksums sent in the trailer, as declared by
+/// `x-amz-trailer`, are moved into `headers`, so they are verified and stored
+/// like checksums sent as headers.
+pub fn decode(headers: &mut HeaderMap, body: &[u8]) -> Result<BytesMut, IncompleteBodyError> {
+    let (chunks, trailer) =
+        split_chunks(body).map_err(|message| IncompleteBodyError { message })?;
+
+    let declared = headers
+        .get("x-amz-trailer")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim().to_lowercase())
+        .filter(|name| name.starts_with("x-amz-checksum-"));
+    if let Some(declared) = declared {
+        let trailers = parse_trailer(trailer);
+        let value = trailers
+            .iter()
+            .find(|(name, _)| *name == declared)
+            .and_then(|(_, value)| HeaderValue::from_str(value).ok())
+            .ok_or_else(|| IncompleteBodyError {
+                message: format!("The trailing {} checksum is missing", declared),
+            })?;
+        if let Ok(name) = HeaderName::try_from(declared) {
+            headers.insert(name, value);
+        }
+    }
+
+    let mut decoded = BytesMut::with_capacity(chunks.iter().map(|chunk| chunk.data.len()).sum());
+    for chunk in chunks {
+        decoded.extend_from_slice(chunk.data);
+    }
+
+    let decoded_content_length = headers
+        .get("x-amz-decoded-content-length")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<usize>().ok());
+
+    match decoded_content_length {
+        Some(length) if length != decoded.len() => Err(IncompleteBodyError {
+            message: "You did not provide the number of bytes specified by the x-amz-decoded-content-length header".to_string(),
+        }),
+        _ => Ok(decoded),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_are_split() {
+        let body = b"5;chunk-signature=ad80c730\r\nhello\r\n0;chunk-signature=b6c6ea8a\r\n\r\n";
+        let chunks = parse_chunks(body).unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].data, b"hello");
+        assert_eq!(chunks[0].signature, Some("ad80c730"));
+        assert_eq!(chunks[1].data, b"");
+        assert_eq!(chunks[1].signature, Some("b6c6ea8a"));
+    }
+
+    #[test]
+    fn oversized_chunks_are_rejected() {
+        for body in [
+            &b"ffffffffffffffff\r\nhello\r\n0\r\n\r\n"[..],
+            b"fffffffffffffffe\r\nhello\r\n0\r\n\r\n",
+            b"6\r\nhello\r\n0\r\n\r\n",
+        ] {
+            assert_eq!(
+                parse_chunks(body).err().as_deref(),
+                Some("Chunk is shorter than its declared size")
+            );
+        }
+
+        assert!(parse_chunks(b"10000000000000000\r\nhello\r\n").is_err());
+    }
+
+    #[test]
+    fn trailing_checksums_are_moved_to_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-amz-trailer"),
+            HeaderValue::from_static("x-amz-checksum-crc32"),
+        );
+        headers.insert(
+            HeaderName::from_static("x-amz-decoded-content-length"),
+            HeaderValue::from_static("5"),
+        );
+
+        let body = b"5\r\nhello\r\n0\r\nx-amz-checksum-crc32:NhCmhg==\r\n\r\n";
+        let decoded = decode(&mut headers, body).unwrap();
+
+        assert_eq!(&decoded[..], b"hello");
+        assert_eq!(
+            headers.get("x-amz-checksum-crc32").unwrap(),
+            &HeaderValue::from_static("NhCmhg==")
+        );
+    }
+}
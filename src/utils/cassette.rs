@@ -0,0 +1,414 @@
+use async_trait::async_trait;
+use azure_core::error::{Error as AzureError, ErrorKind as AzureErrorKind};
+use azure_core::headers::{HeaderName as AzureHeaderName, HeaderValue as AzureHeaderValue};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures_util::{stream, TryStreamExt};
+use rusoto_core::request::{DispatchSignedRequestFuture, HttpResponse};
+use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+use rusoto_core::{ByteStream, DispatchSignedRequest, HttpDispatchError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// The cassette of this process, read from the environment on first use.
+static CASSETTE: OnceLock<Option<Cassette>> = OnceLock::new();
+
+/// The query parameters of Azure SAS tokens, which are left out of recorded
+/// URLs like the `X-Amz-*` parameters of S3 presigned URLs.
+const SAS_PARAMETERS: &[&str] = &[
+    "sv", "ss", "srt", "sp", "se", "st", "spr", "sip", "sr", "sdd", "si", "sig", "ses", "skoid",
+    "sktid", "skt", "ske", "sks", "skv", "saoid", "suoid", "scid",
+];
+
+/// Whether backend interactions are recorded to, or replayed from, cassettes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+/// A backend HTTP response as stored in a cassette file.
+#[derive(Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// The base64-encoded response body.
+    body: String,
+}
+
+/// Records the HTTP interactions of the storage backends and the Source API,
+/// or replays recorded ones, so integration tests can run against real S3,
+/// Azure and Source API responses without network access.
+///
+/// Enabled by `BACKEND_CASSETTE` (`record` or `replay`). Each interaction is
+/// stored as a JSON file in `BACKEND_CASSETTE_DIR` (default `tests/cassettes`),
+/// named by the hash of its method, URL, `Range` and request body. Signatures
+/// and other presigning parameters, including those of Azure SAS tokens, are
+/// left out, so recordings replay with any credentials and contain none.
+/// Replaying an interaction which wasn't recorded answers with a 502, so
+/// tests notice missing recordings.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    mode: CassetteMode,
+    dir: PathBuf,
+}
+
+impl Cassette {
+    fn from_env() -> Option<Self> {
+        let mode = match env::var("BACKEND_CASSETTE").ok()?.as_str() {
+            "record" => CassetteMode::Record,
+            "replay" => CassetteMode::Replay,
+            _ => return None,
+        };
+        let dir =
+            env::var("BACKEND_CASSETTE_DIR").unwrap_or_else(|_| "tests/cassettes".to_string());
+
+        Some(Cassette {
+            mode,
+            dir: PathBuf::from(dir),
+        })
+    }
+
+    /// Returns the cassette configured by `BACKEND_CASSETTE`, which is only
+    /// read once.
+    pub fn current() -> Option<&'static Cassette> {
+        CASSETTE.get_or_init(Cassette::from_env).as_ref()
+    }
+
+    fn path(&self, method: &str, url: &str, range: Option<&str>, body: &[u8]) -> PathBuf {
+        let mut hasher = Sha256::new();
+        for part in [
+            method.as_bytes(),
+            url.as_bytes(),
+            range.unwrap_or_default().as_bytes(),
+        ] {
+            hasher.update(part);
+            hasher.update(b"\n");
+        }
+        hasher.update(body);
+
+        self.dir
+            .join(format!("{}.json", hex::encode(&hasher.finalize()[..16])))
+    }
+
+    fn load(&self, path: &PathBuf) -> Option<Interaction> {
+        let contents = fs::read(path).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    fn save(&self, path: &PathBuf, interaction: &Interaction) {
+        let result = fs::create_dir_all(&self.dir).and_then(|_| {
+            fs::write(
+                path,
+                serde_json::to_vec_pretty(interaction).unwrap_or_default(),
+            )
+        });
+        if let Err(error) = result {
+            log::warn!("Could not record {}: {}", path.display(), error);
+        }
+    }
+}
+
+/// Returns whether a query parameter is part of a signature, i.e. of an S3
+/// presigned URL or an Azure SAS token.
+fn is_signing_parameter(pair: &str) -> bool {
+    let name = pair.split('=').next().unwrap_or_default();
+    name.to_ascii_lowercase().starts_with("x-amz-") || SAS_PARAMETERS.contains(&name)
+}
+
+/// Removes the query parameters of a URL which change with every request or
+/// carry credentials, i.e. those of presigned URLs and SAS tokens.
+fn stable_url(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+
+    let query: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !is_signing_parameter(pair))
+        .collect();
+    if query.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, query.join("&"))
+    }
+}
+
+/// Sends a backend or Source API request through the cassette, if one is
+/// configured.
+pub async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, reqwest::Error> {
+    let Some(cassette) = Cassette::current() else {
+        return request.send().await;
+    };
+
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().to_string();
+    let url = stable_url(request.url().as_str());
+    let range = request
+        .headers()
+        .get(reqwest::header::RANGE)
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default()
+        .to_vec();
+    let path = cassette.path(&method, &url, range.as_deref(), &body);
+
+    let interaction = match cassette.mode {
+        CassetteMode::Replay => match cassette.load(&path) {
+            Some(interaction) => interaction,
+            None => {
+                log::warn!("No recorded interaction for {} {}", method, url);
+                return Ok(reqwest::Response::from(
+                    http::Response::builder()
+                        .status(http::StatusCode::BAD_GATEWAY)
+                        .body(Vec::new())
+                        .unwrap(),
+                ));
+            }
+        },
+        CassetteMode::Record => {
+            let response = client.execute(request).await?;
+            let status = response.status().as_u16();
+            let headers = response
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect();
+            let body = response.bytes().await?;
+
+            let interaction = Interaction {
+                method,
+                url,
+                status,
+                headers,
+                body: STANDARD.encode(&body),
+            };
+            cassette.save(&path, &interaction);
+            interaction
+        }
+    };
+
+    let mut response = http::Response::builder().status(interaction.status);
+    for (name, value) in &interaction.headers {
+        response = response.header(name, value);
+    }
+    let body = STANDARD.decode(&interaction.body).unwrap_or_default();
+
+    Ok(reqwest::Response::from(response.body(body).unwrap()))
+}
+
+/// Dispatches the requests of rusoto clients through the cassette, if one is
+/// configured, and through `inner` otherwise.
+pub struct CassetteDispatcher<D> {
+    inner: D,
+    cassette: Option<&'static Cassette>,
+}
+
+impl<D> CassetteDispatcher<D> {
+    pub fn new(inner: D) -> Self {
+        CassetteDispatcher {
+            inner,
+            cassette: Cassette::current(),
+        }
+    }
+}
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for CassetteDispatcher<D> {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let Some(cassette) = self.cassette else {
+            return self.inner.dispatch(request, timeout);
+        };
+
+        let method = request.method().to_string();
+        let url = stable_url(&format!(
+            "{}://{}{}?{}",
+            request.scheme(),
+            request.hostname(),
+            request.canonical_path(),
+            request.canonical_query_string()
+        ));
+        let range = request
+            .headers()
+            .get("range")
+            .and_then(|values| values.first())
+            .and_then(|value| String::from_utf8(value.clone()).ok());
+        let body = match &request.payload {
+            Some(SignedRequestPayload::Buffer(bytes)) => bytes.to_vec(),
+            _ => Vec::new(),
+        };
+        let path = cassette.path(&method, &url, range.as_deref(), &body);
+
+        let recording = match cassette.mode {
+            CassetteMode::Record => Some(self.inner.dispatch(request, timeout)),
+            CassetteMode::Replay => None,
+        };
+
+        Box::pin(async move {
+            let interaction = match recording {
+                None => cassette.load(&path).ok_or_else(|| {
+                    HttpDispatchError::new(format!(
+                        "No recorded interaction for {} {}",
+                        method, url
+                    ))
+                })?,
+                Some(recording) => {
+                    let response = recording.await?;
+                    let headers = response
+                        .headers
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.clone()))
+                        .collect();
+                    let body: Vec<Bytes> = response
+                        .body
+                        .try_collect()
+                        .await
+                        .map_err(|error| HttpDispatchError::new(error.to_string()))?;
+
+                    let interaction = Interaction {
+                        method,
+                        url,
+                        status: response.status.as_u16(),
+                        headers,
+                        body: STANDARD.encode(body.concat()),
+                    };
+                    cassette.save(&path, &interaction);
+                    interaction
+                }
+            };
+
+            let mut headers = http::HeaderMap::<String>::default();
+            for (name, value) in interaction.headers {
+                if let Ok(name) = http::header::HeaderName::try_from(name) {
+                    headers.append(name, value);
+                }
+            }
+
+            Ok(HttpResponse {
+                status: http::StatusCode::from_u16(interaction.status)
+                    .map_err(|error| HttpDispatchError::new(error.to_string()))?,
+                body: ByteStream::from(STANDARD.decode(&interaction.body).unwrap_or_default()),
+                headers,
+            })
+        })
+    }
+}
+
+/// Sends the requests of Azure SDK clients, such as blob properties and
+/// listings, through a cassette.
+#[derive(Debug)]
+pub struct CassetteHttpClient {
+    inner: Arc<dyn azure_core::HttpClient>,
+    cassette: &'static Cassette,
+}
+
+impl CassetteHttpClient {
+    pub fn new(cassette: &'static Cassette) -> Self {
+        CassetteHttpClient {
+            inner: azure_core::new_http_client(),
+            cassette,
+        }
+    }
+}
+
+#[async_trait]
+impl azure_core::HttpClient for CassetteHttpClient {
+    async fn execute_request(
+        &self,
+        request: &azure_core::Request,
+    ) -> azure_core::Result<azure_core::Response> {
+        let method = request.method().to_string();
+        let url = stable_url(request.url().as_str());
+        let range = request
+            .headers()
+            .get_optional_str(&azure_core::headers::MS_RANGE)
+            .or_else(|| {
+                request
+                    .headers()
+                    .get_optional_str(&azure_core::headers::RANGE)
+            })
+            .map(|s| s.to_string());
+        let body = match request.body() {
+            azure_core::Body::Bytes(bytes) => bytes.to_vec(),
+            _ => Vec::new(),
+        };
+        let path = self.cassette.path(&method, &url, range.as_deref(), &body);
+
+        let interaction = match self.cassette.mode {
+            CassetteMode::Replay => self.cassette.load(&path).ok_or_else(|| {
+                AzureError::with_message(AzureErrorKind::Io, || {
+                    format!("No recorded interaction for {} {}", method, url)
+                })
+            })?,
+            CassetteMode::Record => {
+                let (status, headers, body) =
+                    self.inner.execute_request(request).await?.deconstruct();
+                let interaction = Interaction {
+                    method,
+                    url,
+                    status: u16::from(status),
+                    headers: headers
+                        .iter()
+                        .map(|(name, value)| {
+                            (name.as_str().to_string(), value.as_str().to_string())
+                        })
+                        .collect(),
+                    body: STANDARD.encode(body.collect().await?),
+                };
+                self.cassette.save(&path, &interaction);
+                interaction
+            }
+        };
+
+        let status = azure_core::StatusCode::try_from(interaction.status).map_err(|_| {
+            AzureError::with_message(AzureErrorKind::DataConversion, || {
+                format!("invalid status code {}", interaction.status)
+            })
+        })?;
+        let headers = interaction
+            .headers
+            .into_iter()
+            .map(|(name, value)| (AzureHeaderName::from(name), AzureHeaderValue::from(value)))
+            .collect::<std::collections::HashMap<_, _>>();
+        let body = Bytes::from(STANDARD.decode(&interaction.body).unwrap_or_default());
+
+        Ok(azure_core::Response::new(
+            status,
+            headers.into(),
+            Box::pin(stream::once(async move { Ok(body) })),
+        ))
+    }
+}
+
+/// Replays the cassettes recorded in `tests/cassettes` in this process.
+#[cfg(test)]
+pub fn replay_recorded() {
+    let cassette = CASSETTE.get_or_init(|| {
+        Some(Cassette {
+            mode: CassetteMode::Replay,
+            dir: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cassettes")),
+        })
+    });
+    assert!(
+        cassette
+            .as_ref()
+            .is_some_and(|cassette| cassette.mode == CassetteMode::Replay),
+        "the cassette was configured before the test replayed it"
+    );
+}
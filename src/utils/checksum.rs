@@ -0,0 +1,136 @@
+use crate::utils::errors::{APIError, BadDigestError};
+use actix_web::http::header::HeaderMap;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// The additional checksum algorithms supported by S3.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Crc32c,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: [ChecksumAlgorithm; 4] = [
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::Sha1,
+        ChecksumAlgorithm::Sha256,
+    ];
+
+    /// Parses an algorithm name as used in `x-amz-sdk-checksum-algorithm`.
+    pub fn parse(name: &str) -> Option<ChecksumAlgorithm> {
+        ChecksumAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "CRC32",
+            ChecksumAlgorithm::Crc32c => "CRC32C",
+            ChecksumAlgorithm::Sha1 => "SHA1",
+            ChecksumAlgorithm::Sha256 => "SHA256",
+        }
+    }
+
+    /// The `x-amz-checksum-*` header carrying a checksum of this algorithm.
+    pub fn header_name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "x-amz-checksum-crc32",
+            ChecksumAlgorithm::Crc32c => "x-amz-checksum-crc32c",
+            ChecksumAlgorithm::Sha1 => "x-amz-checksum-sha1",
+            ChecksumAlgorithm::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+
+    /// The user metadata key under which the checksum of an object is stored.
+    pub fn metadata_key(&self) -> String {
+        format!("checksum-{}", self.name().to_ascii_lowercase())
+    }
+
+    /// Computes the base64 encoded checksum of `bytes`.
+    pub fn compute(&self, bytes: &[u8]) -> String {
+        match self {
+            ChecksumAlgorithm::Crc32 => STANDARD.encode(crc32fast::hash(bytes).to_be_bytes()),
+            ChecksumAlgorithm::Crc32c => STANDARD.encode(crc32c::crc32c(bytes).to_be_bytes()),
+            ChecksumAlgorithm::Sha1 => STANDARD.encode(Sha1::digest(bytes)),
+            ChecksumAlgorithm::Sha256 => STANDARD.encode(Sha256::digest(bytes)),
+        }
+    }
+}
+
+/// A checksum of an object or part, as sent in an `x-amz-checksum-*` header.
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub value: String,
+}
+
+impl Checksum {
+    /// Returns whether a user metadata key holds a checksum stored by the proxy.
+    pub fn is_metadata_key(key: &str) -> bool {
+        ChecksumAlgorithm::ALL
+            .into_iter()
+            .any(|algorithm| algorithm.metadata_key() == key)
+    }
+
+    /// Reads a checksum stored in an object's user metadata.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Option<Checksum> {
+        ChecksumAlgorithm::ALL.into_iter().find_map(|algorithm| {
+            metadata
+                .get(&algorithm.metadata_key())
+                .map(|value| Checksum {
+                    algorithm,
+                    value: value.clone(),
+                })
+        })
+    }
+}
+
+/// Verifies the checksum sent with a PutObject or UploadPart request.
+///
+/// If an `x-amz-checksum-*` header is present its value must match the body,
+/// otherwise a `BadDigest` error is returned. If only `x-amz-sdk-checksum-algorithm`
+/// is present the checksum is computed. Returns `None` if the client did not
+/// ask for a checksum.
+pub fn verify_checksum(
+    headers: &HeaderMap,
+    key: &str,
+    bytes: &[u8],
+) -> Result<Option<Checksum>, Box<dyn APIError>> {
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+
+    for algorithm in ChecksumAlgorithm::ALL {
+        if let Some(expected) = header(algorithm.header_name()) {
+            let value = algorithm.compute(bytes);
+            if value != expected.trim() {
+                return Err(Box::new(BadDigestError {
+                    key: key.to_string(),
+                    algorithm: algorithm.name().to_string(),
+                }));
+            }
+
+            return Ok(Some(Checksum { algorithm, value }));
+        }
+    }
+
+    Ok(header("x-amz-sdk-checksum-algorithm")
+        .and_then(ChecksumAlgorithm::parse)
+        .map(|algorithm| Checksum {
+            algorithm,
+            value: algorithm.compute(bytes),
+        }))
+}
+
+/// Returns whether the client asked for checksums with `x-amz-checksum-mode: ENABLED`.
+pub fn is_checksum_mode_enabled(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-amz-checksum-mode")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|mode| mode.eq_ignore_ascii_case("ENABLED"))
+}
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+/// The source of the current time for expiry decisions: signature and
+/// presigned URL expiry, POST policy expiry, session credential lifetimes and
+/// lifecycle cutoffs.
+///
+/// The clock is part of the `SourceAPI` configuration, so tests can swap in a
+/// clock of their own to exercise skew and expiry edge cases.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock shared between the subsystems using it.
+pub type SharedClock = Arc<dyn Clock>;
+
+/// A clock stopped at a given time, for tests.
+#[cfg(test)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
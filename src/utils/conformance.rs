@@ -0,0 +1,165 @@
+use crate::utils::errors::S3ErrorBody;
+use actix_web::{
+    body::{BodySize, BoxBody, EitherBody, MessageBody},
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        Method, StatusCode,
+    },
+    Error, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    env,
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+/// How closely the proxy follows S3 where doing so changed its historical
+/// behavior, set by `S3_CONFORMANCE`.
+///
+/// `strict` answers like S3: denied requests with `403 Forbidden` rather than
+/// `401 Unauthorized`, every error with an `<Error>` XML body, and ranges
+/// which can't be satisfied with `416 Range Not Satisfiable`. `legacy`, the
+/// default, keeps the behaviors existing clients may rely on, and serves the
+/// whole object for unsatisfiable ranges as HTTP allows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConformanceMode {
+    Strict,
+    Legacy,
+}
+
+impl ConformanceMode {
+    pub fn from_env() -> Self {
+        match env::var("S3_CONFORMANCE").as_deref() {
+            Ok("strict") => ConformanceMode::Strict,
+            Ok("legacy") | Err(_) => ConformanceMode::Legacy,
+            Ok(mode) => {
+                log::warn!("Unknown S3_CONFORMANCE {}, using legacy", mode);
+                ConformanceMode::Legacy
+            }
+        }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        *self == ConformanceMode::Strict
+    }
+}
+
+/// The S3 error code of a response without a body.
+fn error_code(status: StatusCode) -> String {
+    match status.as_u16() {
+        400 => "InvalidRequest".to_string(),
+        401 | 403 => "AccessDenied".to_string(),
+        404 => "NoSuchKey".to_string(),
+        405 => "MethodNotAllowed".to_string(),
+        411 => "MissingContentLength".to_string(),
+        412 => "PreconditionFailed".to_string(),
+        413 => "EntityTooLarge".to_string(),
+        416 => "InvalidRange".to_string(),
+        500 => "InternalError".to_string(),
+        501 => "NotImplemented".to_string(),
+        503 => "ServiceUnavailable".to_string(),
+        _ => status
+            .canonical_reason()
+            .unwrap_or("Error")
+            .replace(' ', ""),
+    }
+}
+
+/// Rewrites the error responses of S3 endpoints in strict mode: `401` becomes
+/// `403`, and errors without a body get an `<Error>` XML body, except for
+/// `HEAD` requests, whose errors S3 sends without one. Internal endpoints are
+/// left alone.
+pub struct Conformance {
+    mode: ConformanceMode,
+}
+
+impl Conformance {
+    pub fn new(mode: ConformanceMode) -> Self {
+        Conformance { mode }
+    }
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for Conformance
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ConformanceMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ConformanceMiddleware {
+            service: Rc::new(service),
+            mode: self.mode,
+        }))
+    }
+}
+
+pub struct ConformanceMiddleware<S> {
+    service: Rc<S>,
+    mode: ConformanceMode,
+}
+
+impl<S, B> Service<ServiceRequest> for ConformanceMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let strict = self.mode.is_strict() && !req.path().starts_with("/_");
+
+        Box::pin(async move {
+            let is_head = req.method() == Method::HEAD;
+            let mut res = svc.call(req).await?;
+            if !strict || !(res.status().is_client_error() || res.status().is_server_error()) {
+                return Ok(res.map_into_left_body());
+            }
+
+            if res.status() == StatusCode::UNAUTHORIZED {
+                *res.response_mut().status_mut() = StatusCode::FORBIDDEN;
+            }
+
+            let is_empty = matches!(
+                res.response().body().size(),
+                BodySize::None | BodySize::Sized(0)
+            );
+            if is_head || !is_empty {
+                return Ok(res.map_into_left_body());
+            }
+
+            let (req, response) = res.into_parts();
+            let mut builder = HttpResponse::build(response.status());
+            for (name, value) in response.headers() {
+                if name != CONTENT_LENGTH && name != CONTENT_TYPE {
+                    builder.append_header((name.clone(), value.clone()));
+                }
+            }
+            let body = S3ErrorBody {
+                code: error_code(response.status()),
+                message: response
+                    .status()
+                    .canonical_reason()
+                    .unwrap_or("Error")
+                    .to_string(),
+                key: Some(req.path().trim_start_matches('/').to_string()),
+            };
+            let response = builder.content_type("application/xml").body(body.to_xml());
+
+            Ok(ServiceResponse::new(req, response).map_into_right_body())
+        })
+    }
+}
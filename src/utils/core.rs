@@ -1,12 +1,27 @@
+use crate::backends::common::{ObjectLock, ServerSideEncryption};
+use crate::utils::checksum::Checksum;
+use crate::utils::errors::{APIError, InvalidArgumentError, KeyTooLongError};
+use crate::utils::virtual_host::OriginalPath;
 use actix_web::{
     body::{BodySize, MessageBody},
-    web, Error as ActixError,
+    http::header::{HeaderMap, HeaderName, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH},
+    web, Error as ActixError, HttpMessage, HttpRequest, HttpResponseBuilder,
 };
+use chrono::DateTime;
 use futures::Stream;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use pin_project_lite::pin_project;
+use std::collections::HashMap;
+use std::fmt;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+/// The maximum length of an S3 object key in bytes.
+pub const MAX_KEY_LENGTH: usize = 1024;
+
+/// The maximum number of `/`-separated segments in an object key.
+pub const MAX_KEY_DEPTH: usize = 256;
+
 pin_project! {
     pub struct StreamingResponse<S> {
         #[pin]
@@ -115,3 +130,387 @@ pub fn split_at_first_slash(input: &str) -> (&str, &str) {
         None => (input, ""),
     }
 }
+
+/// Parses an `x-amz-copy-source` header into its account, repository and key.
+///
+/// The header has the form `/{account_id}/{repository_id}/{key}`, optionally
+/// percent-encoded, with or without the leading slash and with an optional
+/// `?versionId=` suffix which is ignored.
+///
+/// Returns `None` if the header does not reference an object.
+pub fn parse_copy_source(header: &str) -> Option<(String, String, String)> {
+    let path = header.split('?').next()?;
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let (account_id, rest) = split_at_first_slash(path.trim_start_matches('/'));
+    let (repository_id, key) = split_at_first_slash(rest);
+
+    if account_id.is_empty() || repository_id.is_empty() || key.is_empty() {
+        return None;
+    }
+
+    Some((
+        account_id.to_string(),
+        repository_id.to_string(),
+        key.to_string(),
+    ))
+}
+
+/// Validates an object key against S3 limits before it reaches a backend.
+///
+/// The key is checked as clients see it, i.e. `{repository_id}/{key}`, since
+/// the account is the bucket.
+pub fn validate_key(repository_id: &str, key: &str) -> Result<(), Box<dyn APIError>> {
+    let full_key = format!("{}/{}", repository_id, key);
+
+    if full_key.len() > MAX_KEY_LENGTH {
+        return Err(Box::new(KeyTooLongError {
+            key: full_key,
+            message: format!(
+                "Your key is too long. Keys may be at most {} bytes.",
+                MAX_KEY_LENGTH
+            ),
+        }));
+    }
+
+    if full_key.split('/').count() > MAX_KEY_DEPTH {
+        return Err(Box::new(KeyTooLongError {
+            key: full_key,
+            message: format!(
+                "Your key is too deep. Keys may have at most {} segments.",
+                MAX_KEY_DEPTH
+            ),
+        }));
+    }
+
+    Ok(())
+}
+
+/// A single byte range from an HTTP `Range` header, e.g. `bytes=0-99`,
+/// `bytes=100-` or `bytes=-1024`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    /// A range starting at `start`, ending at `end` (inclusive) or the end of the object.
+    FromStart { start: u64, end: Option<u64> },
+    /// The last `length` bytes of the object.
+    Suffix { length: u64 },
+}
+
+impl ByteRange {
+    /// Parses a `Range` header value. Returns `None` for unsupported or invalid
+    /// ranges, including headers with more than one range.
+    pub fn parse(header: &str) -> Option<ByteRange> {
+        ByteRange::parse_spec(header.strip_prefix("bytes=")?)
+    }
+
+    /// Parses a `Range` header value with one or more ranges, e.g.
+    /// `bytes=0-99,1000-1099`. Returns `None` if any of the ranges is invalid.
+    pub fn parse_all(header: &str) -> Option<Vec<ByteRange>> {
+        header
+            .strip_prefix("bytes=")?
+            .split(',')
+            .map(ByteRange::parse_spec)
+            .collect()
+    }
+
+    /// Parses a single range of a `Range` header, e.g. `0-99`.
+    fn parse_spec(spec: &str) -> Option<ByteRange> {
+        let (start, end) = spec.trim().split_once('-')?;
+
+        if start.is_empty() {
+            return Some(ByteRange::Suffix {
+                length: end.parse::<u64>().ok()?,
+            });
+        }
+
+        let start = start.parse::<u64>().ok()?;
+        if end.is_empty() {
+            return Some(ByteRange::FromStart { start, end: None });
+        }
+
+        let end = end.parse::<u64>().ok()?;
+        if end < start {
+            return None;
+        }
+
+        Some(ByteRange::FromStart {
+            start,
+            end: Some(end),
+        })
+    }
+
+    /// Resolves the range against the total object size, returning the first
+    /// and last byte positions (inclusive), or `None` if it cannot be satisfied.
+    pub fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromStart { start, end } => {
+                if start >= total {
+                    return None;
+                }
+
+                Some((start, end.map_or(total - 1, |end| end.min(total - 1))))
+            }
+            ByteRange::Suffix { length } => {
+                if length == 0 || total == 0 {
+                    return None;
+                }
+
+                Some((total.saturating_sub(length), total - 1))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    /// Formats the range as a `Range` header value.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ByteRange::FromStart { start, end: None } => write!(f, "bytes={}-", start),
+            ByteRange::FromStart {
+                start,
+                end: Some(end),
+            } => write!(f, "bytes={}-{}", start, end),
+            ByteRange::Suffix { length } => write!(f, "bytes=-{}", length),
+        }
+    }
+}
+
+/// Merges resolved `(start, end)` ranges which overlap or are adjacent, in
+/// the order of their starts, so that no byte is served twice.
+pub fn coalesce_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match coalesced.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => coalesced.push((start, end)),
+        }
+    }
+
+    coalesced
+}
+
+/// Reads the `x-amz-object-lock-*` headers of a write request.
+pub fn object_lock_from_headers(headers: &HeaderMap) -> ObjectLock {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string())
+    };
+
+    ObjectLock {
+        mode: header("x-amz-object-lock-mode"),
+        retain_until_date: header("x-amz-object-lock-retain-until-date"),
+        legal_hold_status: header("x-amz-object-lock-legal-hold"),
+    }
+}
+
+/// Adds an object's Object Lock settings to a response as the
+/// `x-amz-object-lock-*` headers.
+pub fn insert_object_lock(response: &mut HttpResponseBuilder, object_lock: ObjectLock) {
+    let headers = [
+        ("x-amz-object-lock-mode", object_lock.mode),
+        (
+            "x-amz-object-lock-retain-until-date",
+            object_lock.retain_until_date,
+        ),
+        (
+            "x-amz-object-lock-legal-hold",
+            object_lock.legal_hold_status,
+        ),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            response.insert_header((name, value));
+        }
+    }
+}
+
+/// Reads the `x-amz-server-side-encryption*` headers of a write request.
+pub fn server_side_encryption_from_headers(headers: &HeaderMap) -> ServerSideEncryption {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string())
+    };
+
+    ServerSideEncryption {
+        algorithm: header("x-amz-server-side-encryption"),
+        kms_key_id: header("x-amz-server-side-encryption-aws-kms-key-id"),
+    }
+}
+
+/// Adds an object's server-side encryption settings to a response as the
+/// `x-amz-server-side-encryption*` headers.
+pub fn insert_server_side_encryption(
+    response: &mut HttpResponseBuilder,
+    server_side_encryption: ServerSideEncryption,
+) {
+    let headers = [
+        (
+            "x-amz-server-side-encryption",
+            server_side_encryption.algorithm,
+        ),
+        (
+            "x-amz-server-side-encryption-aws-kms-key-id",
+            server_side_encryption.kms_key_id,
+        ),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            response.insert_header((name, value));
+        }
+    }
+}
+
+/// Characters which are percent-encoded in the keys of object URLs.
+const OBJECT_URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Returns an ETag in the quoted form SDKs expect, whether or not the
+/// backend quoted it.
+pub fn quote_etag(etag: &str) -> String {
+    format!("\"{}\"", etag.trim_matches('"'))
+}
+
+/// Returns the URL under which the proxy serves an object, for the
+/// `Location` of upload responses. `key` includes the repository ID.
+pub fn object_url(req: &HttpRequest, account_id: &str, key: &str) -> String {
+    let connection_info = req.connection_info();
+    let key = utf8_percent_encode(key, OBJECT_URL_ENCODE_SET);
+
+    // Virtual-hosted-style requests already name the account in the host
+    if req.extensions().contains::<OriginalPath>() {
+        return format!(
+            "{}://{}/{}",
+            connection_info.scheme(),
+            connection_info.host(),
+            key
+        );
+    }
+
+    format!(
+        "{}://{}/{}/{}",
+        connection_info.scheme(),
+        connection_info.host(),
+        account_id,
+        key
+    )
+}
+
+/// The prefix of user metadata headers.
+const USER_METADATA_PREFIX: &str = "x-amz-meta-";
+
+/// Reads the `x-amz-meta-*` headers of a write request, keyed by lowercase
+/// name without the prefix.
+pub fn user_metadata_from_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = name.as_str().strip_prefix(USER_METADATA_PREFIX)?;
+            Some((name.to_string(), value.to_str().ok()?.to_string()))
+        })
+        .collect()
+}
+
+/// Rejects user metadata named like the checksums the proxy stores in
+/// `x-amz-meta-checksum-*`, which would otherwise be served as verified
+/// checksums of the object.
+pub fn check_user_metadata(
+    key: &str,
+    metadata: &HashMap<String, String>,
+) -> Result<(), InvalidArgumentError> {
+    match metadata
+        .keys()
+        .find(|name| Checksum::is_metadata_key(&name.to_ascii_lowercase()))
+    {
+        Some(name) => Err(InvalidArgumentError {
+            key: key.to_string(),
+            message: format!("{}{} is reserved", USER_METADATA_PREFIX, name),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Adds an object's user metadata to a response as `x-amz-meta-*` headers.
+/// Entries which aren't valid headers are left out.
+pub fn insert_user_metadata(response: &mut HttpResponseBuilder, metadata: HashMap<String, String>) {
+    for (name, value) in metadata {
+        let name = HeaderName::try_from(format!("{}{}", USER_METADATA_PREFIX, name.to_lowercase()));
+        if let (Ok(name), Ok(value)) = (name, HeaderValue::try_from(value)) {
+            response.insert_header((name, value));
+        }
+    }
+}
+
+/// Returns whether a request acknowledges that the requester pays for it
+/// (`x-amz-request-payer: requester`).
+pub fn is_requester_payer(headers: &HeaderMap) -> bool {
+    headers
+        .get("x-amz-request-payer")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|payer| payer.eq_ignore_ascii_case("requester"))
+}
+
+/// Evaluates `If-None-Match` and `If-Modified-Since` against an object's metadata.
+///
+/// Returns `true` if the client's cached copy is current and a `304 Not Modified`
+/// should be sent. As per RFC 7232, `If-Modified-Since` is ignored when
+/// `If-None-Match` is present.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: &str) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        let Ok(if_none_match) = if_none_match.to_str() else {
+            return false;
+        };
+
+        // ETags are compared weakly for GET and HEAD requests
+        let etag = etag.trim_start_matches("W/");
+        return if_none_match
+            .split(',')
+            .map(|candidate| candidate.trim())
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag);
+    }
+
+    if let Some(if_modified_since) = headers.get(IF_MODIFIED_SINCE) {
+        let since = if_modified_since
+            .to_str()
+            .ok()
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+        let modified = DateTime::parse_from_rfc2822(last_modified).ok();
+
+        if let (Some(since), Some(modified)) = (since, modified) {
+            return modified <= since;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_are_coalesced() {
+        assert_eq!(
+            coalesce_ranges(vec![(500, 599), (0, 99), (100, 199), (50, 149)]),
+            vec![(0, 199), (500, 599)]
+        );
+        assert_eq!(
+            coalesce_ranges(vec![(0, 999), (0, 999), (10, 20)]),
+            vec![(0, 999)]
+        );
+        assert_eq!(
+            coalesce_ranges(vec![(0, 99), (101, 199)]),
+            vec![(0, 99), (101, 199)]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use actix_web::{
+    http::header::{
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_MAX_AGE,
+        ACCESS_CONTROL_REQUEST_HEADERS,
+    },
+    HttpRequest, HttpResponse,
+};
+
+/// Methods supported on `/{account_id}/{repository_id}/{key}`.
+pub const OBJECT_METHODS: &str = "GET, HEAD, PUT, POST, DELETE, OPTIONS";
+
+/// Methods supported on `/{account_id}`, which also matches `/_uploads`.
+pub const BUCKET_METHODS: &str = "GET, HEAD, POST, OPTIONS";
+
+/// Methods supported on `/{account_id}/{repository_id}`, which also matches
+/// two-segment endpoints such as `/_admin/slo` and `/_auth/session-token`.
+/// Longer `/_admin/*` and `/_jobs/*` paths are matched by the object routes.
+pub const REPOSITORY_METHODS: &str = "GET, HEAD, POST, OPTIONS";
+
+/// Methods supported on `/`.
+pub const SERVICE_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Request headers browsers may send in addition to any `x-amz-*` header.
+const ALLOWED_HEADERS: [&str; 12] = [
+    "authorization",
+    "content-type",
+    "content-md5",
+    "content-length",
+    "range",
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-unmodified-since",
+    "cache-control",
+    "expires",
+    "x-source-after-write",
+];
+
+/// Response headers readable by browser clients. Credentialed requests don't
+/// support wildcards, so every header has to be listed explicitly.
+pub const EXPOSED_HEADERS: [&str; 27] = [
+    "etag",
+    "content-range",
+    "content-length",
+    "content-type",
+    "last-modified",
+    "accept-ranges",
+    "retry-after",
+    "x-amz-request-id",
+    "x-amz-version-id",
+    "x-amz-delete-marker",
+    "x-amz-object-lock-mode",
+    "x-amz-object-lock-retain-until-date",
+    "x-amz-object-lock-legal-hold",
+    "x-amz-storage-class",
+    "x-amz-server-side-encryption",
+    "x-amz-server-side-encryption-aws-kms-key-id",
+    "x-amz-restore",
+    "x-amz-mp-parts-count",
+    "x-amz-checksum-crc32",
+    "x-amz-checksum-crc32c",
+    "x-amz-checksum-sha1",
+    "x-amz-checksum-sha256",
+    "x-source-successor",
+    "x-source-dedup",
+    "x-source-consistency-token",
+    "x-source-denial-reason",
+    "x-version",
+];
+
+fn is_allowed_header(header: &str) -> bool {
+    header.starts_with("x-amz-") || ALLOWED_HEADERS.contains(&header)
+}
+
+/// Builds the response to a CORS preflight request.
+///
+/// The requested headers are echoed back when they are `x-amz-*` headers or one
+/// of [`ALLOWED_HEADERS`], since `Access-Control-Allow-Headers` can't contain a
+/// prefix wildcard. The `Access-Control-Allow-Origin` header is added by the
+/// `Cors` middleware.
+pub fn preflight_response(req: &HttpRequest, methods: &'static str) -> HttpResponse {
+    let requested_headers = req
+        .headers()
+        .get(ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+
+    let allowed_headers = requested_headers
+        .split(',')
+        .map(|header| header.trim().to_lowercase())
+        .filter(|header| is_allowed_header(header))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let mut response = HttpResponse::NoContent();
+    response
+        .insert_header((ACCESS_CONTROL_ALLOW_METHODS, methods))
+        .insert_header((ACCESS_CONTROL_MAX_AGE, "3600"));
+
+    if !allowed_headers.is_empty() {
+        response.insert_header((ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers));
+    }
+
+    response.finish()
+}
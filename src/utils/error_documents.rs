@@ -0,0 +1,162 @@
+use crate::apis::source::SourceAPI;
+use crate::apis::API;
+use actix_web::{
+    body::{BoxBody, EitherBody},
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        Method, StatusCode,
+    },
+    web, Error, HttpResponse,
+};
+use futures_util::{future::LocalBoxFuture, StreamExt};
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+/// Error documents larger than this are not served.
+const MAX_ERROR_DOCUMENT_BYTES: u64 = 1024 * 1024;
+
+/// Serves the error documents of a product, e.g. a branded 404 page, in place
+/// of the error responses of object GETs made by browsers.
+///
+/// Products list their documents in `error_documents`, keyed by status code,
+/// each naming a key within the product such as `.source/errors/404.html`.
+/// Only requests accepting `text/html` get error documents; the response keeps
+/// the status of the error it replaces. Documents which can't be read, or are
+/// larger than `MAX_ERROR_DOCUMENT_BYTES`, leave the error response as it is.
+pub struct ErrorDocuments;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for ErrorDocuments
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ErrorDocumentsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorDocumentsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ErrorDocumentsMiddleware<S> {
+    service: Rc<S>,
+}
+
+/// Returns the account and repository of an object GET made by a browser.
+fn browser_object_request(req: &ServiceRequest) -> Option<(String, String)> {
+    if req.method() != Method::GET {
+        return None;
+    }
+
+    let accepts_html = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if !accepts_html {
+        return None;
+    }
+
+    let mut segments = req.path().trim_start_matches('/').splitn(3, '/');
+    let account_id = segments.next().filter(|s| !s.is_empty())?;
+    let repository_id = segments.next().filter(|s| !s.is_empty())?;
+    // Only requests for an object, not for the bucket or repository
+    segments.next().filter(|key| !key.is_empty())?;
+
+    Some((account_id.to_string(), repository_id.to_string()))
+}
+
+/// Reads the error document a product configured for `status`, with its
+/// content type.
+async fn read_error_document(
+    api_client: &SourceAPI,
+    account_id: &String,
+    repository_id: &String,
+    status: StatusCode,
+) -> Option<(String, Vec<u8>)> {
+    let repository = api_client
+        .get_repository_record(account_id, repository_id)
+        .await
+        .ok()?;
+    let key = repository.error_documents.get(&status.as_u16())?;
+
+    let client = api_client
+        .get_backend_client(account_id, repository_id, false)
+        .await
+        .ok()?;
+    let mut object = client
+        .get_object(key.clone(), None, None, None)
+        .await
+        .ok()?;
+    if object.content_length > MAX_ERROR_DOCUMENT_BYTES {
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = object.body.next().await {
+        bytes.extend_from_slice(&chunk.ok()?);
+    }
+
+    Some((object.content_type, bytes))
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorDocumentsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let target = browser_object_request(&req);
+        let api_client = req.app_data::<web::Data<SourceAPI>>().cloned();
+
+        Box::pin(async move {
+            let res = svc.call(req).await?;
+
+            let status = res.status();
+            let (Some((account_id, repository_id)), Some(api_client)) = (target, api_client) else {
+                return Ok(res.map_into_left_body());
+            };
+            if !status.is_client_error() {
+                return Ok(res.map_into_left_body());
+            }
+
+            let Some((content_type, document)) =
+                read_error_document(&api_client, &account_id, &repository_id, status).await
+            else {
+                return Ok(res.map_into_left_body());
+            };
+
+            let (req, res) = res.into_parts();
+            let mut response = HttpResponse::build(status);
+            // Headers describing the error, such as why access was denied
+            for (name, value) in res.headers() {
+                if name.as_str().starts_with("x-") {
+                    response.insert_header((name.clone(), value.clone()));
+                }
+            }
+            let response = response
+                .insert_header((CONTENT_TYPE, content_type))
+                .insert_header(("Cache-Control", "no-store"))
+                .body(document);
+
+            Ok(ServiceResponse::new(req, response).map_into_right_body())
+        })
+    }
+}
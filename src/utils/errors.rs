@@ -1,7 +1,9 @@
 use actix_web::HttpResponse;
+use quick_xml::se::to_string_with_root;
 use serde::Serialize;
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
 pub trait APIError: std::error::Error + Send + Sync {
     fn to_response(&self) -> HttpResponse;
@@ -89,3 +91,1060 @@ impl fmt::Display for InternalServerError {
 }
 
 impl Error for InternalServerError {}
+
+#[derive(Serialize, Debug)]
+pub struct TermsNotAcceptedError {
+    pub account_id: String,
+    pub repository_id: String,
+    pub acceptance_url: String,
+}
+
+impl APIError for TermsNotAcceptedError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::Forbidden().json(self)
+    }
+}
+
+impl fmt::Display for TermsNotAcceptedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Terms Not Accepted: {}/{}",
+            self.account_id, self.repository_id
+        )
+    }
+}
+
+impl Error for TermsNotAcceptedError {}
+
+/// Returned for every request to a retired repository.
+#[derive(Serialize, Debug)]
+pub struct RepositoryTombstonedError {
+    pub account_id: String,
+    pub repository_id: String,
+    /// The `{account_id}/{repository_id}` of the repository replacing this one.
+    pub successor: Option<String>,
+}
+
+impl APIError for RepositoryTombstonedError {
+    fn to_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::Gone();
+        // HEAD responses have no body, so the successor is also sent as a header
+        if let Some(successor) = &self.successor {
+            response.insert_header(("x-source-successor", successor.as_str()));
+        }
+        response.content_type("application/xml").body(
+            S3ErrorBody {
+                code: "Gone".to_string(),
+                message: match &self.successor {
+                    Some(successor) => format!(
+                        "The repository has been retired and replaced by {}",
+                        successor
+                    ),
+                    None => "The repository has been retired".to_string(),
+                },
+                key: Some(format!("{}/{}", self.account_id, self.repository_id)),
+            }
+            .to_xml(),
+        )
+    }
+}
+
+impl fmt::Display for RepositoryTombstonedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Repository Tombstoned: {}/{}",
+            self.account_id, self.repository_id
+        )
+    }
+}
+
+impl Error for RepositoryTombstonedError {}
+
+/// The body of an S3 `<Error>` response.
+#[derive(Serialize, Debug)]
+pub struct S3ErrorBody {
+    #[serde(rename = "Code")]
+    pub code: String,
+    #[serde(rename = "Message")]
+    pub message: String,
+    #[serde(rename = "Key")]
+    pub key: Option<String>,
+}
+
+impl S3ErrorBody {
+    pub fn to_xml(&self) -> String {
+        to_string_with_root("Error", self).unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyTooLongError {
+    pub key: String,
+    pub message: String,
+}
+
+impl APIError for KeyTooLongError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "KeyTooLongError".to_string(),
+                    message: self.message.clone(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for KeyTooLongError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key Too Long: {}", self.message)
+    }
+}
+
+impl Error for KeyTooLongError {}
+
+#[derive(Serialize, Debug)]
+pub struct NoSuchUploadError {
+    pub upload_id: String,
+}
+
+impl APIError for NoSuchUploadError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::NotFound()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "NoSuchUpload".to_string(),
+                    message: "The specified upload does not exist.".to_string(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for NoSuchUploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No Such Upload: {}", self.upload_id)
+    }
+}
+
+impl Error for NoSuchUploadError {}
+
+#[derive(Serialize, Debug)]
+pub struct AuthorizationHeaderMalformedError {
+    pub message: String,
+}
+
+impl APIError for AuthorizationHeaderMalformedError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "AuthorizationHeaderMalformed".to_string(),
+                    message: self.message.clone(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for AuthorizationHeaderMalformedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Authorization Header Malformed: {}", self.message)
+    }
+}
+
+impl Error for AuthorizationHeaderMalformedError {}
+
+#[derive(Serialize, Debug)]
+pub struct AuthorizationQueryParametersError {
+    pub message: String,
+}
+
+impl APIError for AuthorizationQueryParametersError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "AuthorizationQueryParametersError".to_string(),
+                    message: self.message.clone(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for AuthorizationQueryParametersError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Authorization Query Parameters Error: {}", self.message)
+    }
+}
+
+impl Error for AuthorizationQueryParametersError {}
+
+/// A signed request whose `x-amz-date` is too far from the proxy's clock.
+#[derive(Serialize, Debug)]
+pub struct RequestTimeTooSkewedError {}
+
+impl APIError for RequestTimeTooSkewedError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::Forbidden()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "RequestTimeTooSkewed".to_string(),
+                    message:
+                        "The difference between the request time and the current time is too large."
+                            .to_string(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for RequestTimeTooSkewedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Request Time Too Skewed")
+    }
+}
+
+impl Error for RequestTimeTooSkewedError {}
+
+#[derive(Serialize, Debug)]
+pub struct PreconditionFailedError {
+    pub key: String,
+}
+
+impl APIError for PreconditionFailedError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::PreconditionFailed()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "PreconditionFailed".to_string(),
+                    message: "At least one of the pre-conditions you specified did not hold"
+                        .to_string(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for PreconditionFailedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Precondition Failed: {}", self.key)
+    }
+}
+
+impl Error for PreconditionFailedError {}
+
+#[derive(Serialize, Debug)]
+pub struct BadDigestError {
+    pub key: String,
+    pub algorithm: String,
+}
+
+impl APIError for BadDigestError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "BadDigest".to_string(),
+                    message: format!(
+                        "The {} you specified did not match the calculated checksum.",
+                        self.algorithm
+                    ),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for BadDigestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bad Digest: {} ({})", self.key, self.algorithm)
+    }
+}
+
+impl Error for BadDigestError {}
+
+#[derive(Serialize, Debug)]
+pub struct InvalidRangeError {
+    pub key: String,
+    pub content_length: u64,
+}
+
+impl APIError for InvalidRangeError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::RangeNotSatisfiable()
+            .content_type("application/xml")
+            .insert_header(("Content-Range", format!("bytes */{}", self.content_length)))
+            .body(
+                S3ErrorBody {
+                    code: "InvalidRange".to_string(),
+                    message: "The requested range is not satisfiable".to_string(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for InvalidRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid Range: {} ({} bytes)",
+            self.key, self.content_length
+        )
+    }
+}
+
+impl Error for InvalidRangeError {}
+
+/// Why a request was denied access to a product, reported to the caller in
+/// the `x-source-denial-reason` header and the `DenialReason` element of the
+/// error body, and recorded in the access log.
+///
+/// Reasons only name the check which failed, never the details of the
+/// product's configuration.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum DenialReason {
+    /// The caller's role doesn't grant the permission.
+    Role,
+    /// Temporary credentials were used outside the repository they're scoped to.
+    SessionScope,
+    /// The product is disabled.
+    ProductDisabled,
+    /// The product's data is restricted to its members.
+    DataMode,
+    /// The product's access policy rejected the request.
+    Policy,
+    /// The data connection of the product doesn't allow the operation.
+    DataConnection,
+    /// The object is protected by Object Lock retention or a legal hold.
+    ObjectLock,
+    /// The credentials of a browser upload could not be verified.
+    Credentials,
+    /// A browser upload doesn't satisfy its POST policy.
+    PostPolicy,
+    /// The product's hotlinking policy rejected a browser request.
+    Hotlinking,
+}
+
+impl DenialReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DenialReason::Role => "role",
+            DenialReason::SessionScope => "session_scope",
+            DenialReason::ProductDisabled => "product_disabled",
+            DenialReason::DataMode => "data_mode",
+            DenialReason::Policy => "policy",
+            DenialReason::DataConnection => "data_connection",
+            DenialReason::ObjectLock => "object_lock",
+            DenialReason::Credentials => "credentials",
+            DenialReason::PostPolicy => "post_policy",
+            DenialReason::Hotlinking => "hotlinking",
+        }
+    }
+}
+
+/// The body of an `AccessDenied` error, which also names why access was denied.
+#[derive(Serialize, Debug)]
+struct AccessDeniedBody {
+    #[serde(rename = "Code")]
+    code: String,
+    #[serde(rename = "Message")]
+    message: String,
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "DenialReason")]
+    reason: &'static str,
+}
+
+fn access_denied_response(
+    mut response: actix_web::HttpResponseBuilder,
+    key: &str,
+    message: &str,
+    reason: DenialReason,
+) -> HttpResponse {
+    response
+        .content_type("application/xml")
+        .insert_header(("x-source-denial-reason", reason.as_str()))
+        .body(
+            to_string_with_root(
+                "Error",
+                &AccessDeniedBody {
+                    code: "AccessDenied".to_string(),
+                    message: message.to_string(),
+                    key: key.to_string(),
+                    reason: reason.as_str(),
+                },
+            )
+            .unwrap_or_default(),
+        )
+}
+
+#[derive(Serialize, Debug)]
+pub struct AccessDeniedError {
+    pub key: String,
+    pub message: String,
+    pub reason: DenialReason,
+}
+
+impl APIError for AccessDeniedError {
+    fn to_response(&self) -> HttpResponse {
+        access_denied_response(
+            HttpResponse::Forbidden(),
+            &self.key,
+            &self.message,
+            self.reason,
+        )
+    }
+}
+
+impl fmt::Display for AccessDeniedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}",
+            self.message,
+            self.reason.as_str(),
+            self.key
+        )
+    }
+}
+
+impl Error for AccessDeniedError {}
+
+/// A request the caller's permissions on the product don't allow, answered
+/// with `403 AccessDenied` as S3 does.
+#[derive(Serialize, Debug)]
+pub struct UnauthorizedError {
+    pub key: String,
+    pub reason: DenialReason,
+}
+
+impl APIError for UnauthorizedError {
+    fn to_response(&self) -> HttpResponse {
+        access_denied_response(
+            HttpResponse::Forbidden(),
+            &self.key,
+            "Access Denied",
+            self.reason,
+        )
+    }
+}
+
+impl fmt::Display for UnauthorizedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unauthorized ({}): {}", self.reason.as_str(), self.key)
+    }
+}
+
+impl Error for UnauthorizedError {}
+
+#[derive(Serialize, Debug)]
+pub struct SlowDownError {
+    pub account_id: String,
+}
+
+impl APIError for SlowDownError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::ServiceUnavailable()
+            .content_type("application/xml")
+            .insert_header(("Retry-After", "1"))
+            .body(
+                S3ErrorBody {
+                    code: "SlowDown".to_string(),
+                    message: "Too many concurrent listings for this account. Please reduce your request rate.".to_string(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for SlowDownError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Slow Down: {}", self.account_id)
+    }
+}
+
+impl Error for SlowDownError {}
+
+#[derive(Serialize, Debug)]
+pub struct IncompleteBodyError {
+    pub message: String,
+}
+
+impl APIError for IncompleteBodyError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "IncompleteBody".to_string(),
+                    message: self.message.clone(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for IncompleteBodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Incomplete Body: {}", self.message)
+    }
+}
+
+impl Error for IncompleteBodyError {}
+
+/// An origin answered with something other than the object it was asked for,
+/// such as the error page of an intermediate proxy.
+#[derive(Serialize, Debug)]
+pub struct BadGatewayError {
+    pub key: String,
+    pub message: String,
+}
+
+impl APIError for BadGatewayError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadGateway()
+            .content_type("application/xml")
+            .body(
+            S3ErrorBody {
+                code: "BadGateway".to_string(),
+                message:
+                    "The storage backend returned an unexpected response. Please retry the request."
+                        .to_string(),
+                key: Some(self.key.clone()),
+            }
+            .to_xml(),
+        )
+    }
+}
+
+impl fmt::Display for BadGatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bad Gateway: {} ({})", self.key, self.message)
+    }
+}
+
+impl Error for BadGatewayError {}
+
+#[derive(Serialize, Debug)]
+pub struct NoSuchVersionError {
+    pub key: String,
+    pub version_id: String,
+}
+
+impl APIError for NoSuchVersionError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::NotFound()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "NoSuchVersion".to_string(),
+                    message: "The specified version does not exist.".to_string(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for NoSuchVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No Such Version: {} ({})", self.key, self.version_id)
+    }
+}
+
+impl Error for NoSuchVersionError {}
+
+/// A data connection is missing settings its backend needs, or has invalid
+/// ones. The details are logged rather than returned to clients.
+#[derive(Serialize, Debug)]
+pub struct ConfigurationError {
+    pub data_connection_id: String,
+    pub message: String,
+}
+
+impl APIError for ConfigurationError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::InternalServerError()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "InternalError".to_string(),
+                    message: "The storage backend of this repository is misconfigured.".to_string(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid data connection {}: {}",
+            self.data_connection_id, self.message
+        )
+    }
+}
+
+impl Error for ConfigurationError {}
+
+/// An operation the object's storage class doesn't allow, such as reading an
+/// archived object which hasn't been restored.
+#[derive(Serialize, Debug)]
+pub struct InvalidObjectStateError {
+    pub key: String,
+    pub message: String,
+}
+
+impl APIError for InvalidObjectStateError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::Forbidden()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "InvalidObjectState".to_string(),
+                    message: self.message.clone(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for InvalidObjectStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Object State: {}: {}", self.key, self.message)
+    }
+}
+
+impl Error for InvalidObjectStateError {}
+
+#[derive(Serialize, Debug)]
+pub struct RestoreAlreadyInProgressError {
+    pub key: String,
+}
+
+impl APIError for RestoreAlreadyInProgressError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::Conflict()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "RestoreAlreadyInProgress".to_string(),
+                    message: "Object restore is already in progress".to_string(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for RestoreAlreadyInProgressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Restore Already In Progress: {}", self.key)
+    }
+}
+
+impl Error for RestoreAlreadyInProgressError {}
+
+/// A hash request for a range larger than the proxy hashes.
+#[derive(Serialize, Debug)]
+pub struct HashRangeTooLargeError {
+    pub key: String,
+    pub max_bytes: u64,
+}
+
+impl APIError for HashRangeTooLargeError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "InvalidRequest".to_string(),
+                    message: format!(
+                        "Hashes cover at most {} bytes, request a smaller range",
+                        self.max_bytes
+                    ),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for HashRangeTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Hash Range Too Large: {} (max {} bytes)",
+            self.key, self.max_bytes
+        )
+    }
+}
+
+impl Error for HashRangeTooLargeError {}
+
+/// A part number the object doesn't have.
+#[derive(Serialize, Debug)]
+pub struct InvalidPartNumberError {
+    pub key: String,
+}
+
+impl APIError for InvalidPartNumberError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::RangeNotSatisfiable()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "InvalidPartNumber".to_string(),
+                    message: "The requested partnumber is not satisfiable".to_string(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for InvalidPartNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Part Number: {}", self.key)
+    }
+}
+
+impl Error for InvalidPartNumberError {}
+
+/// The error of a fetch shared by the lookups coalesced into it.
+#[derive(Debug)]
+pub struct SharedAPIError(pub Arc<Box<dyn APIError>>);
+
+impl APIError for SharedAPIError {
+    fn to_response(&self) -> HttpResponse {
+        self.0.to_response()
+    }
+}
+
+impl fmt::Display for SharedAPIError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for SharedAPIError {}
+
+/// A request for a repository its publisher put in maintenance.
+#[derive(Serialize, Debug)]
+pub struct RepositoryInMaintenanceError {
+    pub account_id: String,
+    pub repository_id: String,
+    pub message: Option<String>,
+    pub retry_after: u64,
+}
+
+impl APIError for RepositoryInMaintenanceError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::ServiceUnavailable()
+            .content_type("application/xml")
+            .insert_header(("Retry-After", self.retry_after.to_string()))
+            .body(
+                S3ErrorBody {
+                    code: "ServiceUnavailable".to_string(),
+                    message: self.message.clone().unwrap_or_else(|| {
+                        "This repository is temporarily unavailable for maintenance. Please retry later."
+                            .to_string()
+                    }),
+                    key: Some(format!("{}/{}", self.account_id, self.repository_id)),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for RepositoryInMaintenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Repository In Maintenance: {}/{}",
+            self.account_id, self.repository_id
+        )
+    }
+}
+
+impl Error for RepositoryInMaintenanceError {}
+
+/// A part of a multipart upload, other than the last, below the minimum part
+/// size.
+#[derive(Serialize, Debug)]
+pub struct EntityTooSmallError {
+    pub key: String,
+    pub part_number: i64,
+}
+
+impl APIError for EntityTooSmallError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "EntityTooSmall".to_string(),
+                    message: format!(
+                        "Your proposed upload is smaller than the minimum allowed size: part {} is below 5 MiB and is not the last part.",
+                        self.part_number
+                    ),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for EntityTooSmallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Entity Too Small: {} part {}",
+            self.key, self.part_number
+        )
+    }
+}
+
+impl Error for EntityTooSmallError {}
+
+/// A multipart completion whose parts aren't in ascending order.
+#[derive(Serialize, Debug)]
+pub struct InvalidPartOrderError {
+    pub key: String,
+}
+
+impl APIError for InvalidPartOrderError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "InvalidPartOrder".to_string(),
+                    message: "The list of parts was not in ascending order. The parts list must be specified in order by part number.".to_string(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for InvalidPartOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Part Order: {}", self.key)
+    }
+}
+
+impl Error for InvalidPartOrderError {}
+
+/// A request body which isn't the XML document S3 expects, or which names
+/// more than S3 allows.
+#[derive(Serialize, Debug)]
+pub struct MalformedXMLError {}
+
+impl APIError for MalformedXMLError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "MalformedXML".to_string(),
+                    message: "The XML you provided was not well-formed or did not validate against our published schema"
+                        .to_string(),
+                    key: None,
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for MalformedXMLError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Malformed XML")
+    }
+}
+
+impl Error for MalformedXMLError {}
+
+/// A request argument outside of what S3 allows, e.g. a part number above
+/// 10,000.
+#[derive(Serialize, Debug)]
+pub struct InvalidArgumentError {
+    pub key: String,
+    pub message: String,
+}
+
+impl APIError for InvalidArgumentError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "InvalidArgument".to_string(),
+                    message: self.message.clone(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for InvalidArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Argument: {}: {}", self.key, self.message)
+    }
+}
+
+impl Error for InvalidArgumentError {}
+
+/// An operation the storage backend of a repository doesn't support.
+#[derive(Serialize, Debug)]
+pub struct NotImplementedError {
+    pub key: String,
+    pub operation: &'static str,
+}
+
+impl APIError for NotImplementedError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::NotImplemented()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "NotImplemented".to_string(),
+                    message: format!(
+                        "{} is not supported by the storage backend of this repository.",
+                        self.operation
+                    ),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for NotImplementedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Not Implemented: {}: {}", self.operation, self.key)
+    }
+}
+
+impl Error for NotImplementedError {}
+
+/// A buffered operation rejected because the memory budget of the instance
+/// stayed exhausted.
+#[derive(Serialize, Debug)]
+pub struct MemoryBudgetExhaustedError {
+    pub key: String,
+}
+
+impl APIError for MemoryBudgetExhaustedError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::ServiceUnavailable()
+            .content_type("application/xml")
+            .insert_header(("Retry-After", "1"))
+            .body(
+                S3ErrorBody {
+                    code: "SlowDown".to_string(),
+                    message: "The server is busy with other large requests. Please retry."
+                        .to_string(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for MemoryBudgetExhaustedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Memory Budget Exhausted: {}", self.key)
+    }
+}
+
+impl Error for MemoryBudgetExhaustedError {}
+
+/// A request body longer than the proxy buffers for its operation.
+#[derive(Serialize, Debug)]
+pub struct EntityTooLargeError {
+    pub key: String,
+    pub max_size: u64,
+}
+
+impl APIError for EntityTooLargeError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "EntityTooLarge".to_string(),
+                    message: format!(
+                        "Your proposed upload exceeds the maximum allowed size of {} bytes",
+                        self.max_size
+                    ),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for EntityTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Entity Too Large: {}", self.key)
+    }
+}
+
+impl Error for EntityTooLargeError {}
+
+#[derive(Serialize, Debug)]
+pub struct InvalidRequestError {
+    pub key: String,
+    pub message: String,
+}
+
+impl APIError for InvalidRequestError {
+    fn to_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .content_type("application/xml")
+            .body(
+                S3ErrorBody {
+                    code: "InvalidRequest".to_string(),
+                    message: self.message.clone(),
+                    key: Some(self.key.clone()),
+                }
+                .to_xml(),
+            )
+    }
+}
+
+impl fmt::Display for InvalidRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Request: {}: {}", self.key, self.message)
+    }
+}
+
+impl Error for InvalidRequestError {}
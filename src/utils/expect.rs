@@ -0,0 +1,111 @@
+use crate::apis::source::{BackendOperation, RepositoryPermission, SourceAPI};
+use crate::utils::auth::{load_identity, UserIdentity};
+use crate::utils::conformance::ConformanceMode;
+use crate::utils::errors::{APIError, AccessDeniedError};
+use crate::utils::virtual_host::VirtualHosts;
+use actix_http::{HttpMessage, Method, Request};
+use actix_web::{error::InternalError, web, Error, HttpResponse};
+use percent_encoding::percent_decode_str;
+
+/// Decides whether to accept the body of a request sent with `Expect: 100-continue`.
+///
+/// Object uploads are authenticated from their headers and authorized before
+/// `100 Continue` is sent, so that clients don't send large bodies only to have
+/// them rejected. The declared `x-amz-content-sha256` is trusted at this point;
+/// the body is checked against it by `LoadIdentity` once it has been received.
+/// Other requests, and uploads whose authorization can't be decided up front,
+/// are continued and handled as usual.
+pub async fn check_expectation(
+    source_api: web::Data<SourceAPI>,
+    virtual_hosts: VirtualHosts,
+    conformance: ConformanceMode,
+    req: Request,
+) -> Result<Request, Error> {
+    if req.method() != Method::PUT {
+        return Ok(req);
+    }
+
+    let path = virtual_hosts
+        .path_style(req.headers(), req.uri())
+        .unwrap_or_else(|| req.path().to_string());
+    let mut segments = path.trim_matches('/').splitn(3, '/');
+    let (Some(account_id), Some(repository_id), Some(key)) =
+        (segments.next(), segments.next(), segments.next())
+    else {
+        return Ok(req);
+    };
+
+    let (account_id, repository_id) = (account_id.to_string(), repository_id.to_string());
+    let key = percent_decode_str(key).decode_utf8_lossy().to_string();
+
+    let api_key = load_identity(
+        &source_api,
+        req.method().as_str(),
+        req.path(),
+        req.headers(),
+        req.uri().query().unwrap_or(""),
+        None,
+    )
+    .await
+    .ok();
+    let user_identity = UserIdentity { api_key };
+
+    let rejection = match source_api
+        .is_authorized(
+            user_identity.clone(),
+            &account_id,
+            &repository_id,
+            RepositoryPermission::Write,
+        )
+        .await
+    {
+        // Answered before the app, so the conformance middleware doesn't see it
+        Ok(false) if conformance.is_strict() => Some(
+            AccessDeniedError {
+                key: key.clone(),
+                message: "Access Denied".to_string(),
+                reason: source_api
+                    .denial_reason(
+                        &user_identity,
+                        &account_id,
+                        &repository_id,
+                        RepositoryPermission::Write,
+                    )
+                    .await,
+            }
+            .to_response(),
+        ),
+        Ok(false) => Some(HttpResponse::Unauthorized().finish()),
+        Ok(true) => match source_api
+            .check_policy(
+                &user_identity,
+                &account_id,
+                &repository_id,
+                &key,
+                RepositoryPermission::Write,
+            )
+            .await
+        {
+            Ok(_) => source_api
+                .check_operation(&account_id, &repository_id, BackendOperation::Write)
+                .await
+                .err()
+                .map(|error| error.to_response()),
+            Err(error) => Some(error.to_response()),
+        },
+        Err(_) => None,
+    };
+
+    match rejection {
+        Some(response) => {
+            log::info!(
+                "Rejected upload to {}/{}/{} before receiving its body",
+                account_id,
+                repository_id,
+                key
+            );
+            Err(InternalError::from_response("Upload rejected", response).into())
+        }
+        None => Ok(req),
+    }
+}
@@ -0,0 +1,148 @@
+use crate::utils::cors::EXPOSED_HEADERS;
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::HeaderName,
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    env,
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+/// Standard response headers allowed in addition to [`EXPOSED_HEADERS`].
+/// Entries ending with `*` match any header with that prefix.
+const DEFAULT_ALLOWED_HEADERS: [&str; 12] = [
+    "cache-control",
+    "expires",
+    "retry-after",
+    "location",
+    "vary",
+    "www-authenticate",
+    "content-encoding",
+    "content-disposition",
+    "content-language",
+    "access-control-*",
+    "x-amz-meta-*",
+    "x-amz-checksum-*",
+];
+
+/// Removes response headers that aren't explicitly allowed, so headers of the
+/// origin storage (`x-amz-id-2` of a customer bucket, `x-ms-*` request ids,
+/// server tokens) can't leak as header passthrough grows.
+///
+/// Headers set by the handlers and [`EXPOSED_HEADERS`] are allowed by default.
+/// `RESPONSE_HEADER_ALLOWLIST` adds headers to the allow list and
+/// `RESPONSE_HEADER_STRIPLIST` removes headers even when they are allowed.
+/// Both are comma-separated lists of lowercase header names, where a trailing
+/// `*` matches a prefix.
+///
+/// The policy wraps every other middleware, so it also applies to responses
+/// they produce, such as error documents, CORS and load shedding responses.
+pub struct HeaderPolicy {
+    allowed: Rc<Vec<String>>,
+    stripped: Rc<Vec<String>>,
+}
+
+impl HeaderPolicy {
+    pub fn from_env() -> Self {
+        let mut allowed: Vec<String> = EXPOSED_HEADERS
+            .iter()
+            .chain(DEFAULT_ALLOWED_HEADERS.iter())
+            .map(|header| header.to_string())
+            .collect();
+        allowed.extend(patterns_from_env("RESPONSE_HEADER_ALLOWLIST"));
+
+        HeaderPolicy {
+            allowed: Rc::new(allowed),
+            stripped: Rc::new(patterns_from_env("RESPONSE_HEADER_STRIPLIST")),
+        }
+    }
+}
+
+fn patterns_from_env(name: &str) -> Vec<String> {
+    env::var(name)
+        .map(|value| {
+            value
+                .split(',')
+                .map(|pattern| pattern.trim().to_lowercase())
+                .filter(|pattern| !pattern.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn matches_any(header: &str, patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => header.starts_with(prefix),
+            None => header == pattern,
+        })
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for HeaderPolicy
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HeaderPolicyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HeaderPolicyMiddleware {
+            service: Rc::new(service),
+            allowed: self.allowed.clone(),
+            stripped: self.stripped.clone(),
+        }))
+    }
+}
+
+pub struct HeaderPolicyMiddleware<S> {
+    service: Rc<S>,
+    allowed: Rc<Vec<String>>,
+    stripped: Rc<Vec<String>>,
+}
+
+impl<S, B> Service<ServiceRequest> for HeaderPolicyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let allowed = self.allowed.clone();
+        let stripped = self.stripped.clone();
+
+        Box::pin(async move {
+            let mut res = svc.call(req).await?;
+
+            let disallowed: Vec<HeaderName> = res
+                .headers()
+                .keys()
+                .filter(|name| {
+                    !matches_any(name.as_str(), &allowed) || matches_any(name.as_str(), &stripped)
+                })
+                .cloned()
+                .collect();
+
+            for name in disallowed {
+                res.headers_mut().remove(name);
+            }
+
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,297 @@
+use crate::apis::source::{HotlinkPolicy, SourceAPI};
+use crate::utils::auth::UserIdentity;
+use crate::utils::errors::{APIError, AccessDeniedError, DenialReason};
+use actix_web::{
+    body::{BoxBody, EitherBody},
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{ORIGIN, REFERER, USER_AGENT},
+        Method,
+    },
+    web, Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Mutex,
+};
+use url::Url;
+
+/// The query parameter carrying the token of a `token` hotlinking policy.
+const TOKEN_PARAM: &str = "source-token";
+
+/// The linking hosts counted individually per repository. As hosts come from
+/// the `Origin` or `Referer` of requests, any beyond these are only counted
+/// as `other_hosts`.
+const MAX_HOSTS: usize = 100;
+
+/// Why a browser request was blocked.
+#[derive(Debug, Clone, Copy)]
+enum BlockReason {
+    /// The linking site isn't in `allowed_referers`.
+    NotAllowed,
+    /// The request sent neither a `Referer` nor an `Origin`.
+    Empty,
+    /// The request didn't carry the policy's token.
+    Token,
+}
+
+/// The hotlinks blocked for a repository.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HotlinkCounters {
+    pub not_allowed: u64,
+    pub empty: u64,
+    pub token: u64,
+    /// Blocked requests by the host they were linked from.
+    pub hosts: HashMap<String, u64>,
+    /// Blocked requests linked from hosts beyond the first `MAX_HOSTS`.
+    pub other_hosts: u64,
+}
+
+/// Counts the hotlinks blocked since this instance started, by repository.
+#[derive(Default)]
+pub struct HotlinkStats(Mutex<HashMap<String, HotlinkCounters>>);
+
+impl HotlinkStats {
+    fn record(&self, repository: String, reason: BlockReason, host: Option<&str>) {
+        let mut stats = self.0.lock().unwrap();
+        let counters = stats.entry(repository).or_default();
+        match reason {
+            BlockReason::NotAllowed => counters.not_allowed += 1,
+            BlockReason::Empty => counters.empty += 1,
+            BlockReason::Token => counters.token += 1,
+        }
+        if let Some(host) = host {
+            if counters.hosts.len() < MAX_HOSTS || counters.hosts.contains_key(host) {
+                *counters.hosts.entry(host.to_string()).or_default() += 1;
+            } else {
+                counters.other_hosts += 1;
+            }
+        }
+    }
+
+    pub fn report(&self) -> HashMap<String, HotlinkCounters> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Enforces the `hotlinking` policy of a product on browser reads of its
+/// objects, answering blocked requests with `403 Access Denied`.
+///
+/// Signed requests, requests from non-browser clients and same-origin
+/// requests are never blocked.
+pub struct HotlinkProtection;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for HotlinkProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HotlinkProtectionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HotlinkProtectionMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct HotlinkProtectionMiddleware<S> {
+    service: Rc<S>,
+}
+
+/// Returns the account, repository and key of an anonymous browser read.
+fn browser_read(req: &ServiceRequest) -> Option<(String, String, String)> {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) || req.path().starts_with("/_") {
+        return None;
+    }
+
+    let is_signed = req
+        .extensions()
+        .get::<UserIdentity>()
+        .is_some_and(|identity| identity.api_key.is_some());
+    if is_signed || !is_browser(req) {
+        return None;
+    }
+
+    let mut segments = req.path().trim_start_matches('/').splitn(3, '/');
+    let account_id = segments.next().filter(|s| !s.is_empty())?;
+    let repository_id = segments.next().filter(|s| !s.is_empty())?;
+    let key = segments.next().filter(|s| !s.is_empty())?;
+
+    Some((
+        account_id.to_string(),
+        repository_id.to_string(),
+        key.to_string(),
+    ))
+}
+
+/// Returns whether a request comes from a browser, by the fetch metadata and
+/// `Origin` browsers send, or failing that by its `User-Agent`.
+fn is_browser(req: &ServiceRequest) -> bool {
+    let headers = req.headers();
+    headers.contains_key("sec-fetch-mode")
+        || headers.contains_key(ORIGIN)
+        || headers
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|user_agent| user_agent.starts_with("Mozilla/"))
+}
+
+/// Returns the host a request was linked from, by its `Origin` or `Referer`.
+fn linking_host(req: &ServiceRequest) -> Option<String> {
+    [ORIGIN, REFERER]
+        .iter()
+        .filter_map(|name| req.headers().get(name))
+        .filter_map(|h| h.to_str().ok())
+        .filter_map(|value| Url::parse(value).ok())
+        .find_map(|url| url.host_str().map(|host| host.to_lowercase()))
+}
+
+/// Returns whether `host` matches an `allowed_referers` entry.
+fn host_matches(allowed: &str, host: &str) -> bool {
+    let allowed = allowed.trim().to_lowercase();
+    match allowed.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => host == allowed,
+    }
+}
+
+/// Returns why a browser request breaks a policy, if it does.
+fn check_policy(
+    policy: &HotlinkPolicy,
+    req: &ServiceRequest,
+    host: Option<&str>,
+) -> Option<BlockReason> {
+    if let Some(token) = &policy.token {
+        let sent = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .ok()
+            .and_then(|params| params.get(TOKEN_PARAM).cloned());
+        return match sent {
+            Some(sent) if sent == *token => None,
+            _ => Some(BlockReason::Token),
+        };
+    }
+
+    let same_origin = req
+        .headers()
+        .get("sec-fetch-site")
+        .is_some_and(|h| h == "same-origin");
+    if same_origin {
+        return None;
+    }
+
+    match host {
+        None if policy.block_empty => Some(BlockReason::Empty),
+        None => None,
+        Some(host)
+            if !policy.allowed_referers.is_empty()
+                && !policy
+                    .allowed_referers
+                    .iter()
+                    .any(|allowed| host_matches(allowed, host)) =>
+        {
+            Some(BlockReason::NotAllowed)
+        }
+        Some(_) => None,
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for HotlinkProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+
+        Box::pin(async move {
+            let target = browser_read(&req);
+            let api_client = req.app_data::<web::Data<SourceAPI>>().cloned();
+            let (Some((account_id, repository_id, key)), Some(api_client)) = (target, api_client)
+            else {
+                return Ok(svc.call(req).await?.map_into_left_body());
+            };
+
+            let policy = api_client
+                .get_repository_record(&account_id, &repository_id)
+                .await
+                .ok()
+                .and_then(|repository| repository.hotlinking);
+            let Some(policy) = policy else {
+                return Ok(svc.call(req).await?.map_into_left_body());
+            };
+
+            let host = linking_host(&req);
+            let Some(reason) = check_policy(&policy, &req, host.as_deref()) else {
+                return Ok(svc.call(req).await?.map_into_left_body());
+            };
+
+            if let Some(stats) = req.app_data::<web::Data<HotlinkStats>>() {
+                stats.record(
+                    format!("{}/{}", account_id, repository_id),
+                    reason,
+                    host.as_deref(),
+                );
+            }
+
+            let error = AccessDeniedError {
+                key,
+                message: match reason {
+                    BlockReason::NotAllowed => "Hotlinking from this site is not allowed",
+                    BlockReason::Empty => "A Referer or Origin is required",
+                    BlockReason::Token => "A valid source-token is required",
+                }
+                .to_string(),
+                reason: DenialReason::Hotlinking,
+            };
+            Ok(req.into_response(error.to_response()).map_into_right_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_beyond_the_cap_are_counted_as_other() {
+        let stats = HotlinkStats::default();
+        for i in 0..MAX_HOSTS + 5 {
+            stats.record(
+                "example/weather".to_string(),
+                BlockReason::NotAllowed,
+                Some(&format!("site-{}.example.com", i)),
+            );
+        }
+        stats.record(
+            "example/weather".to_string(),
+            BlockReason::NotAllowed,
+            Some("site-0.example.com"),
+        );
+
+        let report = stats.report();
+        let counters = &report["example/weather"];
+        assert_eq!(counters.hosts.len(), MAX_HOSTS);
+        assert_eq!(counters.hosts["site-0.example.com"], 2);
+        assert_eq!(counters.other_hosts, 5);
+        assert_eq!(counters.not_allowed, MAX_HOSTS as u64 + 6);
+    }
+}
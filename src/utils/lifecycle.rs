@@ -0,0 +1,259 @@
+use crate::apis::source::{BackendOperation, SourceAPI, SourceRepository};
+use crate::apis::API;
+use actix_web::web;
+use chrono::{DateTime, Duration as ChronoDuration};
+use core::num::NonZeroU32;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::time::Duration;
+
+/// The page size of the listings a lifecycle pass makes.
+const LIFECYCLE_PAGE_SIZE: u32 = 1000;
+
+/// An expiration rule of a repository: objects under `prefix` are deleted
+/// once they were last modified more than `expiration_days` ago.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleRule {
+    pub prefix: String,
+    pub expiration_days: u32,
+    /// Logs the objects the rule would expire without deleting them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What a rule expired in one pass.
+#[derive(Default)]
+struct RuleOutcome {
+    expired: u64,
+    failed: u64,
+}
+
+/// Emulates S3 lifecycle expiration for repositories, whose publishers don't
+/// control the lifecycle policies of the buckets they are stored in.
+///
+/// Every `LIFECYCLE_INTERVAL_SECONDS` (default 3600), the repositories of the
+/// accounts in `LIFECYCLE_ACCOUNTS` (a comma-separated list) are checked for
+/// `lifecycle_rules`, and expired objects are deleted through the repository's
+/// backend client. Every deletion is logged. `LIFECYCLE_DRY_RUN=true` turns
+/// every rule into a dry run. The job doesn't run unless `LIFECYCLE_ACCOUNTS`
+/// is set.
+pub struct LifecycleJob {
+    accounts: Vec<String>,
+    interval: Duration,
+    dry_run: bool,
+}
+
+impl LifecycleJob {
+    pub fn from_env() -> Self {
+        let accounts = env::var("LIFECYCLE_ACCOUNTS")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|account_id| account_id.trim().to_string())
+                    .filter(|account_id| !account_id.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let interval = env::var("LIFECYCLE_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(3600)
+            .max(60);
+        let dry_run = env::var("LIFECYCLE_DRY_RUN").is_ok_and(|value| value == "true");
+
+        LifecycleJob {
+            accounts,
+            interval: Duration::from_secs(interval),
+            dry_run,
+        }
+    }
+
+    /// Starts the job in the background, if any accounts are configured.
+    pub fn start(self, api_client: web::Data<SourceAPI>) {
+        if self.accounts.is_empty() {
+            return;
+        }
+
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                self.run(&api_client).await;
+            }
+        });
+    }
+
+    async fn run(&self, api_client: &SourceAPI) {
+        for account_id in &self.accounts {
+            let repositories = match api_client.list_repositories(account_id).await {
+                Ok(repositories) => repositories,
+                Err(error) => {
+                    log::warn!(
+                        "Lifecycle could not list the repositories of {}: {}",
+                        account_id,
+                        error
+                    );
+                    continue;
+                }
+            };
+
+            for repository in repositories {
+                if repository.state == "tombstoned" {
+                    continue;
+                }
+
+                for rule in &repository.lifecycle_rules {
+                    self.apply(api_client, &repository, rule).await;
+                }
+            }
+        }
+    }
+
+    async fn apply(
+        &self,
+        api_client: &SourceAPI,
+        repository: &SourceRepository,
+        rule: &LifecycleRule,
+    ) {
+        let (account_id, repository_id) = (&repository.account_id, &repository.repository_id);
+        let dry_run = self.dry_run || rule.dry_run;
+
+        // A rule without a prefix would expire the whole repository
+        if rule.prefix.is_empty() || rule.expiration_days == 0 {
+            log::warn!(
+                "Lifecycle skipped invalid rule of {}/{}: {:?}",
+                account_id,
+                repository_id,
+                rule
+            );
+            return;
+        }
+
+        if !dry_run {
+            if let Err(error) = api_client
+                .check_operation(account_id, repository_id, BackendOperation::Delete)
+                .await
+            {
+                log::warn!(
+                    "Lifecycle skipped {}/{}: {}",
+                    account_id,
+                    repository_id,
+                    error
+                );
+                return;
+            }
+        }
+
+        let client = match api_client
+            .get_backend_client(account_id, repository_id, false)
+            .await
+        {
+            Ok(client) => client,
+            Err(error) => {
+                log::warn!(
+                    "Lifecycle could not create a client for {}/{}: {}",
+                    account_id,
+                    repository_id,
+                    error
+                );
+                return;
+            }
+        };
+
+        let cutoff = api_client.clock.now() - ChronoDuration::days(rule.expiration_days.into());
+        let repository_prefix = format!("{}/", repository_id);
+        let max_keys = NonZeroU32::new(LIFECYCLE_PAGE_SIZE).unwrap();
+        let mut outcome = RuleOutcome::default();
+        let mut continuation_token = None;
+
+        loop {
+            let page = match client
+                .list_objects_v2(
+                    rule.prefix.clone(),
+                    continuation_token,
+                    None,
+                    None,
+                    max_keys,
+                )
+                .await
+            {
+                Ok(page) => page,
+                Err(error) => {
+                    log::warn!(
+                        "Lifecycle could not list {}/{}/{}: {}",
+                        account_id,
+                        repository_id,
+                        rule.prefix,
+                        error
+                    );
+                    break;
+                }
+            };
+
+            for content in &page.contents {
+                let Some(key) = content.key.strip_prefix(&repository_prefix) else {
+                    continue;
+                };
+                let is_expired = DateTime::parse_from_rfc3339(&content.last_modified)
+                    .is_ok_and(|last_modified| last_modified < cutoff);
+                if !is_expired {
+                    continue;
+                }
+
+                if dry_run {
+                    log::info!(
+                        "Lifecycle would expire {}/{}/{} (last modified {}, rule {} after {} days)",
+                        account_id,
+                        repository_id,
+                        key,
+                        content.last_modified,
+                        rule.prefix,
+                        rule.expiration_days
+                    );
+                    outcome.expired += 1;
+                    continue;
+                }
+
+                match client.delete_object(key.to_string(), None).await {
+                    Ok(_) => {
+                        log::info!(
+                            "Lifecycle expired {}/{}/{} (last modified {}, rule {} after {} days)",
+                            account_id,
+                            repository_id,
+                            key,
+                            content.last_modified,
+                            rule.prefix,
+                            rule.expiration_days
+                        );
+                        outcome.expired += 1;
+                    }
+                    Err(error) => {
+                        log::warn!(
+                            "Lifecycle could not expire {}/{}/{}: {}",
+                            account_id,
+                            repository_id,
+                            key,
+                            error
+                        );
+                        outcome.failed += 1;
+                    }
+                }
+            }
+
+            match page.next_continuation_token {
+                Some(token) if page.is_truncated => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+
+        log::info!(
+            "Lifecycle rule {} of {}/{} {} {} objects ({} failed)",
+            rule.prefix,
+            account_id,
+            repository_id,
+            if dry_run { "would expire" } else { "expired" },
+            outcome.expired,
+            outcome.failed
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::backends::common::{ListBucketResult, Repository};
+use crate::utils::errors::APIError;
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web, Error,
+};
+use core::num::NonZeroU32;
+use futures_util::future::LocalBoxFuture;
+use moka::future::Cache;
+use std::{
+    env,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
+
+/// The backend parameters of a listing page.
+pub struct ListPage<'a> {
+    pub prefix: &'a str,
+    pub continuation_token: Option<&'a str>,
+    pub start_after: Option<&'a str>,
+    pub delimiter: Option<&'a str>,
+    pub max_keys: NonZeroU32,
+}
+
+/// Caches the backend listing pages of public products.
+///
+/// Catalog UIs list the same prefixes over and over, and every listing is a
+/// billed LIST request on the origin. Pages are kept for
+/// `LIST_CACHE_TTL_SECONDS` (default 10, 0 disables the cache), at most
+/// `LIST_CACHE_MAX_ENTRIES` (default 10000) of them. Successful writes through
+/// the proxy evict the pages of the product they were made to, see
+/// [`ListCacheInvalidation`].
+#[derive(Clone)]
+pub struct ListCache {
+    pages: Option<Arc<Cache<String, ListBucketResult>>>,
+}
+
+impl ListCache {
+    pub fn from_env() -> Self {
+        let ttl = env::var("LIST_CACHE_TTL_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(10);
+        let max_entries = env::var("LIST_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(10_000);
+
+        let pages = (ttl > 0).then(|| {
+            Arc::new(
+                Cache::builder()
+                    .max_capacity(max_entries)
+                    .time_to_live(Duration::from_secs(ttl))
+                    .support_invalidation_closures()
+                    .build(),
+            )
+        });
+
+        ListCache { pages }
+    }
+
+    fn repository_key(account_id: &str, repository_id: &str) -> String {
+        format!("{}/{}\n", account_id, repository_id)
+    }
+
+    fn cache_key(account_id: &str, repository_id: &str, page: &ListPage) -> String {
+        format!(
+            "{}{}\n{}\n{}\n{}\n{}",
+            ListCache::repository_key(account_id, repository_id),
+            page.prefix,
+            page.continuation_token.unwrap_or_default(),
+            page.start_after.unwrap_or_default(),
+            page.delimiter.unwrap_or_default(),
+            page.max_keys
+        )
+    }
+
+    /// Lists a page of a repository, from the cache if `cacheable`.
+    pub async fn list(
+        &self,
+        client: &dyn Repository,
+        account_id: &str,
+        repository_id: &str,
+        page: &ListPage<'_>,
+        cacheable: bool,
+    ) -> Result<ListBucketResult, Box<dyn APIError>> {
+        let cached = self
+            .pages
+            .as_ref()
+            .filter(|_| cacheable)
+            .map(|pages| (pages, ListCache::cache_key(account_id, repository_id, page)));
+
+        if let Some((pages, key)) = &cached {
+            if let Some(result) = pages.get(key).await {
+                return Ok(result);
+            }
+        }
+
+        let result = client
+            .list_objects_v2(
+                page.prefix.to_string(),
+                page.continuation_token.map(str::to_string),
+                page.start_after.map(str::to_string),
+                page.delimiter.map(str::to_string),
+                page.max_keys,
+            )
+            .await?;
+
+        if let Some((pages, key)) = cached {
+            pages.insert(key, result.clone()).await;
+        }
+
+        Ok(result)
+    }
+
+    /// Evicts the pages of a repository, or of every repository of the account
+    /// when `repository_id` is `None`.
+    pub fn invalidate(&self, account_id: &str, repository_id: Option<&str>) {
+        let Some(pages) = &self.pages else {
+            return;
+        };
+
+        let prefix = match repository_id {
+            Some(repository_id) => ListCache::repository_key(account_id, repository_id),
+            None => format!("{}/", account_id),
+        };
+        if let Err(error) = pages.invalidate_entries_if(move |key, _| key.starts_with(&prefix)) {
+            log::warn!("Could not invalidate cached listings: {}", error);
+        }
+    }
+}
+
+/// Evicts the cached listings of a product after a successful write to it,
+/// i.e. any request other than a GET, HEAD or OPTIONS to one of its paths.
+/// Writes to the account path, such as `DeleteObjects`, evict the listings of
+/// the whole account.
+pub struct ListCacheInvalidation {
+    cache: web::Data<ListCache>,
+}
+
+impl ListCacheInvalidation {
+    pub fn new(cache: web::Data<ListCache>) -> Self {
+        ListCacheInvalidation { cache }
+    }
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for ListCacheInvalidation
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ListCacheInvalidationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ListCacheInvalidationMiddleware {
+            service: Rc::new(service),
+            cache: self.cache.clone(),
+        }))
+    }
+}
+
+pub struct ListCacheInvalidationMiddleware<S> {
+    service: Rc<S>,
+    cache: web::Data<ListCache>,
+}
+
+impl<S, B> Service<ServiceRequest> for ListCacheInvalidationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let cache = self.cache.clone();
+
+        let is_write = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        let mut segments = req.path().trim_start_matches('/').splitn(3, '/');
+        let account_id = segments
+            .next()
+            .filter(|account_id| !account_id.is_empty() && !account_id.starts_with('_'))
+            .map(|account_id| account_id.to_string());
+        let repository_id = segments
+            .next()
+            .filter(|repository_id| !repository_id.is_empty())
+            .map(|repository_id| repository_id.to_string());
+
+        Box::pin(async move {
+            let res = svc.call(req).await?;
+
+            if let Some(account_id) = account_id.filter(|_| is_write) {
+                if res.status().is_success() {
+                    cache.invalidate(&account_id, repository_id.as_deref());
+                }
+            }
+
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Counts the listings in flight for each account so that an account's
+/// `max_concurrent_listings` limit can be enforced.
+///
+/// Counts are held by each proxy instance, so the limit applies per instance.
+#[derive(Clone, Default)]
+pub struct ListingSlots {
+    in_flight: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+/// A reserved listing slot, released when dropped.
+pub struct ListingSlot {
+    account_id: String,
+    in_flight: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl ListingSlots {
+    /// Reserves a slot for a listing of `account_id`, or returns `None` if
+    /// `limit` listings of the account are already in flight.
+    pub fn try_acquire(&self, account_id: &str, limit: Option<u32>) -> Option<ListingSlot> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let count = in_flight.entry(account_id.to_string()).or_default();
+
+        if limit.is_some_and(|limit| *count >= limit) {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(ListingSlot {
+            account_id: account_id.to_string(),
+            in_flight: self.in_flight.clone(),
+        })
+    }
+}
+
+impl Drop for ListingSlot {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.account_id) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                in_flight.remove(&self.account_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    Error, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    cell::Cell,
+    env,
+    future::{ready, Ready},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// How often each worker's event loop is probed for scheduling latency.
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Rejects a fraction of new requests with `503 Service Unavailable` when the
+/// worker's event loop is overloaded.
+///
+/// Each worker runs a probe that measures how late a timer fires; the smoothed
+/// lag is compared against a target latency (`LOAD_SHED_TARGET_LATENCY_MS`,
+/// default 100ms). Once the lag exceeds the target, the share of rejected
+/// requests grows linearly until everything is rejected at twice the target.
+/// Object downloads and uploads are never shed so in-flight transfers and the
+/// requests that start them are protected.
+pub struct LoadShedding {
+    target_latency: Duration,
+}
+
+impl LoadShedding {
+    pub fn from_env() -> Self {
+        let target_latency = env::var("LOAD_SHED_TARGET_LATENCY_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(100);
+
+        LoadShedding {
+            target_latency: Duration::from_millis(target_latency),
+        }
+    }
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for LoadShedding
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LoadSheddingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let lag = Rc::new(Cell::new(Duration::ZERO));
+        let probe_lag = Rc::downgrade(&lag);
+
+        actix_web::rt::spawn(async move {
+            loop {
+                let started = Instant::now();
+                actix_web::rt::time::sleep(PROBE_INTERVAL).await;
+                let sample = started.elapsed().saturating_sub(PROBE_INTERVAL);
+
+                // Stop probing once the worker has shut down
+                let Some(lag) = probe_lag.upgrade() else {
+                    break;
+                };
+                lag.set((lag.get() * 7 + sample) / 8);
+            }
+        });
+
+        ready(Ok(LoadSheddingMiddleware {
+            service: Rc::new(service),
+            target_latency: self.target_latency,
+            lag,
+            counter: Cell::new(0),
+        }))
+    }
+}
+
+pub struct LoadSheddingMiddleware<S> {
+    service: Rc<S>,
+    target_latency: Duration,
+    lag: Rc<Cell<Duration>>,
+    counter: Cell<u64>,
+}
+
+impl<S> LoadSheddingMiddleware<S> {
+    /// Returns the percentage of sheddable requests that should be rejected.
+    fn shed_percentage(&self) -> u64 {
+        let lag = self.lag.get();
+        if self.target_latency.is_zero() || lag <= self.target_latency {
+            return 0;
+        }
+
+        let excess = (lag - self.target_latency).as_millis() * 100;
+        (excess / self.target_latency.as_millis().max(1)).min(100) as u64
+    }
+}
+
+/// Object downloads and uploads are streaming transfers and are never shed.
+fn is_sheddable(req: &ServiceRequest) -> bool {
+    let is_object_path = req.path().trim_matches('/').splitn(3, '/').count() == 3;
+    let is_transfer = req.method() == Method::GET || req.method() == Method::PUT;
+
+    !(is_object_path && is_transfer)
+}
+
+impl<S, B> Service<ServiceRequest> for LoadSheddingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let shed_percentage = self.shed_percentage();
+
+        if shed_percentage > 0 && is_sheddable(&req) {
+            let count = self.counter.get().wrapping_add(1);
+            self.counter.set(count);
+
+            if count % 100 < shed_percentage {
+                log::warn!(
+                    "Shedding request to {} (event loop lag {}ms)",
+                    req.path(),
+                    self.lag.get().as_millis()
+                );
+
+                let response = HttpResponse::ServiceUnavailable()
+                    .insert_header(("Retry-After", "1"))
+                    .finish();
+
+                return Box::pin(
+                    async move { Ok(req.into_response(response).map_into_right_body()) },
+                );
+            }
+        }
+
+        let svc = self.service.clone();
+
+        Box::pin(async move {
+            let res = svc.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
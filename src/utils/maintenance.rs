@@ -0,0 +1,235 @@
+use crate::apis::source::{RepositoryPermission, SourceAPI};
+use crate::utils::auth::UserIdentity;
+use crate::utils::errors::{APIError, RepositoryInMaintenanceError};
+use actix_web::{
+    body::{BoxBody, EitherBody},
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web, Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+use url::form_urlencoded;
+
+/// Answers every request for a product in maintenance, set by `maintenance`
+/// in its metadata, with `503 Service Unavailable` and a `Retry-After`, so
+/// publishers can pause reads and writes during data fixes without revoking
+/// permissions. The product's admins are let through.
+///
+/// Preflight requests and internal endpoints other than the prefix jobs are
+/// never blocked. Requests naming their repository in the body, such as
+/// DeleteObjects and form uploads, are checked by their handlers.
+pub struct MaintenanceMode;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for MaintenanceMode
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MaintenanceModeMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MaintenanceModeMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct MaintenanceModeMiddleware<S> {
+    service: Rc<S>,
+}
+
+/// Internal endpoints which act on a repository named in their path.
+const REPOSITORY_JOB_ROUTES: [&str; 2] = ["/_jobs/delete-prefix/", "/_jobs/update-metadata/"];
+
+/// Returns the account and repository a request is for: the first segments of
+/// its path, or for account listings, the first segment of the `prefix`.
+fn repository_request(req: &ServiceRequest) -> Option<(String, String)> {
+    if req.method() == Method::OPTIONS {
+        return None;
+    }
+
+    let path = match REPOSITORY_JOB_ROUTES
+        .iter()
+        .find_map(|route| req.path().strip_prefix(route))
+    {
+        Some(path) => path,
+        None if req.path().starts_with("/_") => return None,
+        None => req.path().trim_start_matches('/'),
+    };
+
+    let mut segments = path.splitn(3, '/');
+    let account_id = segments.next().filter(|s| !s.is_empty())?;
+    let repository_id = match segments.next().filter(|s| !s.is_empty()) {
+        Some(repository_id) => repository_id.to_string(),
+        None => form_urlencoded::parse(req.query_string().as_bytes())
+            .find(|(name, _)| name == "prefix")
+            .and_then(|(_, prefix)| {
+                prefix
+                    .split_once('/')
+                    .map(|(repository_id, _)| repository_id.to_string())
+            })
+            .filter(|repository_id| !repository_id.is_empty())?,
+    };
+
+    Some((account_id.to_string(), repository_id))
+}
+
+/// Returns the error to answer a request with, if its product is in
+/// maintenance and the requester isn't one of its admins.
+///
+/// Products the requester can't see are left to the handlers, so that the
+/// maintenance message doesn't reveal unlisted and restricted products.
+pub async fn check_maintenance(
+    api_client: &SourceAPI,
+    user_identity: UserIdentity,
+    account_id: &String,
+    repository_id: &String,
+) -> Option<RepositoryInMaintenanceError> {
+    let repository = api_client
+        .get_repository_record(account_id, repository_id)
+        .await
+        .ok()?;
+    let maintenance = repository.maintenance.clone()?;
+
+    if !api_client
+        .is_visible(&repository, user_identity.clone())
+        .await
+    {
+        return None;
+    }
+
+    if user_identity.api_key.is_some()
+        && api_client
+            .is_authorized(
+                user_identity,
+                account_id,
+                repository_id,
+                RepositoryPermission::Admin,
+            )
+            .await
+            .unwrap_or(false)
+    {
+        return None;
+    }
+
+    Some(RepositoryInMaintenanceError {
+        account_id: account_id.to_string(),
+        repository_id: repository_id.to_string(),
+        message: maintenance.message,
+        retry_after: maintenance.retry_after,
+    })
+}
+
+impl<S, B> Service<ServiceRequest> for MaintenanceModeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+
+        Box::pin(async move {
+            let target = repository_request(&req);
+            let api_client = req.app_data::<web::Data<SourceAPI>>().cloned();
+            let (Some((account_id, repository_id)), Some(api_client)) = (target, api_client) else {
+                return Ok(svc.call(req).await?.map_into_left_body());
+            };
+
+            let user_identity = req
+                .extensions()
+                .get::<UserIdentity>()
+                .cloned()
+                .unwrap_or(UserIdentity { api_key: None });
+            if let Some(error) =
+                check_maintenance(&api_client, user_identity, &account_id, &repository_id).await
+            {
+                return Ok(req.into_response(error.to_response()).map_into_right_body());
+            }
+
+            Ok(svc.call(req).await?.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn target(method: Method, uri: &str) -> Option<(String, String)> {
+        repository_request(
+            &TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .to_srv_request(),
+        )
+    }
+
+    fn repository(account_id: &str, repository_id: &str) -> Option<(String, String)> {
+        Some((account_id.to_string(), repository_id.to_string()))
+    }
+
+    #[test]
+    fn repository_is_taken_from_the_path() {
+        assert_eq!(
+            target(Method::GET, "/example/weather/stations.csv"),
+            repository("example", "weather")
+        );
+        assert_eq!(
+            target(Method::PUT, "/example/weather/"),
+            repository("example", "weather")
+        );
+        assert_eq!(
+            target(Method::OPTIONS, "/example/weather/stations.csv"),
+            None
+        );
+    }
+
+    #[test]
+    fn account_listings_are_checked_for_their_prefix() {
+        assert_eq!(
+            target(
+                Method::GET,
+                "/example?list-type=2&prefix=weather%2Fdaily%2F"
+            ),
+            repository("example", "weather")
+        );
+        assert_eq!(
+            target(Method::GET, "/example?prefix=weather/"),
+            repository("example", "weather")
+        );
+
+        // Listings of the repositories themselves
+        assert_eq!(target(Method::GET, "/example?prefix=wea"), None);
+        assert_eq!(target(Method::GET, "/example"), None);
+    }
+
+    #[test]
+    fn prefix_jobs_are_checked() {
+        assert_eq!(
+            target(Method::POST, "/_jobs/delete-prefix/example/weather/daily/"),
+            repository("example", "weather")
+        );
+        assert_eq!(
+            target(Method::POST, "/_jobs/update-metadata/example/weather/"),
+            repository("example", "weather")
+        );
+        assert_eq!(target(Method::GET, "/_admin/memory"), None);
+    }
+}
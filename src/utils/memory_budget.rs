@@ -0,0 +1,346 @@
+use crate::utils::errors::{
+    APIError, EntityTooLargeError, IncompleteBodyError, MemoryBudgetExhaustedError,
+};
+use actix_web::error::PayloadError;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Instant};
+
+/// The memory buffered operations may hold when `MEMORY_BUDGET_MAX_BYTES`
+/// isn't set.
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// How long an operation waits for memory when `MEMORY_BUDGET_WAIT_MS` isn't
+/// set.
+const DEFAULT_WAIT: Duration = Duration::from_secs(2);
+
+/// The longest request body buffered by uploads and `DeleteObjects`.
+pub const MAX_BODY_SIZE: u64 = 50 * 1024 * 1024;
+
+#[derive(Default)]
+struct BudgetState {
+    used: u64,
+    peak: u64,
+    waiting: u64,
+    rejected: u64,
+}
+
+/// The memory budget of this instance, as reported by the admin endpoint.
+#[derive(Serialize)]
+pub struct MemoryReport {
+    pub max_bytes: u64,
+    pub used_bytes: u64,
+    pub peak_bytes: u64,
+    /// Operations waiting for memory to be released.
+    pub waiting: u64,
+    /// Operations rejected since this instance started.
+    pub rejected: u64,
+}
+
+/// Why a request body couldn't be buffered.
+pub enum BufferError {
+    /// The body is longer than the limit it was read with.
+    TooLarge { limit: u64 },
+    /// Memory for the body couldn't be reserved in time.
+    Exhausted,
+    /// The body couldn't be received.
+    Payload(PayloadError),
+}
+
+impl BufferError {
+    pub fn into_api_error(self, key: &str) -> Box<dyn APIError> {
+        match self {
+            BufferError::TooLarge { limit } => Box::new(EntityTooLargeError {
+                key: key.to_string(),
+                max_size: limit,
+            }),
+            BufferError::Exhausted => Box::new(MemoryBudgetExhaustedError {
+                key: key.to_string(),
+            }),
+            BufferError::Payload(error) => Box::new(IncompleteBodyError {
+                message: error.to_string(),
+            }),
+        }
+    }
+}
+
+/// Bounds the memory held by operations which buffer whole bodies, such as
+/// uploads, `CompleteMultipartUpload` documents, previews and range cache
+/// fills, so that many of them at once can't exhaust the process.
+///
+/// Operations reserve the bytes they buffer before doing so and release them
+/// when their reservation is dropped. Ranges a fill inserts into the range
+/// cache are no longer counted once the fill completes; the cache is bounded
+/// by `RANGE_CACHE_MAX_BYTES` instead. While the budget of
+/// `MEMORY_BUDGET_MAX_BYTES` (default 1 GiB) is exhausted, operations wait up
+/// to `MEMORY_BUDGET_WAIT_MS` (default 2000) for memory to be released before
+/// they are rejected. The budget is held by each proxy instance.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    max_bytes: u64,
+    wait: Duration,
+    state: Arc<Mutex<BudgetState>>,
+    released: Arc<Notify>,
+}
+
+/// Reserved bytes of the memory budget, released when dropped.
+pub struct MemoryReservation {
+    bytes: u64,
+    state: Arc<Mutex<BudgetState>>,
+    released: Arc<Notify>,
+}
+
+impl MemoryBudget {
+    pub fn from_env() -> Self {
+        let max_bytes = env::var("MEMORY_BUDGET_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        let wait = env::var("MEMORY_BUDGET_WAIT_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map_or(DEFAULT_WAIT, Duration::from_millis);
+
+        MemoryBudget {
+            max_bytes,
+            wait,
+            state: Arc::default(),
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    fn try_reserve(&self, bytes: u64) -> Option<MemoryReservation> {
+        let mut state = self.state.lock().unwrap();
+        if state.used + bytes > self.max_bytes {
+            return None;
+        }
+
+        state.used += bytes;
+        state.peak = state.peak.max(state.used);
+
+        Some(MemoryReservation {
+            bytes,
+            state: self.state.clone(),
+            released: self.released.clone(),
+        })
+    }
+
+    /// Reserves `bytes`, waiting for other operations to release memory if
+    /// the budget is exhausted. Returns `None` if the memory can't be
+    /// reserved in time, or at all.
+    pub async fn reserve(&self, bytes: u64) -> Option<MemoryReservation> {
+        if let Some(reservation) = self.try_reserve(bytes) {
+            return Some(reservation);
+        }
+
+        if bytes <= self.max_bytes {
+            let _waiting = Waiting::new(&self.state);
+            let deadline = Instant::now() + self.wait;
+            let reservation = loop {
+                // Registered before retrying, so a release in between isn't missed
+                let released = self.released.notified();
+                tokio::pin!(released);
+                released.as_mut().enable();
+
+                if let Some(reservation) = self.try_reserve(bytes) {
+                    break Some(reservation);
+                }
+                if timeout_at(deadline, released).await.is_err() {
+                    break None;
+                }
+            };
+
+            if reservation.is_some() {
+                return reservation;
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.rejected += 1;
+        log::warn!(
+            "Memory budget exhausted: {} of {} bytes in use, rejected {} more",
+            state.used,
+            self.max_bytes,
+            bytes
+        );
+        None
+    }
+
+    /// Buffers a request body of at most `limit` bytes.
+    ///
+    /// Memory is reserved before the body is read: for its `content_length`,
+    /// or for `limit` when it is sent without one, in which case the
+    /// reservation shrinks to the body once it has been read. A body which was
+    /// already reserved for, as by `LoadIdentity`, is read under `reservation`.
+    pub async fn buffer<S>(
+        &self,
+        mut body: S,
+        content_length: Option<u64>,
+        limit: u64,
+        reservation: Option<MemoryReservation>,
+    ) -> Result<(BytesMut, MemoryReservation), BufferError>
+    where
+        S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+    {
+        if content_length.is_some_and(|length| length > limit) {
+            return Err(BufferError::TooLarge { limit });
+        }
+
+        let mut reservation = match reservation {
+            Some(reservation) => reservation,
+            None => self
+                .reserve(content_length.unwrap_or(limit))
+                .await
+                .ok_or(BufferError::Exhausted)?,
+        };
+
+        let mut buffered = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(BufferError::Payload)?;
+            if (buffered.len() + chunk.len()) as u64 > limit {
+                return Err(BufferError::TooLarge { limit });
+            }
+            buffered.extend_from_slice(&chunk);
+        }
+
+        reservation.shrink(buffered.len() as u64);
+        Ok((buffered, reservation))
+    }
+
+    pub fn report(&self) -> MemoryReport {
+        let state = self.state.lock().unwrap();
+        MemoryReport {
+            max_bytes: self.max_bytes,
+            used_bytes: state.used,
+            peak_bytes: state.peak,
+            waiting: state.waiting,
+            rejected: state.rejected,
+        }
+    }
+}
+
+/// Counts an operation as waiting for memory until dropped, including when
+/// its request is cancelled while it waits.
+struct Waiting<'a>(&'a Mutex<BudgetState>);
+
+impl<'a> Waiting<'a> {
+    fn new(state: &'a Mutex<BudgetState>) -> Self {
+        state.lock().unwrap().waiting += 1;
+        Waiting(state)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().waiting -= 1;
+    }
+}
+
+impl MemoryReservation {
+    /// Releases the reserved bytes beyond `bytes`.
+    fn shrink(&mut self, bytes: u64) {
+        if bytes < self.bytes {
+            self.state.lock().unwrap().used -= self.bytes - bytes;
+            self.bytes = bytes;
+            self.released.notify_waiters();
+        }
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().used -= self.bytes;
+        self.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    fn budget(max_bytes: u64) -> MemoryBudget {
+        MemoryBudget {
+            max_bytes,
+            wait: Duration::from_millis(10),
+            state: Arc::default(),
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    fn body(chunks: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, PayloadError>> + Unpin {
+        stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from_static(chunk)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn bodies_are_reserved_for_their_content_length() {
+        let budget = budget(1024);
+        let (buffered, _reservation) = budget
+            .buffer(body(&[b"station,", b"temperature\n"]), Some(20), 100, None)
+            .await
+            .ok()
+            .unwrap();
+
+        assert_eq!(&buffered[..], b"station,temperature\n");
+        assert_eq!(budget.report().used_bytes, 20);
+    }
+
+    #[tokio::test]
+    async fn chunked_bodies_are_reserved_for_the_limit_and_shrunk() {
+        let budget = budget(1024);
+        let (buffered, reservation) = budget
+            .buffer(body(&[b"A,12.5\n"]), None, 100, None)
+            .await
+            .ok()
+            .unwrap();
+
+        assert_eq!(buffered.len(), 7);
+        assert_eq!(budget.report().used_bytes, 7);
+        assert_eq!(budget.report().peak_bytes, 100);
+
+        drop(reservation);
+        assert_eq!(budget.report().used_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn bodies_beyond_the_limit_are_rejected() {
+        let budget = budget(1024);
+
+        // Declared too long, before anything is reserved
+        let declared = budget.buffer(body(&[]), Some(101), 100, None).await;
+        assert!(matches!(
+            declared,
+            Err(BufferError::TooLarge { limit: 100 })
+        ));
+        assert_eq!(budget.report().peak_bytes, 0);
+
+        // Sent too long without a Content-Length
+        let sent = budget
+            .buffer(body(&[&[0; 60], &[0; 60]]), None, 100, None)
+            .await;
+        assert!(matches!(sent, Err(BufferError::TooLarge { limit: 100 })));
+        assert_eq!(budget.report().used_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn exhausted_budget_rejects_bodies() {
+        let budget = budget(100);
+        let _held = budget.reserve(80).await.unwrap();
+
+        let result = budget
+            .buffer(body(&[b"A,12.5\n"]), Some(50), 100, None)
+            .await;
+        assert!(matches!(result, Err(BufferError::Exhausted)));
+        assert_eq!(budget.report().rejected, 1);
+    }
+}
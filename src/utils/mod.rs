@@ -1,4 +1,36 @@
+pub mod api_mirror;
 pub mod auth;
+pub mod aws_chunked;
+pub mod cassette;
+pub mod checksum;
+pub mod clock;
+pub mod conformance;
 pub mod core;
+pub mod cors;
+pub mod error_documents;
 pub mod errors;
+pub mod expect;
+pub mod header_policy;
+pub mod hotlinking;
+pub mod lifecycle;
+pub mod list_cache;
+pub mod listing_slots;
+pub mod load_shedding;
+pub mod maintenance;
+pub mod memory_budget;
+pub mod normalize_path;
+pub mod params;
+pub mod policy;
+pub mod post_policy;
+pub mod prefix_jobs;
+pub mod preview;
+pub mod range_cache;
+pub mod recent_writes;
 pub mod repository;
+pub mod session_token;
+pub mod single_flight;
+pub mod slo;
+pub mod uploads;
+pub mod virtual_host;
+pub mod warm_pool;
+pub mod write_spool;
@@ -0,0 +1,107 @@
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::uri::{PathAndQuery, Uri},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+/// Trailing characters of a path which are never part of a key.
+const TRAILING_WHITESPACE: [&str; 4] = [" ", "\t", "%20", "%09"];
+
+/// Normalizes a request path: duplicate slashes are collapsed, `.` and `..`
+/// segments resolved, and trailing whitespace and slashes removed. `..` never
+/// climbs above the root, so it can't leave the account a path names.
+pub fn normalize_path(path: &str) -> String {
+    let mut path = path;
+    while let Some(trimmed) = TRAILING_WHITESPACE
+        .iter()
+        .find_map(|suffix| path.strip_suffix(suffix))
+    {
+        path = trimmed;
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+/// Routes requests by their normalized path, see [`normalize_path`], so paths
+/// like `/{account_id}//{repository_id}/./{key}` reach the object they name
+/// rather than an empty repository.
+///
+/// Signatures are verified before this, against the path as it was sent and
+/// signed.
+pub struct NormalizePaths;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for NormalizePaths
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = NormalizePathsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(NormalizePathsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct NormalizePathsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for NormalizePathsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let path = normalize_path(req.uri().path());
+
+        if path != req.uri().path() {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+
+            let mut parts = req.uri().clone().into_parts();
+            if let Ok(path_and_query) = PathAndQuery::try_from(path_and_query) {
+                parts.path_and_query = Some(path_and_query);
+
+                if let Ok(uri) = Uri::from_parts(parts) {
+                    req.match_info_mut().get_mut().update(&uri);
+                    req.head_mut().uri = uri;
+                }
+            }
+        }
+
+        let svc = self.service.clone();
+        Box::pin(async move { svc.call(req).await })
+    }
+}
@@ -0,0 +1,349 @@
+use core::num::NonZeroU32;
+use serde::{de, Deserialize, Deserializer};
+
+/// The largest part number S3 accepts for a multipart upload.
+pub const MAX_PART_NUMBER: i64 = 10000;
+
+/// Deserializes a `partNumber` query parameter, rejecting values outside of
+/// the `1..=10000` range accepted by S3.
+fn deserialize_part_number<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => match value.parse::<i64>() {
+            Ok(part_number) if (1..=MAX_PART_NUMBER).contains(&part_number) => {
+                Ok(Some(part_number))
+            }
+            _ => Err(de::Error::custom(format!(
+                "partNumber must be an integer between 1 and {}",
+                MAX_PART_NUMBER
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Deserializes the `encoding-type` query parameter. S3 only supports `url`.
+fn deserialize_encoding_type<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) if value == "url" => Ok(Some(value)),
+        Some(_) => Err(de::Error::custom("encoding-type must be url")),
+        None => Ok(None),
+    }
+}
+
+/// Query parameters accepted by `GET /{account_id}/{repository_id}/{key}`.
+#[derive(Debug, Deserialize)]
+pub struct GetObjectParams {
+    #[serde(rename = "uploadId")]
+    pub upload_id: Option<String>,
+    #[serde(rename = "max-parts")]
+    pub max_parts: Option<NonZeroU32>,
+    #[serde(rename = "part-number-marker")]
+    pub part_number_marker: Option<i64>,
+    pub tagging: Option<String>,
+    /// Returns a preview of the object instead of its contents. Only `csv` is supported.
+    pub preview: Option<String>,
+    /// The number of rows in a CSV preview.
+    pub rows: Option<NonZeroU32>,
+    /// Returns a PNG thumbnail with the given maximum edge length in pixels.
+    pub thumbnail: Option<u32>,
+    /// Returns the object count and total size under the key, as a folder.
+    pub stats: Option<String>,
+    /// Returns the canned ACL of the object.
+    pub acl: Option<String>,
+    /// Returns the hash of the object, or of the requested range, instead of
+    /// its contents. Only `sha256` is supported.
+    pub hash: Option<String>,
+    /// Reads the given version on a versioned backend instead of the current one.
+    #[serde(rename = "versionId")]
+    pub version_id: Option<String>,
+    /// Reads one part of a multipart object, as with a range covering it.
+    #[serde(rename = "partNumber")]
+    pub part_number: Option<i64>,
+    #[serde(rename = "response-content-type")]
+    pub response_content_type: Option<String>,
+    #[serde(rename = "response-content-language")]
+    pub response_content_language: Option<String>,
+    #[serde(rename = "response-expires")]
+    pub response_expires: Option<String>,
+    #[serde(rename = "response-cache-control")]
+    pub response_cache_control: Option<String>,
+    #[serde(rename = "response-content-disposition")]
+    pub response_content_disposition: Option<String>,
+    #[serde(rename = "response-content-encoding")]
+    pub response_content_encoding: Option<String>,
+}
+
+/// The headers which `response-*` parameters override, in parameter order.
+const RESPONSE_OVERRIDE_HEADERS: [&str; 6] = [
+    "content-type",
+    "content-language",
+    "expires",
+    "cache-control",
+    "content-disposition",
+    "content-encoding",
+];
+
+fn response_overrides(values: [&Option<String>; 6]) -> Vec<(&'static str, &str)> {
+    RESPONSE_OVERRIDE_HEADERS
+        .into_iter()
+        .zip(values)
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+        .collect()
+}
+
+impl GetObjectParams {
+    /// Returns the response headers overridden with `response-*` parameters,
+    /// e.g. to force a download with `response-content-disposition=attachment`.
+    pub fn response_overrides(&self) -> Vec<(&'static str, &str)> {
+        response_overrides([
+            &self.response_content_type,
+            &self.response_content_language,
+            &self.response_expires,
+            &self.response_cache_control,
+            &self.response_content_disposition,
+            &self.response_content_encoding,
+        ])
+    }
+}
+
+/// Query parameters accepted by `HEAD /{account_id}/{repository_id}/{key}`.
+#[derive(Debug, Deserialize)]
+pub struct HeadObjectParams {
+    #[serde(rename = "versionId")]
+    pub version_id: Option<String>,
+    #[serde(rename = "partNumber")]
+    pub part_number: Option<i64>,
+    #[serde(rename = "response-content-type")]
+    pub response_content_type: Option<String>,
+    #[serde(rename = "response-content-language")]
+    pub response_content_language: Option<String>,
+    #[serde(rename = "response-expires")]
+    pub response_expires: Option<String>,
+    #[serde(rename = "response-cache-control")]
+    pub response_cache_control: Option<String>,
+    #[serde(rename = "response-content-disposition")]
+    pub response_content_disposition: Option<String>,
+    #[serde(rename = "response-content-encoding")]
+    pub response_content_encoding: Option<String>,
+}
+
+impl HeadObjectParams {
+    /// Returns the response headers overridden with `response-*` parameters,
+    /// as a GET with the same parameters would.
+    pub fn response_overrides(&self) -> Vec<(&'static str, &str)> {
+        response_overrides([
+            &self.response_content_type,
+            &self.response_content_language,
+            &self.response_expires,
+            &self.response_cache_control,
+            &self.response_content_disposition,
+            &self.response_content_encoding,
+        ])
+    }
+}
+
+/// Query parameters accepted by `DELETE /{account_id}/{repository_id}/{key}`.
+#[derive(Debug, Deserialize)]
+pub struct DeleteParams {
+    #[serde(rename = "uploadId")]
+    pub upload_id: Option<String>,
+    pub tagging: Option<String>,
+    /// Permanently deletes the given version instead of adding a delete marker.
+    #[serde(rename = "versionId")]
+    pub version_id: Option<String>,
+}
+
+/// Query parameters accepted by `PUT /{account_id}/{repository_id}/{key}`.
+#[derive(Debug, Deserialize)]
+pub struct PutParams {
+    #[serde(
+        rename = "partNumber",
+        default,
+        deserialize_with = "deserialize_part_number"
+    )]
+    pub part_number: Option<i64>,
+    #[serde(rename = "uploadId")]
+    pub upload_id: Option<String>,
+    pub tagging: Option<String>,
+    /// `PutObjectAcl`, which is accepted but ignored.
+    pub acl: Option<String>,
+}
+
+/// Query parameters accepted by `POST /{account_id}/{repository_id}/{key}`.
+///
+/// Presence-only parameters such as `?uploads` deserialize to `Some("")`.
+#[derive(Debug, Deserialize)]
+pub struct PostParams {
+    pub uploads: Option<String>,
+    #[serde(rename = "uploadId")]
+    pub upload_id: Option<String>,
+    pub restore: Option<String>,
+}
+
+/// Query parameters accepted by `POST /{account_id}`.
+#[derive(Debug, Deserialize)]
+pub struct BucketPostParams {
+    pub delete: Option<String>,
+}
+
+/// Query parameters accepted by `GET /{account_id}` (ListObjectsV2, or
+/// ListObjects V1 when `list-type` is absent).
+#[derive(Debug, Deserialize)]
+pub struct ListObjectsV2Params {
+    pub prefix: Option<String>,
+    #[serde(rename = "list-type")]
+    pub list_type: Option<u8>,
+    /// The V1 equivalent of `continuation-token` or `start-after`.
+    pub marker: Option<String>,
+    #[serde(rename = "max-keys")]
+    pub max_keys: Option<NonZeroU32>,
+    pub delimiter: Option<String>,
+    #[serde(rename = "continuation-token")]
+    pub continuation_token: Option<String>,
+    #[serde(rename = "start-after")]
+    pub start_after: Option<String>,
+    #[serde(rename = "fetch-owner")]
+    pub fetch_owner: Option<bool>,
+    /// Lists the repositories of an organization's member accounts alongside
+    /// its own, as `{account_id}/{repository_id}/` prefixes.
+    #[serde(rename = "include-members")]
+    pub include_members: Option<bool>,
+    #[serde(
+        rename = "encoding-type",
+        default,
+        deserialize_with = "deserialize_encoding_type"
+    )]
+    pub _encoding_type: Option<String>,
+}
+
+/// Query parameters accepted by `POST /_admin/warm/{account_id}/{repository_id}`.
+#[derive(Debug, Deserialize)]
+pub struct WarmParams {
+    /// The number of objects to list and HEAD after the repository is resolved.
+    pub sample: Option<u32>,
+}
+
+/// Query parameters accepted by `POST /_auth/session-token`.
+#[derive(Debug, Deserialize)]
+pub struct SessionTokenParams {
+    pub account_id: String,
+    pub repository_id: String,
+    /// The lifetime of the credentials, between 15 minutes and 12 hours.
+    pub duration_seconds: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Query;
+
+    fn parse<T: de::DeserializeOwned>(query: &str) -> Result<T, String> {
+        Query::<T>::from_query(query)
+            .map(Query::into_inner)
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn part_numbers_must_be_within_range() {
+        let params: PutParams =
+            parse("partNumber=1&uploadId=2~uV9b3kP0x7Qm5Lr8Tz1Yc4Ne6Wa0Hd").unwrap();
+        assert_eq!(params.part_number, Some(1));
+        assert_eq!(
+            params.upload_id.as_deref(),
+            Some("2~uV9b3kP0x7Qm5Lr8Tz1Yc4Ne6Wa0Hd")
+        );
+
+        let params: PutParams = parse("partNumber=10000&uploadId=example").unwrap();
+        assert_eq!(params.part_number, Some(MAX_PART_NUMBER));
+
+        for part_number in ["0", "10001", "-1", "one", ""] {
+            let error = parse::<PutParams>(&format!("partNumber={}", part_number)).unwrap_err();
+            assert!(
+                error.contains("partNumber must be an integer between 1 and 10000"),
+                "{}: {}",
+                part_number,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn presence_only_parameters_are_empty_strings() {
+        let params: PostParams = parse("uploads").unwrap();
+        assert_eq!(params.uploads.as_deref(), Some(""));
+        assert_eq!(params.upload_id, None);
+
+        let params: PostParams = parse("uploads=").unwrap();
+        assert_eq!(params.uploads.as_deref(), Some(""));
+
+        let params: PutParams = parse("acl").unwrap();
+        assert_eq!(params.acl.as_deref(), Some(""));
+        assert_eq!(params.part_number, None);
+
+        let params: BucketPostParams = parse("delete").unwrap();
+        assert_eq!(params.delete.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn list_parameters() {
+        let params: ListObjectsV2Params = parse(
+            "list-type=2&prefix=weather%2Fdaily%2F&delimiter=%2F&max-keys=100\
+             &continuation-token=MTAwCg&start-after=weather%2Fa&fetch-owner=true\
+             &encoding-type=url",
+        )
+        .unwrap();
+        assert_eq!(params.list_type, Some(2));
+        assert_eq!(params.prefix.as_deref(), Some("weather/daily/"));
+        assert_eq!(params.delimiter.as_deref(), Some("/"));
+        assert_eq!(params.max_keys.map(NonZeroU32::get), Some(100));
+        assert_eq!(params.continuation_token.as_deref(), Some("MTAwCg"));
+        assert_eq!(params.start_after.as_deref(), Some("weather/a"));
+        assert_eq!(params.fetch_owner, Some(true));
+
+        // ListObjects V1
+        let params: ListObjectsV2Params = parse("marker=weather%2Fb&max-keys=1").unwrap();
+        assert_eq!(params.list_type, None);
+        assert_eq!(params.marker.as_deref(), Some("weather/b"));
+    }
+
+    #[test]
+    fn invalid_list_parameters_are_rejected() {
+        assert!(parse::<ListObjectsV2Params>("encoding-type=base64").is_err());
+        assert!(parse::<ListObjectsV2Params>("max-keys=0").is_err());
+        assert!(parse::<ListObjectsV2Params>("max-keys=-1").is_err());
+        assert!(parse::<ListObjectsV2Params>("list-type=two").is_err());
+    }
+
+    #[test]
+    fn response_overrides_are_ordered_by_header() {
+        let params: GetObjectParams = parse(
+            "response-content-disposition=attachment&response-content-type=text%2Fcsv\
+             &partNumber=2&versionId=3HL4kqtJlcpXroDTDmJ%2BrmSpXd3dIbrHY",
+        )
+        .unwrap();
+        assert_eq!(
+            params.response_overrides(),
+            vec![
+                ("content-type", "text/csv"),
+                ("content-disposition", "attachment"),
+            ]
+        );
+        assert_eq!(params.part_number, Some(2));
+        assert_eq!(
+            params.version_id.as_deref(),
+            Some("3HL4kqtJlcpXroDTDmJ+rmSpXd3dIbrHY")
+        );
+
+        let params: HeadObjectParams = parse("response-cache-control=no-cache").unwrap();
+        assert_eq!(
+            params.response_overrides(),
+            vec![("cache-control", "no-cache")]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::apis::source::RepositoryPermission;
+use crate::utils::auth::UserIdentity;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum PolicyEffect {
+    #[serde(rename = "allow")]
+    Allow,
+    #[serde(rename = "deny")]
+    Deny,
+}
+
+/// Conditions on the caller that must all hold for a statement to apply.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PolicyConditions {
+    /// Requires the request to be signed with an MFA-authenticated API key.
+    pub mfa_authenticated: Option<bool>,
+    /// Requires the request to be signed (`false`) or anonymous (`true`).
+    pub anonymous: Option<bool>,
+}
+
+/// A single statement of a repository access policy, e.g.
+///
+/// ```json
+/// { "effect": "allow", "actions": ["write"], "key_prefixes": ["incoming/"] }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyStatement {
+    pub effect: PolicyEffect,
+    pub actions: Vec<RepositoryPermission>,
+    /// The keys the statement applies to. An empty list matches every key.
+    #[serde(default)]
+    pub key_prefixes: Vec<String>,
+    #[serde(default)]
+    pub conditions: PolicyConditions,
+}
+
+impl PolicyStatement {
+    fn matches(&self, user_identity: &UserIdentity, key: &str) -> bool {
+        let key_matches = self.key_prefixes.is_empty()
+            || self
+                .key_prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix.as_str()));
+
+        let mfa_authenticated = user_identity
+            .api_key
+            .as_ref()
+            .is_some_and(|api_key| api_key.mfa_authenticated);
+        let mfa_matches = self
+            .conditions
+            .mfa_authenticated
+            .is_none_or(|required| required == mfa_authenticated);
+
+        let anonymous_matches = self
+            .conditions
+            .anonymous
+            .is_none_or(|required| required == user_identity.api_key.is_none());
+
+        key_matches && mfa_matches && anonymous_matches
+    }
+}
+
+/// Evaluates a repository's policy statements for a request that has already
+/// been granted `permission` by the caller's role.
+///
+/// Policies can only narrow access: any matching `deny` statement rejects the
+/// request, and if any `allow` statements cover the action, at least one of
+/// them has to match. Actions without statements are left to the role alone.
+pub fn evaluate_policy(
+    statements: &[PolicyStatement],
+    user_identity: &UserIdentity,
+    key: &str,
+    permission: &RepositoryPermission,
+) -> bool {
+    let applicable = statements
+        .iter()
+        .filter(|statement| statement.actions.contains(permission));
+
+    let mut has_allow = false;
+    let mut allowed = false;
+
+    for statement in applicable {
+        let matches = statement.matches(user_identity, key);
+        match statement.effect {
+            PolicyEffect::Deny if matches => return false,
+            PolicyEffect::Deny => {}
+            PolicyEffect::Allow => {
+                has_allow = true;
+                allowed |= matches;
+            }
+        }
+    }
+
+    !has_allow || allowed
+}
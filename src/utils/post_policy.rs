@@ -0,0 +1,278 @@
+use actix_multipart::Multipart;
+use actix_web::{
+    error::PayloadError,
+    http::header::{HeaderMap, CONTENT_TYPE},
+    web::Bytes,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A browser form upload (`POST /{account_id}` with a `multipart/form-data`
+/// body), as sent by S3 POST policy clients.
+pub struct FormUpload {
+    /// The form fields preceding the file, keyed by lowercase name.
+    pub fields: HashMap<String, String>,
+    pub file: Bytes,
+    /// The content type of the file part, used when the form doesn't set one.
+    pub file_content_type: Option<String>,
+}
+
+impl FormUpload {
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    /// Returns the `key` field, with `${filename}` replaced by the name of the
+    /// uploaded file.
+    pub fn key(&self) -> Option<String> {
+        let key = self.field("key")?;
+        Some(key.replace("${filename}", self.field("filename").unwrap_or("")))
+    }
+
+    /// Returns the `x-amz-meta-*` fields, keyed by name without the prefix.
+    pub fn metadata(&self) -> HashMap<String, String> {
+        self.fields
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.strip_prefix("x-amz-meta-")?;
+                Some((name.to_string(), value.clone()))
+            })
+            .collect()
+    }
+
+    pub fn content_type(&self) -> Option<String> {
+        self.field("content-type")
+            .map(|content_type| content_type.to_string())
+            .or_else(|| self.file_content_type.clone())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename = "PostResponse")]
+pub struct PostResponse {
+    #[serde(rename = "Location")]
+    pub location: String,
+    #[serde(rename = "Bucket")]
+    pub bucket: String,
+    #[serde(rename = "Key")]
+    pub key: String,
+}
+
+#[derive(Deserialize)]
+struct PolicyDocument {
+    expiration: String,
+    conditions: Vec<Value>,
+}
+
+/// Returns whether a request is a browser form upload.
+pub fn is_form_upload(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("multipart/form-data"))
+}
+
+/// Parses the body of a browser form upload.
+///
+/// As with S3, the `file` field must be the last field of the form; anything
+/// after it is ignored. The file name is made available as the `filename`
+/// field.
+pub async fn parse_form(headers: &HeaderMap, body: Bytes) -> Result<FormUpload, String> {
+    let mut multipart = Multipart::new(
+        headers,
+        stream::once(async move { Ok::<Bytes, PayloadError>(body) }),
+    );
+    let mut fields = HashMap::new();
+
+    while let Some(field) = multipart.next().await {
+        let mut field = field.map_err(|error| error.to_string())?;
+        let name = field.name().unwrap_or("").to_lowercase();
+        let filename = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(|filename| filename.to_string());
+        let file_content_type = field.content_type().map(|mime| mime.to_string());
+
+        let mut value = Vec::new();
+        while let Some(chunk) = field.next().await {
+            value.extend_from_slice(&chunk.map_err(|error| error.to_string())?);
+        }
+
+        if name == "file" {
+            if let Some(filename) = filename {
+                fields.insert("filename".to_string(), filename);
+            }
+            return Ok(FormUpload {
+                fields,
+                file: Bytes::from(value),
+                file_content_type,
+            });
+        }
+
+        let value = String::from_utf8(value).map_err(|_| format!("Field {} is not UTF-8", name))?;
+        fields.insert(name, value);
+    }
+
+    Err("POST requires exactly one file upload per request.".to_string())
+}
+
+/// Fields which are never covered by the conditions of a policy.
+fn is_exempt_field(name: &str) -> bool {
+    matches!(name, "policy" | "x-amz-signature" | "file" | "filename")
+        || name.starts_with("x-ignore-")
+}
+
+/// Checks a form upload against its base64 encoded policy document.
+///
+/// Every condition must hold, and every form field must be covered by a
+/// condition, so a signed policy can't be reused to set other fields. The
+/// returned error is the reason the upload is "invalid according to policy".
+pub fn check_policy_document(
+    policy: &str,
+    bucket: &str,
+    form: &FormUpload,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let document: PolicyDocument = STANDARD
+        .decode(policy)
+        .ok()
+        .and_then(|decoded| serde_json::from_slice(&decoded).ok())
+        .ok_or("Policy could not be parsed as a valid JSON.")?;
+
+    let expiration = DateTime::parse_from_rfc3339(&document.expiration)
+        .map_err(|_| "Invalid Policy: Invalid 'expiration' value.")?;
+    if expiration < now {
+        return Err("Policy expired.".to_string());
+    }
+
+    let key = form.key().unwrap_or_default();
+    let value_of = |name: &str| -> Option<String> {
+        match name {
+            "bucket" => Some(bucket.to_string()),
+            "key" => Some(key.clone()),
+            _ => form.field(name).map(|value| value.to_string()),
+        }
+    };
+
+    let mut covered: Vec<String> = Vec::new();
+
+    for condition in &document.conditions {
+        match condition {
+            Value::Object(entries) => {
+                for (name, expected) in entries {
+                    let name = name.to_lowercase();
+                    if value_of(&name).as_deref() != expected.as_str() {
+                        return Err(format!(
+                            "Policy Condition failed: [\"eq\", \"${}\", {}]",
+                            name, expected
+                        ));
+                    }
+                    covered.push(name);
+                }
+            }
+            Value::Array(items) => {
+                let operator = items.first().and_then(Value::as_str).unwrap_or("");
+
+                if operator.eq_ignore_ascii_case("content-length-range") {
+                    let min = items.get(1).and_then(Value::as_u64).unwrap_or(0);
+                    let max = items.get(2).and_then(Value::as_u64).unwrap_or(u64::MAX);
+                    let length = form.file.len() as u64;
+                    if length < min {
+                        return Err(
+                            "Your proposed upload is smaller than the minimum allowed size"
+                                .to_string(),
+                        );
+                    }
+                    if length > max {
+                        return Err(
+                            "Your proposed upload exceeds the maximum allowed size".to_string()
+                        );
+                    }
+                    continue;
+                }
+
+                let (Some(name), Some(expected)) = (
+                    items.get(1).and_then(Value::as_str),
+                    items.get(2).and_then(Value::as_str),
+                ) else {
+                    return Err(format!("Invalid Policy: Invalid condition {}", condition));
+                };
+                let name = name.trim_start_matches('$').to_lowercase();
+                let value = value_of(&name).unwrap_or_default();
+
+                let holds = match operator.to_lowercase().as_str() {
+                    "eq" => value == expected,
+                    "starts-with" => value.starts_with(expected),
+                    _ => return Err(format!("Invalid Policy: Unknown operator {}", operator)),
+                };
+                if !holds {
+                    return Err(format!("Policy Condition failed: {}", condition));
+                }
+                covered.push(name);
+            }
+            _ => return Err(format!("Invalid Policy: Invalid condition {}", condition)),
+        }
+    }
+
+    for name in form.fields.keys() {
+        if !is_exempt_field(name) && !covered.contains(name) {
+            return Err(format!("Extra input fields: {}", name));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::{Clock, FixedClock};
+
+    /// A policy allowing uploads under `weather/` until `expiration`.
+    fn policy(expiration: &str) -> String {
+        STANDARD.encode(format!(
+            r#"{{"expiration":"{}","conditions":[{{"bucket":"example"}},["starts-with","$key","weather/"]]}}"#,
+            expiration
+        ))
+    }
+
+    fn form() -> FormUpload {
+        FormUpload {
+            fields: HashMap::from([("key".to_string(), "weather/stations.csv".to_string())]),
+            file: Bytes::from_static(b"station,temperature\n"),
+            file_content_type: None,
+        }
+    }
+
+    fn check_at(policy: &str, now: &str) -> Result<(), String> {
+        let clock = FixedClock(
+            DateTime::parse_from_rfc3339(now)
+                .unwrap()
+                .with_timezone(&Utc),
+        );
+        check_policy_document(policy, "example", &form(), clock.now())
+    }
+
+    #[test]
+    fn policy_is_valid_until_its_expiration() {
+        let policy = policy("2024-05-14T12:00:00.000Z");
+        assert_eq!(check_at(&policy, "2024-05-14T11:59:59Z"), Ok(()));
+        assert_eq!(check_at(&policy, "2024-05-14T12:00:00Z"), Ok(()));
+        assert_eq!(
+            check_at(&policy, "2024-05-14T12:00:01Z"),
+            Err("Policy expired.".to_string())
+        );
+    }
+
+    #[test]
+    fn policy_expiration_must_be_a_timestamp() {
+        assert_eq!(
+            check_at(&policy("tomorrow"), "2024-05-14T12:00:00Z"),
+            Err("Invalid Policy: Invalid 'expiration' value.".to_string())
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::Utc;
+use moka::future::Cache;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The most error messages kept with a job.
+const MAX_JOB_ERRORS: usize = 100;
+
+/// The progress of a prefix deletion or metadata update started through the
+/// proxy.
+#[derive(Debug, Clone, Serialize)]
+pub struct PrefixJob {
+    pub job_id: String,
+    /// `delete-prefix` or `update-metadata`.
+    pub operation: &'static str,
+    #[serde(skip)]
+    pub access_key_id: String,
+    pub account_id: String,
+    pub repository_id: String,
+    pub prefix: String,
+    /// `running`, `completed` or `failed`.
+    pub state: String,
+    pub started: String,
+    pub finished: Option<String>,
+    pub listed: u64,
+    pub deleted: u64,
+    pub updated: u64,
+    pub failed: u64,
+    /// The first errors the job ran into.
+    pub errors: Vec<String>,
+}
+
+/// A handle to a running job, through which it reports its progress.
+#[derive(Clone)]
+pub struct PrefixJobHandle {
+    job: Arc<Mutex<PrefixJob>>,
+}
+
+impl PrefixJobHandle {
+    pub fn job_id(&self) -> String {
+        self.job.lock().unwrap().job_id.clone()
+    }
+
+    pub fn record_listed(&self, count: u64) {
+        self.job.lock().unwrap().listed += count;
+    }
+
+    pub fn record_deleted(&self) {
+        self.job.lock().unwrap().deleted += 1;
+    }
+
+    pub fn record_updated(&self) {
+        self.job.lock().unwrap().updated += 1;
+    }
+
+    pub fn record_failed(&self, key: &str, message: String) {
+        let mut job = self.job.lock().unwrap();
+        job.failed += 1;
+        if job.errors.len() < MAX_JOB_ERRORS {
+            job.errors.push(format!("{}: {}", key, message));
+        }
+    }
+
+    /// Marks the job as finished, as `failed` if it was interrupted by `error`.
+    pub fn finish(&self, error: Option<String>) {
+        let mut job = self.job.lock().unwrap();
+        job.finished = Some(Utc::now().to_rfc3339());
+        match error {
+            Some(error) => {
+                job.state = "failed".to_string();
+                job.errors.push(error);
+            }
+            None => job.state = "completed".to_string(),
+        }
+    }
+}
+
+/// Tracks prefix jobs so their owners can follow their progress.
+///
+/// Like [`UploadRegistry`](crate::utils::uploads::UploadRegistry), jobs are
+/// held in memory by the proxy instance running them, for a day after they
+/// were last looked at.
+#[derive(Clone)]
+pub struct PrefixJobRegistry {
+    jobs: Arc<Cache<String, Arc<Mutex<PrefixJob>>>>,
+}
+
+impl PrefixJobRegistry {
+    pub fn new() -> Self {
+        let jobs = Arc::new(
+            Cache::builder()
+                .time_to_idle(Duration::from_secs(60 * 60 * 24)) // Expire after a day of inactivity
+                .build(),
+        );
+
+        PrefixJobRegistry { jobs }
+    }
+
+    pub async fn start(
+        &self,
+        operation: &'static str,
+        access_key_id: String,
+        account_id: String,
+        repository_id: String,
+        prefix: String,
+    ) -> PrefixJobHandle {
+        let job_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let job = Arc::new(Mutex::new(PrefixJob {
+            job_id: job_id.clone(),
+            operation,
+            access_key_id,
+            account_id,
+            repository_id,
+            prefix,
+            state: "running".to_string(),
+            started: Utc::now().to_rfc3339(),
+            finished: None,
+            listed: 0,
+            deleted: 0,
+            updated: 0,
+            failed: 0,
+            errors: Vec::new(),
+        }));
+
+        self.jobs.insert(job_id, job.clone()).await;
+        PrefixJobHandle { job }
+    }
+
+    /// Returns a job, if it was started with the given access key.
+    pub async fn get(&self, job_id: &str, access_key_id: &str) -> Option<PrefixJob> {
+        let job = self.jobs.get(job_id).await?;
+        let job = job.lock().unwrap().clone();
+        (job.access_key_id == access_key_id).then_some(job)
+    }
+}
@@ -0,0 +1,79 @@
+use image::{imageops::FilterType, ImageFormat};
+use serde::Serialize;
+use std::io::Cursor;
+
+/// The number of bytes read from the start of an object for a CSV preview.
+pub const CSV_PREVIEW_BYTES: u64 = 1024 * 1024;
+
+/// The default and maximum number of rows in a CSV preview.
+pub const DEFAULT_PREVIEW_ROWS: usize = 50;
+pub const MAX_PREVIEW_ROWS: usize = 1000;
+
+/// Images larger than this are not thumbnailed.
+pub const MAX_THUMBNAIL_SOURCE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// The largest thumbnail edge length in pixels.
+pub const MAX_THUMBNAIL_SIZE: u32 = 1024;
+
+#[derive(Debug, Serialize)]
+pub struct CsvPreview {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// Whether the object has more rows than were returned.
+    pub truncated: bool,
+}
+
+/// Parses the first `rows` records of a CSV file, using the first record as the header.
+///
+/// `bytes` may be a prefix of the object, in which case `partial` must be set so
+/// that the trailing, possibly incomplete, line is discarded.
+pub fn csv_preview(bytes: &[u8], rows: usize, partial: bool) -> Result<CsvPreview, String> {
+    let bytes = if partial {
+        match bytes.iter().rposition(|b| *b == b'\n') {
+            Some(end) => &bytes[..=end],
+            None => return Err("The first row is too long to preview".to_string()),
+        }
+    } else {
+        bytes
+    };
+
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(bytes);
+
+    let columns = reader
+        .headers()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|column| column.to_string())
+        .collect();
+
+    let mut records = reader.records();
+    let mut preview = CsvPreview {
+        columns,
+        rows: Vec::new(),
+        truncated: false,
+    };
+
+    for record in records.by_ref().take(rows) {
+        let record = record.map_err(|e| e.to_string())?;
+        preview
+            .rows
+            .push(record.iter().map(|field| field.to_string()).collect());
+    }
+
+    preview.truncated = partial || records.next().is_some();
+
+    Ok(preview)
+}
+
+/// Renders a PNG thumbnail whose longest edge is at most `size` pixels.
+pub fn thumbnail(bytes: &[u8], size: u32) -> Result<Vec<u8>, String> {
+    let image = image::load_from_memory(bytes).map_err(|e| e.to_string())?;
+    let thumbnail = image.resize(size, size, FilterType::Triangle);
+
+    let mut output = Cursor::new(Vec::new());
+    thumbnail
+        .write_to(&mut output, ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+
+    Ok(output.into_inner())
+}
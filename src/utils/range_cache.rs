@@ -0,0 +1,101 @@
+use crate::backends::common::ObjectHeaders;
+use bytes::Bytes;
+use moka::future::Cache;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The largest metadata range that is kept in memory.
+const MAX_CACHED_RANGE: u64 = 4 * 1024 * 1024;
+
+/// A byte range of an object as returned by the backend.
+#[derive(Clone)]
+pub struct CachedRange {
+    pub bytes: Bytes,
+    pub headers: ObjectHeaders,
+}
+
+/// Caches the metadata ranges of cloud-optimized formats.
+///
+/// Clients such as DuckDB and GDAL start every query by reading the footer of
+/// a Parquet file or the header of a GeoTIFF, so the same small ranges are
+/// fetched over and over. Entries are keyed by the object's ETag and so are
+/// never served for a modified object. The cache size is set with
+/// `RANGE_CACHE_MAX_BYTES` (default 256 MiB).
+///
+/// Cached ranges aren't counted against the `MemoryBudget`: the cache is
+/// bounded by its own size, and evicts entries rather than waiting for memory,
+/// so counting them would only let a full cache hold memory that uploads wait
+/// for. The budget and the cache size are sized together instead.
+#[derive(Clone)]
+pub struct RangeCache {
+    ranges: Arc<Cache<String, CachedRange>>,
+}
+
+impl RangeCache {
+    pub fn from_env() -> Self {
+        let max_bytes = env::var("RANGE_CACHE_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(256 * 1024 * 1024);
+
+        let ranges = Arc::new(
+            Cache::builder()
+                .weigher(|_, range: &CachedRange| range.bytes.len().try_into().unwrap_or(u32::MAX))
+                .max_capacity(max_bytes)
+                .time_to_idle(Duration::from_secs(60 * 60)) // Expire after an hour without reads
+                .build(),
+        );
+
+        RangeCache { ranges }
+    }
+
+    fn extension(key: &str) -> String {
+        key.rsplit_once('.')
+            .map(|(_, extension)| extension.to_ascii_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the object is of a format whose metadata ranges are cached.
+    pub fn is_cacheable_format(key: &str) -> bool {
+        matches!(
+            RangeCache::extension(key).as_str(),
+            "parquet" | "geoparquet" | "tif" | "tiff" | "pmtiles" | "fgb"
+        )
+    }
+
+    /// Returns whether `start..=end` of an object of `total` bytes is a metadata
+    /// range of a known format.
+    pub fn is_cacheable(key: &str, start: u64, end: u64, total: u64) -> bool {
+        if end - start + 1 > MAX_CACHED_RANGE {
+            return false;
+        }
+
+        match RangeCache::extension(key).as_str() {
+            // Parquet metadata is stored at the end of the file
+            "parquet" | "geoparquet" => end + 1 == total,
+            // TIFF, PMTiles and FlatGeobuf keep their headers and indexes at the start
+            "tif" | "tiff" | "pmtiles" | "fgb" => start == 0,
+            _ => false,
+        }
+    }
+
+    fn cache_key(path: &str, etag: &str, start: u64, end: u64) -> String {
+        format!("{}\n{}\n{}-{}", path, etag, start, end)
+    }
+
+    pub async fn get(&self, path: &str, etag: &str, start: u64, end: u64) -> Option<CachedRange> {
+        self.ranges
+            .get(&RangeCache::cache_key(path, etag, start, end))
+            .await
+    }
+
+    pub async fn insert(&self, path: &str, start: u64, end: u64, range: CachedRange) {
+        self.ranges
+            .insert(
+                RangeCache::cache_key(path, &range.headers.etag, start, end),
+                range,
+            )
+            .await;
+    }
+}
@@ -0,0 +1,191 @@
+use crate::backends::common::{CommonPrefix, Content, ListBucketResult};
+use chrono::{SecondsFormat, Utc};
+use moka::future::Cache;
+use rand::distributions::{Alphanumeric, DistString};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The response header carrying the consistency token of a write.
+pub const CONSISTENCY_TOKEN_HEADER: &str = "x-source-consistency-token";
+
+/// The request header listing the consistency tokens a listing must reflect.
+pub const AFTER_WRITE_HEADER: &str = "x-source-after-write";
+
+/// The most tokens a listing request may pass.
+const MAX_AFTER_WRITE_TOKENS: usize = 100;
+
+/// The most writes each proxy instance remembers.
+const MAX_RECENT_WRITES: u64 = 100_000;
+
+/// A write made through the proxy, as it should appear in listings.
+#[derive(Clone)]
+pub struct RecentWrite {
+    seq: u64,
+    pub account_id: String,
+    pub repository_id: String,
+    /// The listed key, `{repository_id}/{key}`.
+    pub key: String,
+    /// The written object, or `None` for a deletion.
+    pub object: Option<Content>,
+}
+
+/// Remembers recent writes so that listings made right after them see them,
+/// even before the backend lists them.
+///
+/// Every write returns a token in `x-source-consistency-token`. Listings sent
+/// with those tokens in `x-source-after-write` (comma-separated) have the
+/// writes merged in: written objects are added and deleted ones removed.
+/// Tokens are held in memory by the proxy instance which issued them, for
+/// five minutes, and the oldest are evicted early beyond 100,000 writes.
+#[derive(Clone)]
+pub struct RecentWrites {
+    writes: Arc<Cache<String, RecentWrite>>,
+    next_seq: Arc<AtomicU64>,
+}
+
+impl RecentWrites {
+    pub fn new() -> Self {
+        let writes = Arc::new(
+            Cache::builder()
+                .max_capacity(MAX_RECENT_WRITES)
+                .time_to_live(Duration::from_secs(5 * 60))
+                .build(),
+        );
+
+        RecentWrites {
+            writes,
+            next_seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Records a write, returning its consistency token.
+    pub async fn record(
+        &self,
+        account_id: &str,
+        repository_id: &str,
+        key: &str,
+        object: Option<Content>,
+    ) -> String {
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        let write = RecentWrite {
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            account_id: account_id.to_string(),
+            repository_id: repository_id.to_string(),
+            key: format!("{}/{}", repository_id, key),
+            object,
+        };
+
+        self.writes.insert(token.clone(), write).await;
+        token
+    }
+
+    /// Returns the writes to a repository named by an `x-source-after-write`
+    /// header, in the order they were made.
+    pub async fn lookup(
+        &self,
+        tokens: &str,
+        account_id: &str,
+        repository_id: &str,
+    ) -> Vec<RecentWrite> {
+        let mut writes = Vec::new();
+        for token in tokens.split(',').take(MAX_AFTER_WRITE_TOKENS) {
+            if let Some(write) = self.writes.get(token.trim()).await {
+                if write.account_id == account_id && write.repository_id == repository_id {
+                    writes.push(write);
+                }
+            }
+        }
+
+        writes.sort_by_key(|write| write.seq);
+        writes
+    }
+}
+
+/// The listing entry of an object written just now.
+pub fn written_object(repository_id: &str, key: &str, size: i64, etag: String) -> Content {
+    Content {
+        key: format!("{}/{}", repository_id, key),
+        last_modified: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        etag,
+        size,
+        storage_class: "STANDARD".to_string(),
+        owner: None,
+    }
+}
+
+/// Merges recent writes into a listing page.
+///
+/// Only keys under `prefix` which sort after `after`, from the first entry of
+/// continued pages and, for truncated pages, before the last entry of the page
+/// are merged, so a write shows up on one page only. Pages may hold a few more
+/// entries than were asked for.
+///
+/// # Arguments
+///
+/// * `result` - A normalized listing page.
+/// * `writes` - The writes to merge, oldest first.
+/// * `prefix` - The listed prefix, including the repository.
+/// * `delimiter` - The delimiter the page was listed with.
+/// * `after` - The key the page starts after, if any.
+/// * `continued` - Whether the page continues an earlier one.
+pub fn merge_recent_writes(
+    result: &mut ListBucketResult,
+    writes: &[RecentWrite],
+    prefix: &str,
+    delimiter: Option<&str>,
+    after: Option<&str>,
+    continued: bool,
+) {
+    let names = || {
+        result
+            .contents
+            .iter()
+            .map(|content| content.key.clone())
+            .chain(result.common_prefixes.iter().map(|p| p.prefix.clone()))
+    };
+    let first = names().min().filter(|_| continued);
+    let last = names().max().filter(|_| result.is_truncated);
+
+    for write in writes {
+        let Some(rest) = write.key.strip_prefix(prefix) else {
+            continue;
+        };
+        if after.is_some_and(|after| write.key.as_str() <= after)
+            || first.as_ref().is_some_and(|first| write.key < *first)
+            || last.as_ref().is_some_and(|last| write.key >= *last)
+        {
+            continue;
+        }
+
+        // Objects below a delimiter are rolled up into their common prefix
+        if let Some(position) = delimiter
+            .filter(|delimiter| !delimiter.is_empty())
+            .and_then(|delimiter| rest.find(delimiter).map(|p| p + delimiter.len()))
+        {
+            let common_prefix = format!("{}{}", prefix, &rest[..position]);
+            if write.object.is_some()
+                && !result
+                    .common_prefixes
+                    .iter()
+                    .any(|p| p.prefix == common_prefix)
+            {
+                result.common_prefixes.push(CommonPrefix {
+                    prefix: common_prefix,
+                });
+            }
+            continue;
+        }
+
+        result.contents.retain(|content| content.key != write.key);
+        if let Some(object) = &write.object {
+            result.contents.push(object.clone());
+        }
+    }
+
+    result.contents.sort_by(|a, b| a.key.cmp(&b.key));
+    result
+        .common_prefixes
+        .sort_by(|a, b| a.prefix.cmp(&b.prefix));
+    result.key_count = (result.contents.len() + result.common_prefixes.len()) as i64;
+}
@@ -0,0 +1,147 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::env;
+
+/// What temporary credentials were issued for, carried in their session token
+/// so that any proxy instance can verify them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    pub access_key_id: String,
+    /// The long-lived key the credentials were derived from.
+    pub parent_access_key_id: String,
+    pub account_id: String,
+    pub repository_id: String,
+    pub expiration: DateTime<Utc>,
+}
+
+/// Signs and verifies the session tokens of temporary credentials.
+///
+/// A token is the claims followed by their HMAC-SHA256, and the secret access
+/// key of the credentials is another HMAC of the claims, so neither is stored
+/// anywhere. The key is `SESSION_TOKEN_SECRET`, or `SOURCE_KEY` when it isn't
+/// set, and must be the same on every proxy instance.
+pub struct SessionSigner {
+    key: Vec<u8>,
+}
+
+impl SessionSigner {
+    pub fn from_env() -> Self {
+        let key = env::var("SESSION_TOKEN_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty())
+            .or_else(|| env::var("SOURCE_KEY").ok())
+            .unwrap_or_default();
+
+        SessionSigner {
+            key: key.into_bytes(),
+        }
+    }
+
+    fn mac(&self, purpose: &str, payload: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key size");
+        mac.update(purpose.as_bytes());
+        mac.update(b"\n");
+        mac.update(payload.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Returns the session token and secret access key of `claims`.
+    pub fn sign(&self, claims: &SessionClaims) -> (String, String) {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+        let token = format!(
+            "{}.{}",
+            payload,
+            URL_SAFE_NO_PAD.encode(self.mac("token", &payload))
+        );
+        let secret_access_key = hex::encode(&self.mac("secret", &payload)[..20]);
+
+        (token, secret_access_key)
+    }
+
+    /// Returns the claims and secret access key of a session token this
+    /// signer issued for `access_key_id`, unless it has expired at `now`.
+    pub fn verify(
+        &self,
+        access_key_id: &str,
+        token: &str,
+        now: DateTime<Utc>,
+    ) -> Option<(SessionClaims, String)> {
+        let (payload, signature) = token.split_once('.')?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key size");
+        mac.update(b"token\n");
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?)
+            .ok()?;
+
+        let claims: SessionClaims =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        if claims.access_key_id != access_key_id || claims.expiration <= now {
+            return None;
+        }
+
+        let secret_access_key = hex::encode(&self.mac("secret", payload)[..20]);
+        Some((claims, secret_access_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(key: &str) -> SessionSigner {
+        SessionSigner {
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-14T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn claims() -> SessionClaims {
+        SessionClaims {
+            access_key_id: "ASIAEXAMPLE".to_string(),
+            parent_access_key_id: "SCKEXAMPLE".to_string(),
+            account_id: "example".to_string(),
+            repository_id: "weather".to_string(),
+            expiration: now() + chrono::Duration::hours(1),
+        }
+    }
+
+    #[test]
+    fn tokens_verify_on_any_instance_with_the_same_key() {
+        let (token, secret_access_key) = signer("shared").sign(&claims());
+
+        assert_eq!(
+            signer("shared").verify("ASIAEXAMPLE", &token, now()),
+            Some((claims(), secret_access_key))
+        );
+        assert_eq!(signer("other").verify("ASIAEXAMPLE", &token, now()), None);
+    }
+
+    #[test]
+    fn tampered_expired_or_misused_tokens_are_rejected() {
+        let signer = signer("shared");
+        let (token, _) = signer.sign(&claims());
+
+        let mut longer = claims();
+        longer.expiration = now() + chrono::Duration::days(30);
+        let (longer_token, _) = signer.sign(&longer);
+        let (_, signature) = token.split_once('.').unwrap();
+        let (payload, _) = longer_token.split_once('.').unwrap();
+        let tampered = format!("{}.{}", payload, signature);
+
+        assert_eq!(signer.verify("ASIAEXAMPLE", &tampered, now()), None);
+        assert_eq!(
+            signer.verify("ASIAEXAMPLE", &token, now() + chrono::Duration::hours(1)),
+            None
+        );
+        assert_eq!(signer.verify("ASIAOTHER", &token, now()), None);
+    }
+}
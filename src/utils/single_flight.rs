@@ -0,0 +1,101 @@
+use crate::utils::errors::{APIError, SharedAPIError};
+use moka::future::Cache;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How the lookups of a cache were answered since this instance started.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CacheCounters {
+    /// Lookups answered from the cache.
+    pub hits: u64,
+    /// Misses which fetched the value from the Source API.
+    pub fetches: u64,
+    /// Misses which waited for a fetch already in flight instead of starting
+    /// their own, i.e. stampedes avoided.
+    pub coalesced: u64,
+    /// The total time spent fetching, in milliseconds.
+    pub fetch_ms: u64,
+    /// The total time coalesced lookups waited for the fetch, in milliseconds.
+    pub wait_ms: u64,
+    pub max_wait_ms: u64,
+}
+
+/// Counters of the Source API caches, keyed by cache name.
+#[derive(Debug, Default, Clone)]
+pub struct CacheStats(Arc<Mutex<HashMap<&'static str, CacheCounters>>>);
+
+impl CacheStats {
+    pub fn report(&self) -> HashMap<&'static str, CacheCounters> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn record(&self, cache: &'static str, update: impl FnOnce(&mut CacheCounters)) {
+        update(self.0.lock().unwrap().entry(cache).or_default());
+    }
+}
+
+/// Returns the value cached under `key`, or fetches it with `fetch`.
+///
+/// Concurrent misses for the same key are coalesced into a single fetch, which
+/// every caller waits for, so an expiring entry doesn't send a stampede of
+/// requests to the Source API. Failed fetches aren't cached; their error is
+/// shared by the callers that waited for them.
+pub async fn get_or_fetch<V, F>(
+    cache: &Cache<String, V>,
+    stats: &CacheStats,
+    name: &'static str,
+    key: String,
+    fetch: F,
+) -> Result<V, Box<dyn APIError>>
+where
+    V: Clone + Send + Sync + 'static,
+    F: Future<Output = Result<V, Box<dyn APIError>>>,
+{
+    if let Some(value) = cache.get(&key).await {
+        stats.record(name, |counters| counters.hits += 1);
+        return Ok(value);
+    }
+
+    let started = Instant::now();
+    let fetched = AtomicBool::new(false);
+    let result = cache
+        .try_get_with(key.clone(), async {
+            fetched.store(true, Ordering::Relaxed);
+            fetch.await
+        })
+        .await;
+    let elapsed = as_millis(started.elapsed());
+
+    if fetched.load(Ordering::Relaxed) {
+        stats.record(name, |counters| {
+            counters.fetches += 1;
+            counters.fetch_ms += elapsed;
+        });
+    } else {
+        log::debug!(
+            "Coalesced {} cache lookup of {} after waiting {}ms",
+            name,
+            key,
+            elapsed
+        );
+        stats.record(name, |counters| {
+            counters.coalesced += 1;
+            counters.wait_ms += elapsed;
+            counters.max_wait_ms = counters.max_wait_ms.max(elapsed);
+        });
+    }
+
+    result.map_err(|error| Box::new(SharedAPIError(error)) as Box<dyn APIError>)
+}
+
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
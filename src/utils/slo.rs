@@ -0,0 +1,430 @@
+use actix_web::{
+    body::{BodySize, MessageBody},
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web, Error,
+};
+use futures_util::future::LocalBoxFuture;
+use pin_project_lite::pin_project;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    future::{ready, Ready},
+    pin::Pin,
+    rc::Rc,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Upper bounds of the time to first byte histogram, in milliseconds.
+const HISTOGRAM_BOUNDS_MS: [u64; 8] = [50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// The short window used alongside the rolling window to confirm a burn.
+const SHORT_WINDOW_MINUTES: u64 = 5;
+
+/// Alerts aren't sent for a class with fewer requests in the short window.
+const MIN_ALERT_REQUESTS: u64 = 20;
+
+/// Time between two alerts for the same class.
+const ALERT_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+
+/// The classes requests are tracked in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestClass {
+    PublicGet,
+    AuthenticatedGet,
+    Head,
+    List,
+    Write,
+}
+
+impl RequestClass {
+    /// Classifies a request, or returns `None` for internal endpoints.
+    fn of(req: &ServiceRequest) -> Option<RequestClass> {
+        if req.path().starts_with("/_") {
+            return None;
+        }
+
+        let is_object_path = req.path().trim_matches('/').splitn(3, '/').count() == 3;
+        let is_authenticated = req.headers().contains_key("Authorization")
+            || req.query_string().contains("X-Amz-Signature=");
+
+        match *req.method() {
+            Method::GET if !is_object_path => Some(RequestClass::List),
+            Method::GET if is_authenticated => Some(RequestClass::AuthenticatedGet),
+            Method::GET => Some(RequestClass::PublicGet),
+            Method::HEAD => Some(RequestClass::Head),
+            Method::PUT | Method::POST | Method::DELETE => Some(RequestClass::Write),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MinuteBucket {
+    minute: u64,
+    total: u64,
+    within_target: u64,
+    histogram: [u64; HISTOGRAM_BOUNDS_MS.len() + 1],
+}
+
+/// The state of a request class over the rolling window.
+#[derive(Serialize)]
+pub struct ClassReport {
+    pub requests: u64,
+    pub within_target: u64,
+    pub compliance: f64,
+    pub burn_rate: f64,
+    pub short_window_requests: u64,
+    pub short_window_burn_rate: f64,
+    /// Request counts by histogram bucket, keyed by upper bound (`le_<ms>`).
+    pub ttfb_histogram_ms: Vec<(String, u64)>,
+}
+
+#[derive(Serialize)]
+pub struct SloReport {
+    pub target_ms: u64,
+    pub objective: f64,
+    pub window_minutes: u64,
+    pub classes: HashMap<RequestClass, ClassReport>,
+}
+
+#[derive(Serialize)]
+struct BurnRateAlert<'a> {
+    class: RequestClass,
+    target_ms: u64,
+    objective: f64,
+    compliance: f64,
+    burn_rate: f64,
+    short_window_burn_rate: f64,
+    threshold: f64,
+    window_minutes: u64,
+    message: &'a str,
+}
+
+/// Tracks time to first byte against a latency objective, e.g. "95% of public
+/// GETs send their first byte within 500ms".
+///
+/// Requests are counted in per-minute buckets over a rolling window
+/// (`SLO_WINDOW_MINUTES`, default 60) against `SLO_TTFB_TARGET_MS` (default
+/// 500) and `SLO_OBJECTIVE` (default 0.95). When `SLO_ALERT_WEBHOOK_URL` is
+/// set, an alert is posted to it once the error budget of a class burns faster
+/// than `SLO_BURN_RATE_THRESHOLD` (default 10) over both the rolling window and
+/// the last five minutes.
+pub struct SloTracker {
+    target: Duration,
+    objective: f64,
+    window_minutes: u64,
+    burn_rate_threshold: f64,
+    webhook_url: Option<String>,
+    buckets: Mutex<HashMap<RequestClass, VecDeque<MinuteBucket>>>,
+    last_alerts: Mutex<HashMap<RequestClass, Instant>>,
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<T>().ok())
+        .unwrap_or(default)
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
+
+impl SloTracker {
+    pub fn from_env() -> Self {
+        SloTracker {
+            target: Duration::from_millis(env_or("SLO_TTFB_TARGET_MS", 500)),
+            objective: env_or("SLO_OBJECTIVE", 0.95_f64).clamp(0.0, 0.9999),
+            window_minutes: env_or("SLO_WINDOW_MINUTES", 60_u64).max(SHORT_WINDOW_MINUTES),
+            burn_rate_threshold: env_or("SLO_BURN_RATE_THRESHOLD", 10.0),
+            webhook_url: env::var("SLO_ALERT_WEBHOOK_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
+            buckets: Mutex::new(HashMap::new()),
+            last_alerts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the time to first byte of a request.
+    pub fn record(&self, class: RequestClass, ttfb: Duration) {
+        let minute = current_minute();
+        let histogram_index = HISTOGRAM_BOUNDS_MS
+            .iter()
+            .position(|bound| ttfb.as_millis() <= *bound as u128)
+            .unwrap_or(HISTOGRAM_BOUNDS_MS.len());
+
+        {
+            let mut buckets = self.buckets.lock().unwrap();
+            let class_buckets = buckets.entry(class).or_default();
+
+            if class_buckets.back().map(|bucket| bucket.minute) != Some(minute) {
+                class_buckets.push_back(MinuteBucket {
+                    minute,
+                    total: 0,
+                    within_target: 0,
+                    histogram: [0; HISTOGRAM_BOUNDS_MS.len() + 1],
+                });
+            }
+            while class_buckets
+                .front()
+                .is_some_and(|bucket| bucket.minute + self.window_minutes <= minute)
+            {
+                class_buckets.pop_front();
+            }
+
+            let bucket = class_buckets.back_mut().unwrap();
+            bucket.total += 1;
+            if ttfb <= self.target {
+                bucket.within_target += 1;
+            }
+            bucket.histogram[histogram_index] += 1;
+        }
+
+        if self.webhook_url.is_some() {
+            self.check_burn_rate(class);
+        }
+    }
+
+    /// Returns the burn rate of the error budget over a set of buckets, along
+    /// with the number of requests and those within target.
+    fn burn_rate<'a>(&self, buckets: impl Iterator<Item = &'a MinuteBucket>) -> (f64, u64, u64) {
+        let (total, within_target) = buckets.fold((0, 0), |(total, within_target), bucket| {
+            (total + bucket.total, within_target + bucket.within_target)
+        });
+
+        if total == 0 {
+            return (0.0, 0, 0);
+        }
+
+        let error_rate = (total - within_target) as f64 / total as f64;
+        (error_rate / (1.0 - self.objective), total, within_target)
+    }
+
+    fn class_report(&self, buckets: &VecDeque<MinuteBucket>, minute: u64) -> ClassReport {
+        let in_window = |bucket: &&MinuteBucket| bucket.minute + self.window_minutes > minute;
+        let in_short_window =
+            |bucket: &&MinuteBucket| bucket.minute + SHORT_WINDOW_MINUTES > minute;
+
+        let (burn_rate, requests, within_target) = self.burn_rate(buckets.iter().filter(in_window));
+        let (short_window_burn_rate, short_window_requests, _) =
+            self.burn_rate(buckets.iter().filter(in_short_window));
+
+        let mut histogram = [0; HISTOGRAM_BOUNDS_MS.len() + 1];
+        for bucket in buckets.iter().filter(in_window) {
+            for (count, bucket_count) in histogram.iter_mut().zip(bucket.histogram.iter()) {
+                *count += bucket_count;
+            }
+        }
+
+        ClassReport {
+            requests,
+            within_target,
+            compliance: if requests == 0 {
+                1.0
+            } else {
+                within_target as f64 / requests as f64
+            },
+            burn_rate,
+            short_window_requests,
+            short_window_burn_rate,
+            ttfb_histogram_ms: HISTOGRAM_BOUNDS_MS
+                .iter()
+                .map(|bound| format!("le_{}", bound))
+                .chain(std::iter::once("le_inf".to_string()))
+                .zip(histogram)
+                .collect(),
+        }
+    }
+
+    pub fn report(&self) -> SloReport {
+        let minute = current_minute();
+        let buckets = self.buckets.lock().unwrap();
+
+        SloReport {
+            target_ms: self.target.as_millis() as u64,
+            objective: self.objective,
+            window_minutes: self.window_minutes,
+            classes: buckets
+                .iter()
+                .map(|(class, class_buckets)| (*class, self.class_report(class_buckets, minute)))
+                .collect(),
+        }
+    }
+
+    fn check_burn_rate(&self, class: RequestClass) {
+        let Some(webhook_url) = self.webhook_url.clone() else {
+            return;
+        };
+
+        let report = {
+            let buckets = self.buckets.lock().unwrap();
+            match buckets.get(&class) {
+                Some(class_buckets) => self.class_report(class_buckets, current_minute()),
+                None => return,
+            }
+        };
+
+        if report.short_window_requests < MIN_ALERT_REQUESTS
+            || report.burn_rate < self.burn_rate_threshold
+            || report.short_window_burn_rate < self.burn_rate_threshold
+        {
+            return;
+        }
+
+        {
+            let mut last_alerts = self.last_alerts.lock().unwrap();
+            if last_alerts
+                .get(&class)
+                .is_some_and(|sent| sent.elapsed() < ALERT_COOLDOWN)
+            {
+                return;
+            }
+            last_alerts.insert(class, Instant::now());
+        }
+
+        let message = format!(
+            "Time to first byte of {:?} requests is burning its error budget {:.1}x too fast ({:.2}% within {}ms)",
+            class,
+            report.burn_rate,
+            report.compliance * 100.0,
+            self.target.as_millis()
+        );
+        log::warn!("{}", message);
+
+        let alert = serde_json::to_value(BurnRateAlert {
+            class,
+            target_ms: self.target.as_millis() as u64,
+            objective: self.objective,
+            compliance: report.compliance,
+            burn_rate: report.burn_rate,
+            short_window_burn_rate: report.short_window_burn_rate,
+            threshold: self.burn_rate_threshold,
+            window_minutes: self.window_minutes,
+            message: &message,
+        })
+        .unwrap_or_default();
+
+        actix_web::rt::spawn(async move {
+            let client = reqwest::Client::new();
+            if let Err(error) = client.post(webhook_url).json(&alert).send().await {
+                log::warn!("Failed to send SLO alert: {}", error);
+            }
+        });
+    }
+}
+
+pin_project! {
+    /// Records the time to first byte once the wrapped body yields.
+    pub struct FirstByteBody<B> {
+        #[pin]
+        body: B,
+        recorder: Option<(web::Data<SloTracker>, RequestClass, Instant)>,
+    }
+}
+
+impl<B: MessageBody> MessageBody for FirstByteBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<web::Bytes, Self::Error>>> {
+        let this = self.project();
+        let poll = this.body.poll_next(cx);
+
+        if poll.is_ready() {
+            if let Some((tracker, class, started)) = this.recorder.take() {
+                tracker.record(class, started.elapsed());
+            }
+        }
+
+        poll
+    }
+}
+
+/// Measures the time to first byte of every request for the [`SloTracker`].
+pub struct SloTracking {
+    tracker: web::Data<SloTracker>,
+}
+
+impl SloTracking {
+    pub fn new(tracker: web::Data<SloTracker>) -> Self {
+        SloTracking { tracker }
+    }
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for SloTracking
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<FirstByteBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SloTrackingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SloTrackingMiddleware {
+            service: Rc::new(service),
+            tracker: self.tracker.clone(),
+        }))
+    }
+}
+
+pub struct SloTrackingMiddleware<S> {
+    service: Rc<S>,
+    tracker: web::Data<SloTracker>,
+}
+
+impl<S, B> Service<ServiceRequest> for SloTrackingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<FirstByteBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let tracker = self.tracker.clone();
+        let started = Instant::now();
+        let class = RequestClass::of(&req);
+
+        Box::pin(async move {
+            let res = svc.call(req).await?;
+
+            let mut recorder = class.map(|class| (tracker, class, started));
+
+            // Empty bodies are never polled, so the headers are the first byte
+            if matches!(
+                res.response().body().size(),
+                BodySize::None | BodySize::Sized(0)
+            ) {
+                if let Some((tracker, class, started)) = recorder.take() {
+                    tracker.record(class, started.elapsed());
+                }
+            }
+
+            Ok(res.map_body(|_, body| FirstByteBody { body, recorder }))
+        })
+    }
+}
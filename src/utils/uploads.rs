@@ -0,0 +1,95 @@
+use chrono::Utc;
+use moka::future::Cache;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The progress of a single multipart upload started through the proxy.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadSession {
+    #[serde(skip)]
+    pub access_key_id: String,
+    pub account_id: String,
+    pub repository_id: String,
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: String,
+    pub last_activity: String,
+    pub parts_completed: usize,
+    pub bytes_received: u64,
+    /// Sizes of the uploaded parts by part number. Re-uploading a part replaces it.
+    #[serde(skip)]
+    pub part_sizes: BTreeMap<i64, u64>,
+}
+
+/// Tracks in-progress multipart uploads so publishers can query their progress.
+///
+/// Sessions are held in memory by each proxy instance and expire after a day
+/// without activity, so the view only covers uploads routed to this instance.
+#[derive(Clone)]
+pub struct UploadRegistry {
+    sessions: Arc<Cache<String, Arc<Mutex<UploadSession>>>>,
+}
+
+impl UploadRegistry {
+    pub fn new() -> Self {
+        let sessions = Arc::new(
+            Cache::builder()
+                .time_to_idle(Duration::from_secs(60 * 60 * 24)) // Expire after a day of inactivity
+                .build(),
+        );
+
+        UploadRegistry { sessions }
+    }
+
+    pub async fn start(
+        &self,
+        access_key_id: String,
+        account_id: String,
+        repository_id: String,
+        key: String,
+        upload_id: String,
+    ) {
+        let now = Utc::now().to_rfc3339();
+        let session = UploadSession {
+            access_key_id,
+            account_id,
+            repository_id,
+            key,
+            upload_id: upload_id.clone(),
+            initiated: now.clone(),
+            last_activity: now,
+            parts_completed: 0,
+            bytes_received: 0,
+            part_sizes: BTreeMap::new(),
+        };
+
+        self.sessions
+            .insert(upload_id, Arc::new(Mutex::new(session)))
+            .await;
+    }
+
+    pub async fn record_part(&self, upload_id: &str, part_number: i64, size: u64) {
+        if let Some(session) = self.sessions.get(upload_id).await {
+            let mut session = session.lock().unwrap();
+            session.part_sizes.insert(part_number, size);
+            session.parts_completed = session.part_sizes.len();
+            session.bytes_received = session.part_sizes.values().sum();
+            session.last_activity = Utc::now().to_rfc3339();
+        }
+    }
+
+    pub async fn finish(&self, upload_id: &str) {
+        self.sessions.invalidate(upload_id).await;
+    }
+
+    /// Returns the active sessions started with the given access key.
+    pub fn sessions_for(&self, access_key_id: &str) -> Vec<UploadSession> {
+        self.sessions
+            .iter()
+            .map(|(_, session)| session.lock().unwrap().clone())
+            .filter(|session| session.access_key_id == access_key_id)
+            .collect()
+    }
+}
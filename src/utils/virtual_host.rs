@@ -0,0 +1,136 @@
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderMap, HOST},
+        uri::{PathAndQuery, Uri},
+    },
+    Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{
+    env,
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+/// The path a virtual-hosted-style request was sent to, before it was
+/// rewritten. SigV4 signatures are computed over this path.
+#[derive(Clone)]
+pub struct OriginalPath(pub String);
+
+/// Rewrites virtual-hosted-style requests, where the account is the first
+/// label of the host (`{account_id}.data.source.coop`), into the path-style
+/// requests (`/{account_id}/...`) the handlers are routed by.
+///
+/// `VIRTUAL_HOST_DOMAINS` is a comma-separated list of the domains accounts
+/// are hosted under, `data.source.coop` by default. Requests to any other host
+/// are left as they are.
+#[derive(Clone)]
+pub struct VirtualHosts {
+    domains: Rc<Vec<String>>,
+}
+
+impl VirtualHosts {
+    pub fn from_env() -> Self {
+        let domains = env::var("VIRTUAL_HOST_DOMAINS")
+            .unwrap_or_else(|_| "data.source.coop".to_string())
+            .split(',')
+            .map(|domain| domain.trim().trim_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+
+        VirtualHosts {
+            domains: Rc::new(domains),
+        }
+    }
+
+    /// Returns the account a request is addressed to by its host, if any.
+    fn account_id(&self, host: &str) -> Option<String> {
+        // Without the port
+        let host = host
+            .rsplit_once(':')
+            .map_or(host, |(host, _)| host)
+            .to_ascii_lowercase();
+
+        self.domains.iter().find_map(|domain| {
+            let account_id = host.strip_suffix(domain.as_str())?.strip_suffix('.')?;
+            (!account_id.is_empty() && !account_id.contains('.')).then(|| account_id.to_string())
+        })
+    }
+
+    /// Returns the path-style path of a virtual-hosted-style request.
+    pub fn path_style(&self, headers: &HeaderMap, uri: &Uri) -> Option<String> {
+        let host = headers
+            .get(HOST)
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| uri.host())?;
+        let account_id = self.account_id(host)?;
+
+        Some(match uri.path() {
+            "" | "/" => format!("/{}", account_id),
+            path => format!("/{}{}", account_id, path),
+        })
+    }
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for VirtualHosts
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = VirtualHostsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(VirtualHostsMiddleware {
+            service: Rc::new(service),
+            virtual_hosts: self.clone(),
+        }))
+    }
+}
+
+pub struct VirtualHostsMiddleware<S> {
+    service: Rc<S>,
+    virtual_hosts: VirtualHosts,
+}
+
+impl<S, B> Service<ServiceRequest> for VirtualHostsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if let Some(path) = self.virtual_hosts.path_style(req.headers(), req.uri()) {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+
+            let mut parts = req.uri().clone().into_parts();
+            if let Ok(path_and_query) = PathAndQuery::try_from(path_and_query) {
+                parts.path_and_query = Some(path_and_query);
+
+                if let Ok(uri) = Uri::from_parts(parts) {
+                    let original_path = OriginalPath(req.path().to_string());
+                    req.extensions_mut().insert(original_path);
+                    req.match_info_mut().get_mut().update(&uri);
+                    req.head_mut().uri = uri;
+                }
+            }
+        }
+
+        let svc = self.service.clone();
+        Box::pin(async move { svc.call(req).await })
+    }
+}
@@ -0,0 +1,108 @@
+use crate::apis::source::SourceAPI;
+use actix_web::web;
+use futures_util::future::join_all;
+use serde::Serialize;
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Idle connections to the Source API are kept open this long.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Builds the HTTP client shared by all requests to the Source API.
+///
+/// Connections are kept alive and reused, so cache misses don't pay for a TLS
+/// handshake, and HTTP/2 is used when the Source API negotiates it, with
+/// keep-alive pings so idle connections aren't dropped by load balancers.
+pub fn source_api_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .pool_max_idle_per_host(32)
+        .tcp_keepalive(Duration::from_secs(60))
+        .http2_adaptive_window(true)
+        .http2_keep_alive_interval(Duration::from_secs(30))
+        .http2_keep_alive_while_idle(true)
+        .build()
+        .unwrap_or_default()
+}
+
+/// Round trips of the pings keeping connections to the Source API warm.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PingCounters {
+    pub pings: u64,
+    pub failures: u64,
+    pub last_ms: Option<u64>,
+    pub max_ms: u64,
+    pub total_ms: u64,
+}
+
+pub type PingStats = Arc<Mutex<PingCounters>>;
+
+/// Keeps a pool of connections to the Source API warm.
+///
+/// Every `SOURCE_API_PING_SECONDS` (default 30, `0` disables the pings),
+/// `SOURCE_API_WARM_CONNECTIONS` (default 4) concurrent `HEAD` requests are
+/// sent to the Source API, so that many pooled connections stay open between
+/// bursts of traffic. Their round trips are reported by
+/// `GET /_admin/source-api`.
+pub struct WarmPool {
+    interval: Option<Duration>,
+    connections: usize,
+}
+
+impl WarmPool {
+    pub fn from_env() -> Self {
+        let interval = env::var("SOURCE_API_PING_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(30);
+        let connections = env::var("SOURCE_API_WARM_CONNECTIONS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(4)
+            .max(1);
+
+        WarmPool {
+            interval: (interval > 0).then(|| Duration::from_secs(interval)),
+            connections,
+        }
+    }
+
+    /// Starts pinging in the background, unless pings are disabled.
+    pub fn start(self, api_client: web::Data<SourceAPI>) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(interval);
+            loop {
+                interval.tick().await;
+                join_all((0..self.connections).map(|_| ping(&api_client))).await;
+            }
+        });
+    }
+}
+
+async fn ping(api_client: &SourceAPI) {
+    let started = Instant::now();
+    let result = api_client.http.head(&api_client.endpoint).send().await;
+    let elapsed = started.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+
+    let mut counters = api_client.ping_stats.lock().unwrap();
+    counters.pings += 1;
+    match result {
+        // Any response means the connection is open
+        Ok(_) => {
+            counters.last_ms = Some(elapsed);
+            counters.max_ms = counters.max_ms.max(elapsed);
+            counters.total_ms += elapsed;
+        }
+        Err(error) => {
+            counters.failures += 1;
+            log::warn!("Could not ping the Source API: {}", error);
+        }
+    }
+}
@@ -0,0 +1,560 @@
+use crate::apis::source::SourceAPI;
+use crate::apis::API;
+use crate::backends::common::{ObjectLock, PutObjectOptions, ServerSideEncryption};
+use crate::utils::errors::APIError;
+use actix_web::web::{self, Bytes};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A spooled write, stored as `{seq}.json` next to its body in `{seq}.body`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpoolEntry {
+    pub seq: u64,
+    pub account_id: String,
+    pub repository_id: String,
+    pub key: String,
+    pub requester_pays: bool,
+    pub content_type: Option<String>,
+    pub metadata: HashMap<String, String>,
+    /// Absent from entries spooled by earlier versions.
+    #[serde(default)]
+    pub object_lock: ObjectLock,
+    #[serde(default)]
+    pub tagging: Option<String>,
+    #[serde(default)]
+    pub server_side_encryption: ServerSideEncryption,
+    pub size: u64,
+}
+
+impl SpoolEntry {
+    fn path(&self) -> String {
+        format!("{}/{}/{}", self.account_id, self.repository_id, self.key)
+    }
+}
+
+/// The directory of a spool holding writes the backend rejected for good.
+const DEAD_LETTER_DIR: &str = "dead-letter";
+
+/// A spooled write the backend rejected, e.g. with `AccessDenied`, which is
+/// no longer replayed. Its entry and body are kept in the spool's
+/// `dead-letter` directory, along with the error in `{seq}.error`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub seq: u64,
+    pub path: String,
+    pub error: String,
+}
+
+/// Why a spooled write couldn't be replayed.
+struct ReplayError {
+    message: String,
+    /// The backend rejected the write, so replaying it again won't succeed.
+    permanent: bool,
+}
+
+impl ReplayError {
+    fn transient(error: impl ToString) -> Self {
+        ReplayError {
+            message: error.to_string(),
+            permanent: false,
+        }
+    }
+
+    /// Failures answered with a `4xx` are permanent, others are retried.
+    fn from_api_error(error: Box<dyn APIError>) -> Self {
+        ReplayError {
+            message: error.to_string(),
+            permanent: error.to_response().status().is_client_error(),
+        }
+    }
+}
+
+/// The object a write is for.
+pub struct SpoolTarget {
+    pub account_id: String,
+    pub repository_id: String,
+    pub key: String,
+    pub requester_pays: bool,
+}
+
+#[derive(Default)]
+struct SpoolState {
+    next_seq: u64,
+    pending: BTreeMap<u64, SpoolEntry>,
+    bytes: u64,
+    dead_letters: Vec<DeadLetter>,
+}
+
+/// The pending writes of the spool, as reported by the admin endpoint.
+#[derive(Serialize)]
+pub struct SpoolReport {
+    pub enabled: bool,
+    pub pending: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub keys: Vec<String>,
+    pub dead_letters: Vec<DeadLetter>,
+}
+
+/// A write-behind spool for self-hosted deployments on unreliable links.
+///
+/// When `WRITE_SPOOL_DIR` is set, PutObject requests which fail because the
+/// backend is unavailable are written to disk and answered with
+/// `202 Accepted`. A replay worker retries them every
+/// `WRITE_SPOOL_REPLAY_SECONDS` (default 30) until the backend accepts them.
+/// Writes the backend rejects with a `4xx` are moved to the `dead-letter`
+/// directory of the spool instead, logged and reported by the admin endpoint.
+///
+/// Entries are fsynced before a write is acknowledged and survive restarts.
+/// Writes to a key are replayed in the order they were received, and while
+/// a key has spooled writes, later writes to it are spooled behind them. The
+/// spool holds at most `WRITE_SPOOL_MAX_BYTES` (default 10 GiB).
+///
+/// The spool is disabled unless `WRITE_SPOOL_DIR` is set, and must stay
+/// disabled in the hosted deployment, where reads would not see spooled
+/// writes across instances.
+pub struct WriteSpool {
+    dir: Option<PathBuf>,
+    max_bytes: u64,
+    replay_interval: Duration,
+    state: Mutex<SpoolState>,
+}
+
+impl WriteSpool {
+    pub fn from_env() -> io::Result<Self> {
+        let dir = env::var("WRITE_SPOOL_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        let max_bytes = env::var("WRITE_SPOOL_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(10 * 1024 * 1024 * 1024);
+        let replay_interval = Duration::from_secs(
+            env::var("WRITE_SPOOL_REPLAY_SECONDS")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(30)
+                .max(1),
+        );
+
+        let state = match &dir {
+            Some(dir) => recover(dir)?,
+            None => SpoolState::default(),
+        };
+
+        if let Some(dir) = &dir {
+            log::info!(
+                "Write spool enabled in {} with {} pending writes",
+                dir.display(),
+                state.pending.len()
+            );
+        }
+
+        Ok(WriteSpool {
+            dir,
+            max_bytes,
+            replay_interval,
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Returns whether a failed write may be spooled: the backend failed or
+    /// was unreachable, rather than rejecting the request.
+    pub fn is_spoolable(&self, error: &dyn APIError) -> bool {
+        self.is_enabled() && error.to_response().status().is_server_error()
+    }
+
+    /// Returns whether writes to an object are waiting in the spool.
+    pub fn has_pending(&self, account_id: &str, repository_id: &str, key: &str) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        let path = format!("{}/{}/{}", account_id, repository_id, key);
+        let state = self.state.lock().unwrap();
+        state.pending.values().any(|entry| entry.path() == path)
+    }
+
+    /// Durably stores a write for replay. Returns why it couldn't be spooled.
+    pub async fn spool(
+        &self,
+        target: SpoolTarget,
+        options: &PutObjectOptions,
+        bytes: Bytes,
+    ) -> Result<(), String> {
+        let Some(dir) = self.dir.clone() else {
+            return Err("the write spool is disabled".to_string());
+        };
+
+        // Preconditions and retention can only be evaluated by the backend
+        if options.if_none_match
+            || options.object_lock.mode.is_some()
+            || options.object_lock.legal_hold_status.is_some()
+        {
+            return Err("conditional and Object Lock writes are not spooled".to_string());
+        }
+
+        // Stored the way the S3 backend stores checksums, as user metadata
+        let mut metadata = options.metadata.clone();
+        if let Some(checksum) = &options.checksum {
+            metadata.insert(checksum.algorithm.metadata_key(), checksum.value.clone());
+        }
+
+        let size = bytes.len() as u64;
+        let entry = {
+            let mut state = self.state.lock().unwrap();
+            if state.bytes + size > self.max_bytes {
+                return Err("the write spool is full".to_string());
+            }
+
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            // Reserved now, so concurrent writes can't overfill the spool
+            state.bytes += size;
+
+            SpoolEntry {
+                seq,
+                account_id: target.account_id,
+                repository_id: target.repository_id,
+                key: target.key,
+                requester_pays: target.requester_pays,
+                content_type: options.content_type.clone(),
+                metadata,
+                object_lock: options.object_lock.clone(),
+                tagging: options.tagging.clone(),
+                server_side_encryption: options.server_side_encryption.clone(),
+                size,
+            }
+        };
+
+        let persisted = entry.clone();
+        let result = web::block(move || persist(&dir, &persisted, &bytes))
+            .await
+            .map_err(|error| error.to_string())
+            .and_then(|result| result.map_err(|error| error.to_string()));
+
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(()) => {
+                state.pending.insert(entry.seq, entry);
+                Ok(())
+            }
+            Err(error) => {
+                state.bytes -= size;
+                Err(format!("the write could not be spooled: {}", error))
+            }
+        }
+    }
+
+    pub fn report(&self) -> SpoolReport {
+        let state = self.state.lock().unwrap();
+        SpoolReport {
+            enabled: self.is_enabled(),
+            pending: state.pending.len(),
+            bytes: state.bytes,
+            max_bytes: self.max_bytes,
+            keys: state.pending.values().map(SpoolEntry::path).collect(),
+            dead_letters: state.dead_letters.clone(),
+        }
+    }
+
+    /// Replays pending writes in the order they were spooled. A write that
+    /// fails holds back later writes to the same key until the next pass.
+    pub async fn replay(&self, api_client: &SourceAPI) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+
+        let entries: Vec<SpoolEntry> = {
+            let state = self.state.lock().unwrap();
+            state.pending.values().cloned().collect()
+        };
+
+        let mut blocked = HashSet::new();
+        for entry in entries {
+            let path = entry.path();
+            if blocked.contains(&path) {
+                continue;
+            }
+
+            match self.replay_entry(api_client, &dir, &entry).await {
+                Ok(()) => {}
+                Err(error) if error.permanent => {
+                    log::error!(
+                        "Spooled write {} of {} was rejected, moving it to the dead letters: {}",
+                        entry.seq,
+                        path,
+                        error.message
+                    );
+                    let seq = entry.seq;
+                    let (dead_letter_dir, message) = (dir.clone(), error.message.clone());
+                    let moved = web::block(move || dead_letter(&dead_letter_dir, seq, &message))
+                        .await
+                        .map_err(|error| error.to_string())
+                        .and_then(|result| result.map_err(|error| error.to_string()));
+                    if let Err(error) = moved {
+                        log::warn!(
+                            "Could not move spooled write {} of {}: {}",
+                            seq,
+                            path,
+                            error
+                        );
+                        blocked.insert(path);
+                        continue;
+                    }
+
+                    self.state.lock().unwrap().dead_letters.push(DeadLetter {
+                        seq,
+                        path,
+                        error: error.message,
+                    });
+                }
+                Err(error) => {
+                    log::warn!(
+                        "Could not replay spooled write {} of {}: {}",
+                        entry.seq,
+                        path,
+                        error.message
+                    );
+                    blocked.insert(path);
+                    continue;
+                }
+            }
+
+            let mut state = self.state.lock().unwrap();
+            state.pending.remove(&entry.seq);
+            state.bytes -= entry.size;
+        }
+    }
+
+    async fn replay_entry(
+        &self,
+        api_client: &SourceAPI,
+        dir: &Path,
+        entry: &SpoolEntry,
+    ) -> Result<(), ReplayError> {
+        let body_path = dir.join(format!("{:020}.body", entry.seq));
+        let bytes = web::block(move || fs::read(body_path))
+            .await
+            .map_err(ReplayError::transient)?
+            .map_err(ReplayError::transient)?;
+
+        let client = api_client
+            .get_backend_client(
+                &entry.account_id,
+                &entry.repository_id,
+                entry.requester_pays,
+            )
+            .await
+            .map_err(ReplayError::from_api_error)?;
+
+        let options = PutObjectOptions {
+            content_type: entry.content_type.clone(),
+            metadata: entry.metadata.clone(),
+            object_lock: entry.object_lock.clone(),
+            tagging: entry.tagging.clone(),
+            server_side_encryption: entry.server_side_encryption.clone(),
+            ..Default::default()
+        };
+        client
+            .put_object(entry.key.clone(), Bytes::from(bytes), options)
+            .await
+            .map_err(ReplayError::from_api_error)?;
+
+        // The entry is removed before its body, so a crash in between leaves
+        // an orphaned body rather than an entry without one
+        let dir = dir.to_path_buf();
+        let seq = entry.seq;
+        web::block(move || {
+            fs::remove_file(dir.join(format!("{:020}.json", seq)))?;
+            fs::remove_file(dir.join(format!("{:020}.body", seq)))
+        })
+        .await
+        .map_err(ReplayError::transient)?
+        .map_err(ReplayError::transient)
+    }
+
+    /// Starts the replay worker, if the spool is enabled.
+    pub fn start(spool: web::Data<WriteSpool>, api_client: web::Data<SourceAPI>) {
+        if !spool.is_enabled() {
+            return;
+        }
+
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(spool.replay_interval);
+            loop {
+                interval.tick().await;
+                spool.replay(&api_client).await;
+            }
+        });
+    }
+}
+
+/// Writes a file and fsyncs it before moving it into place, so that a file
+/// under its final name is always complete.
+fn write_durably(dir: &Path, name: &str, contents: &[u8]) -> io::Result<()> {
+    let temporary = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temporary, dir.join(name))
+}
+
+/// Stores a spooled write. The entry is written last, so only complete
+/// writes are ever recovered.
+fn persist(dir: &Path, entry: &SpoolEntry, bytes: &[u8]) -> io::Result<()> {
+    write_durably(dir, &format!("{:020}.body", entry.seq), bytes)?;
+    write_durably(
+        dir,
+        &format!("{:020}.json", entry.seq),
+        &serde_json::to_vec(entry)?,
+    )?;
+    // Makes the renames durable
+    File::open(dir)?.sync_all()
+}
+
+/// Moves a spooled write to the dead letters along with its error. The entry
+/// is moved before its body, so a crash in between leaves an orphaned body
+/// which recovery removes rather than a pending entry without one.
+fn dead_letter(dir: &Path, seq: u64, error: &str) -> io::Result<()> {
+    let dead_letter_dir = dir.join(DEAD_LETTER_DIR);
+    fs::create_dir_all(&dead_letter_dir)?;
+
+    write_durably(
+        &dead_letter_dir,
+        &format!("{:020}.error", seq),
+        error.as_bytes(),
+    )?;
+    for name in [format!("{:020}.json", seq), format!("{:020}.body", seq)] {
+        fs::rename(dir.join(&name), dead_letter_dir.join(&name))?;
+    }
+    File::open(dir)?.sync_all()?;
+    File::open(&dead_letter_dir)?.sync_all()
+}
+
+/// Loads the writes in the dead letters of a spool directory.
+fn recover_dead_letters(dir: &Path) -> io::Result<Vec<DeadLetter>> {
+    let dead_letter_dir = dir.join(DEAD_LETTER_DIR);
+    if !dead_letter_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut dead_letters = Vec::new();
+    for file in fs::read_dir(&dead_letter_dir)? {
+        let path = file?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+            continue;
+        }
+
+        let entry: SpoolEntry = serde_json::from_slice(&fs::read(&path)?)?;
+        let error = fs::read_to_string(path.with_extension("error")).unwrap_or_default();
+        dead_letters.push(DeadLetter {
+            seq: entry.seq,
+            path: entry.path(),
+            error,
+        });
+    }
+    dead_letters.sort_by_key(|dead_letter| dead_letter.seq);
+
+    Ok(dead_letters)
+}
+
+/// Loads the pending writes of a spool directory, removing partial writes.
+fn recover(dir: &Path) -> io::Result<SpoolState> {
+    fs::create_dir_all(dir)?;
+
+    let dead_letters = recover_dead_letters(dir)?;
+    // Sequence numbers aren't reused, so dead letters are never overwritten
+    let mut state = SpoolState {
+        next_seq: dead_letters
+            .last()
+            .map_or(0, |dead_letter| dead_letter.seq + 1),
+        dead_letters,
+        ..Default::default()
+    };
+    let mut bodies = HashSet::new();
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+
+        if name.ends_with(".tmp") {
+            fs::remove_file(&path)?;
+        } else if name.ends_with(".body") {
+            bodies.insert(path);
+        } else if name.ends_with(".json") {
+            let entry: SpoolEntry = serde_json::from_slice(&fs::read(&path)?)?;
+            state.bytes += entry.size;
+            state.next_seq = state.next_seq.max(entry.seq + 1);
+            state.pending.insert(entry.seq, entry);
+        }
+    }
+
+    for body in bodies {
+        let seq = body
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        if !seq.is_some_and(|seq| state.pending.contains_key(&seq)) {
+            fs::remove_file(&body)?;
+        } else if let Some(seq) = seq {
+            state.next_seq = state.next_seq.max(seq + 1);
+        }
+    }
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(seq: u64) -> SpoolEntry {
+        SpoolEntry {
+            seq,
+            account_id: "example".to_string(),
+            repository_id: "weather".to_string(),
+            key: "stations.csv".to_string(),
+            requester_pays: false,
+            content_type: Some("text/csv".to_string()),
+            metadata: HashMap::new(),
+            object_lock: ObjectLock::default(),
+            tagging: None,
+            server_side_encryption: ServerSideEncryption::default(),
+            size: 7,
+        }
+    }
+
+    #[test]
+    fn dead_letters_are_no_longer_pending_after_recovery() {
+        let dir = env::temp_dir().join(format!("write-spool-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        persist(&dir, &entry(0), b"A,12.5\n").unwrap();
+        persist(&dir, &entry(1), b"A,13.0\n").unwrap();
+
+        dead_letter(&dir, 0, "Access Denied").unwrap();
+        let state = recover(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(state.pending.keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(state.bytes, 7);
+        assert_eq!(state.next_seq, 2);
+        assert_eq!(state.dead_letters.len(), 1);
+        assert_eq!(state.dead_letters[0].path, "example/weather/stations.csv");
+        assert_eq!(state.dead_letters[0].error, "Access Denied");
+    }
+}